@@ -1,15 +1,104 @@
-use axum::Json;
+use axum::{
+    extract::Request,
+    http::{HeaderName, HeaderValue},
+    middleware::Next,
+    response::Response,
+    Json,
+};
 use either::Either;
 use reqwest::StatusCode;
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
-use tryhcs_shared::api_params::PaginatedResult;
+use tracing::Instrument;
+use tryhcs_shared::{
+    api_params::{ApiEnvelope, ErrorCode, PaginatedResult},
+    validation::ValidationErrors,
+};
+use uuid::Uuid;
+
+use crate::CORRELATION_ID_HEADER_FIELD;
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
-pub struct ErrorMessage(pub String);
+pub struct ErrorMessage {
+    pub code: ErrorCode,
+    pub message: String,
+}
+
+impl ErrorMessage {
+    pub fn new(code: ErrorCode) -> Self {
+        Self {
+            code,
+            message: code.message().into(),
+        }
+    }
+
+    pub fn with_message(code: ErrorCode, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        self.code.status()
+    }
+
+    pub fn response<T>(self) -> ApiResponse<T> {
+        (self.status(), Either::Right(self))
+    }
+}
+
+impl From<ErrorCode> for ErrorMessage {
+    fn from(code: ErrorCode) -> Self {
+        ErrorMessage::new(code)
+    }
+}
 
 pub type ApiResponse<T> = (StatusCode, Either<Option<T>, ErrorMessage>);
 
+pub fn api_error<T>(code: ErrorCode) -> ApiResponse<T> {
+    ErrorMessage::new(code).response()
+}
+
+tokio::task_local! {
+    static CORRELATION_ID: String;
+}
+
+/// Id of the request being served, `None` outside `correlation_id_middleware`
+pub fn correlation_id() -> Option<String> {
+    CORRELATION_ID.try_with(|id| id.clone()).ok()
+}
+
+/// Tags the request with the caller's `X-Correlation-Id` or a new one, it is
+/// echoed back in the header and the body of the response.
+pub async fn correlation_id_middleware(req: Request, next: Next) -> Response {
+    let correlation_id = req
+        .headers()
+        .get(CORRELATION_ID_HEADER_FIELD)
+        .and_then(|v| v.to_str().ok())
+        .filter(|v| {
+            !v.is_empty()
+                && v.len() <= 64
+                && v.chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        })
+        .map(|v| v.to_owned())
+        .unwrap_or_else(|| Uuid::new_v4().to_string());
+
+    let span = tracing::info_span!("request", correlation_id = %correlation_id);
+    let mut response = CORRELATION_ID
+        .scope(correlation_id.clone(), next.run(req).instrument(span))
+        .await;
+
+    if let (Ok(name), Ok(value)) = (
+        HeaderName::from_bytes(CORRELATION_ID_HEADER_FIELD.as_bytes()),
+        HeaderValue::from_str(&correlation_id),
+    ) {
+        response.headers_mut().insert(name, value);
+    }
+    response
+}
+
 pub fn convert_result_to_json_response<T: Serialize>(
     res: eyre::Result<ApiResponse<T>>,
 ) -> (StatusCode, Json<Value>) {
@@ -18,12 +107,11 @@ pub fn convert_result_to_json_response<T: Serialize>(
             return convert_to_json_response(api_response);
         }
         Err(err) => {
+            if let Some(invalid) = err.downcast_ref::<ValidationErrors>() {
+                return convert_validation_errors(invalid);
+            }
             tracing::error!(err=?err);
-            return (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(json!({"error_message": "Request processing failed",
-             "status_code": StatusCode::INTERNAL_SERVER_ERROR.as_u16()})),
-            );
+            return convert_error_to_json_response(ErrorMessage::new(ErrorCode::InternalError));
         }
     }
 }
@@ -32,40 +120,107 @@ pub fn convert_paginated_result_to_json_response<T: Serialize>(
     res: eyre::Result<(StatusCode, PaginatedResult<T>)>,
 ) -> (StatusCode, Json<Value>) {
     match res {
-        Ok((status, api_response)) => {
+        Ok((status, mut api_response)) => {
+            api_response.correlation_id = correlation_id();
             return (status, Json(api_response.into()));
         }
         Err(err) => {
+            if let Some(invalid) = err.downcast_ref::<ValidationErrors>() {
+                return convert_validation_errors(invalid);
+            }
             tracing::error!(err=?err);
-            return (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(json!({"error_message": "Request processing failed",
-                 "status_code": StatusCode::INTERNAL_SERVER_ERROR.as_u16()})),
-            );
+            return convert_error_to_json_response(ErrorMessage::new(ErrorCode::InternalError));
         }
     }
 }
 
+/// Page with no data carrying the error, for handlers returning a `PaginatedResult`
+pub fn paginated_error<T>(error: ErrorMessage) -> (StatusCode, PaginatedResult<T>) {
+    let status = error.status();
+    (
+        status,
+        PaginatedResult {
+            status_code: status.as_u16(),
+            message: error.message,
+            error_code: Some(error.code),
+            ..Default::default()
+        },
+    )
+}
+
 pub fn convert_to_json_response<T: Serialize>(
     (status, result): ApiResponse<T>,
 ) -> (StatusCode, Json<Value>) {
-    let status_code = status.as_u16();
-
     match result {
-        Either::Right(ErrorMessage(message)) => {
-            return (
-                status,
-                Json(json!({"message": message,
-                 "status_code": status_code})),
-            );
+        Either::Right(error) => {
+            return convert_error_to_json_response(error);
         }
         Either::Left(data) => {
-            return (
+            return envelope_response(
                 status,
-                Json(json!({"message": "Successful!",
-                 "data": data,
-                 "status_code": status_code})),
+                ApiEnvelope {
+                    status_code: status.as_u16(),
+                    message: "Successful!".into(),
+                    data,
+                    error_code: None,
+                    errors: vec![],
+                    correlation_id: correlation_id(),
+                },
             );
         }
     }
 }
+
+pub fn convert_error_to_json_response(error: ErrorMessage) -> (StatusCode, Json<Value>) {
+    let status = error.status();
+    envelope_response::<()>(
+        status,
+        ApiEnvelope {
+            status_code: status.as_u16(),
+            message: error.message,
+            data: None,
+            error_code: Some(error.code),
+            errors: vec![],
+            correlation_id: correlation_id(),
+        },
+    )
+}
+
+/// 400 with the message of every invalid field plus the `{field, code, message}`
+/// list so clients can highlight the fields
+pub fn convert_validation_errors(invalid: &ValidationErrors) -> (StatusCode, Json<Value>) {
+    let status = ErrorCode::ValidationFailed.status();
+    envelope_response::<()>(
+        status,
+        ApiEnvelope {
+            status_code: status.as_u16(),
+            message: invalid.to_string(),
+            data: None,
+            error_code: Some(ErrorCode::ValidationFailed),
+            errors: invalid.errors.clone(),
+            correlation_id: correlation_id(),
+        },
+    )
+}
+
+fn envelope_response<T: Serialize>(
+    status: StatusCode,
+    envelope: ApiEnvelope<T>,
+) -> (StatusCode, Json<Value>) {
+    match serde_json::to_value(&envelope) {
+        Ok(body) => (status, Json(body)),
+        Err(err) => {
+            tracing::error!(message = "Response serialization failed", err=?err);
+            let status = StatusCode::INTERNAL_SERVER_ERROR;
+            (
+                status,
+                Json(json!({"message": ErrorCode::InternalError.message(),
+                 "status_code": status.as_u16(),
+                 "data": null,
+                 "error_code": ErrorCode::InternalError,
+                 "errors": [],
+                 "correlation_id": correlation_id()})),
+            )
+        }
+    }
+}
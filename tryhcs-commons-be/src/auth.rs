@@ -1,12 +1,20 @@
+use std::future::Future;
+
 use either::Either;
-use reqwest::StatusCode;
 use serde::{Deserialize, Serialize};
 use tryhcs_shared::{
-    api_params::ErrorMessage,
-    institution_params::{AuthorizedInstitutionUser, AuthorizedUser, InstitutionId},
+    api_params::ErrorCode,
+    institution_params::{
+        AuthorizedInstitutionUser, AuthorizedUser, BasePermission, InstitutionId, PermittedAction,
+    },
 };
 
-use crate::{ADMIN_DOMAIN, FORBIDDEN_API_STATUS_CODE};
+use crate::{api_response::ErrorMessage, redis::Cache, ADMIN_DOMAIN};
+
+const WORKSPACE_STATUS_PREFIX: &str = "WKS-STATUS-";
+const WORKSPACE_STATUS_CACHE_IN_SEC: u64 = 60;
+const WORKSPACE_DEACTIVATED: &str = "Deactivated";
+const WORKSPACE_ACTIVE: &str = "Active";
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct InstitutionAdminUser(pub AuthorizedInstitutionUser);
@@ -14,7 +22,7 @@ pub struct InstitutionAdminUser(pub AuthorizedInstitutionUser);
 impl InstitutionAdminUser {
     pub async fn new(user: AuthorizedInstitutionUser) -> Result<Self, ErrorMessage> {
         if !(InstitutionAdminUser::is_workspace_admin(&user).await) {
-            return Err(ErrorMessage("Unauthorized".into()));
+            return Err(ErrorMessage::new(ErrorCode::AuthForbidden));
         }
 
         return Ok(InstitutionAdminUser(user));
@@ -27,6 +35,61 @@ impl InstitutionAdminUser {
     }
 }
 
+// The institution record decides whether a workspace is deactivated, the
+// workspace extractors of every module cache the answer for a minute so they
+// don't read it on every request. Deactivating flags the workspace right away,
+// a flag that fails to be written only leaves the old status until it expires.
+pub async fn flag_deactivated_workspace(
+    cache: &dyn Cache,
+    workspace_code: &str,
+) -> eyre::Result<()> {
+    cache
+        .set_key(
+            &format!("{}{}", WORKSPACE_STATUS_PREFIX, workspace_code),
+            WORKSPACE_DEACTIVATED,
+            Some(WORKSPACE_STATUS_CACHE_IN_SEC),
+        )
+        .await
+}
+
+pub async fn unflag_deactivated_workspace(
+    cache: &dyn Cache,
+    workspace_code: &str,
+) -> eyre::Result<()> {
+    cache
+        .delete_key(&format!("{}{}", WORKSPACE_STATUS_PREFIX, workspace_code))
+        .await
+}
+
+/// `deactivated_in_db` reads the institution record, it's only awaited when
+/// the status isn't cached or the cache can't be reached.
+pub async fn is_workspace_deactivated(
+    cache: &dyn Cache,
+    workspace_code: &str,
+    deactivated_in_db: impl Future<Output = eyre::Result<bool>>,
+) -> eyre::Result<bool> {
+    let key = format!("{}{}", WORKSPACE_STATUS_PREFIX, workspace_code);
+    match cache.get_key(&key).await {
+        Ok(Some(status)) => return Ok(status == WORKSPACE_DEACTIVATED),
+        Ok(None) => {}
+        Err(err) => tracing::warn!(err=?err, "Workspace status cache unavailable"),
+    }
+
+    let deactivated = deactivated_in_db.await?;
+    let status = if deactivated {
+        WORKSPACE_DEACTIVATED
+    } else {
+        WORKSPACE_ACTIVE
+    };
+    if let Err(err) = cache
+        .set_key(&key, status, Some(WORKSPACE_STATUS_CACHE_IN_SEC))
+        .await
+    {
+        tracing::warn!(err=?err, "Failed to cache workspace status");
+    }
+    Ok(deactivated)
+}
+
 pub fn is_adminstrative_department(dept_name: &str) -> bool {
     ADMIN_DOMAIN.eq_ignore_ascii_case(&dept_name.to_lowercase())
 }
@@ -39,7 +102,7 @@ pub trait TypeAuthenticated {
     fn from_authorized_user(
         user: AuthorizedUser,
         workspace_code: &str,
-    ) -> eyre::Result<Either<Self::Authorized, (StatusCode, ErrorMessage)>>;
+    ) -> eyre::Result<Either<Self::Authorized, ErrorMessage>>;
 }
 
 impl TypeAuthenticated for AuthorizedInstitutionUser {
@@ -54,7 +117,7 @@ impl TypeAuthenticated for AuthorizedInstitutionUser {
     fn from_authorized_user(
         user: AuthorizedUser,
         workspace_code: &str,
-    ) -> eyre::Result<Either<Self, (StatusCode, ErrorMessage)>> {
+    ) -> eyre::Result<Either<Self, ErrorMessage>> {
         let account = user
             .accounts
             .iter()
@@ -62,11 +125,57 @@ impl TypeAuthenticated for AuthorizedInstitutionUser {
             .map(|v| v.clone());
 
         Ok(match account {
-            None => Either::Right((
-                FORBIDDEN_API_STATUS_CODE,
-                ErrorMessage("Unknown Workspace/Institution".into()),
-            )),
+            None => Either::Right(ErrorMessage::new(ErrorCode::AuthUnknownWorkspace)),
             Some(account) => Either::Left(account),
         })
     }
 }
+
+// Marker for the action a `Requires` extractor checks, enum values can't be
+// used as const generics so every checked action gets a marker type.
+pub trait RequiredAction: Send + Sync {
+    const ACTION: PermittedAction;
+}
+
+macro_rules! required_action {
+    ($name:ident, $action:expr) => {
+        pub struct $name;
+
+        impl RequiredAction for $name {
+            const ACTION: PermittedAction = $action;
+        }
+    };
+}
+
+required_action!(
+    PersonnelCreate,
+    PermittedAction::PersonnelManagement(BasePermission::Create)
+);
+required_action!(
+    PersonnelEdit,
+    PermittedAction::PersonnelManagement(BasePermission::Edit)
+);
+required_action!(
+    PersonnelDelete,
+    PermittedAction::PersonnelManagement(BasePermission::Delete)
+);
+required_action!(
+    DepartmentCreate,
+    PermittedAction::DepartmentManagement(BasePermission::Create)
+);
+required_action!(
+    DepartmentEdit,
+    PermittedAction::DepartmentManagement(BasePermission::Edit)
+);
+required_action!(
+    DepartmentDelete,
+    PermittedAction::DepartmentManagement(BasePermission::Delete)
+);
+required_action!(
+    InstitutionSettingView,
+    PermittedAction::InstitutionSetting(BasePermission::View)
+);
+required_action!(
+    InstitutionSettingEdit,
+    PermittedAction::InstitutionSetting(BasePermission::Edit)
+);
@@ -0,0 +1,61 @@
+use std::convert::Infallible;
+
+use axum::{
+    extract::FromRequestParts,
+    http::{header::USER_AGENT, request::Parts},
+};
+use serde::{Deserialize, Serialize};
+
+// Details of the client making the request, the ip address is read from the
+// proxy headers as the services run behind a load balancer.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct ClientContext {
+    pub user_agent: Option<String>,
+    pub ip_address: Option<String>,
+}
+
+impl ClientContext {
+    // a readable default name for devices the user hasn't named
+    pub fn device_name(&self) -> String {
+        let user_agent = match &self.user_agent {
+            None => return "Unknown device".into(),
+            Some(user_agent) => user_agent,
+        };
+
+        let platform = ["Android", "iPhone", "iPad", "Windows", "Macintosh", "Linux"]
+            .into_iter()
+            .find(|p| user_agent.contains(p));
+        let browser = ["Edg", "Chrome", "Firefox", "Safari"]
+            .into_iter()
+            .find(|b| user_agent.contains(b));
+
+        match (browser, platform) {
+            (Some(browser), Some(platform)) => format!("{} on {}", browser, platform),
+            (None, Some(platform)) => platform.into(),
+            _ => user_agent.chars().take(100).collect(),
+        }
+    }
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for ClientContext {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(req: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let header = |name: &str| {
+            req.headers
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .map(|v| v.trim().to_owned())
+                .filter(|v| !v.is_empty())
+        };
+
+        let ip_address = header("X-Forwarded-For")
+            .and_then(|v| v.split(',').next().map(|ip| ip.trim().to_owned()))
+            .or_else(|| header("X-Real-IP"));
+
+        Ok(ClientContext {
+            user_agent: header(USER_AGENT.as_str()),
+            ip_address,
+        })
+    }
+}
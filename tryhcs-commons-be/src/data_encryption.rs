@@ -9,6 +9,8 @@ use lettre::transport::smtp::response;
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use tracing::error;
 
+use crate::env::EnvConfig;
+
 pub trait EncryptableData {
     type Data: Serialize + DeserializeOwned;
     fn encrypt(&mut self, key: &[u8], nonce: &[u8]) -> eyre::Result<()>;
@@ -94,7 +96,6 @@ impl<T: Serialize + DeserializeOwned + Clone> EncryptableData for DeterministicE
             }
         };
 
-        dbg!(&encrypted);
         self.field_data = DeterministicField::V1(SecretField::Encrypted(encrypted));
         Ok(())
     }
@@ -154,7 +155,7 @@ impl<T: Serialize + DeserializeOwned + Clone> EncryptableData for DeterministicE
     fn get_encrypted_data(&mut self, key: &[u8], nonce: &[u8]) -> eyre::Result<Vec<u8>> {
         let data_is_encrypted_before = self.is_encrypted();
         if !data_is_encrypted_before {
-            let _ = self.encrypt(key, nonce);
+            self.encrypt(key, nonce)?;
         }
 
         let result = match &self.field_data {
@@ -170,7 +171,7 @@ impl<T: Serialize + DeserializeOwned + Clone> EncryptableData for DeterministicE
         };
 
         if !data_is_encrypted_before {
-            self.encrypt(key, nonce)?;
+            self.decrypt(key, nonce)?;
         }
         result
     }
@@ -461,6 +462,32 @@ impl Encryptor {
         Encryptor { key, nonce }
     }
 
+    // loads the key encryption key and nonce from their base64 config values
+    pub fn from_base64(key: &str, nonce: &str) -> eyre::Result<Self> {
+        use base64::{prelude::BASE64_STANDARD, Engine};
+
+        let key = BASE64_STANDARD.decode(key.trim())?;
+        let nonce = BASE64_STANDARD.decode(nonce.trim())?;
+        if key.len() != 32 || nonce.len() != 12 {
+            return Err(eyre!(
+                "Encryption key must be 32 bytes and nonce 12 bytes long"
+            ));
+        }
+
+        Ok(Encryptor { key, nonce })
+    }
+
+    // the configured encryptor, None when the deployment has no key yet
+    pub fn from_env(env: &EnvConfig) -> eyre::Result<Option<Self>> {
+        match (&env.data_encryption_key, &env.data_encryption_nonce) {
+            (None, None) => Ok(None),
+            (Some(key), Some(nonce)) => Self::from_base64(key, nonce).map(Some),
+            _ => Err(eyre!(
+                "Both the data encryption key and nonce have to be set"
+            )),
+        }
+    }
+
     pub fn generate() -> Self {
         let key = aes_gcm::Aes256Gcm::generate_key(aes_gcm::aead::OsRng).to_vec(); // Safe to use because the key is never repeated
         let nonce = aes_gcm::Aes256Gcm::generate_nonce(&mut aes_gcm::aead::OsRng).to_vec();
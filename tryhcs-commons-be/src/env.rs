@@ -2,18 +2,60 @@ use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Serialize, Deserialize, Clone, Default)]
 pub struct EnvConfig {
+    // deployment the server runs in (development, test, staging, production),
+    // test doubles like the mock verification provider only run in development and test
+    #[serde(default = "default_app_env")]
+    pub app_env: String,
+
     pub database_url: String,
     pub redis_url: String,
 
     pub session_expires_in_min: u32,
+    #[serde(default = "default_refresh_token_expires_in_days")]
+    pub refresh_token_expires_in_days: u32,
 
     pub otp_length: u8,
     pub otp_expires_in_sec: u8,
+    #[serde(default = "default_max_otp_attempts")]
+    pub max_otp_attempts: i64,
+    #[serde(default = "default_otp_resend_limit")]
+    pub otp_resend_limit: i64,
+    #[serde(default = "default_otp_resend_window_in_sec")]
+    pub otp_resend_window_in_sec: u64,
     pub max_failed_attempts: i32,
+    #[serde(default = "default_lockout_duration_in_min")]
+    pub lockout_duration_in_min: u32,
     pub min_password_length: u32,
+    // region of phone numbers given without a country code
+    #[serde(default = "default_phone_region")]
+    pub default_phone_region: String,
 
     pub presigned_url_expires_in_sec: u64,
 
+    // base64 key encryption key (32 bytes) and nonce (12 bytes) used for data
+    // encrypted at rest. Until they are set authenticator apps can't be
+    // enrolled and identity lookups aren't cached
+    #[serde(default)]
+    pub data_encryption_key: Option<String>,
+    #[serde(default)]
+    pub data_encryption_nonce: Option<String>,
+
+    // HMAC key signing the staff invitation and data export links. Until it is
+    // set a random key is used, the links sent then stop working on restarts
+    #[serde(default)]
+    pub invitation_signing_key: String,
+    #[serde(default = "default_invitation_expires_in_hr")]
+    pub invitation_expires_in_hr: u32,
+    #[serde(default = "default_invitation_reminder_after_hr")]
+    pub invitation_reminder_after_hr: u32,
+    #[serde(default = "default_invitation_max_reminders")]
+    pub invitation_max_reminders: i32,
+
+    // deactivated institutions are purged after the grace period, their data
+    // export can be downloaded until then
+    #[serde(default = "default_institution_purge_grace_in_days")]
+    pub institution_purge_grace_in_days: u32,
+
     pub gemini_api_key: String,
 
     pub cloudflare_r2_url: String,
@@ -32,8 +74,126 @@ pub struct EnvConfig {
     pub no_reply_email_password: String,
     pub app_url: String,
 
+    // identity verification provider (youverify, mock) and the provider a
+    // lookup is retried with when it errors
+    #[serde(default = "default_verification_provider")]
+    pub verification_provider: String,
+    #[serde(default)]
+    pub verification_fallback_provider: Option<String>,
+    // JSON fixtures of the mock provider, it uses the bundled fixtures without it
+    #[serde(default)]
+    pub verification_fixtures_path: Option<String>,
+
+    // hours identity lookup results are reused for before the provider is asked again
+    #[serde(default = "default_registration_lookup_ttl_in_hr")]
+    pub rc_no_lookup_ttl_in_hr: u64,
+    #[serde(default = "default_registration_lookup_ttl_in_hr")]
+    pub tin_lookup_ttl_in_hr: u64,
+    #[serde(default = "default_bvn_lookup_ttl_in_hr")]
+    pub bvn_lookup_ttl_in_hr: u64,
+    #[serde(default = "default_bank_account_lookup_ttl_in_hr")]
+    pub bank_account_lookup_ttl_in_hr: u64,
+    // looked up names scoring (0 to 100) below the review threshold are left for
+    // the reviewer to confirm, below the block threshold the submission is refused
+    #[serde(default = "default_name_match_review_threshold")]
+    pub name_match_review_threshold: u8,
+    #[serde(default = "default_name_match_block_threshold")]
+    pub name_match_block_threshold: u8,
+
+    #[serde(default)]
     pub youverify_base_url: String,
+    #[serde(default)]
     pub youverify_api_key: String,
 
+    // gateway verifying staff licenses with the professional councils, licenses
+    // are left for manual review without it
+    #[serde(default)]
+    pub license_registry_base_url: Option<String>,
+    #[serde(default)]
+    pub license_registry_api_key: Option<String>,
+    // staff are reminded this many days before their license expires
+    #[serde(default = "default_license_expiry_reminder_days")]
+    pub license_expiry_reminder_days: u32,
+
     pub banks_cache_expires_in_hr: u64,
 }
+
+impl EnvConfig {
+    pub fn is_dev_or_test(&self) -> bool {
+        ["development", "dev", "test"]
+            .iter()
+            .any(|env| env.eq_ignore_ascii_case(self.app_env.trim()))
+    }
+}
+
+fn default_app_env() -> String {
+    "production".into()
+}
+
+fn default_max_otp_attempts() -> i64 {
+    5
+}
+
+fn default_otp_resend_limit() -> i64 {
+    3
+}
+
+fn default_otp_resend_window_in_sec() -> u64 {
+    600
+}
+
+fn default_refresh_token_expires_in_days() -> u32 {
+    30
+}
+
+fn default_lockout_duration_in_min() -> u32 {
+    30
+}
+
+fn default_invitation_expires_in_hr() -> u32 {
+    72
+}
+
+fn default_invitation_reminder_after_hr() -> u32 {
+    24
+}
+
+fn default_invitation_max_reminders() -> i32 {
+    2
+}
+
+fn default_institution_purge_grace_in_days() -> u32 {
+    30
+}
+
+fn default_registration_lookup_ttl_in_hr() -> u64 {
+    30 * 24
+}
+
+fn default_bvn_lookup_ttl_in_hr() -> u64 {
+    7 * 24
+}
+
+fn default_bank_account_lookup_ttl_in_hr() -> u64 {
+    24
+}
+
+fn default_name_match_review_threshold() -> u8 {
+    85
+}
+
+fn default_name_match_block_threshold() -> u8 {
+    60
+}
+
+fn default_verification_provider() -> String {
+    "youverify".into()
+}
+
+fn default_license_expiry_reminder_days() -> u32 {
+    30
+}
+
+fn default_phone_region() -> String {
+    "NG".into()
+}
@@ -51,6 +51,15 @@ pub async fn get_upload_client(config: &EnvConfig) -> eyre::Result<Client> {
     Ok(client)
 }
 
+/// Whether the bucket key is one of the institution's own uploads, stored as
+/// `{service}/I{institution_id}S...`. Records and requests can hold keys of any
+/// tenant, only these belong to the institution.
+pub fn is_institution_upload(institution_id: &str, key: &str) -> bool {
+    key.split_once('/').is_some_and(|(service, name)| {
+        !service.is_empty() && name.starts_with(&format!("I{}S", institution_id))
+    })
+}
+
 pub async fn upload_file_to_bucket<S: Into<String>>(
     client: &Client,
     bucket: S,
@@ -74,6 +83,24 @@ pub async fn upload_file_to_bucket<S: Into<String>>(
     Ok(())
 }
 
+pub async fn delete_file<S: Into<String>>(
+    client: &Client,
+    bucket: S,
+    remote_path: S,
+) -> eyre::Result<()> {
+    let remote_path = remote_path.into();
+    client
+        .delete_object()
+        .bucket(bucket.into())
+        .key(&remote_path)
+        .send()
+        .await
+        .wrap_err("failed to delete file from bucket")?;
+
+    info!("Deleted: {} from bucket", remote_path);
+    Ok(())
+}
+
 pub async fn get_file<S: Into<String>>(
     client: &Client,
     bucket: S,
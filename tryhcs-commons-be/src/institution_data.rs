@@ -0,0 +1,26 @@
+use async_trait::async_trait;
+use serde_json::Value;
+
+/// Records a module holds for an institution, written to the export archive
+/// as `{name}.json`. `documents` are the bucket keys of the files the records
+/// reference, they are added to the archive and deleted on purge.
+#[derive(Debug, Clone)]
+pub struct InstitutionDataExport {
+    pub name: String,
+    pub records: Value,
+    pub documents: Vec<String>,
+}
+
+/// Implemented by the modules storing institution data outside the customers
+/// module, so offboarding exports and purges it with the institution.
+#[async_trait]
+pub trait InstitutionDataSource: Send + Sync {
+    async fn export_institution_data(
+        &self,
+        institution_id: i64,
+    ) -> eyre::Result<Vec<InstitutionDataExport>>;
+
+    /// deletes the institution's records, returns the bucket keys of the
+    /// documents they referenced
+    async fn purge_institution_data(&self, institution_id: i64) -> eyre::Result<Vec<String>>;
+}
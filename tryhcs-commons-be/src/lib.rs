@@ -2,11 +2,17 @@ use reqwest::StatusCode;
 
 pub mod api_response;
 pub mod auth;
+pub mod client_context;
 pub mod data_encryption;
 pub mod encryption_context;
 pub mod env;
 pub mod file_upload;
+pub mod institution_data;
+pub mod platform_staff;
 pub mod redis;
+pub mod session;
+pub mod signed_token;
+pub mod totp;
 pub mod utils;
 
 pub const ADMIN_DOMAIN: &str = "Admin";
@@ -18,7 +24,10 @@ pub const UNAUTHORIZED_API_STATUS_CODE: StatusCode = StatusCode::UNAUTHORIZED;
 pub const FORBIDDEN_API_STATUS_CODE: StatusCode = StatusCode::FORBIDDEN;
 pub const NOT_FOUND_API_STATUS_CODE: StatusCode = StatusCode::NOT_FOUND;
 pub const DUPLICATE_API_STATUS_CODE: StatusCode = StatusCode::CONFLICT;
+pub const TOO_MANY_REQUESTS_API_STATUS_CODE: StatusCode = StatusCode::TOO_MANY_REQUESTS;
 
 pub static AUTH_ID_HEADER_FIELD: &str = "Authorization";
 
 pub static WORKSPACE_CODE_HEADER_FIELD: &str = "Workspace";
+
+pub static CORRELATION_ID_HEADER_FIELD: &str = "X-Correlation-Id";
@@ -0,0 +1,9 @@
+use async_trait::async_trait;
+
+/// Implemented by the modules keeping the platform staff, who work for TryHcs
+/// rather than an institution. They log in with their user account like any
+/// staff but get a session without a workspace.
+#[async_trait]
+pub trait PlatformStaffSource: Send + Sync {
+    async fn is_platform_staff(&self, mobile: &str) -> eyre::Result<bool>;
+}
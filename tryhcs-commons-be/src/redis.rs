@@ -1,4 +1,26 @@
-use redis::AsyncCommands;
+use redis::{AsyncCommands, Script};
+
+// deletes the key only when it holds the given value, in one step
+const DELETE_IF_EQUALS_SCRIPT: &str = r#"
+if redis.call('GET', KEYS[1]) == ARGV[1] then
+    return redis.call('DEL', KEYS[1])
+end
+return 0
+"#;
+
+// replaces the value only when the key still holds the expected one, an
+// empty expiry keeps the key without one
+const COMPARE_AND_SET_SCRIPT: &str = r#"
+if redis.call('GET', KEYS[1]) == ARGV[1] then
+    if ARGV[3] == '' then
+        redis.call('SET', KEYS[1], ARGV[2])
+    else
+        redis.call('SET', KEYS[1], ARGV[2], 'EX', ARGV[3])
+    end
+    return 1
+end
+return 0
+"#;
 
 #[async_trait::async_trait]
 pub trait Cache: Send + Sync {
@@ -9,6 +31,23 @@ pub trait Cache: Send + Sync {
         value: &str,
         duration_in_sec: Option<u64>,
     ) -> eyre::Result<()>;
+    async fn delete_key(&self, key: &str) -> eyre::Result<()>;
+    // deletes the key if it holds `value`, true when it did so a value can only be consumed once
+    async fn delete_key_if_equals(&self, key: &str, value: &str) -> eyre::Result<bool>;
+    // sets the key to `value` if it still holds `expected`, false when another write got there first
+    async fn compare_and_set_key(
+        &self,
+        key: &str,
+        expected: &str,
+        value: &str,
+        duration_in_sec: Option<u64>,
+    ) -> eyre::Result<bool>;
+    async fn expire_key(&self, key: &str, duration_in_sec: u64) -> eyre::Result<()>;
+    // increments the counter at key, the expiry is only set when the counter is created
+    async fn increment_key(&self, key: &str, duration_in_sec: u64) -> eyre::Result<i64>;
+    async fn add_set_member(&self, key: &str, member: &str) -> eyre::Result<()>;
+    async fn remove_set_member(&self, key: &str, member: &str) -> eyre::Result<()>;
+    async fn get_set_members(&self, key: &str) -> eyre::Result<Vec<String>>;
 }
 
 #[derive(Clone)]
@@ -39,4 +78,263 @@ impl Cache for RedisCache {
         con.set::<&str, &str, ()>(key, value).await?;
         return Ok(());
     }
+
+    async fn delete_key(&self, key: &str) -> eyre::Result<()> {
+        let RedisCache(redis) = self;
+        let mut con = redis.get_multiplexed_async_connection().await?;
+        con.del::<&str, ()>(key).await?;
+        Ok(())
+    }
+
+    async fn delete_key_if_equals(&self, key: &str, value: &str) -> eyre::Result<bool> {
+        let RedisCache(redis) = self;
+        let mut con = redis.get_multiplexed_async_connection().await?;
+        let deleted: i64 = Script::new(DELETE_IF_EQUALS_SCRIPT)
+            .key(key)
+            .arg(value)
+            .invoke_async(&mut con)
+            .await?;
+        Ok(deleted == 1)
+    }
+
+    async fn compare_and_set_key(
+        &self,
+        key: &str,
+        expected: &str,
+        value: &str,
+        duration_in_sec: Option<u64>,
+    ) -> eyre::Result<bool> {
+        let RedisCache(redis) = self;
+        let mut con = redis.get_multiplexed_async_connection().await?;
+        let set: i64 = Script::new(COMPARE_AND_SET_SCRIPT)
+            .key(key)
+            .arg(expected)
+            .arg(value)
+            .arg(duration_in_sec.map(|d| d.to_string()).unwrap_or_default())
+            .invoke_async(&mut con)
+            .await?;
+        Ok(set == 1)
+    }
+
+    async fn expire_key(&self, key: &str, duration_in_sec: u64) -> eyre::Result<()> {
+        let RedisCache(redis) = self;
+        let mut con = redis.get_multiplexed_async_connection().await?;
+        con.expire::<&str, ()>(key, duration_in_sec as i64).await?;
+        Ok(())
+    }
+
+    async fn increment_key(&self, key: &str, duration_in_sec: u64) -> eyre::Result<i64> {
+        let RedisCache(redis) = self;
+        let mut con = redis.get_multiplexed_async_connection().await?;
+        // creating the counter with its expiry and incrementing it in one
+        // transaction, a counter can't be left without an expiry
+        let (count,): (i64,) = redis::pipe()
+            .atomic()
+            .cmd("SET")
+            .arg(key)
+            .arg(0)
+            .arg("EX")
+            .arg(duration_in_sec)
+            .arg("NX")
+            .ignore()
+            .incr(key, 1)
+            .query_async(&mut con)
+            .await?;
+        Ok(count)
+    }
+
+    async fn add_set_member(&self, key: &str, member: &str) -> eyre::Result<()> {
+        let RedisCache(redis) = self;
+        let mut con = redis.get_multiplexed_async_connection().await?;
+        con.sadd::<&str, &str, ()>(key, member).await?;
+        Ok(())
+    }
+
+    async fn remove_set_member(&self, key: &str, member: &str) -> eyre::Result<()> {
+        let RedisCache(redis) = self;
+        let mut con = redis.get_multiplexed_async_connection().await?;
+        con.srem::<&str, &str, ()>(key, member).await?;
+        Ok(())
+    }
+
+    async fn get_set_members(&self, key: &str) -> eyre::Result<Vec<String>> {
+        let RedisCache(redis) = self;
+        let mut con = redis.get_multiplexed_async_connection().await?;
+        let members = con.smembers::<&str, Vec<String>>(key).await?;
+        Ok(members)
+    }
+}
+
+/// In-process cache with the semantics of the Redis commands above, for tests
+/// and local runs without a Redis server
+#[derive(Default)]
+pub struct MemoryCache {
+    entries: std::sync::Mutex<std::collections::HashMap<String, MemoryEntry>>,
+}
+
+enum MemoryValue {
+    Value(String),
+    Set(std::collections::BTreeSet<String>),
+}
+
+struct MemoryEntry {
+    value: MemoryValue,
+    expires_at: Option<std::time::Instant>,
+}
+
+impl MemoryCache {
+    fn with_entries<T>(
+        &self,
+        f: impl FnOnce(&mut std::collections::HashMap<String, MemoryEntry>) -> T,
+    ) -> T {
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        let now = std::time::Instant::now();
+        entries.retain(|_, entry| entry.expires_at.is_none_or(|at| at > now));
+        f(&mut entries)
+    }
+
+    fn expiry(duration_in_sec: u64) -> Option<std::time::Instant> {
+        Some(std::time::Instant::now() + std::time::Duration::from_secs(duration_in_sec))
+    }
+
+    /// Seconds left before the key expires, None for keys without an expiry or missing keys
+    pub fn ttl(&self, key: &str) -> Option<u64> {
+        self.with_entries(|entries| {
+            entries.get(key).and_then(|e| e.expires_at).map(|at| {
+                at.saturating_duration_since(std::time::Instant::now())
+                    .as_secs()
+            })
+        })
+    }
+
+    pub fn keys(&self) -> Vec<String> {
+        self.with_entries(|entries| entries.keys().cloned().collect())
+    }
+}
+
+#[async_trait::async_trait]
+impl Cache for MemoryCache {
+    async fn get_key(&self, key: &str) -> eyre::Result<Option<String>> {
+        self.with_entries(|entries| match entries.get(key).map(|e| &e.value) {
+            Some(MemoryValue::Value(value)) => Ok(Some(value.clone())),
+            Some(MemoryValue::Set(_)) => Err(eyre::eyre!("{} doesn't hold a string", key)),
+            None => Ok(None),
+        })
+    }
+
+    async fn set_key(
+        &self,
+        key: &str,
+        value: &str,
+        duration_in_sec: Option<u64>,
+    ) -> eyre::Result<()> {
+        self.with_entries(|entries| {
+            entries.insert(
+                key.to_owned(),
+                MemoryEntry {
+                    value: MemoryValue::Value(value.to_owned()),
+                    expires_at: duration_in_sec.and_then(Self::expiry),
+                },
+            );
+        });
+        Ok(())
+    }
+
+    async fn delete_key(&self, key: &str) -> eyre::Result<()> {
+        self.with_entries(|entries| entries.remove(key));
+        Ok(())
+    }
+
+    async fn delete_key_if_equals(&self, key: &str, value: &str) -> eyre::Result<bool> {
+        Ok(self.with_entries(|entries| {
+            let matches = matches!(entries.get(key).map(|e| &e.value), Some(MemoryValue::Value(v)) if v == value);
+            if matches {
+                entries.remove(key);
+            }
+            matches
+        }))
+    }
+
+    async fn compare_and_set_key(
+        &self,
+        key: &str,
+        expected: &str,
+        value: &str,
+        duration_in_sec: Option<u64>,
+    ) -> eyre::Result<bool> {
+        Ok(self.with_entries(|entries| {
+            let matches = matches!(entries.get(key).map(|e| &e.value), Some(MemoryValue::Value(v)) if v == expected);
+            if matches {
+                entries.insert(
+                    key.to_owned(),
+                    MemoryEntry {
+                        value: MemoryValue::Value(value.to_owned()),
+                        expires_at: duration_in_sec.and_then(Self::expiry),
+                    },
+                );
+            }
+            matches
+        }))
+    }
+
+    async fn expire_key(&self, key: &str, duration_in_sec: u64) -> eyre::Result<()> {
+        self.with_entries(|entries| {
+            if let Some(entry) = entries.get_mut(key) {
+                entry.expires_at = Self::expiry(duration_in_sec);
+            }
+        });
+        Ok(())
+    }
+
+    async fn increment_key(&self, key: &str, duration_in_sec: u64) -> eyre::Result<i64> {
+        self.with_entries(|entries| {
+            let entry = entries
+                .entry(key.to_owned())
+                .or_insert_with(|| MemoryEntry {
+                    value: MemoryValue::Value("0".into()),
+                    expires_at: Self::expiry(duration_in_sec),
+                });
+            let MemoryValue::Value(value) = &mut entry.value else {
+                return Err(eyre::eyre!("{} doesn't hold a counter", key));
+            };
+            let count = value.parse::<i64>()? + 1;
+            *value = count.to_string();
+            Ok(count)
+        })
+    }
+
+    async fn add_set_member(&self, key: &str, member: &str) -> eyre::Result<()> {
+        self.with_entries(|entries| {
+            let entry = entries
+                .entry(key.to_owned())
+                .or_insert_with(|| MemoryEntry {
+                    value: MemoryValue::Set(Default::default()),
+                    expires_at: None,
+                });
+            match &mut entry.value {
+                MemoryValue::Set(members) => {
+                    members.insert(member.to_owned());
+                    Ok(())
+                }
+                MemoryValue::Value(_) => Err(eyre::eyre!("{} doesn't hold a set", key)),
+            }
+        })
+    }
+
+    async fn remove_set_member(&self, key: &str, member: &str) -> eyre::Result<()> {
+        self.with_entries(|entries| {
+            if let Some(MemoryValue::Set(members)) = entries.get_mut(key).map(|e| &mut e.value) {
+                members.remove(member);
+            }
+        });
+        Ok(())
+    }
+
+    async fn get_set_members(&self, key: &str) -> eyre::Result<Vec<String>> {
+        self.with_entries(|entries| match entries.get(key).map(|e| &e.value) {
+            Some(MemoryValue::Set(members)) => Ok(members.iter().cloned().collect()),
+            Some(MemoryValue::Value(_)) => Err(eyre::eyre!("{} doesn't hold a set", key)),
+            None => Ok(vec![]),
+        })
+    }
 }
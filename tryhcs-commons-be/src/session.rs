@@ -0,0 +1,398 @@
+use chrono::{DateTime, Duration, Utc};
+use eyre::eyre;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+use tryhcs_shared::institution_params::{AuthenticatedUser, SessionDto};
+use uuid::Uuid;
+
+use crate::{env::EnvConfig, redis::Cache};
+
+// last seen is only persisted when it is older than this, so every request
+// doesn't turn into a cache write
+const LAST_SEEN_RESOLUTION_IN_SEC: i64 = 60;
+
+// a refresh token rotated out this recently is answered with the current
+// pair rather than treated as a reuse, so parallel refreshes of the same
+// client don't revoke its session
+const REFRESH_REUSE_GRACE_IN_SEC: i64 = 30;
+
+// times a session update is retried when concurrent requests keep changing it
+const SESSION_UPDATE_ATTEMPTS: usize = 5;
+
+// lifetime of the access token, slides forward while the session is in use
+pub fn session_expires_in(env: &EnvConfig) -> Duration {
+    Duration::minutes(env.session_expires_in_min as i64)
+}
+
+// lifetime of the token family, a session can't be refreshed past this
+pub fn refresh_token_expires_in(env: &EnvConfig) -> Duration {
+    Duration::days(env.refresh_token_expires_in_days as i64)
+}
+
+// The session id doubles as the refresh token family id, every rotated
+// refresh token issued for the session belongs to the same family.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SessionInfo {
+    pub id: String,
+    pub token: String,
+    pub refresh_token: String,
+    pub mobile: String,
+    pub device_id: String,
+    pub workspace_code: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub last_seen_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    // refresh token replaced by the last rotation and when it was replaced
+    #[serde(default)]
+    pub previous_refresh_token: Option<String>,
+    #[serde(default)]
+    pub rotated_at: Option<DateTime<Utc>>,
+}
+
+impl SessionInfo {
+    pub fn to_dto(&self, current_session_id: Option<&str>) -> SessionDto {
+        SessionDto {
+            id: self.id.clone(),
+            device_id: self.device_id.clone(),
+            workspace_code: self.workspace_code.clone(),
+            current: current_session_id
+                .map(|id| id.eq(&self.id))
+                .unwrap_or(false),
+            created_at: self.created_at,
+            last_seen_at: self.last_seen_at,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct UserSession {
+    pub info: SessionInfo,
+    pub user: AuthenticatedUser,
+}
+
+pub enum RefreshedSession {
+    Refreshed(AuthenticatedUser),
+    Invalid,
+    // a rotated out refresh token was presented, the family has been revoked
+    Reused,
+}
+
+fn session_key(session_id: &str) -> String {
+    format!("SESSION-{}", session_id)
+}
+
+fn session_token_key(token: &str) -> String {
+    format!("SESSION-TOKEN-{}", token)
+}
+
+fn refresh_token_key(refresh_token: &str) -> String {
+    format!("SESSION-REFRESH-{}", refresh_token)
+}
+
+fn user_sessions_key(mobile: &str) -> String {
+    format!("USER-SESSIONS-{}", mobile)
+}
+
+fn remaining_sec(expires_at: DateTime<Utc>) -> u64 {
+    (expires_at - Utc::now()).num_seconds().max(1) as u64
+}
+
+// Creates a new session for the authenticated user and issues its token pair,
+// the returned user carries the tokens the client authenticates with.
+pub async fn create_session(
+    redis: &dyn Cache,
+    authenticated: &AuthenticatedUser,
+    device_id: &str,
+    env: &EnvConfig,
+) -> eyre::Result<AuthenticatedUser> {
+    let now = Utc::now();
+    let info = SessionInfo {
+        id: Uuid::new_v4().to_string(),
+        token: Uuid::new_v4().to_string(),
+        refresh_token: Uuid::new_v4().to_string(),
+        mobile: authenticated.principal.mobile.clone(),
+        device_id: device_id.to_owned(),
+        workspace_code: None,
+        created_at: now,
+        last_seen_at: now,
+        expires_at: now + refresh_token_expires_in(env),
+        previous_refresh_token: None,
+        rotated_at: None,
+    };
+
+    let mut user = authenticated.clone();
+    user.token = None;
+    user.refresh_token = None;
+    let session = UserSession {
+        info: info.clone(),
+        user,
+    };
+
+    save_session(redis, &session).await?;
+    issue_tokens(redis, &session.info, env).await?;
+    redis
+        .add_set_member(&user_sessions_key(&info.mobile), &info.id)
+        .await?;
+
+    Ok(AuthenticatedUser {
+        principal: authenticated.principal.clone(),
+        token: Some(info.token),
+        refresh_token: Some(info.refresh_token),
+    })
+}
+
+async fn issue_tokens(redis: &dyn Cache, info: &SessionInfo, env: &EnvConfig) -> eyre::Result<()> {
+    redis
+        .set_key(
+            &session_token_key(&info.token),
+            &info.id,
+            Some(session_expires_in(env).num_seconds() as u64),
+        )
+        .await?;
+    // rotated out refresh tokens keep pointing at the family until it
+    // expires, which is how a reuse is detected
+    redis
+        .set_key(
+            &refresh_token_key(&info.refresh_token),
+            &info.id,
+            Some(remaining_sec(info.expires_at)),
+        )
+        .await
+}
+
+pub async fn get_session_by_id(
+    redis: &dyn Cache,
+    session_id: &str,
+) -> eyre::Result<Option<UserSession>> {
+    Ok(redis
+        .get_key(&session_key(session_id))
+        .await?
+        .and_then(|v| serde_json::from_str::<UserSession>(&v).ok()))
+}
+
+// Resolves the bearer token to its session, a revoked or expired session
+// resolves to None.
+pub async fn find_session(redis: &dyn Cache, token: &str) -> eyre::Result<Option<UserSession>> {
+    let session_id = match redis.get_key(&session_token_key(token)).await? {
+        None => return Ok(None),
+        Some(session_id) => session_id,
+    };
+
+    let session = get_session_by_id(redis, &session_id).await?;
+    Ok(session.filter(|s| s.info.token.eq(token)))
+}
+
+// Applies `update` to the stored session and saves it only if no other request
+// changed it in the meantime, `update` is run again on the fresh copy when one
+// did. `update` returns false to leave the session as it is, None is returned
+// when the session is gone.
+pub async fn update_session<F>(
+    redis: &dyn Cache,
+    session_id: &str,
+    mut update: F,
+) -> eyre::Result<Option<UserSession>>
+where
+    F: FnMut(&mut UserSession) -> bool + Send,
+{
+    let key = session_key(session_id);
+    for _ in 0..SESSION_UPDATE_ATTEMPTS {
+        let Some(current) = redis.get_key(&key).await? else {
+            return Ok(None);
+        };
+        let mut session = serde_json::from_str::<UserSession>(&current)?;
+        if !update(&mut session) {
+            return Ok(Some(session));
+        }
+
+        let updated = serde_json::to_string(&session)?;
+        let expires_in_sec = Some(remaining_sec(session.info.expires_at));
+        if redis
+            .compare_and_set_key(&key, &current, &updated, expires_in_sec)
+            .await?
+        {
+            return Ok(Some(session));
+        }
+    }
+    Err(eyre!(
+        "Session {} kept changing, gave up updating it",
+        session_id
+    ))
+}
+
+fn rotated_within_grace(info: &SessionInfo, refresh_token: &str) -> bool {
+    info.previous_refresh_token.as_deref() == Some(refresh_token)
+        && info.rotated_at.is_some_and(|rotated_at| {
+            (Utc::now() - rotated_at).num_seconds() < REFRESH_REUSE_GRACE_IN_SEC
+        })
+}
+
+fn session_tokens(session: &UserSession) -> RefreshedSession {
+    RefreshedSession::Refreshed(AuthenticatedUser {
+        principal: session.user.principal.clone(),
+        token: Some(session.info.token.clone()),
+        refresh_token: Some(session.info.refresh_token.clone()),
+    })
+}
+
+// Rotates the token pair of the session the refresh token belongs to. Only one
+// of concurrent refreshes with the same token rotates it, the others get the
+// pair it issued.
+pub async fn refresh_session(
+    redis: &dyn Cache,
+    refresh_token: &str,
+    env: &EnvConfig,
+) -> eyre::Result<RefreshedSession> {
+    let session_id = match redis.get_key(&refresh_token_key(refresh_token)).await? {
+        None => return Ok(RefreshedSession::Invalid),
+        Some(session_id) => session_id,
+    };
+    let Some(session) = get_session_by_id(redis, &session_id).await? else {
+        return Ok(RefreshedSession::Invalid);
+    };
+
+    // the new pair resolves before the session is saved with it, so it works
+    // as soon as any of the concurrent refreshes hands it out
+    let mut next = session.info.clone();
+    next.token = Uuid::new_v4().to_string();
+    next.refresh_token = Uuid::new_v4().to_string();
+    issue_tokens(redis, &next, env).await?;
+
+    let now = Utc::now();
+    let mut rotated_out_token = None;
+    let session = update_session(redis, &session_id, |session| {
+        rotated_out_token = None;
+        if !session.info.refresh_token.eq(refresh_token) {
+            return false;
+        }
+        rotated_out_token = Some(session.info.token.clone());
+        session.info.previous_refresh_token = Some(refresh_token.to_owned());
+        session.info.rotated_at = Some(now);
+        session.info.token = next.token.clone();
+        session.info.refresh_token = next.refresh_token.clone();
+        session.info.last_seen_at = now;
+        true
+    })
+    .await?;
+
+    if let Some(rotated_out_token) = rotated_out_token {
+        redis
+            .delete_key(&session_token_key(&rotated_out_token))
+            .await?;
+        return Ok(session
+            .as_ref()
+            .map(session_tokens)
+            .unwrap_or(RefreshedSession::Invalid));
+    }
+
+    redis.delete_key(&session_token_key(&next.token)).await?;
+    redis
+        .delete_key(&refresh_token_key(&next.refresh_token))
+        .await?;
+    let Some(session) = session else {
+        return Ok(RefreshedSession::Invalid);
+    };
+    if rotated_within_grace(&session.info, refresh_token) {
+        return Ok(session_tokens(&session));
+    }
+
+    warn!(
+        message = "Refresh token reuse detected, revoking session",
+        session_id = session.info.id
+    );
+    revoke_session(redis, &session.info).await?;
+    Ok(RefreshedSession::Reused)
+}
+
+// Records the request activity on the session and slides the access token
+// expiry forward, errors are logged as the request itself shouldn't fail
+// because of it.
+pub async fn touch_session(
+    redis: &dyn Cache,
+    session: &UserSession,
+    workspace_code: Option<&str>,
+    env: &EnvConfig,
+) {
+    let now = Utc::now();
+    let workspace_changed =
+        workspace_code.is_some() && session.info.workspace_code.as_deref() != workspace_code;
+    if !workspace_changed
+        && (now - session.info.last_seen_at).num_seconds() < LAST_SEEN_RESOLUTION_IN_SEC
+    {
+        return;
+    }
+
+    // only the activity fields are written over the stored session, a
+    // concurrent refresh or membership change made meanwhile is kept
+    let updated = update_session(redis, &session.info.id, |stored| {
+        stored.info.last_seen_at = now;
+        if let Some(workspace_code) = workspace_code {
+            stored.info.workspace_code = Some(workspace_code.to_owned());
+        }
+        true
+    })
+    .await;
+    let session = match updated {
+        // revoked meanwhile
+        Ok(None) => return,
+        Ok(Some(session)) => session,
+        Err(err) => {
+            tracing::error!(message="Failed to update session activity", err=?err);
+            return;
+        }
+    };
+
+    let expires_in_sec = session_expires_in(env)
+        .num_seconds()
+        .min((session.info.expires_at - now).num_seconds())
+        .max(1) as u64;
+    if let Err(err) = redis
+        .expire_key(&session_token_key(&session.info.token), expires_in_sec)
+        .await
+    {
+        tracing::error!(message="Failed to extend session expiry", err=?err);
+    }
+}
+
+pub async fn save_session(redis: &dyn Cache, session: &UserSession) -> eyre::Result<()> {
+    redis
+        .set_key(
+            &session_key(&session.info.id),
+            &serde_json::to_string(session)?,
+            Some(remaining_sec(session.info.expires_at)),
+        )
+        .await
+}
+
+pub async fn find_user_sessions(redis: &dyn Cache, mobile: &str) -> eyre::Result<Vec<UserSession>> {
+    let index_key = user_sessions_key(mobile);
+    let mut sessions = vec![];
+    for session_id in redis.get_set_members(&index_key).await? {
+        match get_session_by_id(redis, &session_id).await? {
+            // expired sessions are pruned from the index lazily
+            None => redis.remove_set_member(&index_key, &session_id).await?,
+            Some(session) => sessions.push(session),
+        }
+    }
+    sessions.sort_by_key(|s| std::cmp::Reverse(s.info.last_seen_at));
+    Ok(sessions)
+}
+
+pub async fn revoke_session(redis: &dyn Cache, session: &SessionInfo) -> eyre::Result<()> {
+    redis.delete_key(&session_token_key(&session.token)).await?;
+    redis
+        .delete_key(&refresh_token_key(&session.refresh_token))
+        .await?;
+    redis.delete_key(&session_key(&session.id)).await?;
+    redis
+        .remove_set_member(&user_sessions_key(&session.mobile), &session.id)
+        .await?;
+    Ok(())
+}
+
+pub async fn revoke_user_sessions(redis: &dyn Cache, mobile: &str) -> eyre::Result<usize> {
+    let sessions = find_user_sessions(redis, mobile).await?;
+    for session in &sessions {
+        revoke_session(redis, &session.info).await?;
+    }
+    Ok(sessions.len())
+}
@@ -0,0 +1,44 @@
+use base64::prelude::{Engine as _, BASE64_URL_SAFE_NO_PAD};
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+
+type HmacSha256 = Hmac<Sha256>;
+
+// Tokens are `{subject}.{expires_at}.{signature}`, the subject must not
+// contain dots and expires_at is a unix timestamp in seconds.
+pub fn sign_token(key: &[u8], subject: &str, expires_at: i64) -> String {
+    let payload = format!("{}.{}", subject, expires_at);
+    let signature = BASE64_URL_SAFE_NO_PAD.encode(signature(key, &payload));
+    format!("{}.{}", payload, signature)
+}
+
+// Returns the subject when the token was signed with the key and is not expired
+pub fn verify_signed_token(key: &[u8], token: &str, now: i64) -> Option<String> {
+    let (payload, signature) = token.rsplit_once('.')?;
+    let (subject, expires_at) = payload.split_once('.')?;
+    let expires_at: i64 = expires_at.parse().ok()?;
+    let signature = BASE64_URL_SAFE_NO_PAD.decode(signature).ok()?;
+
+    let mut mac = HmacSha256::new_from_slice(key).ok()?;
+    mac.update(payload.as_bytes());
+    mac.verify_slice(&signature).ok()?;
+
+    if expires_at <= now {
+        return None;
+    }
+    Some(subject.to_owned())
+}
+
+// Only the hash of issued tokens is stored, so a leaked table can't be replayed
+pub fn hash_token(token: &str) -> String {
+    Sha256::digest(token.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+fn signature(key: &[u8], payload: &str) -> Vec<u8> {
+    let mut mac = HmacSha256::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(payload.as_bytes());
+    mac.finalize().into_bytes().to_vec()
+}
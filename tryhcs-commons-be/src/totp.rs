@@ -0,0 +1,84 @@
+use eyre::eyre;
+use rand::{rngs::OsRng, Rng, RngCore};
+use sha2::{Digest, Sha256};
+use totp_rs::{Algorithm, Secret, TOTP};
+
+const TOTP_DIGITS: usize = 6;
+const TOTP_STEP_IN_SEC: u64 = 30;
+// codes from the previous and next step are accepted to allow for clock drift
+const TOTP_SKEW: u64 = 1;
+const RECOVERY_CODE_CHARSET: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+
+// Generates a 160 bit secret, base32 encoded as expected by authenticator apps
+pub fn generate_totp_secret() -> String {
+    let mut secret = [0u8; 20];
+    OsRng.fill_bytes(&mut secret);
+    Secret::Raw(secret.to_vec()).to_encoded().to_string()
+}
+
+fn build_totp(secret: &str, issuer: &str, account_name: &str) -> eyre::Result<TOTP> {
+    let secret = Secret::Encoded(secret.to_owned())
+        .to_bytes()
+        .map_err(|e| eyre!("Invalid totp secret: {:?}", e))?;
+    TOTP::new(
+        Algorithm::SHA1,
+        TOTP_DIGITS,
+        TOTP_SKEW as u8,
+        TOTP_STEP_IN_SEC,
+        secret,
+        Some(issuer.to_owned()),
+        account_name.to_owned(),
+    )
+    .map_err(|e| eyre!("Invalid totp parameters: {:?}", e))
+}
+
+// The otpauth:// url encoded in the enrollment QR code
+pub fn totp_provisioning_url(
+    secret: &str,
+    issuer: &str,
+    account_name: &str,
+) -> eyre::Result<String> {
+    Ok(build_totp(secret, issuer, account_name)?.get_url())
+}
+
+// Returns the time step the code is valid for, the caller should reject steps
+// that have already been used so a code can't be replayed.
+pub fn verify_totp(secret: &str, code: &str, unix_time: u64) -> eyre::Result<Option<u64>> {
+    let code = code.trim();
+    if code.len() != TOTP_DIGITS || !code.chars().all(|c| c.is_ascii_digit()) {
+        return Ok(None);
+    }
+
+    let totp = build_totp(secret, "", "")?;
+    let current_step = unix_time / TOTP_STEP_IN_SEC;
+    let matched_step = (current_step.saturating_sub(TOTP_SKEW)..=current_step + TOTP_SKEW)
+        .find(|step| totp.generate(step * TOTP_STEP_IN_SEC).eq(code));
+    Ok(matched_step)
+}
+
+pub fn generate_recovery_codes(count: usize) -> Vec<String> {
+    let mut rng = OsRng;
+    (0..count)
+        .map(|_| {
+            let code: String = (0..10)
+                .map(|_| {
+                    RECOVERY_CODE_CHARSET[rng.gen_range(0..RECOVERY_CODE_CHARSET.len())] as char
+                })
+                .collect();
+            format!("{}-{}", &code[..5], &code[5..])
+        })
+        .collect()
+}
+
+// Recovery codes are random enough that a fast hash is sufficient, unlike passwords
+pub fn hash_recovery_code(code: &str) -> String {
+    let normalized: String = code
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_uppercase())
+        .collect();
+    Sha256::digest(normalized.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
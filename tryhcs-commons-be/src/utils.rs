@@ -1,4 +1,5 @@
-use rand::Rng;
+use eyre::eyre;
+use rand::{rngs::OsRng, Rng};
 
 pub fn mask_email(email: &str) -> String {
     if let Some((local, domain)) = email.split_once('@') {
@@ -28,6 +29,22 @@ pub fn mask_phone(phone: &str) -> String {
     format!("{}{}", masked_part, last_four)
 }
 
+// Numbers without a country code are read in the default region (an ISO 3166
+// code like NG), the result is E.164 or None for an invalid number
+pub fn normalize_phone(phone: &str, default_region: &str) -> Option<String> {
+    let region = default_region.parse::<phonenumber::country::Id>().ok();
+    let number = phonenumber::parse(region, phone.trim()).ok()?;
+    if !phonenumber::is_valid(&number) {
+        return None;
+    }
+    Some(number.format().mode(phonenumber::Mode::E164).to_string())
+}
+
+// Whether the region can read numbers without a country code, see normalize_phone
+pub fn is_phone_region(region: &str) -> bool {
+    region.parse::<phonenumber::country::Id>().is_ok()
+}
+
 pub fn generate_password(length: usize) -> String {
     let charset: Vec<char> = r#"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789!@#$%^&*()-_=+[]{}|;:'",.<>?/"#.chars().collect();
     let mut rng = rand::thread_rng();
@@ -38,5 +55,52 @@ pub fn generate_password(length: usize) -> String {
 }
 
 pub fn generate_otp(length: u32) -> eyre::Result<String> {
-    return Ok("12345".to_owned());
+    if length == 0 {
+        return Err(eyre!("OTP length must be greater than zero"));
+    }
+
+    let mut rng = OsRng;
+    Ok((0..length)
+        .map(|_| char::from(b'0' + rng.gen_range(0..10u8)))
+        .collect())
+}
+
+// Returns the reason the password doesn't satisfy the password policy
+pub fn check_password_policy(password: &str, min_length: u32) -> Option<String> {
+    if password.chars().count() < min_length as usize {
+        return Some(format!(
+            "Password must be at least {min_length} characters long"
+        ));
+    }
+
+    let has_upper = password.chars().any(|c| c.is_uppercase());
+    let has_lower = password.chars().any(|c| c.is_lowercase());
+    let has_digit = password.chars().any(|c| c.is_ascii_digit());
+    let has_symbol = password
+        .chars()
+        .any(|c| !c.is_alphanumeric() && !c.is_whitespace());
+    if !(has_upper && has_lower && has_digit && has_symbol) {
+        return Some(
+            "Password must contain an uppercase letter, a lowercase letter, a digit and a symbol"
+                .into(),
+        );
+    }
+
+    None
+}
+
+// For user input interpolated into email templates
+pub fn escape_html(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
 }
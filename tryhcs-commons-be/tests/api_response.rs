@@ -0,0 +1,48 @@
+use reqwest::StatusCode;
+use tryhcs_commons_be::api_response::{
+    api_error, convert_to_json_response, convert_validation_errors, ErrorMessage,
+};
+use tryhcs_shared::{
+    api_params::ErrorCode,
+    validation::{ValidationCode, ValidationErrors},
+};
+
+#[test]
+fn should_derive_status_from_error_code() {
+    let (status, _) = api_error::<()>(ErrorCode::AuthInvalidCredentials);
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let (status, _) = api_error::<()>(ErrorCode::StaffAlreadyExists);
+    assert_eq!(status, StatusCode::CONFLICT);
+
+    let (status, _) = api_error::<()>(ErrorCode::OtpTooManyAttempts);
+    assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+}
+
+#[test]
+fn should_render_error_envelope() {
+    let error = ErrorMessage::with_message(ErrorCode::StaffNotFound, "Staff 12 not found");
+    let (status, body) = convert_to_json_response::<()>(error.response());
+
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(body["error_code"], "STAFF_NOT_FOUND");
+    assert_eq!(body["message"], "Staff 12 not found");
+    assert_eq!(body["status_code"], 404);
+    assert!(body["data"].is_null());
+    assert!(body["correlation_id"].is_null());
+}
+
+#[test]
+fn should_list_invalid_fields() {
+    let invalid = ValidationErrors::single(
+        "email",
+        ValidationCode::InvalidEmail,
+        "email is not a valid email address".into(),
+    );
+    let (status, body) = convert_validation_errors(&invalid);
+
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["error_code"], "VALIDATION_FAILED");
+    assert_eq!(body["errors"][0]["field"], "email");
+    assert_eq!(body["errors"][0]["code"], "invalid_email");
+}
@@ -0,0 +1,73 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use tryhcs_commons_be::{
+    auth::{flag_deactivated_workspace, is_workspace_deactivated, unflag_deactivated_workspace},
+    redis::MemoryCache,
+};
+
+const WORKSPACE: &str = "STMARY";
+
+#[tokio::test]
+async fn reads_the_workspace_status_from_the_database_once() {
+    let redis = MemoryCache::default();
+    let reads = AtomicUsize::new(0);
+    let read_db = |deactivated: bool| {
+        let reads = &reads;
+        async move {
+            reads.fetch_add(1, Ordering::SeqCst);
+            Ok(deactivated)
+        }
+    };
+
+    assert!(!is_workspace_deactivated(&redis, WORKSPACE, read_db(false))
+        .await
+        .unwrap());
+    // the cached status is used until it expires
+    assert!(!is_workspace_deactivated(&redis, WORKSPACE, read_db(true))
+        .await
+        .unwrap());
+    assert_eq!(reads.load(Ordering::SeqCst), 1);
+}
+
+#[tokio::test]
+async fn deactivating_overrides_the_cached_status() {
+    let redis = MemoryCache::default();
+    assert!(
+        !is_workspace_deactivated(&redis, WORKSPACE, async { Ok(false) })
+            .await
+            .unwrap()
+    );
+
+    flag_deactivated_workspace(&redis, WORKSPACE).await.unwrap();
+    assert!(
+        is_workspace_deactivated(&redis, WORKSPACE, async { Ok(false) })
+            .await
+            .unwrap()
+    );
+
+    // once cleared the database decides again
+    unflag_deactivated_workspace(&redis, WORKSPACE)
+        .await
+        .unwrap();
+    assert!(
+        is_workspace_deactivated(&redis, WORKSPACE, async { Ok(true) })
+            .await
+            .unwrap()
+    );
+}
+
+#[tokio::test]
+async fn database_errors_are_not_cached() {
+    let redis = MemoryCache::default();
+    let failed = is_workspace_deactivated(&redis, WORKSPACE, async {
+        Err(eyre::eyre!("database unavailable"))
+    })
+    .await;
+    assert!(failed.is_err());
+
+    assert!(
+        is_workspace_deactivated(&redis, WORKSPACE, async { Ok(true) })
+            .await
+            .unwrap()
+    );
+}
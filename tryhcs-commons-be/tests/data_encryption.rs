@@ -4,7 +4,7 @@ use aes_gcm::{AeadCore, KeyInit};
 use serde_json::json;
 use tracing::info;
 use tryhcs_commons_be::data_encryption::{
-    DeterministicEncrypted, EncryptableData, NonDeterministicEncrypted,
+    DeterministicEncrypted, EncryptableData, Encryptor, NonDeterministicEncrypted,
 };
 
 static INIT: Once = Once::new();
@@ -94,3 +94,25 @@ fn should_encrypt_and_decrypt_non_deterministic() {
         .expect("Failed to get the decrypted data");
     assert_eq!(RAW_PLAIN_TEXT, &decrypted_data);
 }
+
+#[test]
+fn should_encrypt_raw_deterministic_values_consistently() {
+    init_logger();
+
+    let encryptor = Encryptor::new(
+        AES_GSM_ENCRYPTION_KEY.to_vec(),
+        AES_GSM_ENCRYPTION_NONCE.to_vec(),
+    );
+    let first = encryptor
+        .set_deterministic(RAW_PLAIN_TEXT.to_string())
+        .expect("Failed to encrypt deterministic value");
+    let second = encryptor
+        .set_deterministic(RAW_PLAIN_TEXT.to_string())
+        .expect("Failed to encrypt deterministic value");
+    assert_eq!(first, second);
+
+    let decrypted: String = encryptor
+        .get_deterministic(first)
+        .expect("Failed to decrypt deterministic value");
+    assert_eq!(RAW_PLAIN_TEXT, &decrypted);
+}
@@ -0,0 +1,25 @@
+use tryhcs_commons_be::file_upload::is_institution_upload;
+
+#[test]
+fn only_claims_the_institutions_own_uploads() {
+    let institution_id = "8d1c0f7e";
+    assert!(is_institution_upload(
+        institution_id,
+        "compliance/I8d1c0f7eS42-T1--cac.pdf"
+    ));
+    assert!(is_institution_upload(
+        institution_id,
+        "institution-logos/I8d1c0f7eS42-T1"
+    ));
+    // another tenant's upload, or a key only mentioning the institution
+    assert!(!is_institution_upload(
+        institution_id,
+        "compliance/I9a2b3c4dS42-T1--cac.pdf"
+    ));
+    assert!(!is_institution_upload(
+        institution_id,
+        "compliance/x/I8d1c0f7eS42-T1"
+    ));
+    assert!(!is_institution_upload(institution_id, "I8d1c0f7eS42-T1"));
+    assert!(!is_institution_upload(institution_id, "/I8d1c0f7eS42-T1"));
+}
@@ -1 +1,7 @@
 pub mod data_encryption;
+pub mod utils;
+pub mod totp;
+pub mod signed_token;
+pub mod api_response;
+pub mod auth;
+pub mod file_upload;
@@ -0,0 +1,159 @@
+use chrono::{Duration, Utc};
+use tryhcs_commons_be::{
+    env::EnvConfig,
+    redis::MemoryCache,
+    session::{
+        create_session, find_session, find_user_sessions, get_session_by_id, refresh_session,
+        revoke_session, touch_session, update_session, RefreshedSession,
+    },
+};
+use tryhcs_shared::institution_params::{AuthenticatedUser, AuthorizedUser};
+
+const MOBILE: &str = "+2348149464289";
+
+fn env() -> EnvConfig {
+    EnvConfig {
+        session_expires_in_min: 30,
+        refresh_token_expires_in_days: 30,
+        ..Default::default()
+    }
+}
+
+async fn login(redis: &MemoryCache) -> AuthenticatedUser {
+    let user = AuthenticatedUser {
+        principal: AuthorizedUser {
+            mobile: MOBILE.into(),
+            accounts: vec![],
+        },
+        token: None,
+        refresh_token: None,
+    };
+    create_session(redis, &user, "device-1", &env())
+        .await
+        .expect("Failed to create session")
+}
+
+fn refreshed(result: RefreshedSession) -> AuthenticatedUser {
+    match result {
+        RefreshedSession::Refreshed(user) => user,
+        RefreshedSession::Invalid => panic!("refresh token was invalid"),
+        RefreshedSession::Reused => panic!("refresh token reuse was detected"),
+    }
+}
+
+#[tokio::test]
+async fn rotates_the_token_pair() {
+    let redis = MemoryCache::default();
+    let user = login(&redis).await;
+    let refresh_token = user.refresh_token.unwrap();
+
+    let rotated = refreshed(
+        refresh_session(&redis, &refresh_token, &env())
+            .await
+            .unwrap(),
+    );
+    assert_ne!(
+        rotated.refresh_token.as_deref(),
+        Some(refresh_token.as_str())
+    );
+
+    assert!(find_session(&redis, &user.token.unwrap())
+        .await
+        .unwrap()
+        .is_none());
+    assert!(find_session(&redis, rotated.token.as_deref().unwrap())
+        .await
+        .unwrap()
+        .is_some());
+}
+
+#[tokio::test]
+async fn parallel_refreshes_share_the_rotated_pair() {
+    let redis = MemoryCache::default();
+    let refresh_token = login(&redis).await.refresh_token.unwrap();
+    let env = env();
+
+    let (first, second) = tokio::join!(
+        refresh_session(&redis, &refresh_token, &env),
+        refresh_session(&redis, &refresh_token, &env),
+    );
+    let (first, second) = (refreshed(first.unwrap()), refreshed(second.unwrap()));
+
+    assert_eq!(first.token, second.token);
+    assert_eq!(first.refresh_token, second.refresh_token);
+    assert_eq!(find_user_sessions(&redis, MOBILE).await.unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn revokes_the_session_when_an_old_refresh_token_is_reused() {
+    let redis = MemoryCache::default();
+    let user = login(&redis).await;
+    let refresh_token = user.refresh_token.unwrap();
+    let rotated = refreshed(
+        refresh_session(&redis, &refresh_token, &env())
+            .await
+            .unwrap(),
+    );
+
+    let session = find_session(&redis, rotated.token.as_deref().unwrap())
+        .await
+        .unwrap()
+        .unwrap();
+    update_session(&redis, &session.info.id, |session| {
+        session.info.rotated_at = Some(Utc::now() - Duration::minutes(5));
+        true
+    })
+    .await
+    .unwrap();
+
+    assert!(matches!(
+        refresh_session(&redis, &refresh_token, &env())
+            .await
+            .unwrap(),
+        RefreshedSession::Reused
+    ));
+    assert!(find_session(&redis, rotated.token.as_deref().unwrap())
+        .await
+        .unwrap()
+        .is_none());
+    assert!(find_user_sessions(&redis, MOBILE).await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn touching_a_stale_session_keeps_changes_made_meanwhile() {
+    let redis = MemoryCache::default();
+    let token = login(&redis).await.token.unwrap();
+    let stale = find_session(&redis, &token).await.unwrap().unwrap();
+
+    let rotated_at = Utc::now();
+    update_session(&redis, &stale.info.id, |session| {
+        session.info.rotated_at = Some(rotated_at);
+        true
+    })
+    .await
+    .unwrap();
+    touch_session(&redis, &stale, Some("WKS-1"), &env()).await;
+
+    let stored = get_session_by_id(&redis, &stale.info.id)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(stored.info.workspace_code.as_deref(), Some("WKS-1"));
+    assert_eq!(stored.info.rotated_at, Some(rotated_at));
+}
+
+#[tokio::test]
+async fn touching_a_revoked_session_doesnt_restore_it() {
+    let redis = MemoryCache::default();
+    let token = login(&redis).await.token.unwrap();
+    let session = find_session(&redis, &token).await.unwrap().unwrap();
+
+    revoke_session(&redis, &session.info).await.unwrap();
+    touch_session(&redis, &session, Some("WKS-1"), &env()).await;
+
+    assert!(get_session_by_id(&redis, &session.info.id)
+        .await
+        .unwrap()
+        .is_none());
+    assert!(find_session(&redis, &token).await.unwrap().is_none());
+}
@@ -0,0 +1,43 @@
+use tryhcs_commons_be::signed_token::{hash_token, sign_token, verify_signed_token};
+
+const KEY: &[u8] = b"invitation-test-signing-key";
+
+#[test]
+fn should_verify_token_before_expiry() {
+    let token = sign_token(KEY, "8f7e0c2a-invite", 1_000);
+    assert_eq!(
+        verify_signed_token(KEY, &token, 999),
+        Some("8f7e0c2a-invite".to_string())
+    );
+}
+
+#[test]
+fn should_reject_expired_token() {
+    let token = sign_token(KEY, "invite", 1_000);
+    assert_eq!(verify_signed_token(KEY, &token, 1_000), None);
+}
+
+#[test]
+fn should_reject_token_signed_with_another_key() {
+    let token = sign_token(b"another-key", "invite", 1_000);
+    assert_eq!(verify_signed_token(KEY, &token, 10), None);
+}
+
+#[test]
+fn should_reject_tampered_token() {
+    let token = sign_token(KEY, "invite", 1_000);
+    let extended = token.replacen(".1000.", ".9000.", 1);
+    assert_eq!(verify_signed_token(KEY, &extended, 10), None);
+    assert_eq!(verify_signed_token(KEY, "invite.1000", 10), None);
+    assert_eq!(verify_signed_token(KEY, "", 10), None);
+}
+
+#[test]
+fn should_hash_tokens_consistently() {
+    let token = sign_token(KEY, "invite", 1_000);
+    assert_eq!(hash_token(&token), hash_token(&token));
+    assert_ne!(
+        hash_token(&token),
+        hash_token(&sign_token(KEY, "invite", 1_001))
+    );
+}
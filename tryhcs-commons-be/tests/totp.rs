@@ -0,0 +1,65 @@
+use tryhcs_commons_be::totp::{
+    generate_recovery_codes, generate_totp_secret, hash_recovery_code, totp_provisioning_url,
+    verify_totp,
+};
+
+// base32 of the RFC 6238 SHA1 test secret "12345678901234567890"
+const RFC_6238_SECRET: &str = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ";
+
+#[test]
+fn should_verify_rfc_6238_test_vectors() {
+    // the RFC vectors are 8 digits, authenticator apps use the last 6
+    for (time, code) in [
+        (59, "287082"),
+        (1111111109, "081804"),
+        (1234567890, "005924"),
+        (2000000000, "279037"),
+    ] {
+        let step = verify_totp(RFC_6238_SECRET, code, time).expect("Failed to verify totp");
+        assert_eq!(step, Some(time / 30), "{time}");
+    }
+}
+
+#[test]
+fn should_accept_codes_within_clock_skew() {
+    assert_eq!(
+        verify_totp(RFC_6238_SECRET, "287082", 59 + 30).expect("Failed to verify totp"),
+        Some(1)
+    );
+    assert_eq!(
+        verify_totp(RFC_6238_SECRET, "287082", 59 + 90).expect("Failed to verify totp"),
+        None
+    );
+}
+
+#[test]
+fn should_reject_malformed_codes() {
+    for code in ["", "28708", "2870822", "28708a"] {
+        assert_eq!(
+            verify_totp(RFC_6238_SECRET, code, 59).expect("Failed to verify totp"),
+            None
+        );
+    }
+}
+
+#[test]
+fn should_generate_provisioning_url_for_new_secret() {
+    let secret = generate_totp_secret();
+    let url = totp_provisioning_url(&secret, "TryHcs", "+2348149464288")
+        .expect("Failed to generate provisioning url");
+    assert!(url.starts_with("otpauth://totp/"));
+    assert!(url.contains(&secret));
+}
+
+#[test]
+fn should_hash_recovery_codes_regardless_of_format() {
+    let codes = generate_recovery_codes(10);
+    assert_eq!(codes.len(), 10);
+
+    let code = &codes[0];
+    assert_eq!(
+        hash_recovery_code(code),
+        hash_recovery_code(&code.replace('-', "").to_lowercase())
+    );
+    assert_ne!(hash_recovery_code(code), hash_recovery_code(&codes[1]));
+}
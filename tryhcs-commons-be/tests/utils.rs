@@ -0,0 +1,97 @@
+use tryhcs_commons_be::utils::{
+    check_password_policy, escape_html, generate_otp, is_phone_region, normalize_phone,
+};
+
+#[test]
+fn should_generate_numeric_otp_of_requested_length() {
+    for length in [4, 6, 8] {
+        let otp = generate_otp(length).expect("Failed to generate otp");
+        assert_eq!(otp.len(), length as usize);
+        assert!(otp.chars().all(|c| c.is_ascii_digit()));
+    }
+}
+
+#[test]
+fn should_not_generate_repeated_otps() {
+    let otps = (0..20)
+        .map(|_| generate_otp(8).expect("Failed to generate otp"))
+        .collect::<std::collections::BTreeSet<_>>();
+    assert!(otps.len() > 1);
+}
+
+#[test]
+fn should_reject_zero_length_otp() {
+    assert!(generate_otp(0).is_err());
+}
+
+#[test]
+fn should_accept_password_matching_policy() {
+    assert_eq!(check_password_policy("!244@bioP", 8), None);
+}
+
+#[test]
+fn should_reject_short_password() {
+    assert!(check_password_policy("!2@bP", 8).is_some());
+}
+
+#[test]
+fn should_reject_password_missing_character_classes() {
+    for password in ["password1!", "PASSWORD1!", "Password!!", "Password12"] {
+        assert!(check_password_policy(password, 8).is_some(), "{password}");
+    }
+}
+
+#[test]
+fn should_normalize_local_and_international_phones_to_e164() {
+    for phone in [
+        "08141234567",
+        "8141234567",
+        "+2348141234567",
+        "2348141234567",
+        " 0814 123 4567 ",
+        "+234 (814) 123-4567",
+    ] {
+        assert_eq!(
+            normalize_phone(phone, "NG"),
+            Some("+2348141234567".to_string()),
+            "{phone}"
+        );
+    }
+}
+
+#[test]
+fn should_keep_foreign_phones_with_country_code() {
+    assert_eq!(
+        normalize_phone("+44 7911 123456", "NG"),
+        Some("+447911123456".to_string())
+    );
+    assert_eq!(
+        normalize_phone("07911 123456", "GB"),
+        Some("+447911123456".to_string())
+    );
+}
+
+#[test]
+fn should_reject_invalid_phones() {
+    for phone in ["", "123", "not a phone", "0814123"] {
+        assert_eq!(normalize_phone(phone, "NG"), None, "{phone}");
+    }
+}
+
+#[test]
+fn should_escape_html_markup() {
+    assert_eq!(
+        escape_html(r#"<a href="x">RC & TIN</a>"#),
+        "&lt;a href=&quot;x&quot;&gt;RC &amp; TIN&lt;/a&gt;"
+    );
+    assert_eq!(escape_html("Ada's clinic"), "Ada&#39;s clinic");
+}
+
+#[test]
+fn should_only_accept_known_phone_regions() {
+    assert!(is_phone_region("NG"));
+    assert!(is_phone_region("GB"));
+    assert!(!is_phone_region("Nigeria"));
+    assert!(!is_phone_region("XX"));
+    assert!(!is_phone_region(""));
+}
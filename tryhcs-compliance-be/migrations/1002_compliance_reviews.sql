@@ -0,0 +1,29 @@
+-- platform staffs work for TryHcs rather than an institution, they login with
+-- their user account and are added by operations
+create table platform_staffs (
+    id bigserial primary key,
+    mobile varchar(20) not null unique,
+    first_name varchar(70) not null,
+    last_name varchar(70) not null,
+    role varchar(40) not null, -- (COMPLIANCE_REVIEWER)
+
+    shadow_id uuid not null unique default gen_random_uuid(),
+    deleted_at timestamptz,
+    modified_at timestamptz not null default Now (),
+    created_at timestamptz not null default Now ()
+);
+
+-- reviewer decisions on a compliance section, reasons are a [{field, reason}] list
+create table compliance_reviews (
+    id bigserial primary key,
+    institution_id bigint not null,
+    section varchar(20) not null, -- (corporate, financial, healthcare)
+    decision varchar(20) not null, -- (VERIFIED, REJECTED)
+    reasons jsonb not null default '[]',
+    reviewed_by bigint not null references platform_staffs (id),
+
+    shadow_id uuid not null unique default gen_random_uuid(),
+    created_at timestamptz not null default Now ()
+);
+
+create index compliance_reviews_institution_idx on compliance_reviews (institution_id, created_at);
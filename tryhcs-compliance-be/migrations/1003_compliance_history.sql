@@ -0,0 +1,28 @@
+-- every change of a compliance section, changes are a [{field, from, to}] list
+-- and lookup the identity provider's result the change was verified with.
+-- rows are never updated and only deleted with the institution's purge.
+create table compliance_history (
+    id bigserial primary key,
+    institution_id bigint not null,
+    section varchar(20) not null, -- (corporate, financial, healthcare)
+    action varchar(20) not null, -- (CREATED, EDITED, STATUS_CHANGED)
+    actor varchar(40) not null, -- shadow id of the staff or platform staff
+    actor_type varchar(20) not null, -- (STAFF, PLATFORM_STAFF)
+    changes jsonb not null default '[]',
+    lookup jsonb,
+
+    shadow_id uuid not null unique default gen_random_uuid(),
+    created_at timestamptz not null default Now ()
+);
+
+create index compliance_history_institution_idx on compliance_history (institution_id, created_at);
+
+create function reject_compliance_history_update() returns trigger as $$
+begin
+    raise exception 'compliance history is append-only';
+end;
+$$ language plpgsql;
+
+create trigger compliance_history_append_only
+    before update on compliance_history
+    for each row execute function reject_compliance_history_update();
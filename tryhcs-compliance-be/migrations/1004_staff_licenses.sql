@@ -0,0 +1,11 @@
+-- a staff holds one license of each type, resubmitting a license replaces it.
+-- registry_lookup is the registry's record of a verified license and
+-- expiry_reminded_at is cleared when the license is renewed.
+alter table staff_compliance
+    add column expires_at date,
+    add column registry_lookup jsonb,
+    add column expiry_reminded_at timestamptz,
+    add column shadow_id uuid not null unique default gen_random_uuid();
+
+create unique index staff_compliance_license_idx on staff_compliance (staff_id, license_type) where deleted_at is null;
+create index staff_compliance_expiry_idx on staff_compliance (expires_at) where deleted_at is null;
@@ -0,0 +1,13 @@
+-- every identity lookup made for an institution, lookups answered from the
+-- cache or by another in-flight lookup aren't billable.
+create table verification_usage (
+    id bigserial primary key,
+    institution_id bigint not null,
+    lookup_type varchar(20) not null, -- (RC_NO, TIN, BVN, BANK_ACCOUNT)
+    provider varchar(20) not null,
+    outcome varchar(20) not null, -- (FOUND, NOT_FOUND, FAILED)
+    billable boolean not null,
+    created_at timestamptz not null default Now ()
+);
+
+create index verification_usage_institution_idx on verification_usage (institution_id, created_at);
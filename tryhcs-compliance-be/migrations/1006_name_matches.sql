@@ -0,0 +1,4 @@
+-- how the names returned by the identity lookups score against each other and
+-- the submitted names, [{field, name, against, against_name, score, outcome}]
+alter table corporate_compliance add column name_matches jsonb not null default '[]';
+alter table financial_compliance add column name_matches jsonb not null default '[]';
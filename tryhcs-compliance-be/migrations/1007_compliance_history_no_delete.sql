@@ -0,0 +1,18 @@
+-- compliance history can't be deleted or truncated either, the purge of an
+-- institution is the only delete and marks its transaction with compliance.purge
+create or replace function reject_compliance_history_update() returns trigger as $$
+begin
+    if tg_op = 'DELETE' and current_setting('compliance.purge', true) = 'on' then
+        return old;
+    end if;
+    raise exception 'compliance history is append-only';
+end;
+$$ language plpgsql;
+
+create trigger compliance_history_no_delete
+    before delete on compliance_history
+    for each row execute function reject_compliance_history_update();
+
+create trigger compliance_history_no_truncate
+    before truncate on compliance_history
+    for each statement execute function reject_compliance_history_update();
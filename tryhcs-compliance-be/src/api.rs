@@ -1,12 +1,15 @@
 use reqwest::StatusCode;
 use sqlx::Either;
 use serde::{Deserialize, Serialize};
-use tryhcs_commons_be::{api_response::{ApiResponse, ErrorMessage}, auth::InstitutionAdminUser};
+use tryhcs_commons_be::{api_response::{api_error, paginated_error, ApiResponse, ErrorMessage}, auth::InstitutionAdminUser, file_upload::{get_presigned_object_url, is_institution_upload}, utils::{escape_html, normalize_phone}};
+use tryhcs_notifications_be::{send_email, send_sms, EmailMessage};
 use std::str::FromStr;
+use chrono::{Days, NaiveDate, Utc};
+use serde_json::{json, Value};
 
-use tryhcs_shared::{compliance_params::{ComplianceStatus, CorporateComplianceDto, CorporateComplianceEdit, FinancialComplianceDto, FinancialComplianceEdit, HealthcareComplianceDto, HealthcareComplianceEdit, NewComplainceEdit, NewHealthcareComplainceEdit, NewinancialComplainceEdit}, institution_params::{InstitutionId, StaffId, StaffShadowId}};
+use tryhcs_shared::{api_params::{ErrorCode, PaginatedQuery, PaginatedResult}, compliance_params::{ComplianceActorType, ComplianceDocumentDto, ComplianceEvaluation, ComplianceFieldReason, ComplianceHistoryDto, ComplianceQueueItemDto, ComplianceResponse, ComplianceReviewDecision, ComplianceReviewDetailsDto, ComplianceReviewReq, ComplianceSection, ComplianceStatus, CorporateComplianceDto, CorporateComplianceEdit, FinancialComplianceDto, FinancialComplianceEdit, HealthcareComplianceDto, HealthcareComplianceEdit, LicenseExpiry, LicenseType, NameMatchDto, NameMatchOutcome, NewComplainceEdit, NewHealthcareComplainceEdit, NewinancialComplainceEdit, StaffLicenseBoardItemDto, StaffLicenseDto, StaffLicenseEdit, VerificationUsageDto}, institution_params::{AuthorizedInstitutionUser, InstitutionId, StaffId, StaffShadowId}, validation::{Validate, ValidationCode, ValidationErrors}};
 
-use crate::app::ComplianceApp;
+use crate::{app::ComplianceApp, name_match::NameMatcher};
 
 use super::repo::*;
 use super::models::*;
@@ -20,12 +23,6 @@ pub struct ComplianceEvaluator<'a> (
     Option<&'a FinancialCompliance>,
     Option<&'a HealthcareCompliance>);
 
-#[derive(Debug, Clone, Serialize, Deserialize,)]
-pub struct ComplianceEvaluation {
-    pub compliance_status: ComplianceStatus,
-    pub compliance_message: String,
-    pub rejected: Vec<String>,
-}
 
 
 impl <'a> ComplianceEvaluator<'a> {
@@ -80,6 +77,7 @@ impl <'a> ComplianceEvaluator<'a> {
                     compliance_status: ComplianceStatus::PENDING,
                     compliance_message: "Registration pending".into(),
                     rejected: vec![],
+                    rejections: vec![],
                 };
         }
         let rejected = statuses.iter()
@@ -94,6 +92,7 @@ impl <'a> ComplianceEvaluator<'a> {
                 compliance_status: ComplianceStatus::REJECTED,
                 compliance_message: "Compliance data rejected".into(),
                 rejected,
+                rejections: vec![],
             };
         }
         if statuses.iter().any(|(status, _ )| *status == ComplianceStatus::SUBMITTED) {
@@ -101,6 +100,7 @@ impl <'a> ComplianceEvaluator<'a> {
                 compliance_status: ComplianceStatus::SUBMITTED,
                 compliance_message: "Compliance verification pending".into(),
                 rejected: vec![],
+                rejections: vec![],
             };
             }
 
@@ -109,19 +109,13 @@ impl <'a> ComplianceEvaluator<'a> {
                     compliance_status: ComplianceStatus::VERIFIED,
                     compliance_message: "Compliance verified".into(),
                     rejected: vec![],
+                    rejections: vec![],
         };
     }
 
 
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct ComplianceResponse {
-    pub corporate: Option<CorporateComplianceDto>,
-    pub financial: Option<FinancialComplianceDto>,
-    pub healthcare: Option<HealthcareComplianceDto>,
-    pub evaluation: ComplianceEvaluation,
-}
 
 #[cfg(test)]
 mod compliance_evaluator_tests {
@@ -133,22 +127,59 @@ mod compliance_evaluator_tests {
         // let app = App::faux();
         // faux::when!(app)
     }
+
+    #[test]
+    fn evaluates_license_expiry() {
+        use chrono::NaiveDate;
+        use tryhcs_shared::compliance_params::LicenseExpiry;
+
+        let today = NaiveDate::from_ymd_opt(2025, 3, 1).unwrap();
+        let expiry = |date: Option<&str>| super::license_expiry(date.map(|d| d.parse().unwrap()), today, 30);
+
+        assert_eq!(expiry(None), LicenseExpiry::Unknown);
+        assert_eq!(expiry(Some("2025-02-28")), LicenseExpiry::Expired);
+        assert_eq!(expiry(Some("2025-03-01")), LicenseExpiry::ExpiringSoon);
+        assert_eq!(expiry(Some("2025-03-31")), LicenseExpiry::ExpiringSoon);
+        assert_eq!(expiry(Some("2025-04-01")), LicenseExpiry::Valid);
+    }
 }
 
-pub async fn get_compliance_data(app: &ComplianceApp, InstitutionAdminUser(user): &InstitutionAdminUser) -> eyre::Result<ApiResponse<ComplianceResponse>> {
-    let institution_id = InstitutionId(user.institution.px);
-    let corporate = app.compliance_repo.get_corporate_compliance(&institution_id).await?;
-    let financial: Option<FinancialCompliance> = app.compliance_repo.get_financial_compliance(&institution_id).await?;
-    let healthcare: Option<HealthcareCompliance> = app.compliance_repo.get_healthcare_compliance(&institution_id).await?;
+// Sections of the institution with their evaluation, rejected sections carry
+// the reasons of their latest review
+async fn compliance_overview(app: &ComplianceApp, institution_id: &InstitutionId) -> eyre::Result<(ComplianceResponse, Vec<ComplianceReview>)> {
+    let corporate = app.compliance_repo.get_corporate_compliance(institution_id).await?;
+    let financial: Option<FinancialCompliance> = app.compliance_repo.get_financial_compliance(institution_id).await?;
+    let healthcare: Option<HealthcareCompliance> = app.compliance_repo.get_healthcare_compliance(institution_id).await?;
+    let reviews = app.compliance_repo.find_compliance_reviews(institution_id).await?;
+
+    let mut evaluation = ComplianceEvaluator(corporate.as_ref(), financial.as_ref(), healthcare.as_ref()).evaluate()?;
+    for section in &evaluation.rejected {
+        let rejection = reviews.iter()
+            .find(|r| r.section.eq_ignore_ascii_case(section) && r.decision.eq_ignore_ascii_case(&ComplianceStatus::REJECTED.to_string()));
+        if let Some(rejection) = rejection {
+            evaluation.rejections.push(rejection.clone().try_into()?);
+        }
+    }
 
-    let evaluation = ComplianceEvaluator(corporate.as_ref(), financial.as_ref(), healthcare.as_ref()).evaluate()?;
     let overview = ComplianceResponse {
-        corporate: corporate.map(|v| v.into()),
-        financial: financial.map(|v| v.into()),
+        corporate: corporate.map(|v| v.try_into()).transpose()?,
+        financial: financial.map(|v| v.try_into()).transpose()?,
         healthcare: healthcare.map(|v| v.into()),
         evaluation,
     };
+    Ok((overview, reviews))
+}
 
+// Keeps institutions.compliance_status on the evaluator's outcome
+async fn sync_compliance_status(app: &ComplianceApp, institution_id: &InstitutionId) -> eyre::Result<ComplianceResponse> {
+    let (overview, _) = compliance_overview(app, institution_id).await?;
+    app.compliance_repo.update_institution_compliance_status(institution_id, &overview.evaluation.compliance_status).await?;
+    Ok(overview)
+}
+
+pub async fn get_compliance_data(app: &ComplianceApp, InstitutionAdminUser(user): &InstitutionAdminUser) -> eyre::Result<ApiResponse<ComplianceResponse>> {
+    let institution_id = InstitutionId(user.institution.px);
+    let (overview, _) = compliance_overview(app, &institution_id).await?;
     Ok((StatusCode::OK, Either::Left(Some(overview))))
 }
 
@@ -199,89 +230,148 @@ pub async fn update_corporate_compliance(
     InstitutionAdminUser(user): &InstitutionAdminUser,
     data: &CorporateComplianceEdit
 ) -> eyre::Result<ApiResponse<CorporateComplianceDto>> {
+    data.validate()?;
     let institution_id = InstitutionId(user.institution.px);
 
     // intentionally not parallezing the request
-    if let Either::Right(error_message) = app.compliance.lookup_rc_no(&data.rc_no).await? {
-        return Ok((StatusCode::BAD_REQUEST, Either::Right(error_message)));
-    }
-    if let Either::Right(error_message) = app.compliance.lookup_tin(&data.tin).await? {
-        return Ok((StatusCode::BAD_REQUEST, Either::Right(error_message)));
-    }
-    if let Either::Right(error_message) = app.compliance.name_lookup(&data.corporate_bank_code, &data.corporate_account_number).await? {
-        return Ok((StatusCode::BAD_REQUEST, Either::Right(error_message)));
+    let business = match app.lookups.lookup_rc_no(&institution_id, &data.rc_no).await? {
+        Either::Right(error_message) => return Ok(error_message.response()),
+        Either::Left(business) => business,
+    };
+    let tin = match app.lookups.lookup_tin(&institution_id, &data.tin).await? {
+        Either::Right(error_message) => return Ok(error_message.response()),
+        Either::Left(tin) => tin,
+    };
+    let account = match app.lookups.name_lookup(&institution_id, &data.corporate_bank_code, &data.corporate_account_number).await? {
+        Either::Right(error_message) => return Ok(error_message.response()),
+        Either::Left(account) => account,
+    };
+    let matcher = NameMatcher::from_env(&app.env)?;
+    let name_matches = vec![
+        matcher.compare(("rc_no", &business.name), ("institution_name", &user.institution.institution_name)),
+        matcher.compare(("tin", &tin.name), ("rc_no", &business.name)),
+        matcher.compare(("corporate_account_number", &account.name), ("rc_no", &business.name)),
+    ];
+    if let Some(error_message) = name_mismatch(&name_matches) {
+        return Ok(error_message.response());
     }
+    let change = staff_change(&user.staff_id, Some(json!({
+        "rc_no": business,
+        "tin": tin,
+        "bank_account": { "name": account.name, "account_number": mask_identifier(&account.account_nmuber), "bank_code": account.bank_code },
+        "name_matches": name_matches,
+    })));
 
     let saved_corporate: Option<CorporateCompliance> = app.compliance_repo.get_corporate_compliance(&institution_id).await?;
     let corporate_data = match saved_corporate {
-        None => {
-            app.compliance_repo.create_corporate_compliance(&institution_id, &NewComplainceEdit(StaffShadowId(user.staff_id.clone()),  data.to_owned())).await?
-        },
+        None => app.compliance_repo.create_corporate_compliance(&institution_id, &NewComplainceEdit(StaffShadowId(user.staff_id.clone()),  data.to_owned()), &name_matches, &change).await?,
         Some(corporate) => {
             if !can_user_update_document(&corporate.stage) {
-                return Ok((StatusCode::FORBIDDEN, Either::Right(ErrorMessage("Forbidden".into()))))
+                return Ok(api_error(ErrorCode::ComplianceLocked))
             }
-            app.compliance_repo.edit_corporate_compliance(&CorporateComplianceId(corporate.id), &data).await?
+            app.compliance_repo.edit_corporate_compliance(&CorporateComplianceId(corporate.id), data, &name_matches, &change).await?
         },
     };
 
-    Ok((StatusCode::OK, Either::Left(Some(corporate_data.into()))))
+    sync_compliance_status(app, &institution_id).await?;
+
+    Ok((StatusCode::OK, Either::Left(Some(corporate_data.try_into()?))))
+}
+
+/// Refusal naming the first pair of names too far apart to belong to the same party
+fn name_mismatch(name_matches: &[NameMatchDto]) -> Option<ErrorMessage> {
+    name_matches.iter()
+        .find(|m| m.outcome == NameMatchOutcome::Blocked)
+        .map(|m| ErrorMessage::with_message(ErrorCode::ComplianceNameMismatch,
+            format!("The {} name doesn't match the {} name", m.field, m.against)))
 }
 
 fn can_user_update_document(current_status: &str) -> bool {
     !ComplianceStatus::VERIFIED.to_string().eq_ignore_ascii_case(current_status) 
 }
 
+fn staff_change(staff_id: &str, lookup: Option<Value>) -> ComplianceChange {
+    ComplianceChange { actor: staff_id.to_owned(), actor_type: ComplianceActorType::Staff, lookup }
+}
+
 pub async fn update_healthcare_compliance(    app: &ComplianceApp, 
     InstitutionAdminUser(user): &InstitutionAdminUser, data: &HealthcareComplianceEdit) -> eyre::Result<ApiResponse<HealthcareComplianceDto>> {
+    data.validate()?;
+    let mut data = data.clone();
+    data.licensed_medical_doctor_phone_no = normalize_phone(&data.licensed_medical_doctor_phone_no, &app.env.default_phone_region)
+        .ok_or_else(|| ValidationErrors::single("licensed_medical_doctor_phone_no", ValidationCode::InvalidPhone,
+            "licensed_medical_doctor_phone_no is not a valid phone number".into()))?;
+    let data = &data;
+
     let institution_id = InstitutionId(user.institution.px);
     let saved_compliance = app.compliance_repo.get_healthcare_compliance(&institution_id).await?;
+    let change = staff_change(&user.staff_id, None);
     let compliance = match saved_compliance {
-        None => {
-            app.compliance_repo.create_healthcare_compliance(&institution_id, &NewHealthcareComplainceEdit(StaffShadowId(user.staff_id.clone()),  data.to_owned())).await?
-        },
+        None => app.compliance_repo.create_healthcare_compliance(&institution_id, &NewHealthcareComplainceEdit(StaffShadowId(user.staff_id.clone()),  data.to_owned()), &change).await?,
         Some(corporate) => {
             if !can_user_update_document(&corporate.stage) {
-                return Ok((StatusCode::FORBIDDEN, Either::Right(ErrorMessage("Forbidden".into()))))
+                return Ok(api_error(ErrorCode::ComplianceLocked))
             }
-            app.compliance_repo.edit_healthcare_compliance(&HealthcareComplianceId(corporate.id), &data).await?
+            app.compliance_repo.edit_healthcare_compliance(&HealthcareComplianceId(corporate.id), data, &change).await?
         },
     };
 
+    sync_compliance_status(app, &institution_id).await?;
+
     Ok((StatusCode::OK, Either::Left(Some(compliance.into()))))
 }
 
 pub async fn update_financial_compliance(    app: &ComplianceApp, 
     InstitutionAdminUser(user): &InstitutionAdminUser, data: &FinancialComplianceEdit) -> eyre::Result<ApiResponse<FinancialComplianceDto>> {
+    data.validate()?;
     let institution_id = InstitutionId(user.institution.px);
    
-   let bvn_search = app.compliance.lookup_bvn(&data.director_legal_bvn).await?;
-   let bvn_error_message: String = "Invalid BVN credentials".into();
-   match bvn_search    {
+   let bvn_search = app.lookups.lookup_bvn(&institution_id, &data.director_legal_bvn).await?;
+   let bvn_info = match bvn_search    {
        Either::Right(_) => {
-            return Ok((StatusCode::BAD_REQUEST, Either::Right(ErrorMessage(bvn_error_message))));     
+            return Ok(api_error(ErrorCode::ComplianceBvnMismatch));     
         },
         Either::Left(bvn_info) => {
             if !bvn_info.date_of_birth.eq_ignore_ascii_case(&data.director_legal_dob) {
-            return Ok((StatusCode::BAD_REQUEST, Either::Right(ErrorMessage(bvn_error_message))));
+            return Ok(api_error(ErrorCode::ComplianceBvnMismatch));
             }
+            bvn_info
         },
     };
+    let bvn_name = [Some(bvn_info.first_name.as_str()), bvn_info.middle_name.as_deref(), Some(bvn_info.last_name.as_str())]
+        .into_iter().flatten().collect::<Vec<_>>().join(" ");
+    let name_matches = vec![
+        NameMatcher::from_env(&app.env)?.compare(("director_legal_bvn", &bvn_name), ("director_legal_name", &data.director_legal_name)),
+    ];
+    if let Some(error_message) = name_mismatch(&name_matches) {
+        return Ok(error_message.response());
+    }
+    // the BVN photo and mobile are left out of the history, the name and birth date are masked
+    // here and in the name matches
+    let change = staff_change(&user.staff_id, Some(json!({
+        "bvn": {
+            "first_name": mask_name(&bvn_info.first_name),
+            "middle_name": bvn_info.middle_name.as_deref().map(mask_name),
+            "last_name": mask_name(&bvn_info.last_name),
+            "date_of_birth": mask_date(&bvn_info.date_of_birth),
+        },
+        "name_matches": name_matches.iter().map(|m| NameMatchDto { name: mask_name(&m.name), ..m.clone() }).collect::<Vec<_>>(),
+    })));
 
     let saved_compliance = app.compliance_repo.get_financial_compliance(&institution_id).await?;
     let corporate_data = match saved_compliance {
-        None => {
-            app.compliance_repo.create_financial_compliance(&institution_id, &NewinancialComplainceEdit(StaffShadowId(user.staff_id.clone()),  data.to_owned())).await?
-        },
+        None => app.compliance_repo.create_financial_compliance(&institution_id, &NewinancialComplainceEdit(StaffShadowId(user.staff_id.clone()),  data.to_owned()), &name_matches, &change).await?,
         Some(compliance) => {
             if !can_user_update_document(&compliance.stage) {
-                return Ok((StatusCode::FORBIDDEN, Either::Right(ErrorMessage("Forbidden".into()))))
+                return Ok(api_error(ErrorCode::ComplianceLocked))
             }
-            app.compliance_repo.edit_financial_compliance(&FinancialComplianceId(compliance.id), &data).await?
+            app.compliance_repo.edit_financial_compliance(&FinancialComplianceId(compliance.id), data, &name_matches, &change).await?
         },
     };
 
-    Ok((StatusCode::OK, Either::Left(Some(corporate_data.into()))))
+    sync_compliance_status(app, &institution_id).await?;
+
+    Ok((StatusCode::OK, Either::Left(Some(corporate_data.try_into()?))))
 }
 
 pub async fn submit_compliance(    app: &ComplianceApp, 
@@ -294,7 +384,7 @@ pub async fn submit_compliance(    app: &ComplianceApp,
     let financial_compliance = {
         match financial_compliance {
             None => {
-                return Ok((StatusCode::BAD_REQUEST, Either::Right(ErrorMessage("Financial compliance details not provided".into()))))
+                return Ok(ErrorMessage::with_message(ErrorCode::ComplianceIncomplete, "Financial compliance details not provided").response())
             },
             Some(compliance) => compliance,
         }
@@ -302,7 +392,7 @@ pub async fn submit_compliance(    app: &ComplianceApp,
     let corporate_compliance = {
         match corporate_compliance {
             None => {
-                return Ok((StatusCode::BAD_REQUEST, Either::Right(ErrorMessage("Corporate compliance details not provided".into()))))
+                return Ok(ErrorMessage::with_message(ErrorCode::ComplianceIncomplete, "Corporate compliance details not provided").response())
             },
             Some(compliance) => compliance,
         }
@@ -310,25 +400,329 @@ pub async fn submit_compliance(    app: &ComplianceApp,
     let healthcare_compliance = {
         match healthcare_compliance {
             None => {
-                return Ok((StatusCode::BAD_REQUEST, Either::Right(ErrorMessage("Healthcare compliance details not provided".into()))))
+                return Ok(ErrorMessage::with_message(ErrorCode::ComplianceIncomplete, "Healthcare compliance details not provided").response())
             },
             Some(compliance) => compliance,
         }
     };
 
+    let change = staff_change(&user.staff_id, None);
+    let submitted = ComplianceStatus::SUBMITTED;
     if !financial_compliance.stage.eq_ignore_ascii_case(&ComplianceStatus::VERIFIED.to_string()) {
-        app.compliance_repo.update_financial_compliance_status(&institution_id, &ComplianceStatus::SUBMITTED).await?;
+        app.compliance_repo.update_financial_compliance_status(&institution_id, &submitted, &change).await?;
     }
     if !corporate_compliance.stage.eq_ignore_ascii_case(&ComplianceStatus::VERIFIED.to_string()) {
-        app.compliance_repo.update_corporate_compliance_status(&institution_id, &ComplianceStatus::SUBMITTED).await?;
+        app.compliance_repo.update_corporate_compliance_status(&institution_id, &submitted, &change).await?;
     }
     if !healthcare_compliance.stage.eq_ignore_ascii_case(&ComplianceStatus::VERIFIED.to_string()) {
-        app.compliance_repo.update_healthcare_compliance_status(&institution_id, &ComplianceStatus::SUBMITTED).await?;
+        app.compliance_repo.update_healthcare_compliance_status(&institution_id, &submitted, &change).await?;
     }
+    sync_compliance_status(app, &institution_id).await?;
 
     Ok((StatusCode::OK, Either::Left(Some(()))))
 }
 
+pub async fn find_review_queue(app: &ComplianceApp, pagination: &PaginatedQuery)
+    -> eyre::Result<(StatusCode, PaginatedResult<ComplianceQueueItemDto>)> {
+    let (items, total) = app.compliance_repo.paginate_review_queue(pagination).await?;
+    Ok((StatusCode::OK, PaginatedResult::new(items.into_iter().map(|i| i.into()).collect(), total, pagination)))
+}
+
+async fn review_details(app: &ComplianceApp, institution: &ReviewInstitution) -> eyre::Result<ComplianceReviewDetailsDto> {
+    let institution_id = InstitutionId(institution.id);
+    let (compliance, reviews) = compliance_overview(app, &institution_id).await?;
+
+    let mut document_keys: Vec<(ComplianceSection, &str, &str)> = vec![];
+    if let Some(key) = compliance.corporate.as_ref().and_then(|c| c.private_healthcare_certificate_url.as_deref()) {
+        document_keys.push((ComplianceSection::Corporate, "private_healthcare_certificate_url", key));
+    }
+    if let Some(financial) = compliance.financial.as_ref() {
+        document_keys.push((ComplianceSection::Financial, "director_legal_gov_id_url", &financial.director_legal_gov_id_url));
+    }
+    if let Some(healthcare) = compliance.healthcare.as_ref() {
+        document_keys.push((ComplianceSection::Healthcare, "licensed_medical_doctor_mdcn_image_url", &healthcare.licensed_medical_doctor_mdcn_image_url));
+    }
+    let mut documents = vec![];
+    for (section, field, key) in document_keys.into_iter().filter(|(_, _, key)| !key.is_empty()) {
+        let url = get_presigned_object_url(&app.s3_client, &app.env.cloudflare_r2_bucket, key, app.env.presigned_url_expires_in_sec).await?;
+        documents.push(ComplianceDocumentDto { section, field: field.to_owned(), url });
+    }
+
+    Ok(ComplianceReviewDetailsDto {
+        institution_id: institution.shadow_id.clone(),
+        institution_name: institution.name.clone(),
+        institution_email: institution.email.clone(),
+        reviews: reviews.into_iter().map(|r| r.try_into()).collect::<eyre::Result<_>>()?,
+        compliance,
+        documents,
+    })
+}
+
+pub async fn get_compliance_review(app: &ComplianceApp, institution_id: &str)
+    -> eyre::Result<ApiResponse<ComplianceReviewDetailsDto>> {
+    let Some(institution) = app.compliance_repo.get_review_institution(institution_id).await? else {
+        return Ok(api_error(ErrorCode::InstitutionNotFound));
+    };
+    let details = review_details(app, &institution).await?;
+    Ok((StatusCode::OK, Either::Left(Some(details))))
+}
+
+/// Compliance changes of the admin's institution, newest first
+pub async fn find_compliance_history(app: &ComplianceApp, InstitutionAdminUser(user): &InstitutionAdminUser, pagination: &PaginatedQuery)
+    -> eyre::Result<(StatusCode, PaginatedResult<ComplianceHistoryDto>)> {
+    compliance_history(app, &InstitutionId(user.institution.px), pagination).await
+}
+
+pub async fn find_institution_compliance_history(app: &ComplianceApp, institution_id: &str, pagination: &PaginatedQuery)
+    -> eyre::Result<(StatusCode, PaginatedResult<ComplianceHistoryDto>)> {
+    let Some(institution) = app.compliance_repo.get_review_institution(institution_id).await? else {
+        return Ok(paginated_error(ErrorCode::InstitutionNotFound.into()));
+    };
+    compliance_history(app, &InstitutionId(institution.id), pagination).await
+}
+
+async fn compliance_history(app: &ComplianceApp, institution_id: &InstitutionId, pagination: &PaginatedQuery)
+    -> eyre::Result<(StatusCode, PaginatedResult<ComplianceHistoryDto>)> {
+    let (history, total) = app.compliance_repo.paginate_compliance_history(institution_id, pagination).await?;
+    let history = history.into_iter().map(|h| h.try_into()).collect::<eyre::Result<_>>()?;
+    Ok((StatusCode::OK, PaginatedResult::new(history, total, pagination)))
+}
+
+pub async fn find_verification_usage(app: &ComplianceApp, institution_id: &str) -> eyre::Result<ApiResponse<Vec<VerificationUsageDto>>> {
+    let Some(institution) = app.compliance_repo.get_review_institution(institution_id).await? else {
+        return Ok(api_error(ErrorCode::InstitutionNotFound));
+    };
+    let usage = app.compliance_repo.summarize_verification_usage(&InstitutionId(institution.id)).await?;
+    Ok((StatusCode::OK, Either::Left(Some(usage.into_iter().map(|u| u.into()).collect()))))
+}
+
+/// Verifies or rejects a submitted section, the institution is emailed the
+/// outcome with the reasons of the reviewer.
+pub async fn review_compliance_section(app: &ComplianceApp, reviewer: &PlatformStaff, institution_id: &str,
+    section: &str, req: &ComplianceReviewReq) -> eyre::Result<ApiResponse<ComplianceReviewDetailsDto>> {
+    req.validate()?;
+    let Some(section) = ComplianceSection::parse(section).filter(|s| *s != ComplianceSection::License) else {
+        return Err(ValidationErrors::single("section", ValidationCode::InvalidChoice,
+            "section must be one of: corporate, financial, healthcare".into()).into());
+    };
+    let mut invalid = ValidationErrors::new();
+    for (i, reason) in req.reasons.iter().enumerate() {
+        invalid.field(&format!("reasons[{}].field", i), &reason.field).one_of(section.fields());
+    }
+    invalid.into_result()?;
+
+    let Some(institution) = app.compliance_repo.get_review_institution(institution_id).await? else {
+        return Ok(api_error(ErrorCode::InstitutionNotFound));
+    };
+    let id = InstitutionId(institution.id);
+    // a reviewer can't pass the compliance of their own institution
+    if app.compliance_repo.is_institution_staff(&id, &reviewer.mobile).await? {
+        return Ok(api_error(ErrorCode::ComplianceReviewConflict));
+    }
+    let stage = match section {
+        ComplianceSection::Corporate => app.compliance_repo.get_corporate_compliance(&id).await?.map(|c| c.stage),
+        ComplianceSection::Financial => app.compliance_repo.get_financial_compliance(&id).await?.map(|c| c.stage),
+        ComplianceSection::Healthcare => app.compliance_repo.get_healthcare_compliance(&id).await?.map(|c| c.stage),
+        ComplianceSection::License => None,
+    };
+    let stage = match stage {
+        None => return Ok(api_error(ErrorCode::ComplianceIncomplete)),
+        Some(stage) if !stage.eq_ignore_ascii_case(&ComplianceStatus::SUBMITTED.to_string()) => {
+            return Ok(api_error(ErrorCode::ComplianceNotSubmitted));
+        },
+        Some(stage) => stage,
+    };
+
+    let decision = match req.decision {
+        ComplianceReviewDecision::Approve => ComplianceStatus::VERIFIED,
+        ComplianceReviewDecision::Reject => ComplianceStatus::REJECTED,
+    };
+    // another reviewer got to the section first
+    if app.compliance_repo.review_compliance_section(&id, section, &stage, &decision, &req.reasons, reviewer).await?.is_none() {
+        return Ok(api_error(ErrorCode::ComplianceNotSubmitted));
+    }
+    let overview = sync_compliance_status(app, &id).await?;
+    tracing::info!("Institution: {} {} compliance {} by {}", institution.shadow_id, section.as_str(), decision, reviewer.shadow_id);
+
+    if let Err(err) = send_review_outcome(app, &institution, section, &decision, &req.reasons, &overview.evaluation).await {
+        tracing::error!(message = "Failed to email compliance review", err=?err);
+    }
+
+    let details = review_details(app, &institution).await?;
+    Ok((StatusCode::OK, Either::Left(Some(details))))
+}
+
+/// Expiry of a license as of `today`, licenses within the reminder window are expiring soon
+pub fn license_expiry(expires_at: Option<NaiveDate>, today: NaiveDate, reminder_days: u32) -> LicenseExpiry {
+    match expires_at {
+        None => LicenseExpiry::Unknown,
+        Some(expires_at) if expires_at < today => LicenseExpiry::Expired,
+        Some(expires_at) if expires_at <= today + Days::new(reminder_days as u64) => LicenseExpiry::ExpiringSoon,
+        Some(_) => LicenseExpiry::Valid,
+    }
+}
+
+fn staff_license_dto(app: &ComplianceApp, license: StaffCompliance, today: NaiveDate) -> eyre::Result<StaffLicenseDto> {
+    Ok(StaffLicenseDto {
+        id: license.shadow_id,
+        license_type: LicenseType::parse(&license.license_type)
+            .ok_or_else(|| eyre::eyre!("Unknown license type: {}", license.license_type))?,
+        license_no: license.license_no,
+        license_certificate_url: license.license_certificate_url,
+        expires_at: license.expires_at,
+        expiry: license_expiry(license.expires_at, today, app.env.license_expiry_reminder_days),
+        stage: license.stage,
+        modified_at: license.modified_at.unwrap_or(license.created_at),
+    })
+}
+
+pub async fn find_staff_licenses(app: &ComplianceApp, user: &AuthorizedInstitutionUser) -> eyre::Result<ApiResponse<Vec<StaffLicenseDto>>> {
+    let Some(staff) = app.compliance_repo.get_license_staff(&InstitutionId(user.institution.px), &user.staff_id).await? else {
+        return Ok(api_error(ErrorCode::StaffNotFound));
+    };
+    let today = Utc::now().date_naive();
+    let licenses = app.compliance_repo.find_staff_licenses(&[staff.id]).await?
+        .into_iter()
+        .map(|l| staff_license_dto(app, l, today))
+        .collect::<eyre::Result<_>>()?;
+    Ok((StatusCode::OK, Either::Left(Some(licenses))))
+}
+
+/// Saves the staff's license, verified when a registry covers the license type
+/// and left submitted for review otherwise.
+pub async fn submit_staff_license(app: &ComplianceApp, user: &AuthorizedInstitutionUser, data: &StaffLicenseEdit)
+    -> eyre::Result<ApiResponse<StaffLicenseDto>> {
+    data.validate()?;
+    let license_type = LicenseType::parse(&data.license_type).ok_or_else(|| eyre::eyre!("Unknown license type: {}", data.license_type))?;
+    let mut expires_at = NaiveDate::parse_from_str(data.expires_at.trim(), "%Y-%m-%d")?;
+    let license_certificate_url = data.license_certificate_url.trim();
+    if !is_institution_upload(&user.institution.id, license_certificate_url) {
+        return Err(ValidationErrors::single("license_certificate_url", ValidationCode::InvalidFormat,
+            "license_certificate_url must be a file uploaded to the institution".into()).into());
+    }
+
+    let institution_id = InstitutionId(user.institution.px);
+    let Some(staff) = app.compliance_repo.get_license_staff(&institution_id, &user.staff_id).await? else {
+        return Ok(api_error(ErrorCode::StaffNotFound));
+    };
+
+    let license_no = data.license_no.trim();
+    let mut name_matches = vec![];
+    let (stage, registry_lookup) = match app.compliance.lookup_license(license_type, license_no).await? {
+        None => (ComplianceStatus::SUBMITTED, None),
+        Some(Either::Right(error_message)) => return Ok(error_message.response()),
+        Some(Either::Left(license)) => {
+            let staff_name = format!("{} {}", staff.first_name, staff.last_name);
+            name_matches.push(NameMatcher::from_env(&app.env)?.compare(("license_no", &license.holder_name), ("staff_name", &staff_name)));
+            if let Some(error_message) = name_mismatch(&name_matches) {
+                return Ok(error_message.response());
+            }
+            expires_at = license.expires_at.unwrap_or(expires_at);
+            // a holder name that only nearly matches leaves the license to be checked by hand
+            let stage = match name_matches[0].outcome {
+                NameMatchOutcome::Matched => ComplianceStatus::VERIFIED,
+                _ => ComplianceStatus::SUBMITTED,
+            };
+            (stage, Some(serde_json::to_value(&license)?))
+        },
+    };
+    let today = Utc::now().date_naive();
+    if expires_at < today {
+        return Err(ValidationErrors::single("expires_at", ValidationCode::InvalidFormat,
+            format!("The license expired on {}", expires_at)).into());
+    }
+
+    let change = staff_change(&user.staff_id, Some(json!({
+        "staff_id": staff.shadow_id,
+        "registry": registry_lookup,
+        "name_matches": name_matches,
+    })));
+    let license = app.compliance_repo.save_staff_license(&institution_id, staff.id, &NewStaffLicense {
+        license_type,
+        license_no: license_no.to_owned(),
+        license_certificate_url: license_certificate_url.to_owned(),
+        expires_at,
+        registry_lookup,
+        stage,
+        created_by: user.staff_id.clone(),
+    }, &change).await?;
+    tracing::info!("Staff: {} submitted {} license, {}", staff.shadow_id, license.license_type, license.stage);
+
+    Ok((StatusCode::OK, Either::Left(Some(staff_license_dto(app, license, today)?))))
+}
+
+/// Licenses of every staff of the institution
+pub async fn find_license_board(app: &ComplianceApp, InstitutionAdminUser(user): &InstitutionAdminUser, pagination: &PaginatedQuery)
+    -> eyre::Result<(StatusCode, PaginatedResult<StaffLicenseBoardItemDto>)> {
+    let (staffs, total) = app.compliance_repo.paginate_license_staffs(&InstitutionId(user.institution.px), pagination).await?;
+    let staff_ids: Vec<i64> = staffs.iter().map(|s| s.id).collect();
+    let mut licenses = app.compliance_repo.find_staff_licenses(&staff_ids).await?;
+
+    let today = Utc::now().date_naive();
+    let mut board = vec![];
+    for staff in staffs {
+        let (staff_licenses, rest): (Vec<_>, Vec<_>) = licenses.into_iter().partition(|l| l.staff_id == staff.id);
+        licenses = rest;
+        let staff_licenses = staff_licenses.into_iter()
+            .map(|l| staff_license_dto(app, l, today))
+            .collect::<eyre::Result<Vec<_>>>()?;
+        board.push(StaffLicenseBoardItemDto {
+            compliant: staff_licenses.iter().any(|l| l.stage.eq_ignore_ascii_case(&ComplianceStatus::VERIFIED.to_string())
+                && l.expiry != LicenseExpiry::Expired),
+            staff_id: staff.shadow_id,
+            first_name: staff.first_name,
+            last_name: staff.last_name,
+            title: staff.title,
+            licenses: staff_licenses,
+        });
+    }
+    Ok((StatusCode::OK, PaginatedResult::new(board, total, pagination)))
+}
+
+/// Texts staff whose license expires within the reminder window, each license
+/// is reminded once until it is renewed.
+pub async fn send_license_expiry_reminders(app: &ComplianceApp) -> eyre::Result<usize> {
+    let expires_by = Utc::now().date_naive() + Days::new(app.env.license_expiry_reminder_days as u64);
+    let licenses = app.compliance_repo.find_licenses_due_reminder(expires_by).await?;
+
+    let mut reminded = 0;
+    for license in licenses {
+        let message = format!("Hi {}, your {} license on {} expires on {}. Renew it and update your license details to stay compliant.",
+            license.first_name, license.license_type, license.institution_name, license.expires_at.format("%d %b %Y"));
+        if let Err(err) = send_sms(&app.env, &license.mobile, &message).await {
+            tracing::error!(message = "Failed to send license expiry reminder", err=?err);
+            continue;
+        }
+        app.compliance_repo.mark_license_reminded(license.id).await?;
+        reminded += 1;
+    }
+    Ok(reminded)
+}
+
+async fn send_review_outcome(app: &ComplianceApp, institution: &ReviewInstitution, section: ComplianceSection, decision: &ComplianceStatus,
+    reasons: &[ComplianceFieldReason], evaluation: &ComplianceEvaluation) -> eyre::Result<()> {
+    let outcome = match decision {
+        ComplianceStatus::VERIFIED => "approved",
+        _ => "rejected",
+    };
+    let reasons = reasons.iter()
+        .map(|r| format!("<li><b>{}</b>: {}</li>", escape_html(&r.field), escape_html(&r.reason)))
+        .collect::<Vec<_>>()
+        .join("");
+    let content = include_str!("../assets/templates/compliance_review.html")
+        .replace("{{institution_name}}", &escape_html(&institution.name))
+        .replace("{{section}}", section.as_str())
+        .replace("{{outcome}}", outcome)
+        .replace("{{reasons}}", &reasons)
+        .replace("{{compliance_message}}", &evaluation.compliance_message);
+
+    send_email(&app.env, EmailMessage {
+        to: institution.email.to_owned(),
+        subject: format!("Your {} compliance details were {}", section.as_str(), outcome),
+        content,
+    }).await
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct NinData {
     pub nin: String,
@@ -350,6 +744,24 @@ pub trait ComplianceVerification: Send + Sync {
     async fn lookup_nin(&self, nin: &str) -> eyre::Result<Either<NinData, ErrorMessage>>;
     async fn lookup_driver_license(&self, license_no: &str) -> eyre::Result<Either<DriverLicenseData, ErrorMessage>> ;
     async fn name_lookup(&self, bank_code: &str, account_number: &str) -> eyre::Result<Either<tryhcs_shared::finance_params::BankAccountInfo, ErrorMessage>>;
+    /// `None` when no license registry covers the license type
+    async fn lookup_license(&self, license_type: LicenseType, license_no: &str) -> eyre::Result<Option<Either<LicenseData, ErrorMessage>>>;
+}
+
+/// Register of a professional council, registries are tried in order and the
+/// first one covering a license type verifies it.
+#[cfg_attr(test, automock)]
+#[async_trait::async_trait]
+pub trait LicenseRegistry: Send + Sync {
+    fn covers(&self, license_type: LicenseType) -> bool;
+    async fn lookup_license(&self, license_type: LicenseType, license_no: &str) -> eyre::Result<Either<LicenseData, ErrorMessage>>;
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LicenseData {
+    pub license_no: String,
+    pub holder_name: String,
+    pub expires_at: Option<NaiveDate>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -14,12 +14,15 @@ use tryhcs_shared::{
     institution_params::{AuthenticatedUser, AuthorizedInstitutionUser},
 };
 
-use crate::{api::ComplianceVerification, repo::ComplianceRepo};
+use crate::{api::ComplianceVerification, lookups::IdentityLookups, repo::ComplianceRepo};
 
 
 pub struct ComplianceApp {
     pub compliance: Arc<dyn ComplianceVerification>,
+    // cached and metered lookups of `compliance`
+    pub lookups: IdentityLookups,
     pub env: EnvConfig,
     pub redis: Arc<dyn Cache>,
     pub compliance_repo: Arc<dyn ComplianceRepo>,
+    pub s3_client: aws_sdk_s3::Client,
 }
@@ -7,10 +7,10 @@ use chrono::Duration;
 use either::Either;
 use serde::Serialize;
 use serde_json::{json, Value};
-use tryhcs_commons_be::api_response::convert_result_to_json_response;
-use tryhcs_shared::compliance_params::{CorporateComplianceEdit, FinancialComplianceEdit, HealthcareComplianceEdit};
+use tryhcs_commons_be::api_response::{convert_paginated_result_to_json_response, convert_result_to_json_response};
+use tryhcs_shared::{api_params::PaginatedQuery, compliance_params::{ComplianceReviewReq, CorporateComplianceEdit, FinancialComplianceEdit, HealthcareComplianceEdit, StaffLicenseEdit}};
 
-use crate::{api, app::ComplianceApp, params::WorkspaceAdmin};
+use crate::{api, app::ComplianceApp, params::{PlatformReviewer, WorkspaceAdmin, WorkspaceUser}};
 
 
 pub  fn compliance_router(app: Arc<ComplianceApp>) -> Router {
@@ -20,6 +20,14 @@ pub  fn compliance_router(app: Arc<ComplianceApp>) -> Router {
         .route("/v1/onboard/finance", post(update_financial_compliance))
         .route("/v1/onboard/healthcare", post(update_healthcare_compliance))
         .route("/v1/onboard/submit", post(submit_compliance))
+        .route("/v1/onboard/history", get(find_compliance_history))
+        .route("/v1/licenses", get(find_staff_licenses).post(submit_staff_license))
+        .route("/v1/licenses/board", get(find_license_board))
+        .route("/v1/review/queue", get(find_review_queue))
+        .route("/v1/review/institutions/{institution_id}", get(get_compliance_review))
+        .route("/v1/review/institutions/{institution_id}/history", get(find_institution_compliance_history))
+        .route("/v1/review/institutions/{institution_id}/verification-usage", get(find_verification_usage))
+        .route("/v1/review/institutions/{institution_id}/{section}", post(review_compliance_section))
         .with_state(app)
         ;
     router
@@ -67,4 +75,86 @@ pub async fn submit_compliance(
     Json(req): Json<Value>,
 ) -> (StatusCode, Json<Value>) {
     convert_result_to_json_response(api::submit_compliance(app.as_ref(), &user).await)
-}
\ No newline at end of file
+}
+
+#[axum::debug_handler]
+pub async fn find_compliance_history(
+    State(app): State<Arc<ComplianceApp>>,
+    WorkspaceAdmin(user): WorkspaceAdmin,
+    Query(req_query): Query<PaginatedQuery>,
+) -> (StatusCode, Json<Value>) {
+    convert_paginated_result_to_json_response(api::find_compliance_history(app.as_ref(), &user, &req_query).await)
+}
+
+#[axum::debug_handler]
+pub async fn find_staff_licenses(
+    State(app): State<Arc<ComplianceApp>>,
+    WorkspaceUser(user): WorkspaceUser,
+) -> (StatusCode, Json<Value>) {
+    convert_result_to_json_response(api::find_staff_licenses(app.as_ref(), &user).await)
+}
+
+#[axum::debug_handler]
+pub async fn submit_staff_license(
+    State(app): State<Arc<ComplianceApp>>,
+    WorkspaceUser(user): WorkspaceUser,
+    Json(req): Json<StaffLicenseEdit>,
+) -> (StatusCode, Json<Value>) {
+    convert_result_to_json_response(api::submit_staff_license(app.as_ref(), &user, &req).await)
+}
+
+#[axum::debug_handler]
+pub async fn find_license_board(
+    State(app): State<Arc<ComplianceApp>>,
+    WorkspaceAdmin(user): WorkspaceAdmin,
+    Query(req_query): Query<PaginatedQuery>,
+) -> (StatusCode, Json<Value>) {
+    convert_paginated_result_to_json_response(api::find_license_board(app.as_ref(), &user, &req_query).await)
+}
+
+#[axum::debug_handler]
+pub async fn find_review_queue(
+    State(app): State<Arc<ComplianceApp>>,
+    _: PlatformReviewer,
+    Query(req_query): Query<PaginatedQuery>,
+) -> (StatusCode, Json<Value>) {
+    convert_paginated_result_to_json_response(api::find_review_queue(app.as_ref(), &req_query).await)
+}
+
+#[axum::debug_handler]
+pub async fn get_compliance_review(
+    State(app): State<Arc<ComplianceApp>>,
+    _: PlatformReviewer,
+    Path(institution_id): Path<String>,
+) -> (StatusCode, Json<Value>) {
+    convert_result_to_json_response(api::get_compliance_review(app.as_ref(), &institution_id).await)
+}
+
+#[axum::debug_handler]
+pub async fn review_compliance_section(
+    State(app): State<Arc<ComplianceApp>>,
+    PlatformReviewer(reviewer): PlatformReviewer,
+    Path((institution_id, section)): Path<(String, String)>,
+    Json(req): Json<ComplianceReviewReq>,
+) -> (StatusCode, Json<Value>) {
+    convert_result_to_json_response(api::review_compliance_section(app.as_ref(), &reviewer, &institution_id, &section, &req).await)
+}
+
+#[axum::debug_handler]
+pub async fn find_institution_compliance_history(
+    State(app): State<Arc<ComplianceApp>>,
+    _: PlatformReviewer,
+    Path(institution_id): Path<String>,
+    Query(req_query): Query<PaginatedQuery>,
+) -> (StatusCode, Json<Value>) {
+    convert_paginated_result_to_json_response(api::find_institution_compliance_history(app.as_ref(), &institution_id, &req_query).await)
+}
+
+#[axum::debug_handler]
+pub async fn find_verification_usage(
+    State(app): State<Arc<ComplianceApp>>,
+    _: PlatformReviewer,
+    Path(institution_id): Path<String>,
+) -> (StatusCode, Json<Value>) {
+    convert_result_to_json_response(api::find_verification_usage(app.as_ref(), &institution_id).await)
+}
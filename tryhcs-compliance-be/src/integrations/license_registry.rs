@@ -0,0 +1,96 @@
+use std::sync::Arc;
+
+use chrono::NaiveDate;
+use either::Either;
+use eyre::eyre;
+use reqwest::{StatusCode, Url};
+use serde::{Deserialize, Serialize};
+use tracing::info;
+use tryhcs_commons_be::{api_response::ErrorMessage, env::EnvConfig};
+use tryhcs_shared::{api_params::ErrorCode, compliance_params::LicenseType};
+
+use crate::api::{LicenseData, LicenseRegistry};
+
+const ACTIVE_LICENSE_STATUS: &str = "active";
+
+/// Gateway to the registers of the councils, `GET {base_url}/v1/licenses/{type}/{license_no}`
+/// answers with the license or a 404.
+#[derive(Clone, Debug)]
+pub struct LicenseRegistryApi {
+    pub base_url: String,
+    pub api_key: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RegistryLicenseResponse {
+    license_no: String,
+    holder_name: String,
+    status: String,
+    expires_at: Option<NaiveDate>,
+}
+
+impl LicenseRegistryApi {
+    /// `None` unless the gateway is configured
+    pub fn from_env(env: &EnvConfig) -> Option<Arc<dyn LicenseRegistry>> {
+        match (&env.license_registry_base_url, &env.license_registry_api_key) {
+            (Some(base_url), Some(api_key)) if !base_url.is_empty() => Some(Arc::new(LicenseRegistryApi {
+                base_url: base_url.trim_end_matches('/').to_owned(),
+                api_key: api_key.to_owned(),
+            })),
+            _ => None,
+        }
+    }
+
+    /// The license number is a path segment of its own, a `/` in it is encoded
+    pub fn license_url(&self, license_type: LicenseType, license_no: &str) -> eyre::Result<Url> {
+        let mut url = Url::parse(&self.base_url)?;
+        url.path_segments_mut()
+            .map_err(|_| eyre!("License registry base url can't take a path: {}", self.base_url))?
+            .pop_if_empty()
+            .extend(["v1", "licenses", license_type.as_str(), license_no]);
+        Ok(url)
+    }
+}
+
+#[async_trait::async_trait]
+impl LicenseRegistry for LicenseRegistryApi {
+    fn covers(&self, _license_type: LicenseType) -> bool {
+        true
+    }
+
+    async fn lookup_license(&self, license_type: LicenseType, license_no: &str) -> eyre::Result<Either<LicenseData, ErrorMessage>> {
+        let url = self.license_url(license_type, license_no)?;
+        info!(message = "send lookup license req", license_type = license_type.as_str());
+
+        let response = reqwest::Client::new()
+            .get(url)
+            .bearer_auth(&self.api_key)
+            .send()
+            .await?;
+
+        let status = response.status();
+        let response = response.text().await?;
+        // the response names the holder, only its status is logged
+        info!(message = "Lookup license response", status = ?status);
+
+        if status == StatusCode::NOT_FOUND {
+            return Ok(Either::Right(ErrorMessage::with_message(ErrorCode::ComplianceLookupFailed,
+                format!("{} license not found", license_type.as_str()))));
+        }
+        if !status.is_success() {
+            return Err(eyre!("License registry responded with {}", status));
+        }
+
+        let license = serde_json::from_str::<RegistryLicenseResponse>(&response)?;
+        if !license.status.eq_ignore_ascii_case(ACTIVE_LICENSE_STATUS) {
+            return Ok(Either::Right(ErrorMessage::with_message(ErrorCode::ComplianceLookupFailed,
+                format!("{} license is {}", license_type.as_str(), license.status.to_lowercase()))));
+        }
+        Ok(Either::Left(LicenseData {
+            license_no: license.license_no,
+            holder_name: license.holder_name,
+            expires_at: license.expires_at,
+        }))
+    }
+}
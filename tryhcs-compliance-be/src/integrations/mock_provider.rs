@@ -0,0 +1,111 @@
+use std::collections::HashMap;
+
+use either::Either;
+use eyre::Context;
+use serde::{Deserialize, Serialize};
+use tryhcs_commons_be::{api_response::ErrorMessage, env::EnvConfig};
+use tryhcs_shared::{api_params::ErrorCode, compliance_params::LicenseType, finance_params::BankAccountInfo};
+
+use crate::api::{BVNData, BusinessData, ComplianceVerification, DriverLicenseData, LicenseData, NinData, TINData};
+
+const BUNDLED_FIXTURES: &str = include_str!("../../assets/fixtures/verification.json");
+
+/// Records the mock provider answers with, keyed by the looked up number.
+/// Bank accounts are keyed `{bank_code}:{account_number}` and licenses `{license_type}:{license_no}`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct VerificationFixtures {
+    pub rc_no: HashMap<String, BusinessData>,
+    pub tin: HashMap<String, TINData>,
+    pub bvn: HashMap<String, BVNData>,
+    pub nin: HashMap<String, NinData>,
+    pub driver_license: HashMap<String, DriverLicenseData>,
+    pub bank_accounts: HashMap<String, BankAccountInfo>,
+    pub licenses: HashMap<String, LicenseData>,
+}
+
+/// Provider answering from fixtures for local development and tests, numbers
+/// missing from the fixtures are not found.
+#[derive(Debug, Clone)]
+pub struct MockVerificationProvider {
+    pub fixtures: VerificationFixtures,
+}
+
+impl MockVerificationProvider {
+    pub fn from_json(fixtures: &str) -> eyre::Result<Self> {
+        let fixtures: VerificationFixtures = serde_json::from_str(fixtures).wrap_err("Invalid verification fixtures")?;
+        Ok(MockVerificationProvider {
+            fixtures: VerificationFixtures {
+                rc_no: normalize(fixtures.rc_no),
+                tin: normalize(fixtures.tin),
+                bvn: normalize(fixtures.bvn),
+                nin: normalize(fixtures.nin),
+                driver_license: normalize(fixtures.driver_license),
+                bank_accounts: normalize(fixtures.bank_accounts),
+                licenses: normalize(fixtures.licenses),
+            },
+        })
+    }
+
+    pub fn bundled() -> eyre::Result<Self> {
+        Self::from_json(BUNDLED_FIXTURES)
+    }
+
+    /// fixtures of `verification_fixtures_path`, the bundled fixtures without it
+    pub fn from_env(env: &EnvConfig) -> eyre::Result<Self> {
+        match env.verification_fixtures_path.as_deref().filter(|p| !p.is_empty()) {
+            None => Self::bundled(),
+            Some(path) => {
+                let fixtures = std::fs::read_to_string(path).wrap_err_with(|| format!("Unable to read verification fixtures: {}", path))?;
+                Self::from_json(&fixtures)
+            },
+        }
+    }
+}
+
+fn fixture_key(key: &str) -> String {
+    key.trim().to_uppercase()
+}
+
+fn normalize<T>(fixtures: HashMap<String, T>) -> HashMap<String, T> {
+    fixtures.into_iter().map(|(key, data)| (fixture_key(&key), data)).collect()
+}
+
+fn find<T: Clone>(fixtures: &HashMap<String, T>, key: &str, not_found: &str) -> Either<T, ErrorMessage> {
+    match fixtures.get(&fixture_key(key)) {
+        Some(data) => Either::Left(data.clone()),
+        None => Either::Right(ErrorMessage::with_message(ErrorCode::ComplianceLookupFailed, not_found)),
+    }
+}
+
+#[async_trait::async_trait]
+impl ComplianceVerification for MockVerificationProvider {
+    async fn lookup_rc_no(&self, rc_no: &str) -> eyre::Result<Either<BusinessData, ErrorMessage>> {
+        Ok(find(&self.fixtures.rc_no, rc_no, "RC number not found"))
+    }
+
+    async fn lookup_tin(&self, tin: &str) -> eyre::Result<Either<TINData, ErrorMessage>> {
+        Ok(find(&self.fixtures.tin, tin, "TIN not found"))
+    }
+
+    async fn lookup_bvn(&self, bvn: &str) -> eyre::Result<Either<BVNData, ErrorMessage>> {
+        Ok(find(&self.fixtures.bvn, bvn, "BVN not found"))
+    }
+
+    async fn lookup_nin(&self, nin: &str) -> eyre::Result<Either<NinData, ErrorMessage>> {
+        Ok(find(&self.fixtures.nin, nin, "NIN not found"))
+    }
+
+    async fn lookup_driver_license(&self, license_no: &str) -> eyre::Result<Either<DriverLicenseData, ErrorMessage>> {
+        Ok(find(&self.fixtures.driver_license, license_no, "License information not found"))
+    }
+
+    async fn name_lookup(&self, bank_code: &str, account_number: &str) -> eyre::Result<Either<BankAccountInfo, ErrorMessage>> {
+        Ok(find(&self.fixtures.bank_accounts, &format!("{}:{}", bank_code, account_number), "Account information not found"))
+    }
+
+    async fn lookup_license(&self, license_type: LicenseType, license_no: &str) -> eyre::Result<Option<Either<LicenseData, ErrorMessage>>> {
+        let not_found = format!("{} license not found", license_type.as_str());
+        Ok(Some(find(&self.fixtures.licenses, &format!("{}:{}", license_type.as_str(), license_no), &not_found)))
+    }
+}
@@ -1 +1,188 @@
-pub mod youverify;
\ No newline at end of file
+use std::{future::Future, sync::Arc};
+
+use either::Either;
+use eyre::eyre;
+use tryhcs_commons_be::{api_response::ErrorMessage, env::EnvConfig};
+use tryhcs_shared::{compliance_params::LicenseType, finance_params::BankAccountInfo};
+
+use crate::api::{BVNData, BusinessData, ComplianceVerification, DriverLicenseData, LicenseData, NinData, TINData};
+
+use self::{license_registry::LicenseRegistryApi, mock_provider::MockVerificationProvider, youverify::YouverifyApi};
+
+pub mod youverify;
+pub mod license_registry;
+pub mod mock_provider;
+
+/// Identity verification vendors, another vendor (Dojah, Smile ID) is added
+/// with its `ComplianceVerification` and a variant here.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VerificationProvider {
+    Youverify,
+    Mock,
+}
+
+impl VerificationProvider {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            VerificationProvider::Youverify => "youverify",
+            VerificationProvider::Mock => "mock",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        [VerificationProvider::Youverify, VerificationProvider::Mock]
+            .into_iter()
+            .find(|p| p.as_str().eq_ignore_ascii_case(value.trim()))
+    }
+
+    fn create(&self, env: &EnvConfig) -> eyre::Result<Arc<dyn ComplianceVerification>> {
+        Ok(match self {
+            VerificationProvider::Youverify if env.youverify_base_url.trim().is_empty() || env.youverify_api_key.trim().is_empty() => {
+                return Err(eyre!("youverify_base_url and youverify_api_key are required to verify with youverify"));
+            },
+            // its fixtures would verify any institution
+            VerificationProvider::Mock if !env.is_dev_or_test() => {
+                return Err(eyre!("The mock verification provider can't be used in {}", env.app_env));
+            },
+            VerificationProvider::Youverify => Arc::new(YouverifyApi {
+                env: Arc::new(env.clone()),
+                license_registries: LicenseRegistryApi::from_env(env).into_iter().collect(),
+            }),
+            VerificationProvider::Mock => Arc::new(MockVerificationProvider::from_env(env)?),
+        })
+    }
+}
+
+/// The configured provider followed by the fallback provider when one is set.
+/// Fails on startup when a provider isn't configured or not allowed in the environment.
+pub fn verification_provider(env: &EnvConfig) -> eyre::Result<Arc<FallbackVerification>> {
+    let provider = |name: &str| VerificationProvider::parse(name)
+        .ok_or_else(|| eyre!("Unknown verification provider: {}", name));
+
+    let primary = provider(&env.verification_provider)?;
+    let mut providers = vec![(primary.as_str(), primary.create(env)?)];
+    if let Some(fallback) = env.verification_fallback_provider.as_deref().filter(|p| !p.trim().is_empty()) {
+        let fallback = provider(fallback)?;
+        providers.push((fallback.as_str(), fallback.create(env)?));
+    }
+    Ok(Arc::new(FallbackVerification { providers }))
+}
+
+/// Tries the providers in order, a provider that errors is retried with the
+/// next one but a number a provider didn't find isn't retried.
+pub struct FallbackVerification {
+    pub providers: Vec<(&'static str, Arc<dyn ComplianceVerification>)>,
+}
+
+impl FallbackVerification {
+    /// The result of `call` with the name of the provider that gave it
+    pub async fn answered<T, F>(&self, lookup: &str, call: impl Fn(Arc<dyn ComplianceVerification>) -> F) -> (&'static str, eyre::Result<T>)
+        where F: Future<Output = eyre::Result<T>> {
+        let mut providers = self.providers.iter().peekable();
+        while let Some((name, provider)) = providers.next() {
+            match call(provider.clone()).await {
+                Err(err) if providers.peek().is_some() => {
+                    tracing::warn!(message = "Verification provider failed, using fallback", provider = name, lookup = lookup, err = ?err);
+                },
+                result => return (name, result),
+            }
+        }
+        ("none", Err(eyre!("No verification provider for {} lookups", lookup)))
+    }
+}
+
+#[async_trait::async_trait]
+impl ComplianceVerification for FallbackVerification {
+    async fn lookup_rc_no(&self, rc_no: &str) -> eyre::Result<Either<BusinessData, ErrorMessage>> {
+        self.answered("rc_no", |p| async move { p.lookup_rc_no(rc_no).await }).await.1
+    }
+
+    async fn lookup_tin(&self, tin: &str) -> eyre::Result<Either<TINData, ErrorMessage>> {
+        self.answered("tin", |p| async move { p.lookup_tin(tin).await }).await.1
+    }
+
+    async fn lookup_bvn(&self, bvn: &str) -> eyre::Result<Either<BVNData, ErrorMessage>> {
+        self.answered("bvn", |p| async move { p.lookup_bvn(bvn).await }).await.1
+    }
+
+    async fn lookup_nin(&self, nin: &str) -> eyre::Result<Either<NinData, ErrorMessage>> {
+        self.answered("nin", |p| async move { p.lookup_nin(nin).await }).await.1
+    }
+
+    async fn lookup_driver_license(&self, license_no: &str) -> eyre::Result<Either<DriverLicenseData, ErrorMessage>> {
+        self.answered("driver_license", |p| async move { p.lookup_driver_license(license_no).await }).await.1
+    }
+
+    async fn name_lookup(&self, bank_code: &str, account_number: &str) -> eyre::Result<Either<BankAccountInfo, ErrorMessage>> {
+        self.answered("bank_account", |p| async move { p.name_lookup(bank_code, account_number).await }).await.1
+    }
+
+    async fn lookup_license(&self, license_type: LicenseType, license_no: &str) -> eyre::Result<Option<Either<LicenseData, ErrorMessage>>> {
+        self.answered("license", |p| async move { p.lookup_license(license_type, license_no).await }).await.1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use either::Either;
+    use eyre::eyre;
+    use tryhcs_commons_be::api_response::ErrorMessage;
+    use tryhcs_shared::{api_params::ErrorCode, compliance_params::LicenseType};
+
+    use crate::api::{ComplianceVerification, MockComplianceVerification};
+
+    use tryhcs_commons_be::env::EnvConfig;
+
+    use super::{license_registry::LicenseRegistryApi, mock_provider::MockVerificationProvider, verification_provider, FallbackVerification};
+
+    #[tokio::test]
+    async fn mock_provider_answers_from_fixtures() {
+        let provider = MockVerificationProvider::bundled().unwrap();
+
+        let business = provider.lookup_rc_no(" rc1234567").await.unwrap().unwrap_left();
+        assert_eq!(business.name, "St. Mary Clinic Limited");
+        let account = provider.name_lookup("058", "0123456789").await.unwrap().unwrap_left();
+        assert_eq!(account.name, "ST. MARY CLINIC LIMITED");
+        assert!(provider.lookup_tin("00000000-0000").await.unwrap().is_right());
+    }
+
+    #[tokio::test]
+    async fn falls_back_when_the_primary_errors() {
+        let mut primary = MockComplianceVerification::new();
+        primary.expect_lookup_bvn().returning(|_| Err(eyre!("connection reset")));
+        primary.expect_lookup_tin().returning(|_| Ok(Either::Right(ErrorMessage::new(ErrorCode::ComplianceLookupFailed))));
+
+        let verification = FallbackVerification {
+            providers: vec![("primary", Arc::new(primary)), ("mock", Arc::new(MockVerificationProvider::bundled().unwrap()))],
+        };
+
+        let (provider, bvn) = verification.answered("bvn", |p| async move { p.lookup_bvn("22345678901").await }).await;
+        assert_eq!(provider, "mock");
+        assert_eq!(bvn.unwrap().unwrap_left().date_of_birth, "1980-04-12");
+        // not found by the primary isn't retried
+        assert!(verification.lookup_tin("12345678-0001").await.unwrap().is_right());
+    }
+
+    #[test]
+    fn checks_the_providers_on_startup() {
+        let env = EnvConfig { app_env: "production".into(), verification_provider: "youverify".into(), ..Default::default() };
+        assert!(verification_provider(&env).is_err());
+
+        let env = EnvConfig { youverify_base_url: "https://api.youverify.co".into(), youverify_api_key: "key".into(), ..env };
+        assert!(verification_provider(&env).is_ok());
+        // the mock provider is refused as the primary or the fallback outside development and test
+        assert!(verification_provider(&EnvConfig { verification_fallback_provider: Some("mock".into()), ..env.clone() }).is_err());
+        assert!(verification_provider(&EnvConfig { verification_provider: "mock".into(), ..env.clone() }).is_err());
+        assert!(verification_provider(&EnvConfig { verification_provider: "mock".into(), app_env: "test".into(), ..env }).is_ok());
+    }
+
+    #[test]
+    fn encodes_the_license_number_into_one_segment() {
+        let registry = LicenseRegistryApi { base_url: "https://registry.example.com/api".into(), api_key: "key".into() };
+
+        let url = registry.license_url(LicenseType::Mdcn, "MDCN/123?x=1").unwrap();
+        assert_eq!(url.as_str(), "https://registry.example.com/api/v1/licenses/MDCN/MDCN%2F123%3Fx=1");
+    }
+}
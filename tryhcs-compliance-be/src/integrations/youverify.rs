@@ -9,16 +9,20 @@ use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use serde_json::json;
 use tracing::{debug, error, info};
 use tryhcs_commons_be::{api_response::ErrorMessage, env::EnvConfig};
-use tryhcs_shared::finance_params::BankAccountInfo;
+use tryhcs_shared::{api_params::ErrorCode, finance_params::BankAccountInfo};
 
-use crate::api::{BVNData, BusinessData, ComplianceVerification, DriverLicenseData, NinData, TINData};
+use tryhcs_shared::compliance_params::LicenseType;
 
+use crate::api::{BVNData, BusinessData, ComplianceVerification, DriverLicenseData, LicenseData, LicenseRegistry, NinData, TINData};
 
 
 
-#[derive(Clone, Debug)]
+
+#[derive(Clone)]
 pub struct YouverifyApi {
     pub env: Arc<EnvConfig>,
+    // Youverify doesn't cover the professional councils
+    pub license_registries: Vec<Arc<dyn LicenseRegistry>>,
 }
 
 pub mod params {
@@ -238,14 +242,14 @@ impl YouverifyApi {
 
         if let Some(response) = response {
             if let Some(validation_error) = response.get("message").map(|v| v.as_str()).flatten() {
-                return Ok(Either::Right(ErrorMessage(validation_error.to_string())));
+                return Ok(Either::Right(ErrorMessage::with_message(ErrorCode::ComplianceLookupFailed, validation_error)));
             }
             let status = response.get(YOUVERIFY_STATUS_FIELD)
             .map(|s| s.as_str())
             .flatten()
             .unwrap_or(YOUVERIFY_NOT_FOUND_STATUS);
             if status.eq_ignore_ascii_case(YOUVERIFY_NOT_FOUND_STATUS) {
-                return Ok(Either::Right(ErrorMessage(error_message.to_string())));
+                return Ok(Either::Right(ErrorMessage::with_message(ErrorCode::ComplianceLookupFailed, error_message)));
             }
             let data: T = serde_json::from_value::<T>(response)?;
             return Ok(Either::Left(data));
@@ -475,6 +479,13 @@ info!(message="lookup BVN response", response=?response);
         });
         Ok(account_info)
     }
+
+    async fn lookup_license(&self, license_type: LicenseType, license_no: &str) -> eyre::Result<Option<Either<LicenseData, ErrorMessage>>> {
+        let Some(registry) = self.license_registries.iter().find(|r| r.covers(license_type)) else {
+            return Ok(None);
+        };
+        Ok(Some(registry.lookup_license(license_type, license_no).await?))
+    }
     
 }
 
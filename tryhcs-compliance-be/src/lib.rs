@@ -3,5 +3,13 @@ pub mod repo;
 pub mod app;
 pub mod api;
 pub mod integrations;
+pub mod lookups;
+pub mod name_match;
 pub mod endpoints;
-pub mod params;
\ No newline at end of file
+pub mod params;
+// versions start from 1001, see tryhcs_customers_be::migrator
+pub fn migrator() -> sqlx::migrate::Migrator {
+    let mut migrator = sqlx::migrate!("./migrations");
+    migrator.set_ignore_missing(true);
+    migrator
+}
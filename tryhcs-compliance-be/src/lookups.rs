@@ -0,0 +1,314 @@
+use std::{collections::HashMap, sync::{Arc, Mutex}};
+
+use base64::{prelude::BASE64_URL_SAFE_NO_PAD, prelude::BASE64_STANDARD, Engine};
+use either::Either;
+use eyre::eyre;
+use futures::{future::{BoxFuture, Shared}, FutureExt};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::Value;
+use tryhcs_commons_be::{api_response::ErrorMessage, data_encryption::Encryptor, env::EnvConfig, redis::Cache};
+use tryhcs_shared::{finance_params::BankAccountInfo, institution_params::InstitutionId};
+
+use crate::{api::{BVNData, BusinessData, TINData}, integrations::FallbackVerification, models::NewVerificationUsage, repo::ComplianceRepo};
+
+/// Billed lookups of the identity provider
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IdentityLookup {
+    RcNo(String),
+    Tin(String),
+    Bvn(String),
+    BankAccount { bank_code: String, account_number: String },
+}
+
+impl IdentityLookup {
+    pub fn lookup_type(&self) -> &'static str {
+        match self {
+            IdentityLookup::RcNo(_) => "RC_NO",
+            IdentityLookup::Tin(_) => "TIN",
+            IdentityLookup::Bvn(_) => "BVN",
+            IdentityLookup::BankAccount { .. } => "BANK_ACCOUNT",
+        }
+    }
+
+    fn identifier(&self) -> String {
+        match self {
+            IdentityLookup::RcNo(id) | IdentityLookup::Tin(id) | IdentityLookup::Bvn(id) => id.trim().to_uppercase(),
+            IdentityLookup::BankAccount { bank_code, account_number } => format!("{}:{}", bank_code.trim(), account_number.trim()),
+        }
+    }
+
+    fn ttl_in_hr(&self, env: &EnvConfig) -> u64 {
+        match self {
+            IdentityLookup::RcNo(_) => env.rc_no_lookup_ttl_in_hr,
+            IdentityLookup::Tin(_) => env.tin_lookup_ttl_in_hr,
+            IdentityLookup::Bvn(_) => env.bvn_lookup_ttl_in_hr,
+            IdentityLookup::BankAccount { .. } => env.bank_account_lookup_ttl_in_hr,
+        }
+    }
+
+    async fn run(&self, providers: &FallbackVerification) -> (&'static str, eyre::Result<Either<Value, ErrorMessage>>) {
+        match self {
+            IdentityLookup::RcNo(rc_no) => providers.answered("rc_no", |p| async move { to_value(p.lookup_rc_no(rc_no).await?) }).await,
+            IdentityLookup::Tin(tin) => providers.answered("tin", |p| async move { to_value(p.lookup_tin(tin).await?) }).await,
+            IdentityLookup::Bvn(bvn) => providers.answered("bvn", |p| async move { to_value(p.lookup_bvn(bvn).await?) }).await,
+            IdentityLookup::BankAccount { bank_code, account_number } => providers.answered("bank_account",
+                |p| async move { to_value(p.name_lookup(bank_code, account_number).await?) }).await,
+        }
+    }
+}
+
+fn to_value<T: Serialize>(result: Either<T, ErrorMessage>) -> eyre::Result<Either<Value, ErrorMessage>> {
+    Ok(match result {
+        Either::Left(data) => Either::Left(serde_json::to_value(data)?),
+        Either::Right(error_message) => Either::Right(error_message),
+    })
+}
+
+/// A provider's answer, cached with the name of the provider that gave it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Answer {
+    provider: String,
+    data: Value,
+}
+
+type SharedLookup = Shared<BoxFuture<'static, (String, Result<Either<Value, ErrorMessage>, Arc<eyre::Report>>)>>;
+
+/// Identity lookups through the providers with their results cached, the cached
+/// results are encrypted and keyed by the deterministic encryption of the
+/// identifier. Identical lookups in flight share the provider's answer and
+/// every lookup is written to the institution's usage ledger.
+pub struct IdentityLookups {
+    providers: Arc<FallbackVerification>,
+    redis: Arc<dyn Cache>,
+    // lookups aren't cached without it
+    encryptor: Option<Arc<Encryptor>>,
+    repo: Arc<dyn ComplianceRepo>,
+    env: EnvConfig,
+    in_flight: Arc<Mutex<HashMap<String, SharedLookup>>>,
+}
+
+impl IdentityLookups {
+    pub fn new(providers: Arc<FallbackVerification>, redis: Arc<dyn Cache>, encryptor: Option<Arc<Encryptor>>,
+        repo: Arc<dyn ComplianceRepo>, env: &EnvConfig) -> Self {
+        IdentityLookups {
+            providers,
+            redis,
+            encryptor,
+            repo,
+            env: env.clone(),
+            in_flight: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    pub async fn lookup_rc_no(&self, institution_id: &InstitutionId, rc_no: &str) -> eyre::Result<Either<BusinessData, ErrorMessage>> {
+        self.lookup(institution_id, IdentityLookup::RcNo(rc_no.to_owned())).await
+    }
+
+    pub async fn lookup_tin(&self, institution_id: &InstitutionId, tin: &str) -> eyre::Result<Either<TINData, ErrorMessage>> {
+        self.lookup(institution_id, IdentityLookup::Tin(tin.to_owned())).await
+    }
+
+    pub async fn lookup_bvn(&self, institution_id: &InstitutionId, bvn: &str) -> eyre::Result<Either<BVNData, ErrorMessage>> {
+        self.lookup(institution_id, IdentityLookup::Bvn(bvn.to_owned())).await
+    }
+
+    pub async fn name_lookup(&self, institution_id: &InstitutionId, bank_code: &str, account_number: &str)
+        -> eyre::Result<Either<BankAccountInfo, ErrorMessage>> {
+        self.lookup(institution_id, IdentityLookup::BankAccount { bank_code: bank_code.to_owned(), account_number: account_number.to_owned() }).await
+    }
+
+    async fn lookup<T: DeserializeOwned>(&self, institution_id: &InstitutionId, lookup: IdentityLookup) -> eyre::Result<Either<T, ErrorMessage>> {
+        let lookup_type = lookup.lookup_type();
+        // the plain identifier only keys the in flight lookups, it never reaches the cache
+        let key = match &self.encryptor {
+            Some(encryptor) => format!("IDENTITY-LOOKUP-{}-{}", lookup_type,
+                BASE64_URL_SAFE_NO_PAD.encode(encryptor.set_deterministic(lookup.identifier())?)),
+            None => format!("IDENTITY-LOOKUP-{}-{}", lookup_type, lookup.identifier()),
+        };
+
+        if let Some(cached) = self.cached(&key).await {
+            self.record_usage(institution_id, lookup_type, &cached.provider, "FOUND", false).await;
+            return Ok(Either::Left(serde_json::from_value(cached.data)?));
+        }
+
+        let (shared, leader) = {
+            let mut in_flight = self.in_flight.lock().map_err(|_| eyre!("Identity lookups lock poisoned"))?;
+            match in_flight.get(&key) {
+                Some(shared) => (shared.clone(), false),
+                None => {
+                    let shared = self.shared_lookup(key.clone(), lookup);
+                    in_flight.insert(key.clone(), shared.clone());
+                    (shared, true)
+                },
+            }
+        };
+        let (provider, result) = shared.await;
+
+        let outcome = match &result {
+            Ok(Either::Left(_)) => "FOUND",
+            Ok(Either::Right(_)) => "NOT_FOUND",
+            Err(_) => "FAILED",
+        };
+        self.record_usage(institution_id, lookup_type, &provider, outcome, leader).await;
+
+        match result.map_err(|err| eyre!("{:?}", err))? {
+            Either::Left(data) => Ok(Either::Left(serde_json::from_value(data)?)),
+            Either::Right(error_message) => Ok(Either::Right(error_message)),
+        }
+    }
+
+    // the lookup caches its answer and leaves the in flight lookups itself, so
+    // it's cleaned up even when the caller that started it is dropped
+    fn shared_lookup(&self, key: String, lookup: IdentityLookup) -> SharedLookup {
+        let providers = self.providers.clone();
+        let redis = self.redis.clone();
+        let encryptor = self.encryptor.clone();
+        let in_flight = self.in_flight.clone();
+        let ttl_in_sec = lookup.ttl_in_hr(&self.env) * 60 * 60;
+        async move {
+            let (provider, result) = lookup.run(&providers).await;
+            if let (Ok(Either::Left(data)), Some(encryptor)) = (&result, encryptor) {
+                let answer = Answer { provider: provider.to_owned(), data: data.clone() };
+                if let Err(err) = store(redis.as_ref(), &encryptor, &key, answer, ttl_in_sec).await {
+                    tracing::error!(message = "Failed to cache identity lookup", err=?err);
+                }
+            }
+            if let Ok(mut in_flight) = in_flight.lock() {
+                in_flight.remove(&key);
+            }
+            (provider.to_owned(), result.map_err(Arc::new))
+        }.boxed().shared()
+    }
+
+    // a cache that can't be read is a miss
+    async fn cached(&self, key: &str) -> Option<Answer> {
+        let encryptor = self.encryptor.as_ref()?;
+        let cached = match self.redis.get_key(key).await {
+            Ok(cached) => cached?,
+            Err(err) => {
+                tracing::error!(message = "Failed to read identity lookup cache", err=?err);
+                return None;
+            },
+        };
+        match BASE64_STANDARD.decode(cached).map_err(eyre::Report::from).and_then(|v| encryptor.get_non_deterministic::<Answer>(v)) {
+            Ok(data) => Some(data),
+            Err(err) => {
+                tracing::error!(message = "Failed to decrypt identity lookup cache", err=?err);
+                None
+            },
+        }
+    }
+
+    async fn record_usage(&self, institution_id: &InstitutionId, lookup_type: &str, provider: &str, outcome: &str, billable: bool) {
+        let usage = NewVerificationUsage {
+            institution_id: institution_id.0,
+            lookup_type: lookup_type.to_owned(),
+            provider: provider.to_owned(),
+            outcome: outcome.to_owned(),
+            billable,
+        };
+        if let Err(err) = self.repo.record_verification_usage(&usage).await {
+            tracing::error!(message = "Failed to record verification usage", err=?err);
+        }
+    }
+}
+
+async fn store(redis: &dyn Cache, encryptor: &Encryptor, key: &str, answer: Answer, ttl_in_sec: u64) -> eyre::Result<()> {
+    if ttl_in_sec == 0 {
+        return Ok(());
+    }
+    let encrypted = BASE64_STANDARD.encode(encryptor.set_non_deterministic(answer)?);
+    redis.set_key(key, &encrypted, Some(ttl_in_sec)).await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::{atomic::{AtomicUsize, Ordering}, Arc, Mutex}, time::Duration};
+
+    use either::Either;
+    use eyre::eyre;
+    use tryhcs_commons_be::{data_encryption::Encryptor, env::EnvConfig, redis::{Cache, MemoryCache}};
+    use tryhcs_shared::institution_params::InstitutionId;
+
+    use crate::{api::{MockComplianceVerification, TINData}, integrations::FallbackVerification, repo::MockComplianceRepo};
+
+    use super::IdentityLookups;
+
+    // answers TIN lookups after blocking its worker for a while, counting the calls
+    fn slow_provider(calls: Arc<AtomicUsize>) -> MockComplianceVerification {
+        let mut provider = MockComplianceVerification::new();
+        provider.expect_lookup_tin().returning(move |_| {
+            calls.fetch_add(1, Ordering::SeqCst);
+            std::thread::sleep(Duration::from_millis(50));
+            Ok(Either::Left(TINData { name: "ST. MARY CLINIC LIMITED".into(), tax_office: None }))
+        });
+        provider
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn coalesces_and_caches_lookups() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let providers = Arc::new(FallbackVerification { providers: vec![("test", Arc::new(slow_provider(calls.clone())))] });
+        let cache = Arc::new(MemoryCache::default());
+        let billable = Arc::new(AtomicUsize::new(0));
+        let mut repo = MockComplianceRepo::new();
+        let counter = billable.clone();
+        repo.expect_record_verification_usage().times(4).returning(move |usage| {
+            assert_eq!(usage.provider, "test");
+            if usage.billable {
+                counter.fetch_add(1, Ordering::SeqCst);
+            }
+            Ok(())
+        });
+
+        let env = EnvConfig { tin_lookup_ttl_in_hr: 1, ..Default::default() };
+        let lookups = Arc::new(IdentityLookups::new(providers, cache.clone(), Some(Arc::new(Encryptor::generate())), Arc::new(repo), &env));
+        let institution_id = InstitutionId(1);
+
+        let tasks: Vec<_> = ["12345678-0001", "12345678-0001", " 12345678-0001 "].into_iter().map(|tin| {
+            let lookups = lookups.clone();
+            tokio::spawn(async move { lookups.lookup_tin(&InstitutionId(1), tin).await })
+        }).collect();
+        for task in tasks {
+            assert_eq!(task.await.unwrap().unwrap().unwrap_left().name, "ST. MARY CLINIC LIMITED");
+        }
+        let cached = lookups.lookup_tin(&institution_id, "12345678-0001").await.unwrap().unwrap_left();
+        assert_eq!(cached.name, "ST. MARY CLINIC LIMITED");
+
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        assert_eq!(billable.load(Ordering::SeqCst), 1);
+        assert!(lookups.in_flight.lock().unwrap().is_empty());
+        // only the encrypted result is cached
+        let keys = cache.keys();
+        assert_eq!(keys.len(), 1);
+        let stored = cache.get_key(&keys[0]).await.unwrap().unwrap();
+        assert!(!stored.contains("CLINIC"));
+    }
+
+    #[tokio::test]
+    async fn records_the_provider_that_answered() {
+        let mut primary = MockComplianceVerification::new();
+        primary.expect_lookup_tin().returning(|_| Err(eyre!("connection reset")));
+        let calls = Arc::new(AtomicUsize::new(0));
+        let providers = Arc::new(FallbackVerification {
+            providers: vec![("primary", Arc::new(primary)), ("fallback", Arc::new(slow_provider(calls)))],
+        });
+        let recorded = Arc::new(Mutex::new(vec![]));
+        let mut repo = MockComplianceRepo::new();
+        let usages = recorded.clone();
+        repo.expect_record_verification_usage().returning(move |usage| {
+            usages.lock().unwrap().push((usage.provider.clone(), usage.billable));
+            Ok(())
+        });
+
+        let env = EnvConfig { tin_lookup_ttl_in_hr: 1, ..Default::default() };
+        let lookups = IdentityLookups::new(providers, Arc::new(MemoryCache::default()), Some(Arc::new(Encryptor::generate())),
+            Arc::new(repo), &env);
+        let institution_id = InstitutionId(1);
+        lookups.lookup_tin(&institution_id, "12345678-0001").await.unwrap();
+        // a cached answer keeps the provider that gave it
+        lookups.lookup_tin(&institution_id, "12345678-0001").await.unwrap();
+
+        assert_eq!(*recorded.lock().unwrap(), vec![("fallback".to_owned(), true), ("fallback".to_owned(), false)]);
+    }
+}
@@ -1,8 +1,9 @@
 use bon::Builder;
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, NaiveDate, Utc};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
-use tryhcs_shared::compliance_params::{CorporateComplianceDto, FinancialComplianceDto, HealthcareComplianceDto};
+use std::str::FromStr;
+use tryhcs_shared::compliance_params::{ComplianceActorType, ComplianceFieldChange, ComplianceHistoryAction, ComplianceHistoryDto, ComplianceQueueItemDto, ComplianceReviewDto, ComplianceSection, ComplianceStatus, CorporateComplianceDto, FinancialComplianceDto, HealthcareComplianceDto, LicenseType, VerificationUsageDto};
 use uuid::Uuid;
 // use tryhcs_derive::{declare_db_columns, query_many, query_one};
 
@@ -23,22 +24,40 @@ pub struct CorporateCompliance {
     
     pub stage: String,
     pub created_by: String,
+    pub name_matches: Value,
 
     pub created_at: DateTime<Utc>,
     pub modified_at: Option<DateTime<Utc>>,
     pub deleted_at: Option<DateTime<Utc>>,
 }
 
-impl From<CorporateCompliance> for CorporateComplianceDto  {
-    fn from(value: CorporateCompliance) -> Self {
-        CorporateComplianceDto {
+impl CorporateCompliance {
+    /// (field, value) pairs diffed into the compliance history
+    pub fn history_fields(&self) -> Vec<(&'static str, Option<String>)> {
+        vec![
+            ("rc_no", Some(self.rc_no.clone())),
+            ("tin", Some(self.tin.clone())),
+            ("corporate_account_number", Some(self.corporate_account_number.clone())),
+            ("corporate_bank_code", Some(self.corporate_bank_code.clone())),
+            ("private_healthcare_certificate_url", self.private_healthcare_certificate_url.clone()),
+            ("stage", Some(self.stage.clone())),
+        ]
+    }
+}
+
+impl TryFrom<CorporateCompliance> for CorporateComplianceDto  {
+    type Error = eyre::Error;
+
+    fn try_from(value: CorporateCompliance) -> eyre::Result<Self> {
+        Ok(CorporateComplianceDto {
             rc_no: value.rc_no,
             tin: value.tin,
             corporate_account_number: value.corporate_account_number,
             corporate_bank_code: value.corporate_bank_code,
             private_healthcare_certificate_url: value.private_healthcare_certificate_url,
             stage: value.stage,
-        }
+            name_matches: serde_json::from_value(value.name_matches)?,
+        })
     }
 }
 
@@ -66,6 +85,20 @@ pub struct HealthcareCompliance {
     pub deleted_at: Option<DateTime<Utc>>,
 }
 
+impl HealthcareCompliance {
+    pub fn history_fields(&self) -> Vec<(&'static str, Option<String>)> {
+        vec![
+            ("licensed_medical_doctor_name", Some(self.licensed_medical_doctor_name.clone())),
+            ("licensed_medical_doctor_mdcn_no", Some(self.licensed_medical_doctor_mdcn_no.clone())),
+            ("licensed_medical_doctor_mdcn_speciality", Some(self.licensed_medical_doctor_mdcn_speciality.clone())),
+            ("licensed_medical_doctor_mdcn_image_url", Some(self.licensed_medical_doctor_mdcn_image_url.clone())),
+            ("licensed_medical_doctor_email", Some(self.licensed_medical_doctor_email.clone())),
+            ("licensed_medical_doctor_phone_no", Some(self.licensed_medical_doctor_phone_no.clone())),
+            ("stage", Some(self.stage.clone())),
+        ]
+    }
+}
+
 impl From<HealthcareCompliance> for HealthcareComplianceDto  {
     fn from(value: HealthcareCompliance) -> Self {
         HealthcareComplianceDto {
@@ -98,22 +131,39 @@ pub struct FinancialCompliance {
 
     pub stage: String,
     pub created_by: String,
+    pub name_matches: Value,
 
     pub created_at: DateTime<Utc>,
     pub modified_at: Option<DateTime<Utc>>,
     pub deleted_at: Option<DateTime<Utc>>,
 }
 
-impl From<FinancialCompliance> for FinancialComplianceDto  {
-    fn from(value: FinancialCompliance) -> Self {
-        FinancialComplianceDto {
+impl FinancialCompliance {
+    pub fn history_fields(&self) -> Vec<(&'static str, Option<String>)> {
+        vec![
+            ("director_legal_name", Some(self.director_legal_name.clone())),
+            ("director_legal_bvn", Some(self.director_legal_bvn.clone())),
+            ("director_legal_dob", Some(self.director_legal_dob.clone())),
+            ("director_legal_gov_id_type", Some(self.director_legal_gov_id_type.clone())),
+            ("director_legal_gov_id_url", Some(self.director_legal_gov_id_url.clone())),
+            ("stage", Some(self.stage.clone())),
+        ]
+    }
+}
+
+impl TryFrom<FinancialCompliance> for FinancialComplianceDto  {
+    type Error = eyre::Error;
+
+    fn try_from(value: FinancialCompliance) -> eyre::Result<Self> {
+        Ok(FinancialComplianceDto {
             director_legal_name: value.director_legal_name,
             director_legal_bvn: value.director_legal_bvn,
             director_legal_dob: value.director_legal_dob,
             director_legal_gov_id_type: value.director_legal_gov_id_type,
             director_legal_gov_id_url: value.director_legal_gov_id_url,
             stage: value.stage,
-        }
+            name_matches: serde_json::from_value(value.name_matches)?,
+        })
     }
 }
 
@@ -129,11 +179,280 @@ pub struct StaffCompliance {
     pub license_type: String,
     pub license_no: Option<String>,
     pub license_certificate_url: Option<String>,
+    pub expires_at: Option<NaiveDate>,
+    pub registry_lookup: Option<Value>,
+    pub expiry_reminded_at: Option<DateTime<Utc>>,
 
     pub stage: String,
     pub created_by: String,
 
+    pub shadow_id: String,
     pub created_at: DateTime<Utc>,
     pub modified_at: Option<DateTime<Utc>>,
     pub deleted_at: Option<DateTime<Utc>>,
-}
\ No newline at end of file
+}
+
+impl StaffCompliance {
+    pub fn history_fields(&self) -> Vec<(&'static str, Option<String>)> {
+        vec![
+            ("license_type", Some(self.license_type.clone())),
+            ("license_no", self.license_no.clone()),
+            ("license_certificate_url", self.license_certificate_url.clone()),
+            ("expires_at", self.expires_at.map(|d| d.to_string())),
+            ("stage", Some(self.stage.clone())),
+        ]
+    }
+}
+
+pub struct NewStaffLicense {
+    pub license_type: LicenseType,
+    pub license_no: String,
+    pub license_certificate_url: String,
+    pub expires_at: NaiveDate,
+    pub registry_lookup: Option<Value>,
+    pub stage: ComplianceStatus,
+    pub created_by: String,
+}
+
+// the staff columns of the license board
+#[derive(Serialize, Deserialize, Debug, Clone, sqlx::FromRow)]
+pub struct LicenseStaff {
+    pub id: i64,
+    pub shadow_id: String,
+    pub first_name: String,
+    pub last_name: String,
+    pub title: String,
+    pub mobile: String,
+}
+
+// license due an expiry reminder with the staff to remind
+#[derive(Serialize, Deserialize, Debug, Clone, sqlx::FromRow)]
+pub struct ExpiringLicense {
+    pub id: i64,
+    pub license_type: String,
+    pub expires_at: NaiveDate,
+    pub first_name: String,
+    pub mobile: String,
+    pub institution_name: String,
+}
+pub const COMPLIANCE_REVIEWER_ROLE: &str = "COMPLIANCE_REVIEWER";
+
+#[derive(Serialize, Deserialize, Debug, Clone, sqlx::FromRow)]
+pub struct PlatformStaff {
+    pub id: i64,
+    pub mobile: String,
+    pub first_name: String,
+    pub last_name: String,
+    pub role: String,
+    pub shadow_id: String,
+    pub deleted_at: Option<DateTime<Utc>>,
+    pub modified_at: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+}
+
+// the institution columns compliance reviews need
+#[derive(Serialize, Deserialize, Debug, Clone, sqlx::FromRow)]
+pub struct ReviewInstitution {
+    pub id: i64,
+    pub shadow_id: String,
+    pub name: String,
+    pub email: String,
+    pub compliance_status: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, sqlx::FromRow)]
+pub struct ComplianceQueueItem {
+    pub institution_id: String,
+    pub institution_name: String,
+    pub institution_email: String,
+    pub compliance_status: String,
+    pub corporate_stage: Option<String>,
+    pub financial_stage: Option<String>,
+    pub healthcare_stage: Option<String>,
+    pub submitted_at: Option<DateTime<Utc>>,
+    pub name_match_review: bool,
+}
+
+impl From<ComplianceQueueItem> for ComplianceQueueItemDto {
+    fn from(value: ComplianceQueueItem) -> Self {
+        ComplianceQueueItemDto {
+            institution_id: value.institution_id,
+            institution_name: value.institution_name,
+            institution_email: value.institution_email,
+            compliance_status: value.compliance_status,
+            corporate_stage: value.corporate_stage,
+            financial_stage: value.financial_stage,
+            healthcare_stage: value.healthcare_stage,
+            submitted_at: value.submitted_at,
+            name_match_review: value.name_match_review,
+        }
+    }
+}
+
+// review with the name of the reviewer
+#[derive(Serialize, Deserialize, Debug, Clone, sqlx::FromRow)]
+pub struct ComplianceReview {
+    pub id: i64,
+    pub institution_id: i64,
+    pub section: String,
+    pub decision: String,
+    pub reasons: Value,
+    pub reviewed_by: i64,
+    pub reviewer_first_name: String,
+    pub reviewer_last_name: String,
+    pub shadow_id: String,
+    pub created_at: DateTime<Utc>,
+}
+
+impl TryFrom<ComplianceReview> for ComplianceReviewDto {
+    type Error = eyre::Error;
+
+    fn try_from(value: ComplianceReview) -> eyre::Result<Self> {
+        Ok(ComplianceReviewDto {
+            id: value.shadow_id,
+            section: ComplianceSection::parse(&value.section)
+                .ok_or_else(|| eyre::eyre!("Unknown compliance section: {}", value.section))?,
+            decision: ComplianceStatus::from_str(&value.decision)?,
+            reasons: serde_json::from_value(value.reasons)?,
+            reviewed_by: format!("{} {}", value.reviewer_first_name, value.reviewer_last_name),
+            created_at: value.created_at,
+        })
+    }
+}
+
+pub struct NewComplianceHistory {
+    pub institution_id: i64,
+    pub section: ComplianceSection,
+    pub action: ComplianceHistoryAction,
+    pub actor: String,
+    pub actor_type: ComplianceActorType,
+    pub changes: Vec<ComplianceFieldChange>,
+    pub lookup: Option<Value>,
+}
+
+/// Who changed a compliance section and the lookup the change was verified with,
+/// the repo records it in the history along with the change
+#[derive(Debug, Clone)]
+pub struct ComplianceChange {
+    pub actor: String,
+    pub actor_type: ComplianceActorType,
+    pub lookup: Option<Value>,
+}
+
+/// Fields whose value differs, every field of `after` when `before` is empty.
+/// Bank and identity numbers are masked after comparing so a change the mask hides is still recorded.
+pub fn field_changes(before: &[(&'static str, Option<String>)], after: &[(&'static str, Option<String>)]) -> Vec<ComplianceFieldChange> {
+    let mask = |field: &str, value: Option<String>| value.map(|v| match field {
+        "director_legal_bvn" | "corporate_account_number" => mask_identifier(&v),
+        "director_legal_dob" => mask_date(&v),
+        _ => v,
+    });
+    after.iter()
+        .filter_map(|(field, to)| {
+            let from = before.iter().find(|(f, _)| f == field).and_then(|(_, v)| v.clone());
+            (from != *to).then(|| ComplianceFieldChange { field: (*field).to_owned(), from: mask(field, from), to: mask(field, to.clone()) })
+        })
+        .collect()
+}
+
+/// Masks all but the last four characters, short values entirely
+pub fn mask_identifier(value: &str) -> String {
+    let chars: Vec<char> = value.chars().collect();
+    let masked = if chars.len() > 4 { chars.len() - 4 } else { chars.len() };
+    chars.iter().enumerate().map(|(i, c)| if i < masked { '*' } else { *c }).collect()
+}
+
+/// Masks every digit of a date, leaving its format
+pub fn mask_date(date: &str) -> String {
+    date.chars().map(|c| if c.is_ascii_digit() { '*' } else { c }).collect()
+}
+
+/// Keeps the initial of each part of a name
+pub fn mask_name(name: &str) -> String {
+    name.split_whitespace()
+        .map(|part| part.chars().enumerate().map(|(i, c)| if i == 0 { c } else { '*' }).collect::<String>())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, sqlx::FromRow)]
+pub struct ComplianceHistory {
+    pub id: i64,
+    pub institution_id: i64,
+    pub section: String,
+    pub action: String,
+    pub actor: String,
+    pub actor_type: String,
+    pub changes: Value,
+    pub lookup: Option<Value>,
+    pub shadow_id: String,
+    pub created_at: DateTime<Utc>,
+}
+
+impl TryFrom<ComplianceHistory> for ComplianceHistoryDto {
+    type Error = eyre::Error;
+
+    fn try_from(value: ComplianceHistory) -> eyre::Result<Self> {
+        Ok(ComplianceHistoryDto {
+            id: value.shadow_id,
+            section: ComplianceSection::parse(&value.section)
+                .ok_or_else(|| eyre::eyre!("Unknown compliance section: {}", value.section))?,
+            action: ComplianceHistoryAction::parse(&value.action)
+                .ok_or_else(|| eyre::eyre!("Unknown compliance history action: {}", value.action))?,
+            actor: value.actor,
+            actor_type: ComplianceActorType::parse(&value.actor_type)
+                .ok_or_else(|| eyre::eyre!("Unknown compliance actor: {}", value.actor_type))?,
+            changes: serde_json::from_value(value.changes)?,
+            lookup: value.lookup,
+            created_at: value.created_at,
+        })
+    }
+}
+
+pub struct NewVerificationUsage {
+    pub institution_id: i64,
+    pub lookup_type: String,
+    pub provider: String,
+    pub outcome: String,
+    pub billable: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, sqlx::FromRow)]
+pub struct VerificationUsage {
+    pub month: String,
+    pub lookup_type: String,
+    pub lookups: i64,
+    pub billable: i64,
+}
+
+impl From<VerificationUsage> for VerificationUsageDto {
+    fn from(value: VerificationUsage) -> Self {
+        VerificationUsageDto {
+            month: value.month,
+            lookup_type: value.lookup_type,
+            lookups: value.lookups,
+            billable: value.billable,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{field_changes, mask_name};
+
+    #[test]
+    fn masks_identity_numbers_in_history() {
+        let before = [("director_legal_bvn", Some("22212345678".to_owned())), ("director_legal_dob", Some("1980-04-12".to_owned()))];
+        let after = [("director_legal_bvn", Some("22298765678".to_owned())), ("director_legal_dob", Some("1980-04-12".to_owned()))];
+
+        // only the last four digits are kept, yet the change behind them is recorded
+        let changes = field_changes(&before, &after);
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].from.as_deref(), Some("*******5678"));
+        assert_eq!(changes[0].to.as_deref(), Some("*******5678"));
+
+        let changes = field_changes(&[], &after);
+        assert_eq!(changes[1].to.as_deref(), Some("****-**-**"));
+        assert_eq!(mask_name("Adaeze Chioma"), "A***** C*****");
+    }
+}
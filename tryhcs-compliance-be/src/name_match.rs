@@ -0,0 +1,201 @@
+use eyre::eyre;
+use strsim::jaro_winkler;
+use tryhcs_commons_be::env::EnvConfig;
+use tryhcs_shared::compliance_params::{NameMatchDto, NameMatchOutcome};
+use unicode_normalization::{char::is_combining_mark, UnicodeNormalization};
+
+// registries and banks add or drop these freely, they say nothing about who the name belongs to
+const IGNORED_WORDS: &[&str] = &["limited", "ltd", "plc", "llc", "inc", "nig", "nigeria", "co", "company", "and", "the",
+    "mr", "mrs", "ms", "miss", "dr", "chief", "alhaji", "alhaja"];
+
+// tokens less alike than this are treated as different words
+const MIN_TOKEN_SIMILARITY: f64 = 0.85;
+
+/// Fuzzy matching of the names identity lookups return against each other and
+/// the names submitted with the compliance sections. Word order, accents, case,
+/// punctuation and initials standing for a name don't lower the score.
+#[derive(Debug, Clone, Copy)]
+pub struct NameMatcher {
+    review_threshold: u8,
+    block_threshold: u8,
+}
+
+// the score with the words of the longer name and how many of them were
+// matched by a full word rather than an initial
+struct Graded {
+    score: u8,
+    words: usize,
+    full_words: usize,
+}
+
+impl NameMatcher {
+    /// Fails unless the thresholds are at most 100 and the block threshold
+    /// isn't above the review threshold
+    pub fn new(review_threshold: u8, block_threshold: u8) -> eyre::Result<Self> {
+        if review_threshold > 100 || block_threshold > review_threshold {
+            return Err(eyre!("name_match_block_threshold ({}) must be at most name_match_review_threshold ({}), which must be at most 100",
+                block_threshold, review_threshold));
+        }
+        Ok(NameMatcher { review_threshold, block_threshold })
+    }
+
+    pub fn from_env(env: &EnvConfig) -> eyre::Result<Self> {
+        Self::new(env.name_match_review_threshold, env.name_match_block_threshold)
+    }
+
+    /// 100 when both names have the same words, 0 when no word matches
+    pub fn score(&self, name: &str, other: &str) -> u8 {
+        self.grade(name, other).score
+    }
+
+    fn grade(&self, name: &str, other: &str) -> Graded {
+        let (mut fewer, more) = {
+            let (a, b) = (tokens(name), tokens(other));
+            if a.len() <= b.len() { (a, b) } else { (b, a) }
+        };
+        if fewer.is_empty() {
+            return Graded { score: 0, words: more.len(), full_words: 0 };
+        }
+
+        // full words are paired first so initials don't take their match
+        fewer.sort_by_key(|t| std::cmp::Reverse(t.chars().count()));
+        let mut unpaired: Vec<&str> = more.iter().map(|t| t.as_str()).collect();
+        let mut total = 0.0;
+        let mut full_words = 0;
+        for token in &fewer {
+            let best = unpaired.iter().enumerate()
+                .map(|(i, other)| (i, token_similarity(token, other)))
+                .max_by(|(_, a), (_, b)| a.total_cmp(b));
+            if let Some((i, similarity)) = best.filter(|(_, similarity)| *similarity > 0.0) {
+                total += similarity;
+                if token.chars().count() > 1 && unpaired[i].chars().count() > 1 {
+                    full_words += 1;
+                }
+                unpaired.remove(i);
+            }
+        }
+
+        // words missing from the shorter name, e.g. a middle name, cost a little
+        let paired = total / fewer.len() as f64;
+        let coverage = fewer.len() as f64 / more.len() as f64;
+        Graded { score: ((0.8 * paired + 0.2 * coverage) * 100.0).round() as u8, words: more.len(), full_words }
+    }
+
+    pub fn outcome(&self, score: u8) -> NameMatchOutcome {
+        if score >= self.review_threshold {
+            NameMatchOutcome::Matched
+        } else if score >= self.block_threshold {
+            NameMatchOutcome::Review
+        } else {
+            NameMatchOutcome::Blocked
+        }
+    }
+
+    /// Evidence of the name looked up for `field` against the `against` name.
+    /// A single word like a first name or only initials can score high against
+    /// a full name, so a match needs two words matched in full and a name
+    /// matched only by initials is blocked.
+    pub fn compare(&self, (field, name): (&str, &str), (against, against_name): (&str, &str)) -> NameMatchDto {
+        let graded = self.grade(name, against_name);
+        let outcome = match self.outcome(graded.score) {
+            _ if graded.full_words == 0 => NameMatchOutcome::Blocked,
+            NameMatchOutcome::Matched if graded.full_words < graded.words.min(2) => NameMatchOutcome::Review,
+            outcome => outcome,
+        };
+        NameMatchDto {
+            field: field.to_owned(),
+            name: name.to_owned(),
+            against: against.to_owned(),
+            against_name: against_name.to_owned(),
+            score: graded.score,
+            outcome,
+        }
+    }
+}
+
+/// lowercase words without accents or punctuation, ignored words left out
+fn tokens(name: &str) -> Vec<String> {
+    let folded: String = name.nfd()
+        .filter(|c| !is_combining_mark(*c) && *c != '\'' && *c != '’')
+        .flat_map(char::to_lowercase)
+        .map(|c| if c.is_alphanumeric() { c } else { ' ' })
+        .collect();
+    folded.split_whitespace()
+        .filter(|t| !IGNORED_WORDS.contains(t))
+        .map(|t| t.to_owned())
+        .collect()
+}
+
+fn token_similarity(token: &str, other: &str) -> f64 {
+    if token == other {
+        return 1.0;
+    }
+    // an initial stands for any word it starts
+    if token.chars().count() == 1 || other.chars().count() == 1 {
+        let initial = |t: &str| t.chars().next();
+        return if initial(token) == initial(other) { 0.9 } else { 0.0 };
+    }
+    let similarity = jaro_winkler(token, other);
+    if similarity >= MIN_TOKEN_SIMILARITY { similarity } else { 0.0 }
+}
+
+#[cfg(test)]
+mod tests {
+    use tryhcs_shared::compliance_params::NameMatchOutcome;
+
+    use super::NameMatcher;
+
+    #[test]
+    fn scores_name_variants() {
+        let matcher = NameMatcher::new(85, 60).unwrap();
+
+        assert_eq!(matcher.score("OBI ADAEZE CHIOMA", "Adaeze Chioma Obi"), 100);
+        assert_eq!(matcher.score("Ọláwálé Adébáyọ̀", "OLAWALE ADEBAYO"), 100);
+        assert_eq!(matcher.score("ST. MARY'S CLINIC LIMITED", "St Marys Clinic"), 100);
+        assert!(matcher.score("Adaeze C. Obi", "Adaeze Chioma Obi") >= 95);
+        // a middle name left out
+        assert!(matcher.score("Adaeze Obi", "Adaeze Chioma Obi") >= 90);
+        assert!(matcher.score("Adaeze Obie", "Adaeze Obi") >= 85);
+
+        assert!(matcher.score("Musa Ibrahim", "Adaeze Obi") < 60);
+        assert_eq!(matcher.score("Ltd", "Adaeze Obi"), 0);
+    }
+
+    #[test]
+    fn grades_scores_by_threshold() {
+        let matcher = NameMatcher::new(85, 60).unwrap();
+
+        let matched = matcher.compare(("tin", "ST. MARY CLINIC LTD"), ("rc_no", "St Mary Clinic Limited"));
+        assert_eq!((matched.score, matched.outcome), (100, NameMatchOutcome::Matched));
+
+        let review = matcher.compare(("director_legal_bvn", "Adaora Obi"), ("director_legal_name", "Adaeze Obi"));
+        assert_eq!(review.outcome, NameMatchOutcome::Review);
+
+        let blocked = matcher.compare(("director_legal_bvn", "Musa Ibrahim"), ("director_legal_name", "Adaeze Obi"));
+        assert_eq!(blocked.outcome, NameMatchOutcome::Blocked);
+    }
+
+    #[test]
+    fn needs_two_full_words_to_match_a_full_name() {
+        let matcher = NameMatcher::new(85, 60).unwrap();
+        let outcome = |name: &str| matcher.compare(("director_legal_bvn", "Adaeze Chioma Obi"), ("director_legal_name", name)).outcome;
+
+        assert_eq!(outcome("Adaeze"), NameMatchOutcome::Review);
+        assert_eq!(outcome("Obi"), NameMatchOutcome::Review);
+        assert_eq!(outcome("A"), NameMatchOutcome::Blocked);
+        assert_eq!(outcome("A. C. O."), NameMatchOutcome::Blocked);
+        assert_eq!(outcome("Adaeze Obi"), NameMatchOutcome::Matched);
+        assert_eq!(outcome("Obi A. C."), NameMatchOutcome::Review);
+        // a one word name matched in full
+        let single = matcher.compare(("tin", "MEDPLUS LTD"), ("rc_no", "Medplus Limited"));
+        assert_eq!(single.outcome, NameMatchOutcome::Matched);
+    }
+
+    #[test]
+    fn rejects_thresholds_out_of_order() {
+        assert!(NameMatcher::new(101, 60).is_err());
+        assert!(NameMatcher::new(60, 85).is_err());
+        assert!(NameMatcher::new(85, 85).is_ok());
+        assert!(NameMatcher::new(100, 0).is_ok());
+    }
+}
@@ -5,7 +5,10 @@ use eyre::{Context, Result};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use tryhcs_commons_be::{
-    auth::{InstitutionAdminUser, TypeAuthenticated},
+    api_response::convert_error_to_json_response,
+    auth::{is_workspace_deactivated, InstitutionAdminUser, TypeAuthenticated},
+    session::{find_session, touch_session},
+    utils::mask_phone,
     AUTH_ID_HEADER_FIELD, WORKSPACE_CODE_HEADER_FIELD,
 };
 
@@ -14,14 +17,32 @@ use std::sync::Arc;
 
 use axum::{extract::FromRequestParts, http::request::Parts, Json};
 use reqwest::StatusCode;
-use serde_json::json;
 
 use tryhcs_shared::{
-    api_params::ErrorMessage,
-    institution_params::{AuthenticatedUser, AuthorizedInstitutionUser},
+    api_params::ErrorCode,
+    institution_params::AuthorizedInstitutionUser,
 };
 
-use crate::app::ComplianceApp;
+use crate::{app::ComplianceApp, models::{PlatformStaff, COMPLIANCE_REVIEWER_ROLE}};
+
+fn reject(code: ErrorCode) -> (StatusCode, Json<Value>) {
+    convert_error_to_json_response(code.into())
+}
+
+async fn check_workspace_active(
+    state: &ComplianceApp,
+    workspace_code: &str,
+) -> Result<(), (StatusCode, Json<Value>)> {
+    let deactivated_in_db = state.compliance_repo.is_workspace_deactivated(workspace_code);
+    match is_workspace_deactivated(state.redis.as_ref(), workspace_code, deactivated_in_db).await {
+        Ok(false) => Ok(()),
+        Ok(true) => Err(reject(ErrorCode::InstitutionDeactivated)),
+        Err(err) => {
+            tracing::error!(message="Workspace status error", err=?err);
+            Err(reject(ErrorCode::InternalError))
+        }
+    }
+}
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub(crate)  struct WorkspaceAdmin(pub InstitutionAdminUser);
@@ -33,11 +54,7 @@ impl FromRequestParts<Arc<ComplianceApp>> for WorkspaceAdmin {
         req: &mut Parts,
         state: &Arc<ComplianceApp>,
     ) -> Result<Self, Self::Rejection> {
-        let status = StatusCode::FORBIDDEN;
-        let status_code: u16 = status.as_u16();
-
         // Manually extract session_id and workspace_code from headers
-        let error_message = "Invalid session";
         let session_id = {
             let header_value = req
                 .headers
@@ -47,22 +64,12 @@ impl FromRequestParts<Arc<ComplianceApp>> for WorkspaceAdmin {
 
             match header_value {
                 None => {
-                    return Err((
-                        StatusCode::UNAUTHORIZED,
-                        Json(
-                            json!({"message": error_message, "status_code": StatusCode::UNAUTHORIZED.as_u16()}),
-                        ),
-                    ));
+                    return Err(reject(ErrorCode::AuthInvalidSession));
                 }
                 Some(header_value) => {
                     let split = header_value.split(" ").into_iter().collect::<Vec<_>>();
                     if split.len() != 2 {
-                        return Err((
-                            StatusCode::UNAUTHORIZED,
-                            Json(
-                                json!({"message": error_message, "status_code": StatusCode::UNAUTHORIZED.as_u16()}),
-                            ),
-                        ));
+                        return Err(reject(ErrorCode::AuthInvalidSession));
                     }
 
                     if split
@@ -70,12 +77,7 @@ impl FromRequestParts<Arc<ComplianceApp>> for WorkspaceAdmin {
                         .map(|v| !v.eq_ignore_ascii_case("Bearer"))
                         .unwrap_or(true)
                     {
-                        return Err((
-                            StatusCode::UNAUTHORIZED,
-                            Json(
-                                json!({"message": error_message, "status_code": StatusCode::UNAUTHORIZED.as_u16()}),
-                            ),
-                        ));
+                        return Err(reject(ErrorCode::AuthInvalidSession));
                     }
 
                     split.get(1).unwrap_or(&"").to_owned()
@@ -83,75 +85,58 @@ impl FromRequestParts<Arc<ComplianceApp>> for WorkspaceAdmin {
             }
         };
 
-        let workspace_code = {
-            let header_value = req
-                .headers
-                .get(WORKSPACE_CODE_HEADER_FIELD)
-                .map(|v| v.to_str().ok())
-                .flatten();
-
-            match header_value {
-                None => {
-                    return Err((
-                        StatusCode::FORBIDDEN,
-                        Json(
-                            json!({"message": "Workpace code is required!", "status_code": StatusCode::FORBIDDEN.as_u16()}),
-                        ),
-                    ));
-                }
-                Some(header_value) => header_value.to_owned(),
-            }
-        };
+        // falls back to the workspace the session was switched to
+        let workspace_code = req
+            .headers
+            .get(WORKSPACE_CODE_HEADER_FIELD)
+            .map(|v| v.to_str().ok())
+            .flatten()
+            .map(|v| v.to_owned());
 
-        let cached_session = state.redis.get_key(&session_id).await;
-        let cached_session = cached_session.map(|c| {
-            c.map(|v| serde_json::from_str::<AuthenticatedUser>(&v).ok())
-                .flatten()
-        });
+        let cached_session = find_session(state.redis.as_ref(), session_id).await;
 
         let user = match cached_session {
             Err(err) => {
                 tracing::error!(message="Get session error", err=?err);
-                return Err((
-                    StatusCode::UNAUTHORIZED,
-                    Json(
-                        json!({"message": error_message, "status_code": StatusCode::UNAUTHORIZED.as_u16()}),
-                    ),
-                ));
+                return Err(reject(ErrorCode::AuthInvalidSession));
             }
             Ok(cached_session) => match cached_session {
                 None => {
                     tracing::error!(message = "Cache session not found");
-                    return Err((
-                        StatusCode::UNAUTHORIZED,
-                        Json(
-                            json!({"message": error_message, "status_code": StatusCode::UNAUTHORIZED.as_u16()}),
-                        ),
-                    ));
+                    return Err(reject(ErrorCode::AuthInvalidSession));
                 }
-                Some(auth_user) => {
+                Some(session) => {
+                    let workspace_code = match workspace_code
+                        .or(session.info.workspace_code.clone())
+                    {
+                        None => {
+                            return Err(reject(ErrorCode::AuthWorkspaceRequired));
+                        }
+                        Some(workspace_code) => workspace_code,
+                    };
+                    check_workspace_active(state, &workspace_code).await?;
                     match AuthorizedInstitutionUser::from_authorized_user(
-                        auth_user.principal,
+                        session.user.principal.clone(),
                         &workspace_code,
                     ) {
                         Err(err) => {
                             tracing::error!(message="Error serializing session", err=?err);
-                            return Err((
-                                StatusCode::UNAUTHORIZED,
-                                Json(
-                                    json!({"message": error_message, "status_code": StatusCode::UNAUTHORIZED.as_u16()}),
-                                ),
-                            ));
+                            return Err(reject(ErrorCode::AuthInvalidSession));
                         }
                         Ok(institution_user) => match institution_user {
-                            Either::Right((status, message)) => {
-                                let status_code = status.as_u16();
-                                return Err((
-                                    status,
-                                    Json(json!({"message": message, "status_code": status_code})),
-                                ));
+                            Either::Right(error) => {
+                                return Err(convert_error_to_json_response(error));
+                            }
+                            Either::Left(data) => {
+                                touch_session(
+                                    state.redis.as_ref(),
+                                    &session,
+                                    Some(&workspace_code),
+                                    &state.env,
+                                )
+                                .await;
+                                data
                             }
-                            Either::Left(data) => data,
                         },
                     }
                 }
@@ -159,12 +144,9 @@ impl FromRequestParts<Arc<ComplianceApp>> for WorkspaceAdmin {
         };
 
         match InstitutionAdminUser::new(user).await {
-            Err(ErrorMessage(message)) => {
-                tracing::error!("Error extracting user into admin; {}", &message);
-                return Err((
-                    status,
-                    Json(json!({"message": message, "status_code": status_code})),
-                ));
+            Err(error) => {
+                tracing::error!("Error extracting user into admin; {}", &error.message);
+                return Err(convert_error_to_json_response(error));
             }
             Ok(user) => {
                 return Ok(WorkspaceAdmin(user));
@@ -183,16 +165,6 @@ impl FromRequestParts<Arc<ComplianceApp>> for WorkspaceUser {
         req: &mut Parts,
         state: &Arc<ComplianceApp>,
     ) -> Result<Self, Self::Rejection> {
-        let wrap_error = |status: StatusCode, message: &str| {
-            let status_code = status.as_u16();
-            (
-                status,
-                Json(json!({"message": message,
-                 "status_code": status_code})),
-            )
-        };
-
-        let error_message = "Invalid session";
         let session_id = {
             let header_value = req
                 .headers
@@ -202,12 +174,12 @@ impl FromRequestParts<Arc<ComplianceApp>> for WorkspaceUser {
 
             match header_value {
                 None => {
-                    return Err(wrap_error(StatusCode::UNAUTHORIZED, error_message));
+                    return Err(reject(ErrorCode::AuthInvalidSession));
                 }
                 Some(header_value) => {
                     let split = header_value.split(" ").into_iter().collect::<Vec<_>>();
                     if split.len() != 2 {
-                        return Err(wrap_error(StatusCode::UNAUTHORIZED, error_message));
+                        return Err(reject(ErrorCode::AuthInvalidSession));
                     }
 
                     if split
@@ -215,7 +187,7 @@ impl FromRequestParts<Arc<ComplianceApp>> for WorkspaceUser {
                         .map(|v| !v.eq_ignore_ascii_case("Bearer"))
                         .unwrap_or(true)
                     {
-                        return Err(wrap_error(StatusCode::UNAUTHORIZED, error_message));
+                        return Err(reject(ErrorCode::AuthInvalidSession));
                     }
 
                     split.get(1).unwrap_or(&"").to_owned()
@@ -223,65 +195,56 @@ impl FromRequestParts<Arc<ComplianceApp>> for WorkspaceUser {
             }
         };
 
-        let workspace_code = {
-            let header_value = req
-                .headers
-                .get(WORKSPACE_CODE_HEADER_FIELD)
-                .map(|v| v.to_str().ok())
-                .flatten();
-
-            match header_value {
-                None => {
-                    return Err(wrap_error(
-                        StatusCode::FORBIDDEN,
-                        "Workpace code is required!",
-                    ));
-                }
-                Some(header_value) => header_value.to_owned(),
-            }
-        };
-
-        let cached_session = state.redis.get_key(&session_id).await;
-        dbg!(session_id, &workspace_code, &cached_session);
-
-        let cached_session = cached_session.map(|c| {
-            c.map(|v| {
-                dbg!(&serde_json::from_str::<AuthenticatedUser>(&v));
-                serde_json::from_str::<AuthenticatedUser>(&v).ok()
-            })
+        // falls back to the workspace the session was switched to
+        let workspace_code = req
+            .headers
+            .get(WORKSPACE_CODE_HEADER_FIELD)
+            .map(|v| v.to_str().ok())
             .flatten()
-        });
+            .map(|v| v.to_owned());
+
+        let cached_session = find_session(state.redis.as_ref(), session_id).await;
 
         match cached_session {
             Err(err) => {
                 tracing::error!(message="Get session error", err=?err);
-                return Err(wrap_error(StatusCode::UNAUTHORIZED, error_message));
+                return Err(reject(ErrorCode::AuthInvalidSession));
             }
 
             Ok(cached_session) => match cached_session {
                 None => {
                     tracing::error!(message = "Cache session not found");
-                    return Err(wrap_error(StatusCode::UNAUTHORIZED, error_message));
+                    return Err(reject(ErrorCode::AuthInvalidSession));
                 }
-                Some(auth_user) => {
+                Some(session) => {
+                    let workspace_code =
+                        match workspace_code.or(session.info.workspace_code.clone()) {
+                            None => {
+                                return Err(reject(ErrorCode::AuthWorkspaceRequired));
+                            }
+                            Some(workspace_code) => workspace_code,
+                        };
+                    check_workspace_active(state, &workspace_code).await?;
                     match AuthorizedInstitutionUser::from_authorized_user(
-                        auth_user.principal,
+                        session.user.principal.clone(),
                         &workspace_code,
                     ) {
                         Err(err) => {
                             tracing::error!(message="Error serializing session", err=?err);
-                            return Err(wrap_error(StatusCode::UNAUTHORIZED, error_message));
+                            return Err(reject(ErrorCode::AuthInvalidSession));
                         }
                         Ok(institution_user) => match institution_user {
-                            Either::Right((status, message)) => {
-                                let status_code = status.as_u16();
-                                return Err((
-                                    status,
-                                    Json(json!({"message": message,
-                                             "status_code": status_code})),
-                                ));
+                            Either::Right(error) => {
+                                return Err(convert_error_to_json_response(error));
                             }
                             Either::Left(data) => {
+                                touch_session(
+                                    state.redis.as_ref(),
+                                    &session,
+                                    Some(&workspace_code),
+                                    &state.env,
+                                )
+                                .await;
                                 return Ok(WorkspaceUser(data));
                             }
                         },
@@ -291,3 +254,56 @@ impl FromRequestParts<Arc<ComplianceApp>> for WorkspaceUser {
         }
     }
 }
+
+// Platform staff with the compliance reviewer role, reviewers don't belong to
+// a workspace so only their session is checked.
+#[derive(Debug, Clone)]
+pub(crate) struct PlatformReviewer(pub PlatformStaff);
+
+impl FromRequestParts<Arc<ComplianceApp>> for PlatformReviewer {
+    type Rejection = (StatusCode, Json<Value>);
+
+    async fn from_request_parts(
+        req: &mut Parts,
+        state: &Arc<ComplianceApp>,
+    ) -> Result<Self, Self::Rejection> {
+        let session_id = match req
+            .headers
+            .get(AUTH_ID_HEADER_FIELD)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.split_once(" "))
+        {
+            Some((scheme, token)) if scheme.eq_ignore_ascii_case("Bearer") => token.to_owned(),
+            _ => {
+                return Err(reject(ErrorCode::AuthInvalidSession));
+            }
+        };
+
+        let session = match find_session(state.redis.as_ref(), &session_id).await {
+            Err(err) => {
+                tracing::error!(message="Get session error", err=?err);
+                return Err(reject(ErrorCode::AuthInvalidSession));
+            }
+            Ok(None) => {
+                tracing::error!(message = "Cache session not found");
+                return Err(reject(ErrorCode::AuthInvalidSession));
+            }
+            Ok(Some(session)) => session,
+        };
+
+        match state.compliance_repo.get_platform_staff(&session.info.mobile).await {
+            Err(err) => {
+                tracing::error!(message="Get platform staff error", err=?err);
+                Err(reject(ErrorCode::InternalError))
+            }
+            Ok(Some(staff)) if staff.role.eq_ignore_ascii_case(COMPLIANCE_REVIEWER_ROLE) => {
+                touch_session(state.redis.as_ref(), &session, None, &state.env).await;
+                Ok(PlatformReviewer(staff))
+            }
+            Ok(_) => {
+                tracing::error!("User: {} isn't a compliance reviewer", mask_phone(&session.info.mobile));
+                Err(reject(ErrorCode::AuthForbidden))
+            }
+        }
+    }
+}
@@ -1,9 +1,11 @@
 use async_trait::async_trait;
 use derive_more::{Display, FromStr};
 use eyre::Context;
-use sqlx::{query_as, PgPool};
-use tryhcs_commons_be::data_encryption::Encryptor;
-use tryhcs_shared::{compliance_params::{ComplianceStatus, CorporateComplianceEdit, FinancialComplianceEdit, HealthcareComplianceEdit, NewComplainceEdit, NewHealthcareComplainceEdit, NewinancialComplainceEdit}, institution_params::InstitutionId};
+use serde_json::Value;
+use chrono::NaiveDate;
+use sqlx::{query, query_as, query_scalar, PgConnection, PgPool};
+use tryhcs_commons_be::{data_encryption::Encryptor, institution_data::{InstitutionDataExport, InstitutionDataSource}, platform_staff::PlatformStaffSource};
+use tryhcs_shared::{api_params::PaginatedQuery, compliance_params::{ComplianceActorType, ComplianceFieldReason, ComplianceHistoryAction, ComplianceSection, ComplianceStatus, CorporateComplianceEdit, FinancialComplianceEdit, HealthcareComplianceEdit, NameMatchDto, NewComplainceEdit, NewHealthcareComplainceEdit, NewinancialComplainceEdit}, institution_params::InstitutionId};
 use super::models::*;
 
 use serde::{Deserialize, Serialize};
@@ -15,21 +17,54 @@ use mockall::{automock, mock, predicate::*};
 #[cfg_attr(test, automock)]
 #[async_trait]
 pub trait ComplianceRepo: Send + Sync {
-    async fn create_corporate_compliance(&self, institution_id: &InstitutionId, data: &NewComplainceEdit) -> eyre::Result<CorporateCompliance>;
-    async fn edit_corporate_compliance(&self, id: &CorporateComplianceId, data: &CorporateComplianceEdit) -> eyre::Result<CorporateCompliance>;
-    async fn update_corporate_compliance_status(&self, institution_id: &InstitutionId, status: &ComplianceStatus) -> eyre::Result<CorporateCompliance>;
+    // the writes of a section record their `change` in the history in the same transaction
+    async fn create_corporate_compliance(&self, institution_id: &InstitutionId, data: &NewComplainceEdit, name_matches: &[NameMatchDto], change: &ComplianceChange) -> eyre::Result<CorporateCompliance>;
+    async fn edit_corporate_compliance(&self, id: &CorporateComplianceId, data: &CorporateComplianceEdit, name_matches: &[NameMatchDto], change: &ComplianceChange) -> eyre::Result<CorporateCompliance>;
+    async fn update_corporate_compliance_status(&self, institution_id: &InstitutionId, status: &ComplianceStatus, change: &ComplianceChange) -> eyre::Result<()>;
     async fn get_corporate_compliance(&self, institution_id: &InstitutionId) -> eyre::Result<Option<CorporateCompliance>>;
 
-    async fn create_healthcare_compliance(&self, institution_id: &InstitutionId, data: &NewHealthcareComplainceEdit) -> eyre::Result<HealthcareCompliance>;
-    async fn edit_healthcare_compliance(&self, id: &HealthcareComplianceId, data: &HealthcareComplianceEdit) -> eyre::Result<HealthcareCompliance>;
-    async fn update_healthcare_compliance_status(&self, institution_id: &InstitutionId, status: &ComplianceStatus) -> eyre::Result<HealthcareCompliance>;
+    async fn create_healthcare_compliance(&self, institution_id: &InstitutionId, data: &NewHealthcareComplainceEdit, change: &ComplianceChange) -> eyre::Result<HealthcareCompliance>;
+    async fn edit_healthcare_compliance(&self, id: &HealthcareComplianceId, data: &HealthcareComplianceEdit, change: &ComplianceChange) -> eyre::Result<HealthcareCompliance>;
+    async fn update_healthcare_compliance_status(&self, institution_id: &InstitutionId, status: &ComplianceStatus, change: &ComplianceChange) -> eyre::Result<()>;
     async fn get_healthcare_compliance(&self, institution_id: &InstitutionId) -> eyre::Result<Option<HealthcareCompliance>>;
 
-    async fn create_financial_compliance(&self, institution_id: &InstitutionId, data: &NewinancialComplainceEdit) -> eyre::Result<FinancialCompliance>;
-    async fn edit_financial_compliance(&self, id: &FinancialComplianceId, data: &FinancialComplianceEdit) -> eyre::Result<FinancialCompliance>;
+    async fn create_financial_compliance(&self, institution_id: &InstitutionId, data: &NewinancialComplainceEdit, name_matches: &[NameMatchDto], change: &ComplianceChange) -> eyre::Result<FinancialCompliance>;
+    async fn edit_financial_compliance(&self, id: &FinancialComplianceId, data: &FinancialComplianceEdit, name_matches: &[NameMatchDto], change: &ComplianceChange) -> eyre::Result<FinancialCompliance>;
     async fn get_financial_compliance(&self, institution_id: &InstitutionId) -> eyre::Result<Option<FinancialCompliance>>;
-    async fn update_financial_compliance_status(&self, institution_id: &InstitutionId, status: &ComplianceStatus) -> eyre::Result<FinancialCompliance>;
-    
+    async fn update_financial_compliance_status(&self, institution_id: &InstitutionId, status: &ComplianceStatus, change: &ComplianceChange) -> eyre::Result<()>;
+
+    async fn get_platform_staff(&self, mobile: &str) -> eyre::Result<Option<PlatformStaff>>;
+    /// whether the mobile has a staff account in the institution that hasn't been removed
+    async fn is_institution_staff(&self, institution_id: &InstitutionId, mobile: &str) -> eyre::Result<bool>;
+    async fn get_review_institution(&self, shadow_id: &str) -> eyre::Result<Option<ReviewInstitution>>;
+    /// purged institutions no longer have a workspace and count as deactivated
+    async fn is_workspace_deactivated(&self, workspace_code: &str) -> eyre::Result<bool>;
+    async fn update_institution_compliance_status(&self, institution_id: &InstitutionId, status: &ComplianceStatus) -> eyre::Result<()>;
+    /// institutions with a submitted section, the longest waiting first
+    async fn paginate_review_queue(&self, pagination: &PaginatedQuery) -> eyre::Result<(Vec<ComplianceQueueItem>, i64)>;
+    /// moves the section from `from_stage` to the decision and records the review with the reviewer's
+    /// history entry, none when the section was no longer at `from_stage`
+    async fn review_compliance_section(&self, institution_id: &InstitutionId, section: ComplianceSection, from_stage: &str, decision: &ComplianceStatus,
+        reasons: &[ComplianceFieldReason], reviewer: &PlatformStaff) -> eyre::Result<Option<ComplianceReview>>;
+    /// newest first
+    async fn find_compliance_reviews(&self, institution_id: &InstitutionId) -> eyre::Result<Vec<ComplianceReview>>;
+
+    /// newest first
+    async fn paginate_compliance_history(&self, institution_id: &InstitutionId, pagination: &PaginatedQuery) -> eyre::Result<(Vec<ComplianceHistory>, i64)>;
+
+    async fn get_license_staff(&self, institution_id: &InstitutionId, staff_id: &str) -> eyre::Result<Option<LicenseStaff>>;
+    /// staffs of the institution by name
+    async fn paginate_license_staffs(&self, institution_id: &InstitutionId, pagination: &PaginatedQuery) -> eyre::Result<(Vec<LicenseStaff>, i64)>;
+    async fn find_staff_licenses(&self, staff_ids: &[i64]) -> eyre::Result<Vec<StaffCompliance>>;
+    /// replaces the staff's license of the same type
+    async fn save_staff_license(&self, institution_id: &InstitutionId, staff_id: i64, license: &NewStaffLicense, change: &ComplianceChange) -> eyre::Result<StaffCompliance>;
+    /// licenses of active staffs expiring by `expires_by` that haven't been reminded, rejected ones excepted
+    async fn find_licenses_due_reminder(&self, expires_by: NaiveDate) -> eyre::Result<Vec<ExpiringLicense>>;
+    async fn mark_license_reminded(&self, license_id: i64) -> eyre::Result<()>;
+
+    async fn record_verification_usage(&self, usage: &NewVerificationUsage) -> eyre::Result<()>;
+    /// lookups of the institution per month and lookup type, the latest month first
+    async fn summarize_verification_usage(&self, institution_id: &InstitutionId) -> eyre::Result<Vec<VerificationUsage>>;
 }
 
 #[derive(Clone)]
@@ -40,14 +75,15 @@ pub struct ComplianceDB {
 
 #[async_trait]
 impl ComplianceRepo for ComplianceDB {
-    async fn create_corporate_compliance(&self, institution_id: &InstitutionId, NewComplainceEdit(staff_id,  data): &NewComplainceEdit) -> eyre::Result<CorporateCompliance> {
-        query_as!(
+    async fn create_corporate_compliance(&self, institution_id: &InstitutionId, NewComplainceEdit(staff_id,  data): &NewComplainceEdit, name_matches: &[NameMatchDto], change: &ComplianceChange) -> eyre::Result<CorporateCompliance> {
+        let mut txn = self.customer_db.begin().await?;
+        let saved = query_as!(
             CorporateCompliance,
             "insert into corporate_compliance
-            (institution_id, rc_no, tin, private_healthcare_certificate_url, corporate_account_number,corporate_bank_code, created_by  )
+            (institution_id, rc_no, tin, private_healthcare_certificate_url, corporate_account_number,corporate_bank_code, created_by, name_matches  )
         values
-            ($1, $2, $3, $4, $5, $6, $7)
-        returning id, institution_id, rc_no, tin, private_healthcare_certificate_url, corporate_account_number,corporate_bank_code, created_by, stage, created_at, modified_at, deleted_at ",
+            ($1, $2, $3, $4, $5, $6, $7, $8)
+        returning id, institution_id, rc_no, tin, private_healthcare_certificate_url, corporate_account_number,corporate_bank_code, created_by, stage, name_matches, created_at, modified_at, deleted_at ",
         institution_id.0,
             data.rc_no,
             data.tin,
@@ -55,55 +91,77 @@ impl ComplianceRepo for ComplianceDB {
             data.corporate_account_number,
             data.corporate_bank_code,
             staff_id.0,
+            serde_json::to_value(name_matches)?,
         )
-        .fetch_one(&self.customer_db)
+        .fetch_one(&mut *txn)
         .await
-        .wrap_err("Error creating corporate compliance")
+        .wrap_err("Error creating corporate compliance")?;
+        record_changes(&mut txn, saved.institution_id, ComplianceSection::Corporate, ComplianceHistoryAction::Created, change,
+            &[], &saved.history_fields()).await?;
+        txn.commit().await?;
+        Ok(saved)
     }
 
-    async fn edit_corporate_compliance(&self, id: &CorporateComplianceId, data: &CorporateComplianceEdit) -> eyre::Result<CorporateCompliance> {
-        query_as!(
+    async fn edit_corporate_compliance(&self, id: &CorporateComplianceId, data: &CorporateComplianceEdit, name_matches: &[NameMatchDto], change: &ComplianceChange) -> eyre::Result<CorporateCompliance> {
+        let mut txn = self.customer_db.begin().await?;
+        let before = query_as!(
+            CorporateCompliance,
+            "select id, institution_id, rc_no, tin, private_healthcare_certificate_url, corporate_account_number, corporate_bank_code, created_by, stage, name_matches, created_at, modified_at, deleted_at from corporate_compliance where id = $1 for update",
+            id.0
+        )
+        .fetch_one(&mut *txn)
+        .await
+        .wrap_err("Error fetching corporate compliance")?;
+        let saved = query_as!(
             CorporateCompliance,
             "update corporate_compliance set
                 rc_no = $2, tin=$3, private_healthcare_certificate_url=$4,
-                corporate_account_number=$5, corporate_bank_code=$6,
+                corporate_account_number=$5, corporate_bank_code=$6, name_matches=$7,
                 stage = 'PENDING' 
             where id = $1
-            returning  id, institution_id, rc_no, tin, private_healthcare_certificate_url, corporate_account_number,corporate_bank_code, created_by, stage, created_at, modified_at, deleted_at 
+            returning  id, institution_id, rc_no, tin, private_healthcare_certificate_url, corporate_account_number,corporate_bank_code, created_by, stage, name_matches, created_at, modified_at, deleted_at 
         ",
         id.0,
             data.rc_no,
             data.tin,
             data.private_healthcare_certificate_url,
             data.corporate_account_number,
-            data.corporate_bank_code
+            data.corporate_bank_code,
+            serde_json::to_value(name_matches)?
         )
-        .fetch_one(&self.customer_db)
+        .fetch_one(&mut *txn)
         .await
-        .wrap_err("Error updating corporate compliance")
+        .wrap_err("Error updating corporate compliance")?;
+        record_changes(&mut txn, saved.institution_id, ComplianceSection::Corporate, ComplianceHistoryAction::Edited, change,
+            &before.history_fields(), &saved.history_fields()).await?;
+        txn.commit().await?;
+        Ok(saved)
     }
 
-    async fn update_corporate_compliance_status(&self, institution_id: &InstitutionId, status: &ComplianceStatus) -> eyre::Result<CorporateCompliance> {
-        query_as!(
-            CorporateCompliance,
-            "update corporate_compliance set 
-                    stage = $2 
-                where institution_id = $1
-            returning id, institution_id, rc_no, tin, private_healthcare_certificate_url, corporate_account_number,corporate_bank_code, created_by, stage, created_at, modified_at, deleted_at 
-        ",
-        institution_id.0,
-            &status.to_string()
+    async fn update_corporate_compliance_status(&self, institution_id: &InstitutionId, status: &ComplianceStatus, change: &ComplianceChange) -> eyre::Result<()> {
+        let mut txn = self.customer_db.begin().await?;
+        let status = status.to_string();
+        let stage = query_scalar!(
+            "with before as (select id, stage from corporate_compliance where institution_id = $1 for update)
+            update corporate_compliance c set stage = $2, modified_at = Now() from before where c.id = before.id
+            returning before.stage",
+            institution_id.0,
+            &status
         )
-        .fetch_one(&self.customer_db)
+        .fetch_one(&mut *txn)
         .await
-        .wrap_err("Error updating corporate compliance status")
+        .wrap_err("Error updating corporate compliance status")?;
+        record_changes(&mut txn, institution_id.0, ComplianceSection::Corporate, ComplianceHistoryAction::StatusChanged, change,
+            &[("stage", Some(stage))], &[("stage", Some(status))]).await?;
+        txn.commit().await?;
+        Ok(())
     }
 
 
     async fn get_corporate_compliance(&self, institution_id: &InstitutionId) -> eyre::Result<Option<CorporateCompliance>> {
         query_as!(
             CorporateCompliance,
-            "select id, institution_id, rc_no, tin, private_healthcare_certificate_url, corporate_account_number,corporate_bank_code, created_by, stage, created_at, modified_at, deleted_at from corporate_compliance where institution_id = $1",
+            "select id, institution_id, rc_no, tin, private_healthcare_certificate_url, corporate_account_number,corporate_bank_code, created_by, stage, name_matches, created_at, modified_at, deleted_at from corporate_compliance where institution_id = $1",
         institution_id.0
         )
         .fetch_optional(&self.customer_db)
@@ -111,8 +169,9 @@ impl ComplianceRepo for ComplianceDB {
         .wrap_err("Error fetching corporate compliance")
     }
 
-    async fn create_healthcare_compliance(&self, institution_id: &InstitutionId, NewHealthcareComplainceEdit(staff_id, data): &NewHealthcareComplainceEdit) -> eyre::Result<HealthcareCompliance> {
-        query_as!(
+    async fn create_healthcare_compliance(&self, institution_id: &InstitutionId, NewHealthcareComplainceEdit(staff_id, data): &NewHealthcareComplainceEdit, change: &ComplianceChange) -> eyre::Result<HealthcareCompliance> {
+        let mut txn = self.customer_db.begin().await?;
+        let saved = query_as!(
             HealthcareCompliance,
             "insert into healthcare_compliance
             (institution_id, licensed_medical_doctor_name, licensed_medical_doctor_mdcn_no,
@@ -134,13 +193,26 @@ impl ComplianceRepo for ComplianceDB {
             data.licensed_medical_doctor_phone_no,
             staff_id.0
         )
-        .fetch_one(&self.customer_db)
+        .fetch_one(&mut *txn)
         .await
-        .wrap_err("Error creating healthcare compliance")
+        .wrap_err("Error creating healthcare compliance")?;
+        record_changes(&mut txn, saved.institution_id, ComplianceSection::Healthcare, ComplianceHistoryAction::Created, change,
+            &[], &saved.history_fields()).await?;
+        txn.commit().await?;
+        Ok(saved)
     }
 
-    async fn edit_healthcare_compliance(&self, id: &HealthcareComplianceId, data: &HealthcareComplianceEdit) -> eyre::Result<HealthcareCompliance> {
-        query_as!(
+    async fn edit_healthcare_compliance(&self, id: &HealthcareComplianceId, data: &HealthcareComplianceEdit, change: &ComplianceChange) -> eyre::Result<HealthcareCompliance> {
+        let mut txn = self.customer_db.begin().await?;
+        let before = query_as!(
+            HealthcareCompliance,
+            "select id, institution_id, licensed_medical_doctor_name, licensed_medical_doctor_mdcn_no, licensed_medical_doctor_mdcn_speciality, licensed_medical_doctor_mdcn_image_url, licensed_medical_doctor_email, licensed_medical_doctor_phone_no, created_by, stage, created_at, modified_at, deleted_at from healthcare_compliance where id = $1 for update",
+            id.0
+        )
+        .fetch_one(&mut *txn)
+        .await
+        .wrap_err("Error fetching healthcare compliance")?;
+        let saved = query_as!(
             HealthcareCompliance,
             "update healthcare_compliance set
                 licensed_medical_doctor_name=$2, licensed_medical_doctor_mdcn_no=$3,
@@ -160,29 +232,33 @@ impl ComplianceRepo for ComplianceDB {
             data.licensed_medical_doctor_email,
             data.licensed_medical_doctor_phone_no,
         )
-        .fetch_one(&self.customer_db)
+        .fetch_one(&mut *txn)
         .await
-        .wrap_err("Error updating healthcare compliance")
+        .wrap_err("Error updating healthcare compliance")?;
+        record_changes(&mut txn, saved.institution_id, ComplianceSection::Healthcare, ComplianceHistoryAction::Edited, change,
+            &before.history_fields(), &saved.history_fields()).await?;
+        txn.commit().await?;
+        Ok(saved)
     }
 
 
-    async fn update_healthcare_compliance_status(&self, institution_id: &InstitutionId, status: &ComplianceStatus) -> eyre::Result<HealthcareCompliance> {
-        query_as!(
-            HealthcareCompliance,
-            "update healthcare_compliance set 
-                    stage = $2 
-                where institution_id = $1
-            returning id, institution_id, licensed_medical_doctor_name, licensed_medical_doctor_mdcn_no,
-             licensed_medical_doctor_mdcn_speciality, licensed_medical_doctor_mdcn_image_url,
-               licensed_medical_doctor_email, licensed_medical_doctor_phone_no,  created_by,
-               stage, created_at, modified_at, deleted_at 
-        ",
-        institution_id.0,
-            &status.to_string()
+    async fn update_healthcare_compliance_status(&self, institution_id: &InstitutionId, status: &ComplianceStatus, change: &ComplianceChange) -> eyre::Result<()> {
+        let mut txn = self.customer_db.begin().await?;
+        let status = status.to_string();
+        let stage = query_scalar!(
+            "with before as (select id, stage from healthcare_compliance where institution_id = $1 for update)
+            update healthcare_compliance c set stage = $2, modified_at = Now() from before where c.id = before.id
+            returning before.stage",
+            institution_id.0,
+            &status
         )
-        .fetch_one(&self.customer_db)
+        .fetch_one(&mut *txn)
         .await
-        .wrap_err("Error updating healthcare compliance status")
+        .wrap_err("Error updating healthcare compliance status")?;
+        record_changes(&mut txn, institution_id.0, ComplianceSection::Healthcare, ComplianceHistoryAction::StatusChanged, change,
+            &[("stage", Some(stage))], &[("stage", Some(status))]).await?;
+        txn.commit().await?;
+        Ok(())
     }
 
     async fn get_healthcare_compliance(&self, institution_id: &InstitutionId) -> eyre::Result<Option<HealthcareCompliance>> {
@@ -199,18 +275,19 @@ impl ComplianceRepo for ComplianceDB {
         .wrap_err("Error fetching corporate compliance")
     }
 
-    async fn create_financial_compliance(&self, institution_id: &InstitutionId, NewinancialComplainceEdit(staff_id, data): &NewinancialComplainceEdit) -> eyre::Result<FinancialCompliance> {
-        query_as!(
+    async fn create_financial_compliance(&self, institution_id: &InstitutionId, NewinancialComplainceEdit(staff_id, data): &NewinancialComplainceEdit, name_matches: &[NameMatchDto], change: &ComplianceChange) -> eyre::Result<FinancialCompliance> {
+        let mut txn = self.customer_db.begin().await?;
+        let saved = query_as!(
             FinancialCompliance,
             "insert into financial_compliance
             (institution_id, director_legal_name, director_legal_bvn, 
             director_legal_dob, director_legal_gov_id_type,  director_legal_gov_id_url,
-             created_by)
+             created_by, name_matches)
         values
-            ($1, $2, $3, $4, $5, $6, $7)
+            ($1, $2, $3, $4, $5, $6, $7, $8)
         returning id, institution_id, director_legal_name, director_legal_bvn, 
             director_legal_dob, director_legal_gov_id_type,  director_legal_gov_id_url,
-             created_by, stage, created_at, modified_at, deleted_at ",
+             created_by, stage, name_matches, created_at, modified_at, deleted_at ",
         institution_id.0,
         data.director_legal_name,
         data.director_legal_bvn,
@@ -218,52 +295,72 @@ impl ComplianceRepo for ComplianceDB {
         data.director_legal_gov_id_type,
         data.director_legal_gov_id_url,
         staff_id.0,
+        serde_json::to_value(name_matches)?,
         )
-        .fetch_one(&self.customer_db)
+        .fetch_one(&mut *txn)
         .await
-        .wrap_err("Error creating financial compliance")
+        .wrap_err("Error creating financial compliance")?;
+        record_changes(&mut txn, saved.institution_id, ComplianceSection::Financial, ComplianceHistoryAction::Created, change,
+            &[], &saved.history_fields()).await?;
+        txn.commit().await?;
+        Ok(saved)
     }
 
-    async fn edit_financial_compliance(&self, id: &FinancialComplianceId, data: &FinancialComplianceEdit) -> eyre::Result<FinancialCompliance> {
-        query_as!(
+    async fn edit_financial_compliance(&self, id: &FinancialComplianceId, data: &FinancialComplianceEdit, name_matches: &[NameMatchDto], change: &ComplianceChange) -> eyre::Result<FinancialCompliance> {
+        let mut txn = self.customer_db.begin().await?;
+        let before = query_as!(
+            FinancialCompliance,
+            "select id, institution_id, director_legal_name, director_legal_bvn, director_legal_dob, director_legal_gov_id_type, director_legal_gov_id_url, created_by, stage, name_matches, created_at, modified_at, deleted_at from financial_compliance where id = $1 for update",
+            id.0
+        )
+        .fetch_one(&mut *txn)
+        .await
+        .wrap_err("Error fetching financial compliance")?;
+        let saved = query_as!(
             FinancialCompliance,
             "update financial_compliance set 
             director_legal_name=$2, director_legal_bvn=$3, 
             director_legal_dob=$4, director_legal_gov_id_type=$5,  director_legal_gov_id_url=$6,
-            stage = 'PENDING' 
+            name_matches=$7, stage = 'PENDING' 
               where id = $1
               returning  id, institution_id, director_legal_name, director_legal_bvn, 
             director_legal_dob, director_legal_gov_id_type,  director_legal_gov_id_url,
-             created_by, stage, created_at, modified_at, deleted_at ",
+             created_by, stage, name_matches, created_at, modified_at, deleted_at ",
         id.0,
         data.director_legal_name,
         data.director_legal_bvn,
         data.director_legal_dob,
         data.director_legal_gov_id_type,
         data.director_legal_gov_id_url,
+        serde_json::to_value(name_matches)?,
         )
-        .fetch_one(&self.customer_db)
+        .fetch_one(&mut *txn)
         .await
-        .wrap_err("Error creating financial compliance")
+        .wrap_err("Error updating financial compliance")?;
+        record_changes(&mut txn, saved.institution_id, ComplianceSection::Financial, ComplianceHistoryAction::Edited, change,
+            &before.history_fields(), &saved.history_fields()).await?;
+        txn.commit().await?;
+        Ok(saved)
     }
 
 
-    async fn update_financial_compliance_status(&self, institution_id: &InstitutionId, status: &ComplianceStatus) -> eyre::Result<FinancialCompliance> {
-        query_as!(
-            FinancialCompliance,
-            "update financial_compliance set 
-                    stage = $2 
-                where institution_id = $1
-            returning  id, institution_id, director_legal_name, director_legal_bvn, 
-            director_legal_dob, director_legal_gov_id_type,  director_legal_gov_id_url,
-             created_by, stage, created_at, modified_at, deleted_at 
-        ",
-        institution_id.0,
-            &status.to_string()
+    async fn update_financial_compliance_status(&self, institution_id: &InstitutionId, status: &ComplianceStatus, change: &ComplianceChange) -> eyre::Result<()> {
+        let mut txn = self.customer_db.begin().await?;
+        let status = status.to_string();
+        let stage = query_scalar!(
+            "with before as (select id, stage from financial_compliance where institution_id = $1 for update)
+            update financial_compliance c set stage = $2, modified_at = Now() from before where c.id = before.id
+            returning before.stage",
+            institution_id.0,
+            &status
         )
-        .fetch_one(&self.customer_db)
+        .fetch_one(&mut *txn)
         .await
-        .wrap_err("Error updating financial compliance status")
+        .wrap_err("Error updating financial compliance status")?;
+        record_changes(&mut txn, institution_id.0, ComplianceSection::Financial, ComplianceHistoryAction::StatusChanged, change,
+            &[("stage", Some(stage))], &[("stage", Some(status))]).await?;
+        txn.commit().await?;
+        Ok(())
     }
 
     async fn get_financial_compliance(&self, institution_id: &InstitutionId) -> eyre::Result<Option<FinancialCompliance>> {
@@ -271,11 +368,466 @@ impl ComplianceRepo for ComplianceDB {
             FinancialCompliance,
             "select  id, institution_id, director_legal_name, director_legal_bvn, 
             director_legal_dob, director_legal_gov_id_type,  director_legal_gov_id_url,
-             created_by, stage, created_at, modified_at, deleted_at  from financial_compliance where institution_id = $1",
+             created_by, stage, name_matches, created_at, modified_at, deleted_at  from financial_compliance where institution_id = $1",
         institution_id.0
         )
         .fetch_optional(&self.customer_db)
         .await
         .wrap_err("Error fetching financial compliance")
     }
-}
\ No newline at end of file
+
+    async fn get_platform_staff(&self, mobile: &str) -> eyre::Result<Option<PlatformStaff>> {
+        query_as!(
+            PlatformStaff,
+            "select id, mobile, first_name, last_name, role, shadow_id::varchar as \"shadow_id!\", deleted_at, modified_at, created_at
+            from platform_staffs where mobile = $1 and deleted_at is null",
+            mobile
+        )
+        .fetch_optional(&self.customer_db)
+        .await
+        .wrap_err("Error fetching platform staff")
+    }
+
+    async fn is_institution_staff(&self, institution_id: &InstitutionId, mobile: &str) -> eyre::Result<bool> {
+        query_scalar!(
+            r#"select exists(select 1 from staffs where institution_id = $1 and mobile = $2 and deleted_at is null) as "exists!""#,
+            institution_id.0,
+            mobile
+        )
+        .fetch_one(&self.customer_db)
+        .await
+        .wrap_err("Error fetching institution staff")
+    }
+
+    async fn get_review_institution(&self, shadow_id: &str) -> eyre::Result<Option<ReviewInstitution>> {
+        query_as!(
+            ReviewInstitution,
+            "select id, shadow_id::varchar as \"shadow_id!\", name, email, compliance_status
+            from institutions where shadow_id::varchar = $1 and deleted_at is null",
+            shadow_id
+        )
+        .fetch_optional(&self.customer_db)
+        .await
+        .wrap_err("Error fetching institution")
+    }
+
+    async fn is_workspace_deactivated(&self, workspace_code: &str) -> eyre::Result<bool> {
+        let deactivated = query!(
+            r#"select deleted_at is not null as "deactivated!" from institutions where workspace_code = $1"#,
+            workspace_code
+        )
+        .fetch_optional(&self.customer_db)
+        .await
+        .wrap_err("Error fetching workspace status")?;
+        Ok(deactivated.map(|w| w.deactivated).unwrap_or(true))
+    }
+
+    async fn update_institution_compliance_status(&self, institution_id: &InstitutionId, status: &ComplianceStatus) -> eyre::Result<()> {
+        query!(
+            "update institutions set compliance_status = $2, modified_at = Now() where id = $1 and compliance_status <> $2",
+            institution_id.0,
+            &status.to_string()
+        )
+        .execute(&self.customer_db)
+        .await
+        .wrap_err("Error updating institution compliance status")?;
+        Ok(())
+    }
+
+    async fn paginate_review_queue(&self, pagination: &PaginatedQuery) -> eyre::Result<(Vec<ComplianceQueueItem>, i64)> {
+        let items = query_as!(
+            ComplianceQueueItem,
+            r#"select i.shadow_id::varchar as "institution_id!", i.name as institution_name, i.email as institution_email, i.compliance_status,
+                c.stage as "corporate_stage?", f.stage as "financial_stage?", h.stage as "healthcare_stage?",
+                greatest(c.modified_at, f.modified_at, h.modified_at) as submitted_at,
+                coalesce(c.name_matches @> '[{"outcome": "REVIEW"}]' or f.name_matches @> '[{"outcome": "REVIEW"}]', false) as "name_match_review!"
+            from institutions i
+            left join corporate_compliance c on c.institution_id = i.id and c.deleted_at is null
+            left join financial_compliance f on f.institution_id = i.id and f.deleted_at is null
+            left join healthcare_compliance h on h.institution_id = i.id and h.deleted_at is null
+            where i.deleted_at is null and 'SUBMITTED' in (c.stage, f.stage, h.stage)
+            order by submitted_at, i.id
+            limit $1 offset $2"#,
+            pagination.limit() as i64,
+            pagination.offset()
+        )
+        .fetch_all(&self.customer_db)
+        .await
+        .wrap_err("Error fetching compliance review queue")?;
+
+        let total = query!(
+            r#"select count(*) as "total!" from institutions i
+            left join corporate_compliance c on c.institution_id = i.id and c.deleted_at is null
+            left join financial_compliance f on f.institution_id = i.id and f.deleted_at is null
+            left join healthcare_compliance h on h.institution_id = i.id and h.deleted_at is null
+            where i.deleted_at is null and 'SUBMITTED' in (c.stage, f.stage, h.stage)"#
+        )
+        .fetch_one(&self.customer_db)
+        .await
+        .wrap_err("Error counting compliance review queue")?
+        .total;
+
+        Ok((items, total))
+    }
+
+    async fn review_compliance_section(&self, institution_id: &InstitutionId, section: ComplianceSection, from_stage: &str, decision: &ComplianceStatus,
+        reasons: &[ComplianceFieldReason], reviewer: &PlatformStaff) -> eyre::Result<Option<ComplianceReview>> {
+        let mut txn = self.customer_db.begin().await?;
+        let institution_id = institution_id.0;
+        let decision = decision.to_string();
+        let updated = match section {
+            ComplianceSection::License => eyre::bail!("Staff licenses aren't reviewed as a compliance section"),
+            ComplianceSection::Corporate => query!(
+                "update corporate_compliance set stage = $3, modified_at = Now() where institution_id = $1 and stage = $2",
+                institution_id, from_stage, &decision
+            ).execute(&mut *txn).await,
+            ComplianceSection::Financial => query!(
+                "update financial_compliance set stage = $3, modified_at = Now() where institution_id = $1 and stage = $2",
+                institution_id, from_stage, &decision
+            ).execute(&mut *txn).await,
+            ComplianceSection::Healthcare => query!(
+                "update healthcare_compliance set stage = $3, modified_at = Now() where institution_id = $1 and stage = $2",
+                institution_id, from_stage, &decision
+            ).execute(&mut *txn).await,
+        }
+        .wrap_err("Error updating compliance status")?;
+        if updated.rows_affected() == 0 {
+            return Ok(None);
+        }
+
+        let review = query_as!(
+            ComplianceReview,
+            r#"with review as (
+                insert into compliance_reviews (institution_id, section, decision, reasons, reviewed_by)
+                values ($1, $2, $3, $4, $5)
+                returning id, institution_id, section, decision, reasons, reviewed_by, shadow_id, created_at
+            )
+            select r.id, r.institution_id, r.section, r.decision, r.reasons, r.reviewed_by,
+                p.first_name as reviewer_first_name, p.last_name as reviewer_last_name, r.shadow_id::varchar as "shadow_id!", r.created_at
+            from review r join platform_staffs p on p.id = r.reviewed_by"#,
+            institution_id,
+            section.as_str(),
+            &decision,
+            serde_json::to_value(reasons)?,
+            reviewer.id
+        )
+        .fetch_one(&mut *txn)
+        .await
+        .wrap_err("Error creating compliance review")?;
+        let change = ComplianceChange { actor: reviewer.shadow_id.clone(), actor_type: ComplianceActorType::PlatformStaff, lookup: None };
+        record_changes(&mut txn, institution_id, section, ComplianceHistoryAction::StatusChanged, &change,
+            &[("stage", Some(from_stage.to_owned()))], &[("stage", Some(decision))]).await?;
+        txn.commit().await?;
+        Ok(Some(review))
+    }
+
+    async fn find_compliance_reviews(&self, institution_id: &InstitutionId) -> eyre::Result<Vec<ComplianceReview>> {
+        query_as!(
+            ComplianceReview,
+            r#"select r.id, r.institution_id, r.section, r.decision, r.reasons, r.reviewed_by,
+                p.first_name as reviewer_first_name, p.last_name as reviewer_last_name, r.shadow_id::varchar as "shadow_id!", r.created_at
+            from compliance_reviews r join platform_staffs p on p.id = r.reviewed_by
+            where r.institution_id = $1
+            order by r.created_at desc, r.id desc"#,
+            institution_id.0
+        )
+        .fetch_all(&self.customer_db)
+        .await
+        .wrap_err("Error fetching compliance reviews")
+    }
+
+    async fn paginate_compliance_history(&self, institution_id: &InstitutionId, pagination: &PaginatedQuery) -> eyre::Result<(Vec<ComplianceHistory>, i64)> {
+        let history = query_as!(
+            ComplianceHistory,
+            r#"select id, institution_id, section, action, actor, actor_type, changes, lookup, shadow_id::varchar as "shadow_id!", created_at
+            from compliance_history
+            where institution_id = $1
+            order by created_at desc, id desc
+            limit $2 offset $3"#,
+            institution_id.0,
+            pagination.limit() as i64,
+            pagination.offset()
+        )
+        .fetch_all(&self.customer_db)
+        .await
+        .wrap_err("Error fetching compliance history")?;
+
+        let total = query!(
+            r#"select count(*) as "total!" from compliance_history where institution_id = $1"#,
+            institution_id.0
+        )
+        .fetch_one(&self.customer_db)
+        .await
+        .wrap_err("Error counting compliance history")?
+        .total;
+
+        Ok((history, total))
+    }
+
+    async fn get_license_staff(&self, institution_id: &InstitutionId, staff_id: &str) -> eyre::Result<Option<LicenseStaff>> {
+        query_as!(
+            LicenseStaff,
+            r#"select id, shadow_id::varchar as "shadow_id!", first_name, last_name, title, mobile
+            from staffs where institution_id = $1 and shadow_id::varchar = $2 and deleted_at is null"#,
+            institution_id.0,
+            staff_id
+        )
+        .fetch_optional(&self.customer_db)
+        .await
+        .wrap_err("Error fetching license staff")
+    }
+
+    async fn paginate_license_staffs(&self, institution_id: &InstitutionId, pagination: &PaginatedQuery) -> eyre::Result<(Vec<LicenseStaff>, i64)> {
+        let staffs = query_as!(
+            LicenseStaff,
+            r#"select id, shadow_id::varchar as "shadow_id!", first_name, last_name, title, mobile
+            from staffs
+            where institution_id = $1 and deleted_at is null
+            order by first_name, last_name, id
+            limit $2 offset $3"#,
+            institution_id.0,
+            pagination.limit() as i64,
+            pagination.offset()
+        )
+        .fetch_all(&self.customer_db)
+        .await
+        .wrap_err("Error fetching license staffs")?;
+
+        let total = query!(
+            r#"select count(*) as "total!" from staffs where institution_id = $1 and deleted_at is null"#,
+            institution_id.0
+        )
+        .fetch_one(&self.customer_db)
+        .await
+        .wrap_err("Error counting license staffs")?
+        .total;
+
+        Ok((staffs, total))
+    }
+
+    async fn find_staff_licenses(&self, staff_ids: &[i64]) -> eyre::Result<Vec<StaffCompliance>> {
+        query_as!(
+            StaffCompliance,
+            r#"select id, staff_id, license_type, license_no, license_certificate_url, expires_at, registry_lookup, expiry_reminded_at,
+                stage, created_by, shadow_id::varchar as "shadow_id!", created_at, modified_at, deleted_at
+            from staff_compliance
+            where staff_id = any($1) and deleted_at is null
+            order by license_type"#,
+            staff_ids
+        )
+        .fetch_all(&self.customer_db)
+        .await
+        .wrap_err("Error fetching staff licenses")
+    }
+
+    async fn save_staff_license(&self, institution_id: &InstitutionId, staff_id: i64, license: &NewStaffLicense, change: &ComplianceChange) -> eyre::Result<StaffCompliance> {
+        let mut txn = self.customer_db.begin().await?;
+        let before = query_as!(
+            StaffCompliance,
+            r#"select id, staff_id, license_type, license_no, license_certificate_url, expires_at, registry_lookup, expiry_reminded_at,
+                stage, created_by, shadow_id::varchar as "shadow_id!", created_at, modified_at, deleted_at
+            from staff_compliance where staff_id = $1 and license_type = $2 and deleted_at is null for update"#,
+            staff_id,
+            license.license_type.as_str()
+        )
+        .fetch_optional(&mut *txn)
+        .await
+        .wrap_err("Error fetching staff license")?;
+        let saved = query_as!(
+            StaffCompliance,
+            r#"insert into staff_compliance
+                (staff_id, license_type, license_no, license_certificate_url, expires_at, registry_lookup, stage, created_by)
+            values
+                ($1, $2, $3, $4, $5, $6, $7, $8)
+            on conflict (staff_id, license_type) where deleted_at is null do update set
+                license_no = excluded.license_no, license_certificate_url = excluded.license_certificate_url,
+                expires_at = excluded.expires_at, registry_lookup = excluded.registry_lookup, stage = excluded.stage,
+                expiry_reminded_at = null, modified_at = Now()
+            returning id, staff_id, license_type, license_no, license_certificate_url, expires_at, registry_lookup, expiry_reminded_at,
+                stage, created_by, shadow_id::varchar as "shadow_id!", created_at, modified_at, deleted_at"#,
+            staff_id,
+            license.license_type.as_str(),
+            license.license_no,
+            license.license_certificate_url,
+            license.expires_at,
+            license.registry_lookup,
+            &license.stage.to_string(),
+            license.created_by
+        )
+        .fetch_one(&mut *txn)
+        .await
+        .wrap_err("Error saving staff license")?;
+        let (action, before) = match before {
+            None => (ComplianceHistoryAction::Created, vec![]),
+            Some(before) => (ComplianceHistoryAction::Edited, before.history_fields()),
+        };
+        record_changes(&mut txn, institution_id.0, ComplianceSection::License, action, change, &before, &saved.history_fields()).await?;
+        txn.commit().await?;
+        Ok(saved)
+    }
+
+    async fn find_licenses_due_reminder(&self, expires_by: NaiveDate) -> eyre::Result<Vec<ExpiringLicense>> {
+        query_as!(
+            ExpiringLicense,
+            r#"select l.id, l.license_type, l.expires_at as "expires_at!", s.first_name, s.mobile, i.name as institution_name
+            from staff_compliance l
+            join staffs s on s.id = l.staff_id and s.deleted_at is null
+            join institutions i on i.id = s.institution_id and i.deleted_at is null
+            where l.deleted_at is null and l.stage <> 'REJECTED' and l.expiry_reminded_at is null
+                and l.expires_at <= $1"#,
+            expires_by
+        )
+        .fetch_all(&self.customer_db)
+        .await
+        .wrap_err("Error fetching licenses due reminder")
+    }
+
+    async fn mark_license_reminded(&self, license_id: i64) -> eyre::Result<()> {
+        query!(
+            "update staff_compliance set expiry_reminded_at = Now() where id = $1",
+            license_id
+        )
+        .execute(&self.customer_db)
+        .await
+        .wrap_err("Error marking license reminded")?;
+        Ok(())
+    }
+
+    async fn record_verification_usage(&self, usage: &NewVerificationUsage) -> eyre::Result<()> {
+        query!(
+            "insert into verification_usage (institution_id, lookup_type, provider, outcome, billable) values ($1, $2, $3, $4, $5)",
+            usage.institution_id,
+            usage.lookup_type,
+            usage.provider,
+            usage.outcome,
+            usage.billable
+        )
+        .execute(&self.customer_db)
+        .await
+        .wrap_err("Error recording verification usage")?;
+        Ok(())
+    }
+
+    async fn summarize_verification_usage(&self, institution_id: &InstitutionId) -> eyre::Result<Vec<VerificationUsage>> {
+        query_as!(
+            VerificationUsage,
+            r#"select to_char(date_trunc('month', created_at), 'YYYY-MM') as "month!", lookup_type,
+                count(*) as "lookups!", count(*) filter (where billable) as "billable!"
+            from verification_usage
+            where institution_id = $1
+            group by 1, 2
+            order by 1 desc, 2"#,
+            institution_id.0
+        )
+        .fetch_all(&self.customer_db)
+        .await
+        .wrap_err("Error summarizing verification usage")
+    }
+}
+
+/// (table, documents column, filter) of the compliance records, staff compliance
+/// belongs to the institution through its staffs, reviews, history and usage have no documents
+const INSTITUTION_COMPLIANCE_TABLES: [(&str, &str, &str); 7] = [
+    ("corporate_compliance", "private_healthcare_certificate_url", "institution_id = $1"),
+    ("healthcare_compliance", "licensed_medical_doctor_mdcn_image_url", "institution_id = $1"),
+    ("financial_compliance", "director_legal_gov_id_url", "institution_id = $1"),
+    ("staff_compliance", "license_certificate_url", "staff_id in (select id from staffs where institution_id = $1)"),
+    ("compliance_reviews", "null::varchar", "institution_id = $1"),
+    ("compliance_history", "null::varchar", "institution_id = $1"),
+    ("verification_usage", "null::varchar", "institution_id = $1"),
+];
+
+#[async_trait]
+impl PlatformStaffSource for ComplianceDB {
+    async fn is_platform_staff(&self, mobile: &str) -> eyre::Result<bool> {
+        Ok(self.get_platform_staff(mobile).await?.is_some())
+    }
+}
+
+#[async_trait]
+impl InstitutionDataSource for ComplianceDB {
+    async fn export_institution_data(&self, institution_id: i64) -> eyre::Result<Vec<InstitutionDataExport>> {
+        let mut exports = vec![];
+        for (table, documents_column, filter) in INSTITUTION_COMPLIANCE_TABLES {
+            let records: Value = sqlx::query_scalar(&format!(
+                "select coalesce(json_agg(c), '[]'::json) from (select * from {table} where {filter}) c"
+            ))
+            .bind(institution_id)
+            .fetch_one(&self.customer_db)
+            .await
+            .wrap_err(format!("Error exporting {table}"))?;
+
+            let documents: Vec<String> = sqlx::query_scalar(&format!(
+                "select {documents_column} from {table} where {filter} and {documents_column} is not null"
+            ))
+            .bind(institution_id)
+            .fetch_all(&self.customer_db)
+            .await
+            .wrap_err(format!("Error fetching {table} documents"))?;
+
+            exports.push(InstitutionDataExport { name: table.to_owned(), records, documents });
+        }
+        Ok(exports)
+    }
+
+    async fn purge_institution_data(&self, institution_id: i64) -> eyre::Result<Vec<String>> {
+        let mut txn = self.customer_db.begin().await?;
+        // lets the history's append-only trigger through for this transaction only
+        sqlx::query("select set_config('compliance.purge', 'on', true)")
+            .execute(&mut *txn)
+            .await
+            .wrap_err("Error marking the compliance purge")?;
+        let mut documents = vec![];
+        for (table, documents_column, filter) in INSTITUTION_COMPLIANCE_TABLES {
+            let deleted: Vec<Option<String>> = sqlx::query_scalar(&format!(
+                "delete from {table} where {filter} returning {documents_column}"
+            ))
+            .bind(institution_id)
+            .fetch_all(&mut *txn)
+            .await
+            .wrap_err(format!("Error purging {table}"))?;
+            documents.extend(deleted.into_iter().flatten());
+        }
+        txn.commit().await?;
+        Ok(documents)
+    }
+}
+
+/// Appends the fields the change touched to the history on the change's own transaction,
+/// nothing is recorded when no field changed
+async fn record_changes(conn: &mut PgConnection, institution_id: i64, section: ComplianceSection, action: ComplianceHistoryAction,
+    change: &ComplianceChange, before: &[(&'static str, Option<String>)], after: &[(&'static str, Option<String>)]) -> eyre::Result<()> {
+    let changes = field_changes(before, after);
+    if changes.is_empty() {
+        return Ok(());
+    }
+    insert_compliance_history(conn, &NewComplianceHistory {
+        institution_id,
+        section,
+        action,
+        actor: change.actor.clone(),
+        actor_type: change.actor_type,
+        changes,
+        lookup: change.lookup.clone(),
+    }).await?;
+    Ok(())
+}
+
+async fn insert_compliance_history(conn: &mut PgConnection, entry: &NewComplianceHistory) -> eyre::Result<ComplianceHistory> {
+    query_as!(
+        ComplianceHistory,
+        r#"insert into compliance_history (institution_id, section, action, actor, actor_type, changes, lookup)
+        values ($1, $2, $3, $4, $5, $6, $7)
+        returning id, institution_id, section, action, actor, actor_type, changes, lookup, shadow_id::varchar as "shadow_id!", created_at"#,
+        entry.institution_id,
+        entry.section.as_str(),
+        entry.action.as_str(),
+        entry.actor,
+        entry.actor_type.as_str(),
+        serde_json::to_value(&entry.changes)?,
+        entry.lookup
+    )
+    .fetch_one(conn)
+    .await
+    .wrap_err("Error recording compliance history")
+}
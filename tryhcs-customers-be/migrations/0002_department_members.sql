@@ -0,0 +1,26 @@
+-- moves department membership out of the departments.staffs_ids jsonb array,
+-- members were stored by either the staff shadow id or the staff id
+
+create table department_members (
+    id bigserial primary key,
+    department_id bigint not null references departments (id) on delete cascade,
+    staff_id bigint not null references staffs (id) on delete cascade,
+    role varchar(100) not null,
+    joined_at timestamptz not null default Now (),
+    left_at timestamptz,
+
+    modified_at timestamptz not null default Now (),
+    created_at timestamptz not null default Now ()
+);
+
+create unique index department_members_active_idx on department_members (department_id, staff_id) where left_at is null;
+create index department_members_staff_idx on department_members (staff_id) where left_at is null;
+
+insert into department_members (department_id, staff_id, role)
+select distinct on (d.id, s.id) d.id, s.id, coalesce(elem->>'role', '')
+from departments d
+cross join lateral jsonb_array_elements(d.staffs_ids) as elem
+join staffs s on s.institution_id = d.institution_id
+    and (s.shadow_id::varchar = elem->>'staff_id' or s.id::varchar = elem->>'staff_id');
+
+alter table departments drop column staffs_ids;
@@ -0,0 +1,23 @@
+-- staff are invited with a signed link and set their own password on accepting,
+-- only the hash of the latest link sent is kept
+
+create table staff_invitations (
+    id bigserial primary key,
+    institution_id bigint not null references institutions (id),
+    staff_id bigint not null references staffs (id) on delete cascade,
+    mobile varchar(30) not null,
+    token_hash varchar(64) not null,
+    status varchar(20) not null default 'Pending',
+    invited_by bigint references staffs (id),
+    expires_at timestamptz not null,
+    last_sent_at timestamptz not null default Now (),
+    reminder_count int not null default 0,
+    responded_at timestamptz,
+
+    shadow_id uuid not null unique,
+    modified_at timestamptz not null default Now (),
+    created_at timestamptz not null default Now ()
+);
+
+create unique index staff_invitations_pending_idx on staff_invitations (staff_id) where status = 'Pending';
+create index staff_invitations_institution_idx on staff_invitations (institution_id, status);
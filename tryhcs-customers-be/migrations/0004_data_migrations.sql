@@ -0,0 +1,7 @@
+-- data fixes that need application code or config run from the platform after
+-- the schema migrations, each one is recorded here once it has been applied
+
+create table data_migrations (
+    name varchar(100) primary key,
+    applied_at timestamptz not null default Now ()
+);
@@ -0,0 +1,24 @@
+-- history of institution profile edits, each row holds the fields changed by
+-- one save as a [{field, from, to}] array
+
+create table institution_changes (
+    id bigserial primary key,
+    institution_id bigint not null references institutions (id) on delete cascade,
+    changed_by bigint references staffs (id),
+    changes jsonb not null default '[]'::jsonb,
+
+    shadow_id uuid not null unique default gen_random_uuid(),
+    created_at timestamptz not null default Now ()
+);
+
+create index institution_changes_institution_idx on institution_changes (institution_id, created_at);
+
+-- created_by was never set on registration, the registering admin is the
+-- first staff created for the institution
+update institutions i set created_by = s.id
+from (
+    select distinct on (institution_id) institution_id, id from staffs
+    where institution_id is not null
+    order by institution_id, id
+) s
+where s.institution_id = i.id and i.created_by = 0;
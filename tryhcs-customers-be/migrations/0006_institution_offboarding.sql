@@ -0,0 +1,22 @@
+-- deactivated institutions have deleted_at set and are purged at purge_at,
+-- exports are zip archives of all the institution's data kept in the bucket
+
+alter table institutions add column purge_at timestamptz;
+
+create index institutions_purge_idx on institutions (purge_at) where purge_at is not null;
+
+create table institution_exports (
+    id bigserial primary key,
+    institution_id bigint not null references institutions (id) on delete cascade,
+    status varchar(20) not null default 'Pending', -- (Pending, Ready, Failed)
+    file_key varchar(255),
+    requested_by bigint references staffs (id) on delete set null,
+    completed_at timestamptz,
+
+    shadow_id uuid not null unique default gen_random_uuid(),
+    modified_at timestamptz not null default Now (),
+    created_at timestamptz not null default Now ()
+);
+
+create index institution_exports_institution_idx on institution_exports (institution_id, created_at);
+create index institution_exports_pending_idx on institution_exports (created_at) where status = 'Pending';
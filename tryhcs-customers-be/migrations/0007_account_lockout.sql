@@ -0,0 +1,2 @@
+-- accounts are locked until this time after too many failed logins
+alter table users add column locked_until timestamptz;
@@ -0,0 +1,24 @@
+-- trusted devices move out of users.device_ids so they can be named and
+-- listed, the devices already trusted are carried over before the column goes
+
+create table user_devices (
+    id bigserial primary key,
+    user_id bigint not null references users (id) on delete cascade,
+    device_id varchar(70) not null,
+    name varchar(100) not null,
+    user_agent text,
+    ip_address varchar(64),
+    first_seen_at timestamptz not null default Now (),
+    last_seen_at timestamptz not null default Now (),
+
+    shadow_id uuid not null unique default gen_random_uuid(),
+    unique (user_id, device_id)
+);
+
+insert into user_devices (user_id, device_id, name, first_seen_at, last_seen_at)
+select distinct on (u.id, d.device_id) u.id, d.device_id, 'Unknown device', u.created_at, coalesce(u.last_login_time, u.created_at)
+from users u, unnest(u.device_ids) as d (device_id)
+where d.device_id <> ''
+on conflict (user_id, device_id) do nothing;
+
+alter table users drop column device_ids;
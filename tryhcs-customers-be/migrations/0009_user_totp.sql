@@ -0,0 +1,13 @@
+-- authenticator app secrets, encrypted with the data encryption key. The
+-- recovery codes are hashed and enabled_at is set once a first code is verified
+
+create table user_totp (
+    user_id bigint primary key references users (id) on delete cascade,
+    secret bytea not null,
+    recovery_codes varchar(64) array not null default array[]::varchar[],
+    last_used_step bigint,
+    enabled_at timestamptz,
+
+    modified_at timestamptz not null default Now (),
+    created_at timestamptz not null default Now ()
+);
@@ -0,0 +1,15 @@
+-- roles are referenced by name from the department members, Superuser is
+-- built in and isn't stored
+create table institution_roles (
+    id bigserial primary key,
+    institution_id bigint not null references institutions (id) on delete cascade,
+    name varchar(100) not null,
+    permissions jsonb not null default '[]'::jsonb,
+
+    shadow_id uuid not null unique default gen_random_uuid(),
+    deleted_at timestamptz,
+    modified_at timestamptz not null default Now (),
+    created_at timestamptz not null default Now ()
+);
+
+create unique index institution_roles_name_idx on institution_roles (institution_id, lower(name)) where deleted_at is null;
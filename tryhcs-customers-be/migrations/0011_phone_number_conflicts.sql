@@ -0,0 +1,16 @@
+-- phone numbers the normalization couldn't rewrite, because they are invalid or
+-- another account already holds the normalized number. They are retried on every
+-- start and resolved once the accounts are merged or the number is fixed by hand
+
+create table phone_number_conflicts (
+    id bigserial primary key,
+    table_name varchar(100) not null,
+    column_name varchar(100) not null,
+    row_id bigint not null,
+    phone varchar(30) not null,
+    normalized varchar(30),
+    resolved_at timestamptz,
+    created_at timestamptz not null default Now (),
+
+    unique (table_name, column_name, row_id)
+);
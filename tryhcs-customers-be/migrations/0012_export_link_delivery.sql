@@ -0,0 +1,9 @@
+-- the export link is emailed when the archive is ready, a link that failed to
+-- send keeps link_sent_at null and is sent again on the next run of the job.
+-- Exports ready before this are taken as sent
+
+alter table institution_exports add column link_sent_at timestamptz;
+
+update institution_exports set link_sent_at = completed_at where status = 'Ready';
+
+create index institution_exports_unsent_idx on institution_exports (completed_at) where status = 'Ready' and link_sent_at is null;
@@ -1,5 +1,9 @@
-use std::{collections::BTreeMap, fmt::format};
+use std::{
+    collections::{btree_map::Entry, BTreeMap, BTreeSet},
+    fmt::format,
+};
 
+use base64::prelude::{Engine as _, BASE64_STANDARD};
 use bon::Builder;
 use chrono::{format, DateTime, Utc};
 use either::Either;
@@ -14,61 +18,133 @@ use scrypt::{
 };
 use serde::{Deserialize, Serialize};
 use tryhcs_commons_be::{
-    api_response::{ApiResponse, ErrorMessage},
-    auth::{is_adminstrative_department, InstitutionAdminUser},
-    file_upload::{get_presigned_object_url, upload_base64_file},
-    utils::{generate_otp, generate_password, mask_email, mask_phone},
-    ADMIN_DOMAIN, BAD_REQUEST_API_STATUS_CODE, DUPLICATE_API_STATUS_CODE,
-    FORBIDDEN_API_STATUS_CODE, NOT_FOUND_API_STATUS_CODE, SUCCESS_API_STATUS_CODE,
-    UNAUTHORIZED_API_STATUS_CODE,
+    api_response::{api_error, paginated_error, ApiResponse, ErrorMessage},
+    auth::{
+        flag_deactivated_workspace, is_adminstrative_department, unflag_deactivated_workspace,
+        InstitutionAdminUser, TypeAuthenticated,
+    },
+    client_context::ClientContext,
+    file_upload::{
+        delete_file, get_file, get_presigned_object_url, is_institution_upload, upload_base64_file,
+        upload_file_to_bucket,
+    },
+    session::{
+        create_session, find_user_sessions, get_session_by_id, refresh_session, revoke_session,
+        revoke_user_sessions, update_session, RefreshedSession, UserSession,
+    },
+    signed_token::{hash_token, sign_token, verify_signed_token},
+    totp::{
+        generate_recovery_codes, generate_totp_secret, hash_recovery_code, totp_provisioning_url,
+        verify_totp,
+    },
+    utils::{check_password_policy, generate_otp, mask_email, mask_phone, normalize_phone},
+    ADMIN_DOMAIN, ADMIN_ROLE, SUCCESS_API_STATUS_CODE,
+};
+use tryhcs_notifications_be::{
+    send_email, send_sms, try_send_sms, EmailMessage, NotificationChannel,
 };
-use tryhcs_notifications_be::{send_email, send_sms, EmailMessage, NotificationChannel};
 use tryhcs_shared::{
-    api_params::PaginatedQuery,
+    api_params::{ErrorCode, PaginatedQuery, PaginatedResult},
     institution_params::{
-        AuthenticatedUser, AuthorizedInstitutionUser, AuthorizedUser, CreateDepartment,
-        CreateInstitution, DepartmentAndStaffDto, DepartmentDto, InitiatedOtp, InstitutionDto,
-        LoginReq, LoginResponse, NewStaff, StaffDto, StaffId, VerifyOTP,
+        sniff_logo_content_type, AcceptInvitationReq, AuthenticatedUser, AuthorizedInstitutionUser,
+        AuthorizedUser, ChangePasswordReq, CreateDepartment, CreateInstitution, CreateRole,
+        DeactivateInstitutionReq, DepartmentAndStaffDto, DepartmentDto, DepartmentMember,
+        DepartmentMemberDto, DisableTotpReq, EditInstitution, ExportDownloadReq, ForgotPasswordReq,
+        InitiatedOtp, InstitutionChangeDto, InstitutionDeactivationDto, InstitutionDto,
+        InstitutionExportDto, InstitutionExportStatus, InstitutionFieldChange,
+        InstitutionLogoUpload, InstitutionProfileDto, InvitationDetailsDto, InvitationStatus,
+        InvitationTokenReq, LoginReq, LoginResponse, NewStaff, PermittedAction, RefreshTokenReq,
+        RenameDeviceReq, ResetPasswordReq, RoleDto, SessionDto, StaffDto, StaffId,
+        StaffImportReport, StaffImportRow, StaffImportRowStatus, StaffImportUpload,
+        StaffInvitationDto, TotpCodeReq, TotpEnrollReq, TotpEnrollmentDto, TotpRecoveryCodesDto,
+        UnlockAccountReq, UserDeviceDto, VerifyOTP, WorkspaceDto,
     },
+    validation::{Validate, ValidationCode, ValidationErrors},
     APIFileUpload, APIFileUploadResponse,
 };
 use uuid::Uuid;
 
 use eyre::{eyre, Context};
-use futures::{future::join_all, join};
+use futures::{future::join_all, join, stream, StreamExt};
 use tracing::info;
 
 use serde_json::{json, Value};
 use std::sync::Arc;
 
-use crate::{app::CustomersApp, db_models::Staff};
+use crate::{
+    app::CustomersApp,
+    db_models::{
+        Department, DepartmentMembership, Institution, InstitutionExport, Role, Staff,
+        StaffInvitation, UserDevice, UserTotp,
+    },
+    db_repo::{NewStaffInvitation, StaffImportEntry, DEPARTMENT_SORT_FIELDS, STAFF_SORT_FIELDS},
+    institution_export::{write_institution_archive, InstitutionArchive},
+    staff_import::{parse_staff_sheet, StaffImportFormat},
+};
+
+// Mobiles are stored in E.164 so the same number always finds the same user,
+// request mobiles are normalized before any lookup
+fn normalize_mobile(
+    app: &CustomersApp,
+    field: &str,
+    mobile: &str,
+) -> Result<String, ValidationErrors> {
+    normalize_phone(mobile, &app.env.default_phone_region).ok_or_else(|| {
+        ValidationErrors::single(
+            field,
+            ValidationCode::InvalidPhone,
+            format!("{} is not a valid phone number", field),
+        )
+    })
+}
+
+// A copy of the request with its mobile normalized, handlers shadow the
+// request with it
+fn with_normalized_mobile<T: Clone>(
+    app: &CustomersApp,
+    req: &T,
+    field: &str,
+    mobile: impl FnOnce(&mut T) -> &mut String,
+) -> Result<T, ValidationErrors> {
+    let mut req = req.clone();
+    let mobile = mobile(&mut req);
+    *mobile = normalize_mobile(app, field, mobile)?;
+    Ok(req)
+}
 
 // InstitutionRegistration
 pub async fn create_institution_init(
     app: &CustomersApp,
     create_req: &CreateInstitution,
 ) -> eyre::Result<ApiResponse<InitiatedOtp>> {
+    create_req.validate()?;
+    let create_req = &with_normalized_mobile(app, create_req, "mobile", |r| &mut r.mobile)?;
+
+    if let Some(violation) =
+        check_password_policy(&create_req.password, app.env.min_password_length)
+    {
+        return Ok(ErrorMessage::with_message(ErrorCode::AuthPasswordPolicy, violation).response());
+    }
+
     if let Some(_) = app
         .db_pool
         .find_institution_by_email(&create_req.email)
         .await?
     {
-        return Ok((
-            DUPLICATE_API_STATUS_CODE,
-            // I am thinking of internalization, just not sure yet!
-            Either::Right(ErrorMessage(
-                "Email is already registered to another institution".into(),
-            )),
-        ));
+        return Ok(api_error(ErrorCode::InstitutionEmailTaken));
     }
 
     let session_id = format!("SZX-CRI-{}", Uuid::new_v4());
-    let initated_otp = send_otp(
+    let initated_otp = match send_otp(
         app,
         NotificationChannel::Email(create_req.email.clone()),
         &session_id,
     )
-    .await?;
+    .await?
+    {
+        Either::Right(err_message) => return Ok(err_message.response()),
+        Either::Left(initated_otp) => initated_otp,
+    };
 
     let req_cache = format!("REQC-{}", &session_id);
     app.redis
@@ -86,7 +162,7 @@ pub async fn create_institution_complete(
     verify_req: &VerifyOTP,
 ) -> eyre::Result<ApiResponse<InstitutionDto>> {
     if let Either::Right(err_message) = verify_otp(app, verify_req).await? {
-        return Ok((BAD_REQUEST_API_STATUS_CODE, Either::Right(err_message)));
+        return Ok(err_message.response());
     }
 
     let req_cache = format!("REQC-{}", &verify_req.session_id);
@@ -99,34 +175,49 @@ pub async fn create_institution_complete(
 
     match cached_req {
         None => {
-            return Ok((
-                BAD_REQUEST_API_STATUS_CODE,
-                Either::Right(ErrorMessage("OTP Expired".into())),
-            ));
+            return Ok(api_error(ErrorCode::OtpExpired));
         }
         Some(mut cached_req) => {
             cached_req.password = hash_password(&cached_req.password)?;
 
             let (institution, staff) = app.db_pool.create_institution(cached_req).await?;
+            // kept until the institution is created so a failed insert can be retried
+            app.redis.delete_key(&req_cache).await?;
             let institution_dto: InstitutionDto = institution.into();
             return Ok((SUCCESS_API_STATUS_CODE, Either::Left(Some(institution_dto))));
         }
     }
 }
 
+const TOTP_LOGIN_SESSION_PREFIX: &str = "SZX-TOTP-";
+const PASSWORD_RESET_SESSION_PREFIX: &str = "SZX-PWD-";
+const UNLOCK_ACCOUNT_SESSION_PREFIX: &str = "SZX-ULK-";
+const TOTP_ISSUER: &str = "TryHcs";
+const TOTP_RECOVERY_CODES_COUNT: usize = 10;
+// invitations sent at once when a staff sheet is imported
+const STAFF_INVITE_CONCURRENCY: usize = 8;
+
+// login awaiting the OTP verification of a new device
+#[derive(Serialize, Deserialize, Debug)]
+struct PendingLogin {
+    req: LoginReq,
+    client: ClientContext,
+}
+
 pub async fn login_init(
     app: &CustomersApp,
     login_req: &LoginReq,
+    client: &ClientContext,
 ) -> eyre::Result<ApiResponse<LoginResponse>> {
+    let login_req =
+        &with_normalized_mobile(app, login_req, "phone_number", |r| &mut r.phone_number)?;
+
     let staff_institutions = app
         .db_pool
         .find_staff_institutions_by_mobile(&login_req.phone_number)
         .await?;
-    if staff_institutions.is_empty() {
-        return Ok((
-            NOT_FOUND_API_STATUS_CODE,
-            Either::Right(ErrorMessage("Invalid credentials".into())),
-        ));
+    if staff_institutions.is_empty() && !is_platform_staff(app, &login_req.phone_number).await? {
+        return Ok(api_error(ErrorCode::AuthInvalidCredentials));
     }
 
     let mut login_response = LoginResponse::default();
@@ -134,64 +225,98 @@ pub async fn login_init(
     let user = app.db_pool.get_user(&login_req.phone_number).await?;
     match user {
         None => {
-            return Ok((
-                NOT_FOUND_API_STATUS_CODE,
-                Either::Right(ErrorMessage("Invalid credentials".into())),
-            ));
+            return Ok(api_error(ErrorCode::AuthInvalidCredentials));
         }
         Some(user) => {
-            if user.failed_attempts >= app.env.max_failed_attempts {
-                return Ok((
-                    UNAUTHORIZED_API_STATUS_CODE,
-                    Either::Right(ErrorMessage(
-                        "Account disabled, max attempts reached".into(),
-                    )),
-                ));
+            if let Some(locked_until) = user.locked_until.filter(|t| *t > Utc::now()) {
+                return Ok(ErrorMessage::with_message(
+                    ErrorCode::AuthAccountLocked,
+                    format!(
+                        "Account locked until {}, unlock your account with an OTP",
+                        locked_until.format("%Y-%m-%d %H:%M UTC")
+                    ),
+                )
+                .response());
             }
             if verify_password(&login_req.password, &user.password).is_err() {
+                let user = app
+                    .db_pool
+                    .record_failed_attempts_user(
+                        &user.mobile,
+                        app.env.max_failed_attempts,
+                        app.env.lockout_duration_in_min as i64 * 60,
+                    )
+                    .await?;
                 let remaining_attempts = app.env.max_failed_attempts - user.failed_attempts;
 
-                let mut error_message = "Invalid credentials".into();
-                if remaining_attempts < 3 {
-                    error_message = format!("{error_message}, {remaining_attempts} remaining!")
+                if user.locked_until.is_some() {
+                    return Ok(api_error(ErrorCode::AuthAccountLocked));
                 }
 
-                if remaining_attempts < 1 {
-                    error_message = "Account disabled".into();
+                let mut error = ErrorMessage::new(ErrorCode::AuthInvalidCredentials);
+                if remaining_attempts < 3 {
+                    error.message = format!("{}, {remaining_attempts} remaining!", error.message)
                 }
-
-                return Ok((
-                    NOT_FOUND_API_STATUS_CODE,
-                    Either::Right(ErrorMessage(error_message)),
-                ));
+                return Ok(error.response());
             }
 
-            if !user.device_ids.is_empty() {
-                // check that the device id exist here, else send OTP;
-
-                if !user.device_ids.contains(&login_req.device_id) {
+            let devices = app.db_pool.find_user_devices(user.id).await?;
+            // a device is trusted once an OTP has been verified on it, a user
+            // without trusted devices gets an OTP like any other new device
+            if !devices.iter().any(|d| d.device_id.eq(&login_req.device_id)) {
+                // without the encryption key the secret can't be read, the SMS OTP stands in
+                let totp_enabled = app.encryptor.is_some()
+                    && app
+                        .db_pool
+                        .get_user_totp(user.id)
+                        .await?
+                        .is_some_and(|t| t.enabled_at.is_some());
+
+                let initated_otp = if totp_enabled {
+                    InitiatedOtp {
+                        session_id: format!("{}{}", TOTP_LOGIN_SESSION_PREFIX, Uuid::new_v4()),
+                        duration: app.env.otp_expires_in_sec as u64,
+                        message: "Please enter the code from your authenticator app".into(),
+                    }
+                } else {
                     let session_id = format!("SZX-LGN-{}", Uuid::new_v4());
-                    let initated_otp = send_otp(
+                    match send_otp(
                         app,
                         NotificationChannel::Mobile(login_req.phone_number.clone()),
                         &session_id,
                     )
+                    .await?
+                    {
+                        Either::Right(err_message) => return Ok(err_message.response()),
+                        Either::Left(initated_otp) => initated_otp,
+                    }
+                };
+
+                let pending_login = PendingLogin {
+                    req: login_req.clone(),
+                    client: client.clone(),
+                };
+                let req_cache = format!("REQC-{}", &initated_otp.session_id);
+                app.redis
+                    .set_key(
+                        &req_cache,
+                        &serde_json::to_string(&pending_login)?,
+                        Some(initated_otp.duration),
+                    )
                     .await?;
 
-                    let req_cache = format!("REQC-{}", &session_id);
-                    app.redis
-                        .set_key(
-                            &req_cache,
-                            &serde_json::to_string(&login_req)?,
-                            Some(initated_otp.duration),
-                        )
-                        .await?;
-
+                if totp_enabled {
+                    login_response.totp = Some(initated_otp);
+                } else {
                     login_response.otp = Some(initated_otp);
-                    return Ok((SUCCESS_API_STATUS_CODE, Either::Left(Some(login_response))));
                 }
+                return Ok((SUCCESS_API_STATUS_CODE, Either::Left(Some(login_response))));
             }
 
+            app.db_pool.record_user_login(&user.mobile).await?;
+            app.db_pool
+                .trust_user_device(user.id, &login_req.device_id, client)
+                .await?;
             let auth = setup_auth_profile_and_token(app, login_req).await?;
             login_response.auth = Some(auth);
             return Ok((SUCCESS_API_STATUS_CODE, Either::Left(Some(login_response))));
@@ -199,12 +324,28 @@ pub async fn login_init(
     }
 }
 
+// platform staff have a user account but no staff account, their session has
+// no workspace
+async fn is_platform_staff(app: &CustomersApp, mobile: &str) -> eyre::Result<bool> {
+    for source in &app.platform_staff {
+        if source.is_platform_staff(mobile).await? {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
 pub async fn login_complete(
     app: &CustomersApp,
     verify_req: &VerifyOTP,
 ) -> eyre::Result<ApiResponse<AuthenticatedUser>> {
-    if let Either::Right(err_message) = verify_otp(app, verify_req).await? {
-        return Ok((BAD_REQUEST_API_STATUS_CODE, Either::Right(err_message)));
+    let verification = if verify_req.session_id.starts_with(TOTP_LOGIN_SESSION_PREFIX) {
+        verify_login_totp(app, verify_req).await?
+    } else {
+        verify_otp(app, verify_req).await?
+    };
+    if let Either::Right(err_message) = verification {
+        return Ok(err_message.response());
     }
 
     let req_cache = format!("REQC-{}", &verify_req.session_id);
@@ -212,17 +353,29 @@ pub async fn login_complete(
         .redis
         .get_key(&req_cache)
         .await?
-        .map(|v| serde_json::from_str::<LoginReq>(&v).ok())
+        .map(|v| serde_json::from_str::<PendingLogin>(&v).ok())
         .flatten();
 
     match cached_req {
         None => {
-            return Ok((
-                BAD_REQUEST_API_STATUS_CODE,
-                Either::Right(ErrorMessage("OTP Expired".into())),
-            ));
+            return Ok(api_error(ErrorCode::OtpExpired));
         }
-        Some(cached_req) => {
+        Some(PendingLogin {
+            req: cached_req,
+            client,
+        }) => {
+            app.redis.delete_key(&req_cache).await?;
+            let user = app
+                .db_pool
+                .record_user_login(&cached_req.phone_number)
+                .await?;
+            // the device is trusted from here on, subsequent logins on it skip the OTP
+            let device = app
+                .db_pool
+                .trust_user_device(user.id, &cached_req.device_id, &client)
+                .await?;
+            send_new_device_alert(app, &user.mobile, &device).await;
+
             let authenticated_user = setup_auth_profile_and_token(app, &cached_req).await?;
             return Ok((
                 SUCCESS_API_STATUS_CODE,
@@ -232,470 +385,2996 @@ pub async fn login_complete(
     }
 }
 
-// impl Into<StaffDto> for AuthorizedInstitutionUser {
-//     fn into(self) -> StaffDto {
-//         StaffDto {
-//             id: self.staff_id,
-//             first_name: self.first_name,
-//             last_name: self.last_name,
-//             mobile: self.mobile,
-//             title: self.title,
-//             institution_id: Some(self.institution.id),
-//             profile_image: self.profile_image_url,
-//         }
-//     }
-// }
+async fn send_new_device_alert(app: &CustomersApp, mobile: &str, device: &UserDevice) {
+    let message = format!(
+        "A new device ({}) can now access your TryHcs account. If this wasn't you, remove the device and change your password.",
+        device.name
+    );
+    if let Err(err) = send_sms(&app.env, mobile, &message).await {
+        tracing::error!(message="Failed to send new device alert", err=?err);
+    }
+}
 
-async fn setup_auth_profile_and_token(
+pub async fn find_user_devices(
     app: &CustomersApp,
-    cached_req: &LoginReq,
-) -> eyre::Result<AuthenticatedUser> {
-    let staff_institutions = app
+    session: &UserSession,
+) -> eyre::Result<ApiResponse<Vec<UserDeviceDto>>> {
+    let user = match app.db_pool.get_user(&session.info.mobile).await? {
+        None => {
+            return Ok(api_error(ErrorCode::AccountNotFound));
+        }
+        Some(user) => user,
+    };
+
+    let devices = app
         .db_pool
-        .find_staff_institutions_by_mobile(&cached_req.phone_number)
+        .find_user_devices(user.id)
         .await?
         .into_iter()
-        .map(|i| (i.id, i))
-        .collect::<BTreeMap<_, _>>();
-    //staff_id
-    let staff_accounts = app
-        .db_pool
-        .find_staff_accounts_by_mobile(&cached_req.phone_number)
-        .await?;
-    let mut accounts = vec![];
-    for s in staff_accounts {
-        if let Some(institution) = s
-            .institution_id
-            .map(|id| staff_institutions.get(&id).cloned())
-            .flatten()
-        {
-            let departments = app
-                .db_pool
-                .find_staff_departments(institution.id, s.id)
-                .await?
-                .into_iter()
-                .map(|v| v.into())
-                .collect();
+        .map(|d| d.to_dto(&session.info.device_id))
+        .collect();
+    Ok((SUCCESS_API_STATUS_CODE, Either::Left(Some(devices))))
+}
 
-            let user = AuthorizedInstitutionUser {
-                staff_id: s.shadow_id,
-                first_name: s.first_name,
-                last_name: s.last_name,
-                mobile: s.mobile,
-                title: s.title,
-                profile_image_url: s.profile_image,
-                departments,
-                institution: institution.into(),
-            };
-            accounts.push(user);
+pub async fn rename_user_device(
+    app: &CustomersApp,
+    session: &UserSession,
+    device_id: &str,
+    req: &RenameDeviceReq,
+) -> eyre::Result<ApiResponse<UserDeviceDto>> {
+    req.validate()?;
+    let name = req.name.trim();
+
+    let user = match app.db_pool.get_user(&session.info.mobile).await? {
+        None => {
+            return Ok(api_error(ErrorCode::AccountNotFound));
         }
-    }
-
-    let authorized_user = AuthorizedUser {
-        mobile: cached_req.phone_number.clone(),
-        accounts: accounts,
-    };
-
-    let authenticated_user = AuthenticatedUser {
-        principal: authorized_user,
-        token: Some(Uuid::new_v4().to_string()),
+        Some(user) => user,
     };
 
-    return Ok(authenticated_user);
-}
-
-pub async fn get_staff_profile(
-    app: &CustomersApp,
-    auth: &AuthorizedInstitutionUser,
-    staff_id: &str,
-) -> eyre::Result<ApiResponse<StaffDto>> {
     match app
         .db_pool
-        .find_institution_staff_by_id_opts(auth.institution.px, staff_id)
+        .rename_user_device(user.id, device_id, name)
         .await?
     {
+        None => Ok(api_error(ErrorCode::DeviceNotFound)),
+        Some(device) => Ok((
+            SUCCESS_API_STATUS_CODE,
+            Either::Left(Some(device.to_dto(&session.info.device_id))),
+        )),
+    }
+}
+
+pub async fn revoke_user_device(
+    app: &CustomersApp,
+    session: &UserSession,
+    device_id: &str,
+) -> eyre::Result<ApiResponse<()>> {
+    let user = match app.db_pool.get_user(&session.info.mobile).await? {
         None => {
-            return Ok((
-                NOT_FOUND_API_STATUS_CODE,
-                Either::Right(ErrorMessage("Staff not found".into())),
-            ));
+            return Ok(api_error(ErrorCode::AccountNotFound));
         }
-        Some(staff) => {
-            return Ok((SUCCESS_API_STATUS_CODE, Either::Left(Some(staff.into()))));
+        Some(user) => user,
+    };
+
+    match app.db_pool.delete_user_device(user.id, device_id).await? {
+        None => Ok(api_error(ErrorCode::DeviceNotFound)),
+        Some(device) => {
+            // sessions started on the device can't outlive its trust
+            for user_session in find_user_sessions(app.redis.as_ref(), &user.mobile).await? {
+                if user_session.info.device_id.eq(&device.device_id) {
+                    revoke_session(app.redis.as_ref(), &user_session.info).await?;
+                }
+            }
+            Ok((SUCCESS_API_STATUS_CODE, Either::Left(None)))
         }
     }
 }
 
-pub async fn find_staffs(
+// Verifies the authenticator code of a login challenge, attempts are limited
+// the same way as SMS OTPs.
+async fn verify_login_totp(
     app: &CustomersApp,
-    auth: &AuthorizedInstitutionUser,
-    pagination: &PaginatedQuery,
-) -> eyre::Result<ApiResponse<Vec<StaffDto>>> {
-    let staffs = app
+    verify: &VerifyOTP,
+) -> eyre::Result<Either<(), ErrorMessage>> {
+    let req_cache = format!("REQC-{}", &verify.session_id);
+    let attempts_cache: String = format!("OTP-ATTEMPTS-{}", &verify.session_id);
+    let invalid_code = ErrorMessage::new(ErrorCode::TotpInvalidCode);
+
+    let pending_login = match app
+        .redis
+        .get_key(&req_cache)
+        .await?
+        .and_then(|v| serde_json::from_str::<PendingLogin>(&v).ok())
+    {
+        None => return Ok(Either::Right(invalid_code)),
+        Some(pending_login) => pending_login,
+    };
+
+    let totp = match app
         .db_pool
-        .find_institution_staffs(auth.institution.px, pagination.query.to_owned())
+        .get_user(&pending_login.req.phone_number)
         .await?
-        .into_iter()
-        .map(|s| s.into())
-        .collect();
-    Ok((SUCCESS_API_STATUS_CODE, Either::Left(Some(staffs))))
+    {
+        None => None,
+        Some(user) => app
+            .db_pool
+            .get_user_totp(user.id)
+            .await?
+            .filter(|t| t.enabled_at.is_some()),
+    };
+    let totp = match totp {
+        None => return Ok(Either::Right(invalid_code)),
+        Some(totp) => totp,
+    };
+
+    if check_totp_code(app, &totp, &verify.otp_code).await? {
+        app.redis.delete_key(&attempts_cache).await?;
+        return Ok(Either::Left(()));
+    }
+
+    let attempts = app
+        .redis
+        .increment_key(&attempts_cache, app.env.otp_expires_in_sec as u64)
+        .await?;
+    if attempts >= app.env.max_otp_attempts {
+        app.redis.delete_key(&req_cache).await?;
+        app.redis.delete_key(&attempts_cache).await?;
+        return Ok(Either::Right(ErrorCode::TotpTooManyAttempts.into()));
+    }
+
+    Ok(Either::Right(invalid_code))
 }
 
-pub async fn find_departments(
-    app: &CustomersApp,
-    auth: &AuthorizedInstitutionUser,
-    search: &PaginatedQuery,
-) -> eyre::Result<ApiResponse<Vec<DepartmentDto>>> {
-    let departments = app
+// Accepts a code from the authenticator app or an unused recovery code, both
+// can only be used once.
+async fn check_totp_code(app: &CustomersApp, totp: &UserTotp, code: &str) -> eyre::Result<bool> {
+    let encryptor = app
+        .encryptor
+        .as_ref()
+        .ok_or_else(|| eyre!("No data encryption key to read the authenticator secret"))?;
+    let secret: String = encryptor.get_non_deterministic(totp.secret.clone())?;
+    if let Some(step) = verify_totp(&secret, code, Utc::now().timestamp() as u64)? {
+        return Ok(app
+            .db_pool
+            .record_user_totp_step(totp.user_id, step as i64)
+            .await?
+            .is_some());
+    }
+
+    Ok(app
         .db_pool
-        .find_institution_departments(auth.institution.px, search.query.to_owned())
+        .consume_user_totp_recovery_code(totp.user_id, &hash_recovery_code(code))
         .await?
-        .into_iter()
-        .map(|s| s.into())
-        .collect();
-    Ok((SUCCESS_API_STATUS_CODE, Either::Left(Some(departments))))
+        .is_some())
 }
 
-pub async fn send_otp<K: AsRef<str>>(
+pub async fn enroll_totp(
     app: &CustomersApp,
-    channel: NotificationChannel,
-    key: K,
-) -> eyre::Result<InitiatedOtp> {
-    let req_cache: String = format!("OTP-{}", key.as_ref());
-    let otp = generate_otp(app.env.otp_length as u32)?;
-    let otp_expires_in_sec = app.env.otp_expires_in_sec as u64;
-    let mut notification_message = "Please enter the OTP sent to".into();
-
-    dbg!((&req_cache, &otp, &otp_expires_in_sec));
+    session: &UserSession,
+    req: &TotpEnrollReq,
+) -> eyre::Result<ApiResponse<TotpEnrollmentDto>> {
+    let encryptor = match &app.encryptor {
+        None => return Ok(api_error(ErrorCode::TotpUnavailable)),
+        Some(encryptor) => encryptor,
+    };
+    let user = match app.db_pool.get_user(&session.info.mobile).await? {
+        None => {
+            return Ok(api_error(ErrorCode::AccountNotFound));
+        }
+        Some(user) => user,
+    };
 
-    app.redis
-        .set_key(&req_cache, &otp, Some(otp_expires_in_sec))
-        .await?;
+    // a stolen session alone can't put its own authenticator on the account
+    if verify_password(&req.password, &user.password).is_err() {
+        return Ok(api_error(ErrorCode::AuthInvalidCurrentPassword));
+    }
 
-    let result = match channel {
-        NotificationChannel::Email(email) => {
-            let content = include_str!("../assets/templates/email_otp.html")
-                .replace("{{otp_code}}", &otp)
-                .replace("{{duration}}", &format!("{}", otp_expires_in_sec));
+    let existing_totp = app.db_pool.get_user_totp(user.id).await?;
+    if existing_totp.is_some_and(|t| t.enabled_at.is_some()) {
+        return Ok(api_error(ErrorCode::TotpAlreadyEnabled));
+    }
 
-            let message = EmailMessage {
-                to: email.to_owned(),
-                subject: "OTP Verification".to_owned(),
-                content,
-            };
+    let secret = generate_totp_secret();
+    let otpauth_url = totp_provisioning_url(&secret, TOTP_ISSUER, &user.mobile)?;
+    app.db_pool
+        .save_user_totp_secret(user.id, encryptor.set_non_deterministic(secret.clone())?)
+        .await?;
 
-            notification_message = format!("{} {}", notification_message, mask_email(&email));
+    Ok((
+        SUCCESS_API_STATUS_CODE,
+        Either::Left(Some(TotpEnrollmentDto {
+            secret,
+            otpauth_url,
+        })),
+    ))
+}
 
-            send_email(&app.env, message).await
+pub async fn verify_totp_enrollment(
+    app: &CustomersApp,
+    session: &UserSession,
+    req: &TotpCodeReq,
+) -> eyre::Result<ApiResponse<TotpRecoveryCodesDto>> {
+    if app.encryptor.is_none() {
+        return Ok(api_error(ErrorCode::TotpUnavailable));
+    }
+    let user = match app.db_pool.get_user(&session.info.mobile).await? {
+        None => {
+            return Ok(api_error(ErrorCode::AccountNotFound));
         }
-        NotificationChannel::Mobile(mobile) => {
-            let message = format!("Your OTP code is {}", otp);
-            notification_message = format!("{} {}", notification_message, mask_phone(&mobile));
+        Some(user) => user,
+    };
 
-            send_sms(&app.env, &mobile, &&message).await
+    let totp = match app.db_pool.get_user_totp(user.id).await? {
+        Some(totp) if totp.enabled_at.is_none() => totp,
+        _ => {
+            return Ok(api_error(ErrorCode::TotpNoPendingEnrollment));
         }
     };
 
-    if let Err(err) = result {
-        tracing::error!(message="Failed to send otp", err=?err);
+    if !check_totp_code(app, &totp, &req.code).await? {
+        return Ok(api_error(ErrorCode::TotpInvalidCode));
     }
 
-    Ok(InitiatedOtp {
-        session_id: key.as_ref().to_owned(),
-        duration: otp_expires_in_sec,
-        message: notification_message,
-    })
-}
-
-pub async fn verify_otp(app: &CustomersApp, verify: &VerifyOTP) -> eyre::Result<Either<(), ErrorMessage>> {
-    let req_cache: String = format!("OTP-{}", &verify.session_id);
+    let recovery_codes = generate_recovery_codes(TOTP_RECOVERY_CODES_COUNT);
+    let recovery_code_hashes: Vec<String> = recovery_codes
+        .iter()
+        .map(|c| hash_recovery_code(c))
+        .collect();
+    app.db_pool
+        .enable_user_totp(user.id, &recovery_code_hashes)
+        .await?;
 
-    dbg!((&req_cache));
+    Ok((
+        SUCCESS_API_STATUS_CODE,
+        Either::Left(Some(TotpRecoveryCodesDto { recovery_codes })),
+    ))
+}
 
-    match app.redis.get_key(&req_cache).await?.map(|cached_value| {
-        dbg!((&req_cache, &cached_value));
+pub async fn regenerate_totp_recovery_codes(
+    app: &CustomersApp,
+    session: &UserSession,
+    req: &TotpCodeReq,
+) -> eyre::Result<ApiResponse<TotpRecoveryCodesDto>> {
+    if app.encryptor.is_none() {
+        return Ok(api_error(ErrorCode::TotpUnavailable));
+    }
+    let user = match app.db_pool.get_user(&session.info.mobile).await? {
+        None => {
+            return Ok(api_error(ErrorCode::AccountNotFound));
+        }
+        Some(user) => user,
+    };
 
-        cached_value.eq_ignore_ascii_case(&verify.otp_code)
-    }) {
-        Some(true) => {
-            return Ok(Either::Left(()));
+    let totp = match app.db_pool.get_user_totp(user.id).await? {
+        Some(totp) if totp.enabled_at.is_some() => totp,
+        _ => {
+            return Ok(api_error(ErrorCode::TotpNotEnabled));
         }
-        _ => return Ok(Either::Right(ErrorMessage("Invalid or Expired OTP".into()))),
+    };
+
+    if !check_totp_code(app, &totp, &req.code).await? {
+        return Ok(api_error(ErrorCode::TotpInvalidCode));
     }
-}
 
-fn hash_password(value: &str) -> eyre::Result<String> {
-    let salt = SaltString::generate(&mut OsRng);
-    Ok(Scrypt.hash_password(value.as_bytes(), &salt)?.to_string())
-}
+    let recovery_codes = generate_recovery_codes(TOTP_RECOVERY_CODES_COUNT);
+    let recovery_code_hashes: Vec<String> = recovery_codes
+        .iter()
+        .map(|c| hash_recovery_code(c))
+        .collect();
+    app.db_pool
+        .update_user_totp_recovery_codes(user.id, &recovery_code_hashes)
+        .await?;
 
-fn verify_password(value: &str, hash: &str) -> eyre::Result<()> {
-    let hash = PasswordHash::new(hash)?;
-    Scrypt.verify_password(value.as_bytes(), &hash)?;
-    Ok(())
+    Ok((
+        SUCCESS_API_STATUS_CODE,
+        Either::Left(Some(TotpRecoveryCodesDto { recovery_codes })),
+    ))
 }
 
-pub async fn get_department_and_staffs(
+pub async fn disable_totp(
     app: &CustomersApp,
-    auth: &AuthorizedInstitutionUser,
-    department_id: &str,
-) -> eyre::Result<ApiResponse<DepartmentAndStaffDto>> {
-    let department = app
-        .db_pool
-        .get_institution_department(auth.institution.px, department_id)
-        .await?;
-
-    match department {
+    session: &UserSession,
+    req: &DisableTotpReq,
+) -> eyre::Result<ApiResponse<()>> {
+    if app.encryptor.is_none() {
+        return Ok(api_error(ErrorCode::TotpUnavailable));
+    }
+    let user = match app.db_pool.get_user(&session.info.mobile).await? {
         None => {
-            return Ok((
-                NOT_FOUND_API_STATUS_CODE,
-                Either::Right(ErrorMessage("Department not found".into())),
-            ));
+            return Ok(api_error(ErrorCode::AccountNotFound));
+        }
+        Some(user) => user,
+    };
+
+    if verify_password(&req.password, &user.password).is_err() {
+        return Ok(api_error(ErrorCode::AuthInvalidCurrentPassword));
+    }
+
+    let totp = match app.db_pool.get_user_totp(user.id).await? {
+        Some(totp) if totp.enabled_at.is_some() => totp,
+        _ => {
+            return Ok(api_error(ErrorCode::TotpNotEnabled));
+        }
+    };
+
+    if !check_totp_code(app, &totp, &req.code).await? {
+        return Ok(api_error(ErrorCode::TotpInvalidCode));
+    }
+
+    app.db_pool.delete_user_totp(user.id).await?;
+    Ok((SUCCESS_API_STATUS_CODE, Either::Left(None)))
+}
+
+pub async fn forgot_password(
+    app: &CustomersApp,
+    req: &ForgotPasswordReq,
+) -> eyre::Result<ApiResponse<InitiatedOtp>> {
+    let req = &with_normalized_mobile(app, req, "phone_number", |r| &mut r.phone_number)?;
+
+    // unknown numbers get the same answer, the session just never verifies
+    let session_id = format!("{}{}", PASSWORD_RESET_SESSION_PREFIX, Uuid::new_v4());
+    let channel = NotificationChannel::Mobile(req.phone_number.clone());
+    if app.db_pool.get_user(&req.phone_number).await?.is_none() {
+        return Ok(match decoy_otp(app, channel, &session_id).await? {
+            Either::Right(err_message) => err_message.response(),
+            Either::Left(initated_otp) => {
+                (SUCCESS_API_STATUS_CODE, Either::Left(Some(initated_otp)))
+            }
+        });
+    }
+
+    let initated_otp = match send_otp(app, channel, &session_id).await? {
+        Either::Right(err_message) => return Ok(err_message.response()),
+        Either::Left(initated_otp) => initated_otp,
+    };
+
+    let req_cache = format!("REQC-{}", &session_id);
+    app.redis
+        .set_key(
+            &req_cache,
+            &serde_json::to_string(&req)?,
+            Some(initated_otp.duration),
+        )
+        .await?;
+    return Ok((SUCCESS_API_STATUS_CODE, Either::Left(Some(initated_otp))));
+}
+
+pub async fn reset_password(
+    app: &CustomersApp,
+    req: &ResetPasswordReq,
+) -> eyre::Result<ApiResponse<()>> {
+    // checked before the OTP is consumed, so the user can retry with a better password
+    if let Some(violation) = check_password_policy(&req.new_password, app.env.min_password_length) {
+        return Ok(ErrorMessage::with_message(ErrorCode::AuthPasswordPolicy, violation).response());
+    }
+
+    // OTP sessions of other flows cache other requests under the same keys
+    if !req.session_id.starts_with(PASSWORD_RESET_SESSION_PREFIX) {
+        return Ok(api_error(ErrorCode::OtpInvalid));
+    }
+    let verify_req = VerifyOTP {
+        otp_code: req.otp_code.clone(),
+        session_id: req.session_id.clone(),
+    };
+    if let Either::Right(err_message) = verify_otp(app, &verify_req).await? {
+        return Ok(err_message.response());
+    }
+
+    let req_cache = format!("REQC-{}", &req.session_id);
+    let cached_req = app
+        .redis
+        .get_key(&req_cache)
+        .await?
+        .map(|v| serde_json::from_str::<ForgotPasswordReq>(&v).ok())
+        .flatten();
+
+    match cached_req {
+        None => {
+            return Ok(api_error(ErrorCode::OtpExpired));
+        }
+        Some(cached_req) => {
+            app.redis.delete_key(&req_cache).await?;
+            let password_hashed = hash_password(&req.new_password)?;
+            app.db_pool
+                .update_user_password(&cached_req.phone_number, &password_hashed)
+                .await?;
+            let revoked =
+                revoke_user_sessions(app.redis.as_ref(), &cached_req.phone_number).await?;
+            info!("Password reset, revoked {} session(s)", revoked);
+            return Ok((SUCCESS_API_STATUS_CODE, Either::Left(None)));
+        }
+    }
+}
+
+pub async fn change_password(
+    app: &CustomersApp,
+    session: &UserSession,
+    req: &ChangePasswordReq,
+) -> eyre::Result<ApiResponse<()>> {
+    if let Some(violation) = check_password_policy(&req.new_password, app.env.min_password_length) {
+        return Ok(ErrorMessage::with_message(ErrorCode::AuthPasswordPolicy, violation).response());
+    }
+
+    let user = match app.db_pool.get_user(&session.info.mobile).await? {
+        None => {
+            return Ok(api_error(ErrorCode::AccountNotFound));
+        }
+        Some(user) => user,
+    };
+
+    if verify_password(&req.current_password, &user.password).is_err() {
+        return Ok(api_error(ErrorCode::AuthInvalidCurrentPassword));
+    }
+
+    let password_hashed = hash_password(&req.new_password)?;
+    app.db_pool
+        .update_user_password(&user.mobile, &password_hashed)
+        .await?;
+
+    // every other session has to login again with the new password
+    for user_session in find_user_sessions(app.redis.as_ref(), &user.mobile).await? {
+        if !user_session.info.id.eq(&session.info.id) {
+            revoke_session(app.redis.as_ref(), &user_session.info).await?;
+        }
+    }
+
+    return Ok((SUCCESS_API_STATUS_CODE, Either::Left(None)));
+}
+
+pub async fn unlock_account_init(
+    app: &CustomersApp,
+    req: &UnlockAccountReq,
+) -> eyre::Result<ApiResponse<InitiatedOtp>> {
+    let req = &with_normalized_mobile(app, req, "phone_number", |r| &mut r.phone_number)?;
+
+    // unknown and unlocked accounts get the same answer as locked ones
+    let session_id = format!("{}{}", UNLOCK_ACCOUNT_SESSION_PREFIX, Uuid::new_v4());
+    let channel = NotificationChannel::Mobile(req.phone_number.clone());
+    let locked = app
+        .db_pool
+        .get_user(&req.phone_number)
+        .await?
+        .is_some_and(|user| user.locked_until.is_some_and(|t| t > Utc::now()));
+    if !locked {
+        return Ok(match decoy_otp(app, channel, &session_id).await? {
+            Either::Right(err_message) => err_message.response(),
+            Either::Left(initated_otp) => {
+                (SUCCESS_API_STATUS_CODE, Either::Left(Some(initated_otp)))
+            }
+        });
+    }
+
+    let initated_otp = match send_otp(app, channel, &session_id).await? {
+        Either::Right(err_message) => return Ok(err_message.response()),
+        Either::Left(initated_otp) => initated_otp,
+    };
+
+    let req_cache = format!("REQC-{}", &session_id);
+    app.redis
+        .set_key(
+            &req_cache,
+            &serde_json::to_string(&req)?,
+            Some(initated_otp.duration),
+        )
+        .await?;
+    return Ok((SUCCESS_API_STATUS_CODE, Either::Left(Some(initated_otp))));
+}
+
+pub async fn unlock_account_complete(
+    app: &CustomersApp,
+    verify_req: &VerifyOTP,
+) -> eyre::Result<ApiResponse<()>> {
+    if !verify_req
+        .session_id
+        .starts_with(UNLOCK_ACCOUNT_SESSION_PREFIX)
+    {
+        return Ok(api_error(ErrorCode::OtpInvalid));
+    }
+    if let Either::Right(err_message) = verify_otp(app, verify_req).await? {
+        return Ok(err_message.response());
+    }
+
+    let req_cache = format!("REQC-{}", &verify_req.session_id);
+    let cached_req = app
+        .redis
+        .get_key(&req_cache)
+        .await?
+        .map(|v| serde_json::from_str::<UnlockAccountReq>(&v).ok())
+        .flatten();
+
+    match cached_req {
+        None => {
+            return Ok(api_error(ErrorCode::OtpExpired));
+        }
+        Some(cached_req) => {
+            app.redis.delete_key(&req_cache).await?;
+            app.db_pool
+                .reset_user_lockout(&cached_req.phone_number)
+                .await?;
+            return Ok((SUCCESS_API_STATUS_CODE, Either::Left(None)));
+        }
+    }
+}
+
+// impl Into<StaffDto> for AuthorizedInstitutionUser {
+//     fn into(self) -> StaffDto {
+//         StaffDto {
+//             id: self.staff_id,
+//             first_name: self.first_name,
+//             last_name: self.last_name,
+//             mobile: self.mobile,
+//             title: self.title,
+//             institution_id: Some(self.institution.id),
+//             profile_image: self.profile_image_url,
+//         }
+//     }
+// }
+
+// Loads every institution account of the mobile number, this is the
+// principal cached on the user's sessions.
+async fn load_authorized_user(app: &CustomersApp, mobile: &str) -> eyre::Result<AuthorizedUser> {
+    let staff_institutions = app
+        .db_pool
+        .find_staff_institutions_by_mobile(mobile)
+        .await?
+        .into_iter()
+        .map(|i| (i.id, i))
+        .collect::<BTreeMap<_, _>>();
+    //staff_id
+    let staff_accounts = app.db_pool.find_staff_accounts_by_mobile(mobile).await?;
+    let mut institution_roles: BTreeMap<i64, Vec<Role>> = BTreeMap::new();
+    let mut accounts = vec![];
+    for s in staff_accounts {
+        if let Some(institution) = s
+            .institution_id
+            .map(|id| staff_institutions.get(&id).cloned())
+            .flatten()
+        {
+            let staff_departments = app
+                .db_pool
+                .find_staff_departments(institution.id, s.id)
+                .await?;
+            let roles = match institution_roles.entry(institution.id) {
+                Entry::Occupied(roles) => roles.into_mut(),
+                Entry::Vacant(entry) => {
+                    entry.insert(app.db_pool.find_institution_roles(institution.id).await?)
+                }
+            };
+            let memberships = app
+                .db_pool
+                .find_staff_memberships(institution.id, s.id)
+                .await?;
+            let permissions = resolve_permissions(&staff_departments, &memberships, roles);
+            let departments = staff_departments.into_iter().map(|v| v.into()).collect();
+
+            let user = AuthorizedInstitutionUser {
+                staff_id: s.shadow_id,
+                first_name: s.first_name,
+                last_name: s.last_name,
+                mobile: s.mobile,
+                title: s.title,
+                profile_image_url: s.profile_image,
+                departments,
+                institution: institution.into(),
+                permissions,
+            };
+            accounts.push(user);
+        }
+    }
+
+    Ok(AuthorizedUser {
+        mobile: mobile.to_owned(),
+        accounts: accounts,
+    })
+}
+
+// A staff is granted the union of the actions of their roles across their
+// departments, Superusers and members of the Admin department are granted
+// every action.
+fn resolve_permissions(
+    departments: &[Department],
+    memberships: &[DepartmentMembership],
+    roles: &[Role],
+) -> Vec<PermittedAction> {
+    if departments
+        .iter()
+        .any(|d| is_adminstrative_department(&d.name))
+    {
+        return PermittedAction::all();
+    }
+
+    let mut permissions = vec![];
+    for membership in memberships {
+        if membership.role.eq_ignore_ascii_case(ADMIN_ROLE) {
+            return PermittedAction::all();
+        }
+
+        let role = roles
+            .iter()
+            .find(|r| r.name.eq_ignore_ascii_case(&membership.role));
+        for action in role.map(|r| r.permitted_actions()).unwrap_or_default() {
+            if !permissions.contains(&action) {
+                permissions.push(action);
+            }
+        }
+    }
+    permissions
+}
+
+// Reloads the principal cached on the active sessions of the user after a
+// membership change, a session scoped to a workspace the user no longer
+// belongs to loses its workspace.
+async fn refresh_user_session_cache(app: &CustomersApp, mobile: &str) -> eyre::Result<()> {
+    let sessions = find_user_sessions(app.redis.as_ref(), mobile).await?;
+    if sessions.is_empty() {
+        return Ok(());
+    }
+
+    let principal = load_authorized_user(app, mobile).await?;
+    for session in sessions {
+        // written with a compare-and-set so a refresh or workspace switch
+        // landing meanwhile isn't overwritten
+        update_session(app.redis.as_ref(), &session.info.id, |stored| {
+            let is_member = stored
+                .info
+                .workspace_code
+                .as_ref()
+                .map(|code| {
+                    principal
+                        .accounts
+                        .iter()
+                        .any(|a| a.institution.workspace_code.eq(code))
+                })
+                .unwrap_or(true);
+            if !is_member {
+                stored.info.workspace_code = None;
+            }
+            stored.user.principal = principal.clone();
+            true
+        })
+        .await?;
+    }
+    Ok(())
+}
+
+// Sessions opened before permissions were resolved at login carry none, every
+// user's sessions are reloaded once so they don't wait for their next login.
+pub async fn refresh_all_user_sessions(app: &CustomersApp) -> eyre::Result<()> {
+    for mobile in app.db_pool.find_user_mobiles().await? {
+        if let Err(err) = refresh_user_session_cache(app, &mobile).await {
+            tracing::error!(mobile, err=?err, "Failed to refresh user sessions");
+        }
+    }
+    Ok(())
+}
+
+// Department, role and institution changes can touch any staff of the
+// institution, their sessions are refreshed one staff at a time in the
+// background so the request doesn't wait on every staff of the institution.
+fn spawn_institution_session_refresh(app: &CustomersApp, institution_id: i64) {
+    let app = app.clone();
+    tokio::spawn(async move {
+        let staffs = match app
+            .db_pool
+            .find_institution_staffs(institution_id, None)
+            .await
+        {
+            Ok(staffs) => staffs,
+            Err(err) => {
+                tracing::error!(institution_id, err=?err, "Failed to load staffs to refresh sessions");
+                return;
+            }
+        };
+        for staff in staffs.iter().filter(|s| s.deleted_at.is_none()) {
+            if let Err(err) = refresh_user_session_cache(&app, &staff.mobile).await {
+                tracing::error!(mobile = staff.mobile, err=?err, "Failed to refresh staff sessions");
+            }
+        }
+    });
+}
+
+async fn setup_auth_profile_and_token(
+    app: &CustomersApp,
+    cached_req: &LoginReq,
+) -> eyre::Result<AuthenticatedUser> {
+    let authorized_user = load_authorized_user(app, &cached_req.phone_number).await?;
+
+    let authenticated_user = AuthenticatedUser {
+        principal: authorized_user,
+        token: None,
+        refresh_token: None,
+    };
+
+    let authenticated_user = create_session(
+        app.redis.as_ref(),
+        &authenticated_user,
+        &cached_req.device_id,
+        &app.env,
+    )
+    .await?;
+
+    return Ok(authenticated_user);
+}
+
+pub async fn refresh_token(
+    app: &CustomersApp,
+    req: &RefreshTokenReq,
+) -> eyre::Result<ApiResponse<AuthenticatedUser>> {
+    match refresh_session(app.redis.as_ref(), &req.refresh_token, &app.env).await? {
+        RefreshedSession::Refreshed(authenticated_user) => Ok((
+            SUCCESS_API_STATUS_CODE,
+            Either::Left(Some(authenticated_user)),
+        )),
+        RefreshedSession::Invalid | RefreshedSession::Reused => {
+            Ok(api_error(ErrorCode::AuthInvalidSession))
+        }
+    }
+}
+
+pub async fn logout(app: &CustomersApp, session: &UserSession) -> eyre::Result<ApiResponse<()>> {
+    revoke_session(app.redis.as_ref(), &session.info).await?;
+    Ok((SUCCESS_API_STATUS_CODE, Either::Left(None)))
+}
+
+pub async fn find_user_sessions_api(
+    app: &CustomersApp,
+    session: &UserSession,
+) -> eyre::Result<ApiResponse<Vec<SessionDto>>> {
+    let sessions = find_user_sessions(app.redis.as_ref(), &session.info.mobile)
+        .await?
+        .iter()
+        .map(|s| s.info.to_dto(Some(&session.info.id)))
+        .collect();
+    Ok((SUCCESS_API_STATUS_CODE, Either::Left(Some(sessions))))
+}
+
+pub async fn revoke_user_session(
+    app: &CustomersApp,
+    session: &UserSession,
+    session_id: &str,
+) -> eyre::Result<ApiResponse<()>> {
+    let user_session = get_session_by_id(app.redis.as_ref(), session_id)
+        .await?
+        .filter(|s| s.info.mobile.eq(&session.info.mobile));
+
+    match user_session {
+        None => Ok(api_error(ErrorCode::SessionNotFound)),
+        Some(user_session) => {
+            revoke_session(app.redis.as_ref(), &user_session.info).await?;
+            Ok((SUCCESS_API_STATUS_CODE, Either::Left(None)))
+        }
+    }
+}
+
+pub async fn find_workspaces(
+    _app: &CustomersApp,
+    session: &UserSession,
+) -> eyre::Result<ApiResponse<Vec<WorkspaceDto>>> {
+    let workspaces = session
+        .user
+        .principal
+        .accounts
+        .iter()
+        .map(|a| WorkspaceDto {
+            workspace_code: a.institution.workspace_code.clone(),
+            institution_id: a.institution.id.clone(),
+            institution_name: a.institution.institution_name.clone(),
+            logo: a.institution.logo.clone(),
+            staff_id: a.staff_id.clone(),
+            current: session
+                .info
+                .workspace_code
+                .as_ref()
+                .map(|code| code.eq(&a.institution.workspace_code))
+                .unwrap_or(false),
+        })
+        .collect();
+    Ok((SUCCESS_API_STATUS_CODE, Either::Left(Some(workspaces))))
+}
+
+// Scopes the session to the workspace, requests without a workspace header
+// are served from it.
+pub async fn switch_workspace(
+    app: &CustomersApp,
+    session: &UserSession,
+    workspace_code: &str,
+) -> eyre::Result<ApiResponse<AuthorizedInstitutionUser>> {
+    let account = match AuthorizedInstitutionUser::from_authorized_user(
+        session.user.principal.clone(),
+        workspace_code,
+    )? {
+        Either::Right(message) => return Ok(message.response()),
+        Either::Left(account) => account,
+    };
+
+    let updated = update_session(app.redis.as_ref(), &session.info.id, |stored| {
+        stored.info.workspace_code = Some(workspace_code.to_owned());
+        true
+    })
+    .await?;
+    if updated.is_none() {
+        return Ok(api_error(ErrorCode::AuthInvalidSession));
+    }
+
+    Ok((SUCCESS_API_STATUS_CODE, Either::Left(Some(account))))
+}
+
+pub async fn get_staff_profile(
+    app: &CustomersApp,
+    auth: &AuthorizedInstitutionUser,
+    staff_id: &str,
+) -> eyre::Result<ApiResponse<StaffDto>> {
+    match app
+        .db_pool
+        .find_institution_staff_by_id_opts(auth.institution.px, staff_id)
+        .await?
+    {
+        None => {
+            return Ok(api_error(ErrorCode::StaffNotFound));
+        }
+        Some(staff) => {
+            return Ok((SUCCESS_API_STATUS_CODE, Either::Left(Some(staff.into()))));
+        }
+    }
+}
+
+fn unknown_sort_field(pagination: &PaginatedQuery, allowed: &[&str]) -> Option<ErrorMessage> {
+    let sort_by = pagination.sort_by.as_ref()?;
+    if allowed.contains(&sort_by.as_str()) {
+        return None;
+    }
+    Some(ErrorMessage::with_message(
+        ErrorCode::SortFieldUnknown,
+        format!(
+            "Cannot sort by {}, expected one of: {}",
+            sort_by,
+            allowed.join(", ")
+        ),
+    ))
+}
+
+pub async fn find_staffs(
+    app: &CustomersApp,
+    auth: &AuthorizedInstitutionUser,
+    pagination: &PaginatedQuery,
+) -> eyre::Result<(StatusCode, PaginatedResult<StaffDto>)> {
+    if let Some(message) = unknown_sort_field(pagination, &STAFF_SORT_FIELDS) {
+        return Ok(paginated_error(message));
+    }
+
+    let (staffs, total) = app
+        .db_pool
+        .paginate_institution_staffs(auth.institution.px, pagination)
+        .await?;
+
+    Ok((
+        SUCCESS_API_STATUS_CODE,
+        PaginatedResult::new(
+            staffs.into_iter().map(|s| s.into()).collect(),
+            total,
+            pagination,
+        ),
+    ))
+}
+
+pub async fn find_departments(
+    app: &CustomersApp,
+    auth: &AuthorizedInstitutionUser,
+    search: &PaginatedQuery,
+) -> eyre::Result<(StatusCode, PaginatedResult<DepartmentDto>)> {
+    if let Some(message) = unknown_sort_field(search, &DEPARTMENT_SORT_FIELDS) {
+        return Ok(paginated_error(message));
+    }
+
+    let (departments, total) = app
+        .db_pool
+        .paginate_institution_departments(auth.institution.px, search)
+        .await?;
+
+    Ok((
+        SUCCESS_API_STATUS_CODE,
+        PaginatedResult::new(
+            departments.into_iter().map(|s| s.into()).collect(),
+            total,
+            search,
+        ),
+    ))
+}
+
+async fn check_otp_rate_limit(
+    app: &CustomersApp,
+    channel: &NotificationChannel,
+) -> eyre::Result<Option<ErrorMessage>> {
+    let rate_limit_cache = match channel {
+        NotificationChannel::Email(email) => format!("OTP-RATE-EMAIL-{}", email.to_lowercase()),
+        NotificationChannel::Mobile(mobile) => format!("OTP-RATE-MOBILE-{}", mobile),
+    };
+    let sent_count = app
+        .redis
+        .increment_key(&rate_limit_cache, app.env.otp_resend_window_in_sec)
+        .await?;
+    if sent_count > app.env.otp_resend_limit {
+        return Ok(Some(ErrorCode::OtpRateLimited.into()));
+    }
+    Ok(None)
+}
+
+fn otp_sent_message(channel: &NotificationChannel) -> String {
+    match channel {
+        NotificationChannel::Email(email) => {
+            format!("Please enter the OTP sent to {}", mask_email(email))
+        }
+        NotificationChannel::Mobile(mobile) => {
+            format!("Please enter the OTP sent to {}", mask_phone(mobile))
+        }
+    }
+}
+
+// Answers like `send_otp` without creating or sending an OTP, for requests
+// that mustn't reveal whether an account exists. The session can't be verified.
+async fn decoy_otp<K: AsRef<str>>(
+    app: &CustomersApp,
+    channel: NotificationChannel,
+    key: K,
+) -> eyre::Result<Either<InitiatedOtp, ErrorMessage>> {
+    if let Some(rate_limited) = check_otp_rate_limit(app, &channel).await? {
+        return Ok(Either::Right(rate_limited));
+    }
+    Ok(Either::Left(InitiatedOtp {
+        session_id: key.as_ref().to_owned(),
+        duration: app.env.otp_expires_in_sec as u64,
+        message: otp_sent_message(&channel),
+    }))
+}
+
+pub async fn send_otp<K: AsRef<str>>(
+    app: &CustomersApp,
+    channel: NotificationChannel,
+    key: K,
+) -> eyre::Result<Either<InitiatedOtp, ErrorMessage>> {
+    if let Some(rate_limited) = check_otp_rate_limit(app, &channel).await? {
+        return Ok(Either::Right(rate_limited));
+    }
+
+    let req_cache: String = format!("OTP-{}", key.as_ref());
+    let otp = generate_otp(app.env.otp_length as u32)?;
+    let otp_expires_in_sec = app.env.otp_expires_in_sec as u64;
+    let notification_message = otp_sent_message(&channel);
+
+    app.redis
+        .set_key(&req_cache, &otp, Some(otp_expires_in_sec))
+        .await?;
+
+    let result = match channel {
+        NotificationChannel::Email(email) => {
+            let content = include_str!("../assets/templates/email_otp.html")
+                .replace("{{otp_code}}", &otp)
+                .replace("{{duration}}", &format!("{}", otp_expires_in_sec));
+
+            let message = EmailMessage {
+                to: email.to_owned(),
+                subject: "OTP Verification".to_owned(),
+                content,
+            };
+
+            send_email(&app.env, message).await
+        }
+        NotificationChannel::Mobile(mobile) => {
+            let message = format!("Your OTP code is {}", otp);
+            send_sms(&app.env, &mobile, &&message).await
+        }
+    };
+
+    if let Err(err) = result {
+        tracing::error!(message="Failed to send otp", err=?err);
+    }
+
+    Ok(Either::Left(InitiatedOtp {
+        session_id: key.as_ref().to_owned(),
+        duration: otp_expires_in_sec,
+        message: notification_message,
+    }))
+}
+
+// An OTP is single use, it is consumed on a successful verification and
+// the session is locked once the wrong guesses reach `max_otp_attempts`.
+pub async fn verify_otp(
+    app: &CustomersApp,
+    verify: &VerifyOTP,
+) -> eyre::Result<Either<(), ErrorMessage>> {
+    let req_cache: String = format!("OTP-{}", &verify.session_id);
+    let attempts_cache: String = format!("OTP-ATTEMPTS-{}", &verify.session_id);
+
+    // consumed by the request that matches it, concurrent requests with the same OTP fail
+    if app
+        .redis
+        .delete_key_if_equals(&req_cache, verify.otp_code.trim())
+        .await?
+    {
+        app.redis.delete_key(&attempts_cache).await?;
+        return Ok(Either::Left(()));
+    }
+    if app.redis.get_key(&req_cache).await?.is_none() {
+        return Ok(Either::Right(ErrorCode::OtpInvalid.into()));
+    }
+
+    let attempts = app
+        .redis
+        .increment_key(&attempts_cache, app.env.otp_expires_in_sec as u64)
+        .await?;
+    if attempts >= app.env.max_otp_attempts {
+        app.redis.delete_key(&req_cache).await?;
+        app.redis.delete_key(&attempts_cache).await?;
+        return Ok(Either::Right(ErrorCode::OtpTooManyAttempts.into()));
+    }
+
+    Ok(Either::Right(ErrorCode::OtpInvalid.into()))
+}
+
+fn hash_password(value: &str) -> eyre::Result<String> {
+    let salt = SaltString::generate(&mut OsRng);
+    Ok(Scrypt.hash_password(value.as_bytes(), &salt)?.to_string())
+}
+
+fn verify_password(value: &str, hash: &str) -> eyre::Result<()> {
+    let hash = PasswordHash::new(hash)?;
+    Scrypt.verify_password(value.as_bytes(), &hash)?;
+    Ok(())
+}
+
+pub async fn get_department_and_staffs(
+    app: &CustomersApp,
+    auth: &AuthorizedInstitutionUser,
+    department_id: &str,
+) -> eyre::Result<ApiResponse<DepartmentAndStaffDto>> {
+    let department = app
+        .db_pool
+        .get_institution_department(auth.institution.px, department_id)
+        .await?;
+
+    match department {
+        None => {
+            return Ok(api_error(ErrorCode::DepartmentNotFound));
+        }
+        Some(department) => {
+            let staffs = app
+                .db_pool
+                .find_department_staffs(auth.institution.px, department_id)
+                .await?
+                .into_iter()
+                .map(|s| s.into())
+                .collect::<Vec<StaffDto>>();
+
+            let department_head = staffs
+                .iter()
+                .find(|s| {
+                    department
+                        .head_staff_id
+                        .as_ref()
+                        .map(|id| id.eq_ignore_ascii_case(&s.id))
+                        .unwrap_or(false)
+                })
+                .cloned();
+
+            let department_and_staffs = DepartmentAndStaffDto {
+                staffs,
+                department: department.into(),
+                department_head,
+            };
+
+            Ok((
+                SUCCESS_API_STATUS_CODE,
+                Either::Left(Some(department_and_staffs)),
+            ))
+        }
+    }
+}
+
+// Department members must be assigned the built in Superuser role or one
+// defined by the institution.
+// the built in Admin department and Superuser role go by these, custom
+// roles and departments can't take them
+fn is_reserved_name(name: &str) -> bool {
+    let name = name.trim();
+    name.eq_ignore_ascii_case(ADMIN_ROLE) || name.eq_ignore_ascii_case(ADMIN_DOMAIN)
+}
+
+fn grants_admin_role(members: &[DepartmentMember]) -> bool {
+    members
+        .iter()
+        .any(|m| m.role.eq_ignore_ascii_case(ADMIN_ROLE))
+}
+
+// Admin department members and Superusers hold every action, only they can
+// grant or take away the Superuser role
+async fn is_superuser(app: &CustomersApp, auth: &AuthorizedInstitutionUser) -> eyre::Result<bool> {
+    if auth.is_workspace_admin() {
+        return Ok(true);
+    }
+    let Some(staff_id) = find_auth_staff_id(app, auth).await? else {
+        return Ok(false);
+    };
+    Ok(app
+        .db_pool
+        .find_staff_memberships(auth.institution.px, staff_id)
+        .await?
+        .iter()
+        .any(|m| m.role.eq_ignore_ascii_case(ADMIN_ROLE)))
+}
+
+fn superuser_required<T>() -> ApiResponse<T> {
+    ErrorMessage::with_message(
+        ErrorCode::AuthForbidden,
+        "Only a Superuser can grant or remove the Superuser role",
+    )
+    .response()
+}
+
+// True when the Superuser memberships `removed` takes away are the last ones of
+// the institution
+async fn removes_last_superuser<F>(
+    app: &CustomersApp,
+    institution_id: i64,
+    removed: F,
+) -> eyre::Result<bool>
+where
+    F: Fn(&DepartmentMembership) -> bool,
+{
+    let memberships = app
+        .db_pool
+        .find_role_memberships(institution_id, ADMIN_ROLE)
+        .await?;
+    Ok(!memberships.is_empty() && memberships.iter().all(removed))
+}
+
+async fn find_unknown_member_role(
+    app: &CustomersApp,
+    institution_id: i64,
+    members: &[DepartmentMember],
+) -> eyre::Result<Option<String>> {
+    let roles = app.db_pool.find_institution_roles(institution_id).await?;
+    Ok(members
+        .iter()
+        .find(|m| {
+            !m.role.eq_ignore_ascii_case(ADMIN_ROLE)
+                && !roles.iter().any(|r| r.name.eq_ignore_ascii_case(&m.role))
+        })
+        .map(|m| m.role.clone()))
+}
+
+async fn is_role_assigned(
+    app: &CustomersApp,
+    institution_id: i64,
+    role_name: &str,
+) -> eyre::Result<bool> {
+    let members = app
+        .db_pool
+        .count_role_members(institution_id, role_name)
+        .await?;
+    Ok(members > 0)
+}
+
+pub async fn find_roles(
+    app: &CustomersApp,
+    InstitutionAdminUser(auth): &InstitutionAdminUser,
+) -> eyre::Result<ApiResponse<Vec<RoleDto>>> {
+    let roles = app
+        .db_pool
+        .find_institution_roles(auth.institution.px)
+        .await?
+        .into_iter()
+        .map(|r| r.into())
+        .collect();
+    Ok((SUCCESS_API_STATUS_CODE, Either::Left(Some(roles))))
+}
+
+pub async fn create_role(
+    app: &CustomersApp,
+    InstitutionAdminUser(auth): &InstitutionAdminUser,
+    req: CreateRole,
+) -> eyre::Result<ApiResponse<RoleDto>> {
+    req.validate()?;
+    let name = req.name.trim();
+    if is_reserved_name(name) {
+        return Ok(api_error(ErrorCode::RoleInvalidName));
+    }
+
+    let roles = app
+        .db_pool
+        .find_institution_roles(auth.institution.px)
+        .await?;
+    if roles.iter().any(|r| r.name.eq_ignore_ascii_case(name)) {
+        return Ok(api_error(ErrorCode::RoleAlreadyExists));
+    }
+
+    let role = app
+        .db_pool
+        .create_role(auth.institution.px, name, &req.permissions)
+        .await?;
+    Ok((SUCCESS_API_STATUS_CODE, Either::Left(Some(role.into()))))
+}
+
+pub async fn edit_role(
+    app: &CustomersApp,
+    InstitutionAdminUser(auth): &InstitutionAdminUser,
+    role_id: &str,
+    req: CreateRole,
+) -> eyre::Result<ApiResponse<RoleDto>> {
+    req.validate()?;
+    let name = req.name.trim();
+    if is_reserved_name(name) {
+        return Ok(api_error(ErrorCode::RoleInvalidName));
+    }
+
+    let roles = app
+        .db_pool
+        .find_institution_roles(auth.institution.px)
+        .await?;
+    let role = match roles
+        .iter()
+        .find(|r| r.shadow_id.eq_ignore_ascii_case(role_id))
+    {
+        None => {
+            return Ok(api_error(ErrorCode::RoleNotFound));
+        }
+        Some(role) => role,
+    };
+
+    if !role.name.eq_ignore_ascii_case(name) {
+        if roles.iter().any(|r| r.name.eq_ignore_ascii_case(name)) {
+            return Ok(api_error(ErrorCode::RoleAlreadyExists));
+        }
+
+        // members reference the role by name
+        if is_role_assigned(app, auth.institution.px, &role.name).await? {
+            return Ok(ErrorMessage::with_message(
+                ErrorCode::RoleInUse,
+                "Role is assigned to department members and can't be renamed",
+            )
+            .response());
+        }
+    }
+
+    let role = app
+        .db_pool
+        .update_role(auth.institution.px, &role.shadow_id, name, &req.permissions)
+        .await?;
+    spawn_institution_session_refresh(app, auth.institution.px);
+
+    Ok((SUCCESS_API_STATUS_CODE, Either::Left(Some(role.into()))))
+}
+
+pub async fn delete_role(
+    app: &CustomersApp,
+    InstitutionAdminUser(auth): &InstitutionAdminUser,
+    role_id: &str,
+) -> eyre::Result<ApiResponse<()>> {
+    let role = match app
+        .db_pool
+        .get_institution_role(auth.institution.px, role_id)
+        .await?
+    {
+        None => {
+            return Ok(api_error(ErrorCode::RoleNotFound));
+        }
+        Some(role) => role,
+    };
+
+    if is_role_assigned(app, auth.institution.px, &role.name).await? {
+        return Ok(api_error(ErrorCode::RoleInUse));
+    }
+
+    app.db_pool
+        .delete_role(auth.institution.px, &role.shadow_id)
+        .await?;
+    Ok((SUCCESS_API_STATUS_CODE, Either::Left(None)))
+}
+
+pub async fn create_department(
+    app: &CustomersApp,
+    auth: &AuthorizedInstitutionUser,
+    create_department: CreateDepartment,
+) -> eyre::Result<ApiResponse<DepartmentDto>> {
+    create_department.validate()?;
+    let insitution_departments = app
+        .db_pool
+        .find_institution_departments(auth.institution.px, None)
+        .await?;
+    let insitution_departments = insitution_departments
+        .iter()
+        .find(|v| v.deleted_at.is_none() && !(is_adminstrative_department(&v.name)));
+
+    if insitution_departments.is_none() {
+        return Ok(api_error(ErrorCode::DepartmentAlreadyExists));
+    }
+
+    // a department named after the Admin department would grant every action
+    if is_reserved_name(&create_department.name) {
+        return Ok(api_error(ErrorCode::DepartmentInvalidName));
+    }
+
+    if let Some(unknown_role) =
+        find_unknown_member_role(app, auth.institution.px, &create_department.staff_ids).await?
+    {
+        return Ok(ErrorMessage::with_message(
+            ErrorCode::RoleUnknown,
+            format!("Unknown role: {}", unknown_role),
+        )
+        .response());
+    }
+
+    if grants_admin_role(&create_department.staff_ids) && !is_superuser(app, auth).await? {
+        return Ok(superuser_required());
+    }
+
+    let department = app
+        .db_pool
+        .create_department(
+            auth.institution.px,
+            create_department.name,
+            create_department.head_staff_id,
+            create_department.phone_no,
+            &create_department.staff_ids,
+            &create_department.domain,
+        )
+        .await?;
+
+    spawn_institution_session_refresh(app, auth.institution.px);
+
+    Ok((
+        SUCCESS_API_STATUS_CODE,
+        Either::Left(Some(department.into())),
+    ))
+}
+
+pub async fn edit_department(
+    app: &CustomersApp,
+    auth: &AuthorizedInstitutionUser,
+    department_id: &str,
+    create_department: CreateDepartment,
+) -> eyre::Result<ApiResponse<DepartmentDto>> {
+    create_department.validate()?;
+    let insitution_departments = app
+        .db_pool
+        .find_institution_departments(auth.institution.px, None)
+        .await?;
+    let insitution_departments = insitution_departments.iter().find(|v| {
+        v.deleted_at.is_none()
+            && !(is_adminstrative_department(&v.name))
+            && v.shadow_id.eq_ignore_ascii_case(department_id)
+    });
+
+    let existing_department = match insitution_departments {
+        None => {
+            return Ok(api_error(ErrorCode::DepartmentNotFound));
+        }
+        Some(department) => department,
+    };
+
+    if is_reserved_name(&create_department.name) {
+        return Ok(api_error(ErrorCode::DepartmentInvalidName));
+    }
+
+    if let Some(unknown_role) =
+        find_unknown_member_role(app, auth.institution.px, &create_department.staff_ids).await?
+    {
+        return Ok(ErrorMessage::with_message(
+            ErrorCode::RoleUnknown,
+            format!("Unknown role: {}", unknown_role),
+        )
+        .response());
+    }
+
+    // the members are replaced, Superusers among the old or the new ones
+    // can only be changed by a Superuser
+    let has_superusers = app
+        .db_pool
+        .find_department_memberships(existing_department.id)
+        .await?
+        .iter()
+        .any(|m| m.role.eq_ignore_ascii_case(ADMIN_ROLE));
+    if (has_superusers || grants_admin_role(&create_department.staff_ids))
+        && !is_superuser(app, auth).await?
+    {
+        return Ok(superuser_required());
+    }
+    if !grants_admin_role(&create_department.staff_ids)
+        && removes_last_superuser(app, auth.institution.px, |m| {
+            m.department_id == existing_department.id
+        })
+        .await?
+    {
+        return Ok(api_error(ErrorCode::RoleLastSuperuser));
+    }
+
+    let department = app
+        .db_pool
+        .edit_department(
+            department_id,
+            create_department.name,
+            create_department.head_staff_id,
+            create_department.phone_no,
+            &create_department.staff_ids,
+            &create_department.domain,
+        )
+        .await?;
+
+    spawn_institution_session_refresh(app, auth.institution.px);
+
+    Ok((
+        SUCCESS_API_STATUS_CODE,
+        Either::Left(Some(department.into())),
+    ))
+}
+
+pub async fn delete_department(
+    app: &CustomersApp,
+    auth: &AuthorizedInstitutionUser,
+    department_id: &str,
+) -> eyre::Result<ApiResponse<()>> {
+    let insitution_departments = app
+        .db_pool
+        .find_institution_departments(auth.institution.px, None)
+        .await?;
+    let insitution_departments = insitution_departments.iter().find(|v| {
+        v.deleted_at.is_none()
+            && !(is_adminstrative_department(&v.name))
+            && v.shadow_id.eq_ignore_ascii_case(department_id)
+    });
+
+    let department = match insitution_departments {
+        None => {
+            return Ok(api_error(ErrorCode::DepartmentNotFound));
+        }
+        Some(department) => department,
+    };
+
+    let has_superusers = app
+        .db_pool
+        .find_department_memberships(department.id)
+        .await?
+        .iter()
+        .any(|m| m.role.eq_ignore_ascii_case(ADMIN_ROLE));
+    if has_superusers && !is_superuser(app, auth).await? {
+        return Ok(superuser_required());
+    }
+    if removes_last_superuser(app, auth.institution.px, |m| {
+        m.department_id == department.id
+    })
+    .await?
+    {
+        return Ok(api_error(ErrorCode::RoleLastSuperuser));
+    }
+
+    app.db_pool.delete_department(department_id).await?;
+    spawn_institution_session_refresh(app, auth.institution.px);
+
+    Ok((SUCCESS_API_STATUS_CODE, Either::Left(None)))
+}
+
+pub async fn add_department_member(
+    app: &CustomersApp,
+    auth: &AuthorizedInstitutionUser,
+    department_id: &str,
+    member: DepartmentMember,
+) -> eyre::Result<ApiResponse<DepartmentMemberDto>> {
+    member.validate()?;
+    let institution_id = auth.institution.px;
+    let department = match app
+        .db_pool
+        .get_institution_department(institution_id, department_id)
+        .await?
+    {
+        None => {
+            return Ok(api_error(ErrorCode::DepartmentNotFound));
+        }
+        Some(department) => department,
+    };
+
+    let staff = match app
+        .db_pool
+        .find_institution_staff_by_id_opts(institution_id, &member.staff_id)
+        .await?
+        .filter(|s| s.deleted_at.is_none())
+    {
+        None => {
+            return Ok(api_error(ErrorCode::StaffNotFound));
+        }
+        Some(staff) => staff,
+    };
+
+    if let Some(unknown_role) =
+        find_unknown_member_role(app, institution_id, std::slice::from_ref(&member)).await?
+    {
+        return Ok(ErrorMessage::with_message(
+            ErrorCode::RoleUnknown,
+            format!("Unknown role: {}", unknown_role),
+        )
+        .response());
+    }
+
+    // the member's current role is replaced, the Admin department and the
+    // Superuser role are only handed out or taken away by a Superuser
+    let current_role = app
+        .db_pool
+        .find_staff_memberships(institution_id, staff.id)
+        .await?
+        .into_iter()
+        .find(|m| m.department_id == department.id)
+        .map(|m| m.role);
+    let changes_superuser = member.role.eq_ignore_ascii_case(ADMIN_ROLE)
+        || current_role.is_some_and(|role| role.eq_ignore_ascii_case(ADMIN_ROLE));
+    if (changes_superuser || is_adminstrative_department(&department.name))
+        && !is_superuser(app, auth).await?
+    {
+        return Ok(superuser_required());
+    }
+    if !member.role.eq_ignore_ascii_case(ADMIN_ROLE)
+        && removes_last_superuser(app, institution_id, |m| {
+            m.department_id == department.id && m.staff_id == staff.id
+        })
+        .await?
+    {
+        return Ok(api_error(ErrorCode::RoleLastSuperuser));
+    }
+
+    let membership = app
+        .db_pool
+        .add_department_member(department.id, staff.id, &member.role)
+        .await?;
+    refresh_user_session_cache(app, &staff.mobile).await?;
+
+    Ok((
+        SUCCESS_API_STATUS_CODE,
+        Either::Left(Some(DepartmentMemberDto {
+            staff_id: staff.shadow_id,
+            role: membership.role,
+            joined_at: membership.joined_at,
+        })),
+    ))
+}
+
+pub async fn remove_department_member(
+    app: &CustomersApp,
+    auth: &AuthorizedInstitutionUser,
+    department_id: &str,
+    staff_id: &str,
+) -> eyre::Result<ApiResponse<()>> {
+    let institution_id = auth.institution.px;
+    let department = match app
+        .db_pool
+        .get_institution_department(institution_id, department_id)
+        .await?
+    {
+        None => {
+            return Ok(api_error(ErrorCode::DepartmentNotFound));
+        }
+        Some(department) => department,
+    };
+
+    let staff = match app
+        .db_pool
+        .find_institution_staff_by_id_opts(institution_id, staff_id)
+        .await?
+    {
+        None => {
+            return Ok(api_error(ErrorCode::StaffNotFound));
+        }
+        Some(staff) => staff,
+    };
+
+    let is_superuser_membership = app
+        .db_pool
+        .find_staff_memberships(institution_id, staff.id)
+        .await?
+        .iter()
+        .any(|m| m.department_id == department.id && m.role.eq_ignore_ascii_case(ADMIN_ROLE));
+    if (is_superuser_membership || is_adminstrative_department(&department.name))
+        && !is_superuser(app, auth).await?
+    {
+        return Ok(superuser_required());
+    }
+    if removes_last_superuser(app, institution_id, |m| {
+        m.department_id == department.id && m.staff_id == staff.id
+    })
+    .await?
+    {
+        return Ok(api_error(ErrorCode::RoleLastSuperuser));
+    }
+
+    match app
+        .db_pool
+        .remove_department_member(department.id, staff.id)
+        .await?
+    {
+        None => Ok(api_error(ErrorCode::StaffNotDepartmentMember)),
+        Some(_) => {
+            refresh_user_session_cache(app, &staff.mobile).await?;
+            Ok((SUCCESS_API_STATUS_CODE, Either::Left(None)))
+        }
+    }
+}
+
+pub async fn add_staff(
+    app: &CustomersApp,
+    auth: &AuthorizedInstitutionUser,
+    mut new_staff: NewStaff,
+) -> eyre::Result<ApiResponse<StaffDto>> {
+    new_staff.validate()?;
+    new_staff.mobile = normalize_mobile(app, "mobile", &new_staff.mobile)?;
+
+    let institution_id = auth.institution.px;
+    let existing_staff = app
+        .db_pool
+        .find_staff_by_mobile_and_insitution_id_opts(institution_id, &new_staff.mobile)
+        .await?;
+    match existing_staff {
+        Some(mut staff) => {
+            if staff.deleted_at.is_none() {
+                return Ok(api_error(ErrorCode::StaffAlreadyExists));
+            }
+
+            staff.first_name = new_staff.first_name;
+            staff.last_name = new_staff.last_name;
+            staff.title = new_staff.title;
+            staff.profile_image = new_staff.profile_image;
+            staff.deleted_at = None;
+
+            staff = app.db_pool.update_staff(staff).await?;
+            // a declined or revoked invitee never joined, so they are invited again
+            let invitation = app.db_pool.find_latest_staff_invitation(staff.id).await?;
+            let accepted = invitation
+                .as_ref()
+                .is_some_and(|i| i.status == InvitationStatus::Accepted.as_str());
+            if !accepted || app.db_pool.get_user(&staff.mobile).await?.is_none() {
+                if let Some(mut pending) =
+                    invitation.filter(|i| i.status == InvitationStatus::Pending.as_str())
+                {
+                    pending.status = InvitationStatus::Revoked.as_str().into();
+                    app.db_pool.update_staff_invitation(pending).await?;
+                }
+                invite_staff(app, auth, &staff).await?;
+            }
+            refresh_user_session_cache(app, &staff.mobile).await?;
+            Ok((SUCCESS_API_STATUS_CODE, Either::Left(Some(staff.into()))))
+        }
+        None => {
+            let staff = app.db_pool.create_staff(institution_id, &new_staff).await?;
+            invite_staff(app, auth, &staff).await?;
+            Ok((SUCCESS_API_STATUS_CODE, Either::Left(Some(staff.into()))))
+        }
+    }
+}
+
+// Saves a new invitation for the staff and sends them its link
+async fn invite_staff(
+    app: &CustomersApp,
+    auth: &AuthorizedInstitutionUser,
+    staff: &Staff,
+) -> eyre::Result<()> {
+    let (token, invitation) = new_staff_invitation(app, find_auth_staff_id(app, auth).await?);
+    app.db_pool
+        .create_staff_invitation(auth.institution.px, staff, &invitation)
+        .await?;
+    if let Err(err) = send_staff_invite(
+        app,
+        &auth.institution.institution_name,
+        &staff.mobile,
+        &token,
+    )
+    .await
+    {
+        tracing::error!(mobile = staff.mobile, err = ?err, "Failed to send staff invite");
+    }
+    Ok(())
+}
+
+async fn find_auth_staff_id(
+    app: &CustomersApp,
+    auth: &AuthorizedInstitutionUser,
+) -> eyre::Result<Option<i64>> {
+    Ok(app
+        .db_pool
+        .find_institution_staff_by_id_opts(auth.institution.px, &auth.staff_id)
+        .await?
+        .map(|s| s.id))
+}
+
+// Signs a fresh invitation link, the invitation is saved by the caller
+fn new_staff_invitation(
+    app: &CustomersApp,
+    invited_by: Option<i64>,
+) -> (String, NewStaffInvitation) {
+    let shadow_id = Uuid::new_v4();
+    let (token, token_hash, expires_at) = sign_invitation(app, &shadow_id.to_string());
+    (
+        token,
+        NewStaffInvitation {
+            shadow_id,
+            token_hash,
+            invited_by,
+            expires_at,
+        },
+    )
+}
+
+fn sign_invitation(app: &CustomersApp, shadow_id: &str) -> (String, String, DateTime<Utc>) {
+    let expires_at = Utc::now() + chrono::Duration::hours(app.env.invitation_expires_in_hr as i64);
+    let (token, token_hash) = sign_invitation_until(app, shadow_id, expires_at);
+    (token, token_hash, expires_at)
+}
+
+fn sign_invitation_until(
+    app: &CustomersApp,
+    shadow_id: &str,
+    expires_at: DateTime<Utc>,
+) -> (String, String) {
+    let token = sign_token(
+        app.env.invitation_signing_key.as_bytes(),
+        shadow_id,
+        expires_at.timestamp(),
+    );
+    let token_hash = hash_token(&token);
+    (token, token_hash)
+}
+
+async fn send_staff_invite(
+    app: &CustomersApp,
+    institution_name: &str,
+    mobile: &str,
+    token: &str,
+) -> eyre::Result<()> {
+    let message = format!(
+        "You have been invited to join {} on {}. Accept the invitation at {}/invitations?token={} within {} hours",
+        institution_name,
+        app.env.app_url,
+        app.env.app_url,
+        token,
+        app.env.invitation_expires_in_hr
+    );
+    try_send_sms(&app.env, mobile, &message).await
+}
+
+struct PendingStaffImport {
+    staff: NewStaff,
+    department_id: Option<i64>,
+    role: Option<String>,
+}
+
+// Validates every row of the sheet, rows are paired with the staff to create
+// when they are valid.
+async fn validate_staff_import(
+    app: &CustomersApp,
+    institution_id: i64,
+    upload: &StaffImportUpload,
+) -> eyre::Result<Either<Vec<(StaffImportRow, Option<PendingStaffImport>)>, ErrorMessage>> {
+    let Some(format) =
+        StaffImportFormat::detect(upload.file_name.as_deref(), upload.content_type.as_deref())
+    else {
+        return Ok(Either::Right(ErrorCode::ImportUnsupportedFormat.into()));
+    };
+    let Ok(content) = BASE64_STANDARD.decode(upload.base64_data.trim()) else {
+        return Ok(Either::Right(ErrorMessage::with_message(
+            ErrorCode::ImportInvalidFile,
+            "The uploaded file is not valid base64",
+        )));
+    };
+    let sheet_rows = match parse_staff_sheet(format, &content) {
+        Ok(rows) => rows,
+        Err(message) => {
+            return Ok(Either::Right(ErrorMessage::with_message(
+                ErrorCode::ImportInvalidFile,
+                message,
+            )))
         }
-        Some(department) => {
-            let staffs = app
+    };
+
+    let mobiles: Vec<String> = sheet_rows
+        .iter()
+        .filter_map(|r| normalize_phone(&r.mobile, &app.env.default_phone_region))
+        .collect();
+    let existing_staffs = app.db_pool.find_staffs_by_mobiles(&mobiles).await?;
+    let departments = app
+        .db_pool
+        .find_institution_departments(institution_id, None)
+        .await?;
+    let roles = app.db_pool.find_institution_roles(institution_id).await?;
+
+    let mut seen_mobiles: BTreeMap<String, u32> = BTreeMap::new();
+    let mut rows = vec![];
+    for sheet_row in sheet_rows {
+        let mut errors: Vec<String> = vec![];
+        let row_staff = NewStaff {
+            first_name: sheet_row.first_name.clone(),
+            last_name: sheet_row.last_name.clone(),
+            mobile: sheet_row.mobile.clone(),
+            title: sheet_row.title.clone(),
+            profile_image: None,
+        };
+        if let Err(invalid) = row_staff.validate() {
+            // the mobile gets its own message below
+            errors.extend(
+                invalid
+                    .errors
+                    .into_iter()
+                    .filter(|e| e.field != "mobile")
+                    .map(|e| e.message),
+            );
+        }
+
+        let mobile = normalize_phone(&sheet_row.mobile, &app.env.default_phone_region);
+        match &mobile {
+            None => errors.push(format!("{} is not a valid mobile number", sheet_row.mobile)),
+            Some(mobile) => {
+                if let Some(first_row) = seen_mobiles.get(mobile) {
+                    errors.push(format!("Duplicate of row {}", first_row));
+                } else {
+                    seen_mobiles.insert(mobile.clone(), sheet_row.row_number);
+                }
+
+                match existing_staffs.iter().find(|s| &s.mobile == mobile) {
+                    Some(staff)
+                        if staff.institution_id == Some(institution_id)
+                            && staff.deleted_at.is_none() =>
+                    {
+                        errors.push("Staff already exists".into())
+                    }
+                    // add_staff restores them and invites them again when needed
+                    Some(staff) if staff.institution_id == Some(institution_id) => {
+                        errors.push("Staff was removed, re-add them individually".into())
+                    }
+                    Some(_) => errors.push("Mobile is in use by another staff record".into()),
+                    None => {}
+                }
+            }
+        }
+
+        let department = match &sheet_row.department {
+            None => None,
+            Some(name) => {
+                let department = departments
+                    .iter()
+                    .find(|d| d.name.eq_ignore_ascii_case(name) || &d.shadow_id == name);
+                if department.is_none() {
+                    errors.push(format!("Unknown department: {}", name));
+                }
+                department
+            }
+        };
+        match (&sheet_row.department, &sheet_row.role) {
+            (Some(_), None) => errors.push("role is required with a department".into()),
+            (None, Some(_)) => errors.push("department is required with a role".into()),
+            (_, Some(role)) if is_reserved_name(role) => {
+                errors.push(format!("{} can't be granted by import", role.trim()))
+            }
+            (_, Some(role)) if !roles.iter().any(|r| r.name.eq_ignore_ascii_case(role)) => {
+                errors.push(format!("Unknown role: {}", role))
+            }
+            _ => {}
+        }
+
+        let pending = match (&mobile, errors.is_empty()) {
+            (Some(mobile), true) => Some(PendingStaffImport {
+                staff: NewStaff {
+                    mobile: mobile.clone(),
+                    ..row_staff
+                },
+                department_id: department.map(|d| d.id),
+                role: sheet_row.role.clone(),
+            }),
+            _ => None,
+        };
+
+        let row = StaffImportRow {
+            row_number: sheet_row.row_number,
+            first_name: sheet_row.first_name,
+            last_name: sheet_row.last_name,
+            mobile: mobile.unwrap_or(sheet_row.mobile),
+            title: sheet_row.title,
+            department: sheet_row.department,
+            role: sheet_row.role,
+            status: if errors.is_empty() {
+                StaffImportRowStatus::Valid
+            } else {
+                StaffImportRowStatus::Invalid
+            },
+            errors,
+        };
+        rows.push((row, pending));
+    }
+
+    Ok(Either::Left(rows))
+}
+
+fn staff_import_report(
+    dry_run: bool,
+    failed_invites: u32,
+    rows: Vec<StaffImportRow>,
+) -> StaffImportReport {
+    let invalid_rows = rows
+        .iter()
+        .filter(|r| r.status == StaffImportRowStatus::Invalid)
+        .count() as u32;
+    StaffImportReport {
+        dry_run,
+        total_rows: rows.len() as u32,
+        valid_rows: rows.len() as u32 - invalid_rows,
+        invalid_rows,
+        failed_invites,
+        rows,
+    }
+}
+
+pub async fn dry_run_staff_import(
+    app: &CustomersApp,
+    auth: &AuthorizedInstitutionUser,
+    upload: &StaffImportUpload,
+) -> eyre::Result<ApiResponse<StaffImportReport>> {
+    let rows = match validate_staff_import(app, auth.institution.px, upload).await? {
+        Either::Left(rows) => rows,
+        Either::Right(message) => return Ok(message.response()),
+    };
+
+    let rows = rows.into_iter().map(|(row, _)| row).collect();
+    Ok((
+        SUCCESS_API_STATUS_CODE,
+        Either::Left(Some(staff_import_report(true, 0, rows))),
+    ))
+}
+
+/// Imports the sheet only when every row is valid, the invitations are sent
+/// once the staffs are saved and rows whose invitation failed are reported.
+pub async fn import_staffs(
+    app: &CustomersApp,
+    auth: &AuthorizedInstitutionUser,
+    upload: &StaffImportUpload,
+) -> eyre::Result<ApiResponse<StaffImportReport>> {
+    let institution_id = auth.institution.px;
+    let rows = match validate_staff_import(app, institution_id, upload).await? {
+        Either::Left(rows) => rows,
+        Either::Right(message) => return Ok(message.response()),
+    };
+
+    let invalid_rows = rows.iter().filter(|(_, pending)| pending.is_none()).count();
+    if invalid_rows > 0 {
+        return Ok(ErrorMessage::with_message(
+            ErrorCode::ImportInvalidRows,
+            format!(
+                "{} rows failed validation, review them with a dry run",
+                invalid_rows
+            ),
+        )
+        .response());
+    }
+
+    let invited_by = find_auth_staff_id(app, auth).await?;
+    let mut report_rows = vec![];
+    let mut entries = vec![];
+    let mut tokens = BTreeMap::new();
+    for (mut row, pending) in rows {
+        let Some(pending) = pending else { continue };
+        let (token, invitation) = new_staff_invitation(app, invited_by);
+        tokens.insert(pending.staff.mobile.clone(), token);
+        entries.push(StaffImportEntry {
+            staff: pending.staff,
+            invitation,
+            department_id: pending.department_id,
+            role: pending.role,
+        });
+        row.status = StaffImportRowStatus::Imported;
+        report_rows.push(row);
+    }
+
+    let imported = app.db_pool.import_staffs(institution_id, &entries).await?;
+    info!(
+        "Imported {} staffs into institution: {}",
+        imported.len(),
+        institution_id
+    );
+
+    let institution_name = &auth.institution.institution_name;
+    let failed_mobiles: BTreeSet<String> =
+        stream::iter(imported.into_iter().filter_map(|(staff, _)| {
+            let token = tokens.remove(&staff.mobile)?;
+            Some((staff.mobile, token))
+        }))
+        .map(|(mobile, token)| async move {
+            match send_staff_invite(app, institution_name, &mobile, &token).await {
+                Ok(()) => None,
+                Err(err) => {
+                    tracing::error!(mobile, err=?err, "Failed to send imported staff invite");
+                    Some(mobile)
+                }
+            }
+        })
+        .buffer_unordered(STAFF_INVITE_CONCURRENCY)
+        .filter_map(|failed| async move { failed })
+        .collect()
+        .await;
+
+    for row in report_rows.iter_mut() {
+        if failed_mobiles.contains(&row.mobile) {
+            row.errors
+                .push("Invitation could not be sent, resend it from the invitations".into());
+        }
+    }
+    Ok((
+        SUCCESS_API_STATUS_CODE,
+        Either::Left(Some(staff_import_report(
+            false,
+            failed_mobiles.len() as u32,
+            report_rows,
+        ))),
+    ))
+}
+
+async fn find_invited_staff(
+    app: &CustomersApp,
+    invitation: &StaffInvitation,
+) -> eyre::Result<Option<Staff>> {
+    Ok(app
+        .db_pool
+        .find_staffs_by_mobiles(std::slice::from_ref(&invitation.mobile))
+        .await?
+        .into_iter()
+        .find(|s| s.id == invitation.staff_id))
+}
+
+// Only the latest link sent for a pending invitation is accepted
+async fn find_linked_invitation(
+    app: &CustomersApp,
+    token: &str,
+) -> eyre::Result<Either<StaffInvitation, ErrorMessage>> {
+    let invalid_link = || Either::Right(ErrorCode::InvitationInvalid.into());
+    let Some(shadow_id) = verify_signed_token(
+        app.env.invitation_signing_key.as_bytes(),
+        token,
+        Utc::now().timestamp(),
+    ) else {
+        return Ok(invalid_link());
+    };
+    let Some(invitation) = app.db_pool.get_staff_invitation(&shadow_id).await? else {
+        return Ok(invalid_link());
+    };
+    if invitation.token_hash != hash_token(token) {
+        return Ok(invalid_link());
+    }
+
+    match invitation.invitation_status() {
+        InvitationStatus::Pending => Ok(Either::Left(invitation)),
+        InvitationStatus::Expired => Ok(invalid_link()),
+        status => Ok(Either::Right(ErrorMessage::with_message(
+            ErrorCode::InvitationNotPending,
+            format!(
+                "Invitation has already been {}",
+                status.as_str().to_lowercase()
+            ),
+        ))),
+    }
+}
+
+pub async fn get_invitation(
+    app: &CustomersApp,
+    req: &InvitationTokenReq,
+) -> eyre::Result<ApiResponse<InvitationDetailsDto>> {
+    let invitation = match find_linked_invitation(app, &req.token).await? {
+        Either::Left(invitation) => invitation,
+        Either::Right(message) => return Ok(message.response()),
+    };
+    let (Some(staff), Some(institution)) = (
+        find_invited_staff(app, &invitation).await?,
+        app.db_pool
+            .get_institution(invitation.institution_id)
+            .await?,
+    ) else {
+        return Ok(api_error(ErrorCode::InvitationNotFound));
+    };
+    let requires_password = app.db_pool.get_user(&staff.mobile).await?.is_none();
+
+    Ok((
+        SUCCESS_API_STATUS_CODE,
+        Either::Left(Some(InvitationDetailsDto {
+            institution_name: institution.name,
+            first_name: staff.first_name,
+            last_name: staff.last_name,
+            title: staff.title,
+            mobile: mask_phone(&staff.mobile),
+            requires_password,
+            expires_at: invitation.expires_at,
+        })),
+    ))
+}
+
+/// The invitee sets their password when accepting, unless the mobile already
+/// has an account in which case the password is ignored. The staff account
+/// only shows up on the invitee's sessions once accepted.
+pub async fn accept_invitation(
+    app: &CustomersApp,
+    req: &AcceptInvitationReq,
+) -> eyre::Result<ApiResponse<()>> {
+    let invitation = match find_linked_invitation(app, &req.token).await? {
+        Either::Left(invitation) => invitation,
+        Either::Right(message) => return Ok(message.response()),
+    };
+
+    let mut password_hashed = None;
+    if app.db_pool.get_user(&invitation.mobile).await?.is_none() {
+        let Some(password) = &req.password else {
+            return Ok(api_error(ErrorCode::AuthPasswordRequired));
+        };
+        if let Some(violation) = check_password_policy(password, app.env.min_password_length) {
+            return Ok(
+                ErrorMessage::with_message(ErrorCode::AuthPasswordPolicy, violation).response(),
+            );
+        }
+        password_hashed = Some(hash_password(password)?);
+    }
+
+    let Some(invitation) = app
+        .db_pool
+        .accept_staff_invitation(
+            invitation.id,
+            &invitation.mobile,
+            password_hashed.as_deref(),
+        )
+        .await?
+    else {
+        return Ok(api_error(ErrorCode::InvitationNotPending));
+    };
+    refresh_user_session_cache(app, &invitation.mobile).await?;
+    Ok((SUCCESS_API_STATUS_CODE, Either::Left(None)))
+}
+
+pub async fn decline_invitation(
+    app: &CustomersApp,
+    req: &InvitationTokenReq,
+) -> eyre::Result<ApiResponse<()>> {
+    let mut invitation = match find_linked_invitation(app, &req.token).await? {
+        Either::Left(invitation) => invitation,
+        Either::Right(message) => return Ok(message.response()),
+    };
+
+    invitation.status = InvitationStatus::Declined.as_str().into();
+    invitation.responded_at = Some(Utc::now());
+    let invitation = app.db_pool.update_staff_invitation(invitation).await?;
+    remove_invited_staff(app, &invitation).await?;
+    Ok((SUCCESS_API_STATUS_CODE, Either::Left(None)))
+}
+
+// a declined or revoked invitee never joined, so their staff record goes too
+async fn remove_invited_staff(
+    app: &CustomersApp,
+    invitation: &StaffInvitation,
+) -> eyre::Result<()> {
+    if let Some(staff) = find_invited_staff(app, invitation).await? {
+        app.db_pool.delete_staff(&staff.shadow_id).await?;
+    }
+    refresh_user_session_cache(app, &invitation.mobile).await
+}
+
+/// Pending invitations of the institution, including the expired ones
+pub async fn find_invitations(
+    app: &CustomersApp,
+    auth: &AuthorizedInstitutionUser,
+) -> eyre::Result<ApiResponse<Vec<StaffInvitationDto>>> {
+    let invitations = app
+        .db_pool
+        .find_institution_invitations(auth.institution.px, InvitationStatus::Pending.as_str())
+        .await?;
+    let mobiles: Vec<String> = invitations.iter().map(|i| i.mobile.clone()).collect();
+    let staffs = app.db_pool.find_staffs_by_mobiles(&mobiles).await?;
+
+    let invitations = invitations
+        .into_iter()
+        .filter_map(|invitation| {
+            let staff = staffs.iter().find(|s| s.id == invitation.staff_id)?;
+            Some(invitation.to_dto(staff))
+        })
+        .collect();
+    Ok((SUCCESS_API_STATUS_CODE, Either::Left(Some(invitations))))
+}
+
+async fn find_pending_institution_invitation(
+    app: &CustomersApp,
+    auth: &AuthorizedInstitutionUser,
+    invitation_id: &str,
+) -> eyre::Result<Either<StaffInvitation, ErrorMessage>> {
+    let invitation = app
+        .db_pool
+        .get_staff_invitation(invitation_id)
+        .await?
+        .filter(|i| i.institution_id == auth.institution.px);
+    match invitation {
+        None => Ok(Either::Right(ErrorCode::InvitationNotFound.into())),
+        Some(invitation) if invitation.status != InvitationStatus::Pending.as_str() => {
+            Ok(Either::Right(ErrorCode::InvitationNotPending.into()))
+        }
+        Some(invitation) => Ok(Either::Left(invitation)),
+    }
+}
+
+/// Sends a new link and restarts the expiry, earlier links stop working
+pub async fn resend_invitation(
+    app: &CustomersApp,
+    auth: &AuthorizedInstitutionUser,
+    invitation_id: &str,
+) -> eyre::Result<ApiResponse<StaffInvitationDto>> {
+    let mut invitation = match find_pending_institution_invitation(app, auth, invitation_id).await?
+    {
+        Either::Left(invitation) => invitation,
+        Either::Right(message) => return Ok(message.response()),
+    };
+    let Some(staff) = find_invited_staff(app, &invitation).await? else {
+        return Ok(api_error(ErrorCode::StaffNotFound));
+    };
+
+    let (token, token_hash, expires_at) = sign_invitation(app, &invitation.shadow_id);
+    invitation.token_hash = token_hash;
+    invitation.expires_at = expires_at;
+    invitation.last_sent_at = Utc::now();
+    invitation.reminder_count = 0;
+    let invitation = app.db_pool.update_staff_invitation(invitation).await?;
+
+    if let Err(err) = send_staff_invite(
+        app,
+        &auth.institution.institution_name,
+        &invitation.mobile,
+        &token,
+    )
+    .await
+    {
+        tracing::error!(mobile = invitation.mobile, err = ?err, "Failed to resend staff invite");
+    }
+    Ok((
+        SUCCESS_API_STATUS_CODE,
+        Either::Left(Some(invitation.to_dto(&staff))),
+    ))
+}
+
+pub async fn revoke_invitation(
+    app: &CustomersApp,
+    auth: &AuthorizedInstitutionUser,
+    invitation_id: &str,
+) -> eyre::Result<ApiResponse<()>> {
+    let mut invitation = match find_pending_institution_invitation(app, auth, invitation_id).await?
+    {
+        Either::Left(invitation) => invitation,
+        Either::Right(message) => return Ok(message.response()),
+    };
+
+    invitation.status = InvitationStatus::Revoked.as_str().into();
+    let invitation = app.db_pool.update_staff_invitation(invitation).await?;
+    remove_invited_staff(app, &invitation).await?;
+    Ok((SUCCESS_API_STATUS_CODE, Either::Left(None)))
+}
+
+/// Re-sends pending invitations that have gone unanswered, each reminder
+/// carries a new link expiring with the invitation, expired invitations are
+/// left for the admins to resend. Returns the number of reminders sent.
+pub async fn send_invitation_reminders(app: &CustomersApp) -> eyre::Result<usize> {
+    let sent_before =
+        Utc::now() - chrono::Duration::hours(app.env.invitation_reminder_after_hr as i64);
+    let invitations = app
+        .db_pool
+        .find_invitations_due_reminder(sent_before, app.env.invitation_max_reminders)
+        .await?;
+
+    let mut institution_names: BTreeMap<i64, String> = BTreeMap::new();
+    let mut reminded = 0;
+    for mut invitation in invitations {
+        if let Entry::Vacant(entry) = institution_names.entry(invitation.institution_id) {
+            let Some(institution) = app
                 .db_pool
-                .find_department_staffs(auth.institution.px, department_id)
+                .get_institution(invitation.institution_id)
                 .await?
-                .into_iter()
-                .map(|s| s.into())
-                .collect::<Vec<StaffDto>>();
+            else {
+                continue;
+            };
+            entry.insert(institution.name);
+        }
+
+        // the reminder link replaces the last one sent but keeps its expiry
+        let (token, token_hash) =
+            sign_invitation_until(app, &invitation.shadow_id, invitation.expires_at);
+        invitation.token_hash = token_hash;
+        invitation.last_sent_at = Utc::now();
+        invitation.reminder_count += 1;
+        let invitation = app.db_pool.update_staff_invitation(invitation).await?;
+
+        match send_staff_invite(
+            app,
+            &institution_names[&invitation.institution_id],
+            &invitation.mobile,
+            &token,
+        )
+        .await
+        {
+            Ok(()) => reminded += 1,
+            Err(err) => {
+                tracing::error!(mobile = invitation.mobile, err = ?err, "Failed to send invitation reminder")
+            }
+        }
+    }
+
+    Ok(reminded)
+}
+
+pub async fn edit_staff(
+    app: &CustomersApp,
+    auth: &AuthorizedInstitutionUser,
+    staff_id: &str,
+    new_staff: NewStaff,
+) -> eyre::Result<ApiResponse<StaffDto>> {
+    new_staff.validate()?;
+    let institution_id = auth.institution.px;
+    let existing_staff: Option<Staff> = app
+        .db_pool
+        .find_institution_staff_by_id_opts(institution_id, staff_id)
+        .await?;
+    match existing_staff {
+        None => {
+            return Ok(api_error(ErrorCode::StaffNotFound));
+        }
+        Some(mut staff) => {
+            staff.first_name = new_staff.first_name;
+            staff.last_name = new_staff.last_name;
+            staff.title = new_staff.title;
+            staff.profile_image = new_staff.profile_image;
+
+            staff = app.db_pool.update_staff(staff).await?;
+            refresh_user_session_cache(app, &staff.mobile).await?;
+            Ok((SUCCESS_API_STATUS_CODE, Either::Left(Some(staff.into()))))
+        }
+    }
+}
+
+pub async fn delete_staff(
+    app: &CustomersApp,
+    auth: &AuthorizedInstitutionUser,
+    staff_id: &str,
+) -> eyre::Result<ApiResponse<()>> {
+    let institution_id = auth.institution.px;
+
+    let existing_staff: Option<Staff> = app
+        .db_pool
+        .find_institution_staff_by_id_opts(institution_id, staff_id)
+        .await?;
+    match existing_staff {
+        None => {
+            return Ok(api_error(ErrorCode::StaffNotFound));
+        }
+        Some(staff) => {
+            let holds_superuser = app
+                .db_pool
+                .find_staff_memberships(institution_id, staff.id)
+                .await?
+                .iter()
+                .any(|m| m.role.eq_ignore_ascii_case(ADMIN_ROLE));
+            if holds_superuser && !is_superuser(app, auth).await? {
+                return Ok(superuser_required());
+            }
+            if removes_last_superuser(app, institution_id, |m| m.staff_id == staff.id).await? {
+                return Ok(api_error(ErrorCode::RoleLastSuperuser));
+            }
+
+            app.db_pool.delete_staff(staff_id).await?;
+            let revoked = revoke_user_sessions(app.redis.as_ref(), &staff.mobile).await?;
+            info!(
+                "Revoked {} session(s) of deleted staff: {}",
+                revoked, staff.shadow_id
+            );
+            Ok((SUCCESS_API_STATUS_CODE, Either::Left(None)))
+        }
+    }
+}
+
+pub async fn unlock_staff(
+    app: &CustomersApp,
+    auth: &AuthorizedInstitutionUser,
+    staff_id: &str,
+) -> eyre::Result<ApiResponse<()>> {
+    let institution_id = auth.institution.px;
+
+    let existing_staff: Option<Staff> = app
+        .db_pool
+        .find_institution_staff_by_id_opts(institution_id, staff_id)
+        .await?;
+    match existing_staff {
+        None => {
+            return Ok(api_error(ErrorCode::StaffNotFound));
+        }
+        Some(staff) => {
+            app.db_pool.reset_user_lockout(&staff.mobile).await?;
+            info!(
+                "Lockout reset for staff: {} by {}",
+                staff.shadow_id, auth.staff_id
+            );
+            Ok((SUCCESS_API_STATUS_CODE, Either::Left(None)))
+        }
+    }
+}
+
+async fn institution_profile(
+    app: &CustomersApp,
+    institution: Institution,
+) -> eyre::Result<InstitutionProfileDto> {
+    let logo_url = match &institution.logo {
+        None => None,
+        Some(logo) => Some(
+            get_presigned_object_url(
+                &app.s3_client,
+                &app.env.cloudflare_r2_bucket,
+                logo,
+                app.env.presigned_url_expires_in_sec,
+            )
+            .await?,
+        ),
+    };
+    let created_by = app
+        .db_pool
+        .get_staff(institution.created_by)
+        .await?
+        .map(|s| s.shadow_id);
+
+    Ok(InstitutionProfileDto {
+        logo_url,
+        created_by,
+        created_at: institution.created_at,
+        modified_at: institution.modified_at,
+        institution: institution.into(),
+    })
+}
 
-            let department_head = staffs
-                .iter()
-                .find(|s| {
-                    department
-                        .head_staff_id
-                        .as_ref()
-                        .map(|id| id.eq_ignore_ascii_case(&s.id))
-                        .unwrap_or(false)
-                })
-                .cloned();
+fn institution_changes(before: &Institution, after: &Institution) -> Vec<InstitutionFieldChange> {
+    [
+        (
+            "institution_name",
+            Some(before.name.clone()),
+            Some(after.name.clone()),
+        ),
+        (
+            "classification",
+            Some(before.classification.clone()),
+            Some(after.classification.clone()),
+        ),
+        (
+            "setting",
+            Some(before.setting.clone()),
+            Some(after.setting.clone()),
+        ),
+        ("address", before.address.clone(), after.address.clone()),
+        ("town", before.town.clone(), after.town.clone()),
+        ("state", before.state.clone(), after.state.clone()),
+        ("logo", before.logo.clone(), after.logo.clone()),
+    ]
+    .into_iter()
+    .filter(|(_, from, to)| from != to)
+    .map(|(field, from, to)| InstitutionFieldChange {
+        field: field.to_owned(),
+        from,
+        to,
+    })
+    .collect()
+}
 
-            let department_and_staffs = DepartmentAndStaffDto {
-                staffs,
-                department: department.into(),
-                department_head,
-            };
+// Saves the edited profile with its history, the institution is embedded in
+// the session of every staff so their sessions are refreshed.
+async fn save_institution(
+    app: &CustomersApp,
+    auth: &AuthorizedInstitutionUser,
+    institution: Institution,
+    edited: Institution,
+) -> eyre::Result<ApiResponse<InstitutionProfileDto>> {
+    let changes = institution_changes(&institution, &edited);
+    if changes.is_empty() {
+        let profile = institution_profile(app, institution).await?;
+        return Ok((SUCCESS_API_STATUS_CODE, Either::Left(Some(profile))));
+    }
 
-            Ok((
-                SUCCESS_API_STATUS_CODE,
-                Either::Left(Some(department_and_staffs)),
-            ))
+    let changed_by = find_auth_staff_id(app, auth).await?;
+    let institution = app
+        .db_pool
+        .update_institution(edited, changed_by, &changes)
+        .await?;
+    info!(
+        "Institution: {} profile changed by {}",
+        institution.shadow_id, auth.staff_id
+    );
+    spawn_institution_session_refresh(app, institution.id);
+
+    let profile = institution_profile(app, institution).await?;
+    Ok((SUCCESS_API_STATUS_CODE, Either::Left(Some(profile))))
+}
+
+pub async fn get_institution_profile(
+    app: &CustomersApp,
+    auth: &AuthorizedInstitutionUser,
+) -> eyre::Result<ApiResponse<InstitutionProfileDto>> {
+    match app.db_pool.get_institution(auth.institution.px).await? {
+        None => Ok(api_error(ErrorCode::InstitutionNotFound)),
+        Some(institution) => {
+            let profile = institution_profile(app, institution).await?;
+            Ok((SUCCESS_API_STATUS_CODE, Either::Left(Some(profile))))
         }
     }
 }
 
-pub async fn create_department(
+pub async fn edit_institution(
     app: &CustomersApp,
     InstitutionAdminUser(auth): &InstitutionAdminUser,
-    create_department: CreateDepartment,
-) -> eyre::Result<ApiResponse<DepartmentDto>> {
-    let insitution_departments = app
-        .db_pool
-        .find_institution_departments(auth.institution.px, None)
-        .await?;
-    let insitution_departments = insitution_departments
-        .iter()
-        .find(|v| v.deleted_at.is_none() && !(is_adminstrative_department(&v.name)));
+    req: EditInstitution,
+) -> eyre::Result<ApiResponse<InstitutionProfileDto>> {
+    req.validate()?;
+    match app.db_pool.get_institution(auth.institution.px).await? {
+        None => Ok(api_error(ErrorCode::InstitutionNotFound)),
+        Some(institution) => {
+            let mut edited = institution.clone();
+            edited.name = req.institution_name;
+            edited.classification = req.classification;
+            edited.setting = req.setting;
+            edited.address = req.address;
+            edited.town = req.town;
+            edited.state = req.state;
+            save_institution(app, auth, institution, edited).await
+        }
+    }
+}
 
-    if insitution_departments.is_none() {
-        return Ok((
-            BAD_REQUEST_API_STATUS_CODE,
-            Either::Right(ErrorMessage("Department already exists".into())),
-        ));
+/// The uploaded bytes decide the stored content type, the logo it replaces
+/// is deleted once the new one is saved.
+pub async fn upload_institution_logo(
+    app: &CustomersApp,
+    InstitutionAdminUser(auth): &InstitutionAdminUser,
+    req: &InstitutionLogoUpload,
+) -> eyre::Result<ApiResponse<InstitutionProfileDto>> {
+    req.validate()?;
+    let Ok(content) = BASE64_STANDARD.decode(req.base64_data.trim()) else {
+        return Err(ValidationErrors::single(
+            "base64_data",
+            ValidationCode::InvalidFormat,
+            "base64_data is not valid base64".into(),
+        )
+        .into());
+    };
+    let Some(content_type) = sniff_logo_content_type(&content) else {
+        return Err(ValidationErrors::single(
+            "base64_data",
+            ValidationCode::InvalidFormat,
+            "logo must be a PNG, JPEG or WebP image".into(),
+        )
+        .into());
+    };
+
+    match app.db_pool.get_institution(auth.institution.px).await? {
+        None => Ok(api_error(ErrorCode::InstitutionNotFound)),
+        Some(institution) => {
+            let path = format!(
+                "institution-logos/I{}S{}-T{}",
+                auth.institution.id,
+                auth.staff_id,
+                chrono::Utc::now().timestamp_millis()
+            );
+            upload_file_to_bucket(
+                &app.s3_client,
+                &app.env.cloudflare_r2_bucket,
+                &path,
+                &content,
+                Some(content_type.to_owned()),
+            )
+            .await?;
+
+            let replaced_logo = institution.logo.clone();
+            let mut edited = institution.clone();
+            edited.logo = Some(path);
+            let saved = save_institution(app, auth, institution, edited).await?;
+            // logos set at registration aren't uploaded by us, only the
+            // institution's own uploads are deleted
+            let own_logos = format!("institution-logos/I{}S", auth.institution.id);
+            if let Some(logo) = replaced_logo.filter(|l| l.starts_with(&own_logos)) {
+                if let Err(err) =
+                    delete_file(&app.s3_client, app.env.cloudflare_r2_bucket.as_str(), &logo).await
+                {
+                    tracing::error!(logo, err = ?err, "Failed to delete replaced institution logo");
+                }
+            }
+            Ok(saved)
+        }
     }
+}
 
-    let department = app
+pub async fn find_institution_changes(
+    app: &CustomersApp,
+    auth: &AuthorizedInstitutionUser,
+    pagination: &PaginatedQuery,
+) -> eyre::Result<(StatusCode, PaginatedResult<InstitutionChangeDto>)> {
+    let (changes, total) = app
         .db_pool
-        .create_department(
-            auth.institution.px,
-            create_department.name,
-            create_department.head_staff_id,
-            create_department.phone_no,
-            &create_department.staff_ids,
-            &create_department.domain,
-        )
+        .paginate_institution_changes(auth.institution.px, pagination)
         .await?;
 
     Ok((
         SUCCESS_API_STATUS_CODE,
-        Either::Left(Some(department.into())),
+        PaginatedResult::new(
+            changes.into_iter().map(|c| c.into()).collect(),
+            total,
+            pagination,
+        ),
     ))
 }
 
-pub async fn edit_department(
+const EXPORT_TOKEN_PREFIX: &str = "export-";
+const EXPORT_FOLDER: &str = "institution-exports";
+// export links of active institutions, deactivated institutions' links last
+// until the purge
+const EXPORT_LINK_EXPIRES_IN_DAYS: i64 = 7;
+
+async fn export_dto(
+    app: &CustomersApp,
+    export: &InstitutionExport,
+) -> eyre::Result<InstitutionExportDto> {
+    let download_url = match (&export.file_key, export.export_status()) {
+        (Some(file_key), InstitutionExportStatus::Ready) => Some(
+            get_presigned_object_url(
+                &app.s3_client,
+                &app.env.cloudflare_r2_bucket,
+                file_key,
+                app.env.presigned_url_expires_in_sec,
+            )
+            .await?,
+        ),
+        _ => None,
+    };
+    Ok(export.to_dto(download_url))
+}
+
+// A pending export is reused so repeated requests don't queue more archives
+async fn queue_institution_export(
+    app: &CustomersApp,
+    institution_id: i64,
+    requested_by: Option<i64>,
+) -> eyre::Result<InstitutionExport> {
+    let exports = app.db_pool.find_institution_exports(institution_id).await?;
+    if let Some(pending) = exports
+        .into_iter()
+        .find(|e| e.export_status() == InstitutionExportStatus::Pending)
+    {
+        return Ok(pending);
+    }
+    app.db_pool
+        .create_institution_export(institution_id, requested_by)
+        .await
+}
+
+/// Deactivates the admin's institution, its staffs lose access right away and
+/// its data is purged after the grace period. An export of the data is queued
+/// and emailed to the institution.
+pub async fn deactivate_institution(
     app: &CustomersApp,
     InstitutionAdminUser(auth): &InstitutionAdminUser,
-    department_id: &str,
-    create_department: CreateDepartment,
-) -> eyre::Result<ApiResponse<DepartmentDto>> {
-    let insitution_departments = app
-        .db_pool
-        .find_institution_departments(auth.institution.px, None)
-        .await?;
-    let insitution_departments = insitution_departments.iter().find(|v| {
-        v.deleted_at.is_none()
-            && !(is_adminstrative_department(&v.name))
-            && v.shadow_id.eq_ignore_ascii_case(department_id)
-    });
+    req: &DeactivateInstitutionReq,
+) -> eyre::Result<ApiResponse<InstitutionDeactivationDto>> {
+    req.validate()?;
+    let Some(institution) = app.db_pool.get_institution(auth.institution.px).await? else {
+        return Ok(api_error(ErrorCode::InstitutionNotFound));
+    };
 
-    if insitution_departments.is_none() {
-        return Ok((
-            NOT_FOUND_API_STATUS_CODE,
-            Either::Right(ErrorMessage("Department not found".into())),
-        ));
+    let user = match app.db_pool.get_user(&auth.mobile).await? {
+        None => {
+            return Ok(api_error(ErrorCode::AccountNotFound));
+        }
+        Some(user) => user,
+    };
+    if verify_password(&req.password, &user.password).is_err() {
+        return Ok(api_error(ErrorCode::AuthInvalidCurrentPassword));
     }
 
-    let department = app
+    let changed_by = find_auth_staff_id(app, auth).await?;
+    let purge_at =
+        Utc::now() + chrono::Duration::days(app.env.institution_purge_grace_in_days as i64);
+    let institution = app
         .db_pool
-        .edit_department(
-            department_id,
-            create_department.name,
-            create_department.head_staff_id,
-            create_department.phone_no,
-            &create_department.staff_ids,
-            &create_department.domain,
-        )
+        .deactivate_institution(institution.id, purge_at, changed_by)
         .await?;
+    flag_deactivated_workspace(app.redis.as_ref(), &institution.workspace_code).await?;
+    info!(
+        "Institution: {} deactivated by {}, purge at {}",
+        institution.shadow_id, auth.staff_id, purge_at
+    );
+
+    let export = queue_institution_export(app, institution.id, changed_by).await?;
+    spawn_institution_session_refresh(app, institution.id);
 
     Ok((
         SUCCESS_API_STATUS_CODE,
-        Either::Left(Some(department.into())),
+        Either::Left(Some(InstitutionDeactivationDto {
+            purge_at,
+            export: export.to_dto(None),
+        })),
     ))
 }
 
-pub async fn delete_department(
+pub async fn request_institution_export(
     app: &CustomersApp,
     InstitutionAdminUser(auth): &InstitutionAdminUser,
-    department_id: &str,
-) -> eyre::Result<ApiResponse<()>> {
-    let insitution_departments = app
+) -> eyre::Result<ApiResponse<InstitutionExportDto>> {
+    let requested_by = find_auth_staff_id(app, auth).await?;
+    let export = queue_institution_export(app, auth.institution.px, requested_by).await?;
+    Ok((
+        StatusCode::ACCEPTED,
+        Either::Left(Some(export.to_dto(None))),
+    ))
+}
+
+pub async fn find_institution_exports(
+    app: &CustomersApp,
+    InstitutionAdminUser(auth): &InstitutionAdminUser,
+) -> eyre::Result<ApiResponse<Vec<InstitutionExportDto>>> {
+    let exports = app
         .db_pool
-        .find_institution_departments(auth.institution.px, None)
+        .find_institution_exports(auth.institution.px)
         .await?;
-    let insitution_departments = insitution_departments.iter().find(|v| {
-        v.deleted_at.is_none()
-            && !(is_adminstrative_department(&v.name))
-            && v.shadow_id.eq_ignore_ascii_case(department_id)
-    });
-
-    if insitution_departments.is_none() {
-        return Ok((
-            NOT_FOUND_API_STATUS_CODE,
-            Either::Right(ErrorMessage("Department not found".into())),
-        ));
+    let mut dtos = vec![];
+    for export in &exports {
+        dtos.push(export_dto(app, export).await?);
     }
+    Ok((SUCCESS_API_STATUS_CODE, Either::Left(Some(dtos))))
+}
 
-    app.db_pool.delete_department(department_id).await?;
+/// Resolves the emailed export link, it is the only way to the archive once
+/// the institution is deactivated.
+pub async fn get_export_download(
+    app: &CustomersApp,
+    req: &ExportDownloadReq,
+) -> eyre::Result<ApiResponse<InstitutionExportDto>> {
+    let Some(shadow_id) = verify_signed_token(
+        app.env.invitation_signing_key.as_bytes(),
+        &req.token,
+        Utc::now().timestamp(),
+    )
+    .and_then(|subject| {
+        subject
+            .strip_prefix(EXPORT_TOKEN_PREFIX)
+            .map(|s| s.to_owned())
+    }) else {
+        return Ok(api_error(ErrorCode::ExportLinkInvalid));
+    };
 
-    Ok((SUCCESS_API_STATUS_CODE, Either::Left(None)))
+    let Some(export) = app.db_pool.get_institution_export(&shadow_id).await? else {
+        return Ok(api_error(ErrorCode::ExportLinkInvalid));
+    };
+    if export.export_status() != InstitutionExportStatus::Ready {
+        return Ok(api_error(ErrorCode::ExportNotReady));
+    }
+
+    let export = export_dto(app, &export).await?;
+    Ok((SUCCESS_API_STATUS_CODE, Either::Left(Some(export))))
 }
 
-pub async fn add_staff(
+// Gathers the records of every module and the documents they reference, a
+// document missing from the bucket is listed in the manifest instead.
+async fn build_institution_archive(
     app: &CustomersApp,
-    InstitutionAdminUser(auth): &InstitutionAdminUser,
-    new_staff: NewStaff,
-) -> eyre::Result<ApiResponse<StaffDto>> {
-    let institution_id = auth.institution.px;
-    let existing_staff = app
-        .db_pool
-        .find_staff_by_mobile_and_insitution_id_opts(institution_id, &new_staff.mobile)
-        .await?;
-    match existing_staff {
-        Some(mut staff) => {
-            if staff.deleted_at.is_none() {
-                return Ok((
-                    BAD_REQUEST_API_STATUS_CODE,
-                    Either::Right(ErrorMessage("Staff already exists".into())),
-                ));
-            }
-
-            staff.first_name = new_staff.first_name;
-            staff.last_name = new_staff.last_name;
-            staff.title = new_staff.title;
-            staff.profile_image = new_staff.profile_image;
-            staff.deleted_at = None;
+    institution: &Institution,
+) -> eyre::Result<Vec<u8>> {
+    let mut archive = InstitutionArchive {
+        records: app
+            .db_pool
+            .export_institution_records(institution.id)
+            .await?,
+        ..Default::default()
+    };
 
-            staff = app.db_pool.update_staff(staff).await?;
-            Ok((SUCCESS_API_STATUS_CODE, Either::Left(Some(staff.into()))))
+    let mut documents: BTreeSet<String> = BTreeSet::new();
+    documents.extend(institution.logo.clone());
+    for source in &app.institution_data {
+        for data in source.export_institution_data(institution.id).await? {
+            documents.extend(data.documents);
+            archive.records.push((data.name, data.records));
         }
-        None => {
-            let password = generate_password(app.env.min_password_length as usize);
-            if let None = app.db_pool.get_user(&new_staff.mobile).await? {
-                let password_hashed = hash_password(&password)?;
-                app.db_pool
-                    .create_user(&new_staff.mobile, &password_hashed)
-                    .await?;
-            }
+    }
 
-            let staff = app.db_pool.create_staff(institution_id, &new_staff).await?;
-            let message = format!(
-                "You have been invited to {} by {}",
-                app.env.app_url, auth.institution.institution_name
+    for key in documents {
+        if !is_institution_upload(&institution.shadow_id, &key) {
+            tracing::warn!(
+                message = "Export skipped a document the institution didn't upload",
+                institution = institution.shadow_id,
+                key = key
             );
-            send_sms(&app.env, &new_staff.mobile, &message).await;
-
-            let message = format!("Your login password is {}", password);
-            send_sms(&app.env, &new_staff.mobile, &message).await;
-            Ok((SUCCESS_API_STATUS_CODE, Either::Left(Some(staff.into()))))
+            continue;
+        }
+        let file = get_file(&app.s3_client, app.env.cloudflare_r2_bucket.as_str(), &key).await;
+        match file {
+            Ok(file) => {
+                let content = file.collect().await?.into_bytes();
+                archive.documents.push((key, content.to_vec()));
+            }
+            Err(err) => {
+                tracing::error!(message = "Export document missing", key = key, err=?err);
+                archive.missing_documents.push(key);
+            }
         }
     }
+
+    write_institution_archive(&institution.name, Utc::now(), &archive)
 }
 
-pub async fn edit_staff(
+async fn send_export_link(
     app: &CustomersApp,
-    InstitutionAdminUser(auth): &InstitutionAdminUser,
-    staff_id: &str,
-    new_staff: NewStaff,
-) -> eyre::Result<ApiResponse<StaffDto>> {
-    let institution_id = auth.institution.px;
-    let existing_staff: Option<Staff> = app
-        .db_pool
-        .find_institution_staff_by_id_opts(institution_id, staff_id)
-        .await?;
-    match existing_staff {
-        None => {
-            return Ok((
-                NOT_FOUND_API_STATUS_CODE,
-                Either::Right(ErrorMessage("Staff not found".into())),
-            ));
+    institution: &Institution,
+    export: &InstitutionExport,
+) -> eyre::Result<()> {
+    let expires_at = institution
+        .purge_at
+        .unwrap_or(Utc::now() + chrono::Duration::days(EXPORT_LINK_EXPIRES_IN_DAYS));
+    let token = sign_token(
+        app.env.invitation_signing_key.as_bytes(),
+        &format!("{}{}", EXPORT_TOKEN_PREFIX, export.shadow_id),
+        expires_at.timestamp(),
+    );
+
+    let content = include_str!("../assets/templates/institution_export.html")
+        .replace("{{institution_name}}", &institution.name)
+        .replace(
+            "{{download_link}}",
+            &format!("{}/exports?token={}", app.env.app_url, token),
+        )
+        .replace("{{expires_at}}", &expires_at.format("%d %B %Y").to_string());
+    send_email(
+        &app.env,
+        EmailMessage {
+            to: institution.email.to_owned(),
+            subject: format!("{} data export", institution.name),
+            content,
+        },
+    )
+    .await
+}
+
+/// Builds the pending export archives, an export that fails is marked as
+/// failed and the admin can request another. The links of ready exports are
+/// emailed until one goes through, a deactivated institution has no other way
+/// to its export.
+pub async fn process_institution_exports(app: &CustomersApp) -> eyre::Result<usize> {
+    let mut processed = 0;
+    for export in app.db_pool.find_pending_exports().await? {
+        let Some(institution) = app
+            .db_pool
+            .get_institution_record(export.institution_id)
+            .await?
+        else {
+            continue;
+        };
+
+        let file_key = format!(
+            "{}/I{}-E{}-T{}.zip",
+            EXPORT_FOLDER,
+            institution.shadow_id,
+            export.shadow_id,
+            Utc::now().timestamp_millis()
+        );
+        let uploaded = match build_institution_archive(app, &institution).await {
+            Ok(content) => {
+                upload_file_to_bucket(
+                    &app.s3_client,
+                    app.env.cloudflare_r2_bucket.as_str(),
+                    &file_key,
+                    &content,
+                    Some("application/zip".into()),
+                )
+                .await
+            }
+            Err(err) => Err(err),
+        };
+
+        match uploaded {
+            Ok(()) => {
+                app.db_pool
+                    .complete_institution_export(
+                        export.id,
+                        InstitutionExportStatus::Ready.as_str(),
+                        Some(file_key),
+                    )
+                    .await?;
+            }
+            Err(err) => {
+                tracing::error!(
+                    message = "Failed to export institution",
+                    institution = institution.shadow_id,
+                    err=?err
+                );
+                app.db_pool
+                    .complete_institution_export(
+                        export.id,
+                        InstitutionExportStatus::Failed.as_str(),
+                        None,
+                    )
+                    .await?;
+            }
         }
-        Some(mut staff) => {
-            staff.first_name = new_staff.first_name;
-            staff.last_name = new_staff.last_name;
-            staff.title = new_staff.title;
-            staff.profile_image = new_staff.profile_image;
+        processed += 1;
+    }
 
-            staff = app.db_pool.update_staff(staff).await?;
-            Ok((SUCCESS_API_STATUS_CODE, Either::Left(Some(staff.into()))))
+    for export in app.db_pool.find_unsent_export_links().await? {
+        let Some(institution) = app
+            .db_pool
+            .get_institution_record(export.institution_id)
+            .await?
+        else {
+            continue;
+        };
+        match send_export_link(app, &institution, &export).await {
+            Ok(()) => app.db_pool.mark_export_link_sent(export.id).await?,
+            Err(err) => tracing::error!(
+                message = "Failed to email export link, retrying on the next run",
+                export = export.shadow_id,
+                err=?err
+            ),
         }
     }
+    Ok(processed)
 }
 
-pub async fn delete_staff(
-    app: &CustomersApp,
-    InstitutionAdminUser(auth): &InstitutionAdminUser,
-    staff_id: &str,
-) -> eyre::Result<ApiResponse<()>> {
-    let institution_id = auth.institution.px;
+/// Permanently deletes the institutions whose grace period is over, with the
+/// data other modules hold for them and their files. An institution that fails
+/// to purge is retried on the next run.
+pub async fn purge_deactivated_institutions(app: &CustomersApp) -> eyre::Result<usize> {
+    let institutions = app.db_pool.find_institutions_due_purge(Utc::now()).await?;
+
+    let mut purged = 0;
+    for institution in institutions {
+        match purge_institution(app, &institution).await {
+            Ok(()) => {
+                info!("Institution: {} purged", institution.shadow_id);
+                purged += 1;
+            }
+            Err(err) => tracing::error!(
+                message = "Failed to purge institution",
+                institution = institution.shadow_id,
+                err=?err
+            ),
+        }
+    }
+    Ok(purged)
+}
 
-    let existing_staff: Option<Staff> = app
-        .db_pool
-        .find_institution_staff_by_id_opts(institution_id, staff_id)
-        .await?;
-    match existing_staff {
-        None => {
-            return Ok((
-                NOT_FOUND_API_STATUS_CODE,
-                Either::Right(ErrorMessage("Staff not found".into())),
-            ));
+async fn purge_institution(app: &CustomersApp, institution: &Institution) -> eyre::Result<()> {
+    let mut files = vec![];
+    for source in &app.institution_data {
+        files.extend(source.purge_institution_data(institution.id).await?);
+    }
+    let purged = app.db_pool.purge_institution(institution.id).await?;
+    files.extend(purged.files);
+
+    for mobile in &purged.mobiles {
+        if let Err(err) = revoke_user_sessions(app.redis.as_ref(), mobile).await {
+            tracing::error!(message = "Failed to revoke purged user sessions", mobile, err=?err);
         }
-        Some(staff) => {
-            app.db_pool.delete_staff(staff_id).await?;
-            Ok((SUCCESS_API_STATUS_CODE, Either::Left(None)))
+    }
+
+    // exports are named by us, every other key comes from the records
+    let exports = format!("{}/I{}-E", EXPORT_FOLDER, institution.shadow_id);
+    for file in files {
+        if !file.starts_with(&exports) && !is_institution_upload(&institution.shadow_id, &file) {
+            tracing::warn!(
+                message = "Purge kept a file the institution didn't upload",
+                institution = institution.shadow_id,
+                key = file
+            );
+            continue;
+        }
+        if let Err(err) =
+            delete_file(&app.s3_client, app.env.cloudflare_r2_bucket.as_str(), &file).await
+        {
+            tracing::error!(message = "Failed to delete purged file", key = file, err=?err);
         }
     }
+    if let Err(err) =
+        unflag_deactivated_workspace(app.redis.as_ref(), &institution.workspace_code).await
+    {
+        tracing::error!(message = "Failed to clear purged workspace status", err=?err);
+    }
+    Ok(())
 }
 
 pub async fn upload_base64_file_api(
@@ -5,7 +5,10 @@ use reqwest::StatusCode;
 use serde_json::{json, Value};
 use tryhcs_commons_be::{
     auth::{InstitutionAdminUser, TypeAuthenticated},
+    data_encryption::Encryptor,
     env::EnvConfig,
+    institution_data::InstitutionDataSource,
+    platform_staff::PlatformStaffSource,
     redis::Cache,
     AUTH_ID_HEADER_FIELD, WORKSPACE_CODE_HEADER_FIELD,
 };
@@ -16,9 +19,16 @@ use tryhcs_shared::{
 
 use crate::db_repo::EhrDataRepo as CustomerDbRepo;
 
+#[derive(Clone)]
 pub struct CustomersApp {
     pub db_pool: Arc<dyn CustomerDbRepo>,
     pub s3_client: aws_sdk_s3::Client,
     pub env: EnvConfig,
     pub redis: Arc<dyn Cache>,
+    /// None until a data encryption key is configured
+    pub encryptor: Option<Arc<Encryptor>>,
+    /// other modules' institution data, exported and purged on offboarding
+    pub institution_data: Vec<Arc<dyn InstitutionDataSource>>,
+    /// other modules' platform staff, allowed to log in without a staff account
+    pub platform_staff: Vec<Arc<dyn PlatformStaffSource>>,
 }
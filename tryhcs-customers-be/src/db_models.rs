@@ -40,14 +40,102 @@ pub struct User {
     pub mobile: String,
     pub password: String,
     pub failed_attempts: i32,
-    pub device_ids: Vec<String>,
     pub shadow_id: String,
     pub last_login_time: Option<DateTime<Utc>>,
+    pub locked_until: Option<DateTime<Utc>>,
     pub deleted_at: Option<DateTime<Utc>>,
     pub modified_at: DateTime<Utc>,
     pub created_at: DateTime<Utc>,
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone, Builder, sqlx::FromRow)]
+pub struct UserDevice {
+    pub id: i64,
+    pub user_id: i64,
+    pub device_id: String,
+    pub name: String,
+    pub user_agent: Option<String>,
+    pub ip_address: Option<String>,
+    pub shadow_id: String,
+    pub first_seen_at: DateTime<Utc>,
+    pub last_seen_at: DateTime<Utc>,
+}
+
+impl UserDevice {
+    pub fn to_dto(self, current_device_id: &str) -> UserDeviceDto {
+        UserDeviceDto {
+            current: self.device_id.eq(current_device_id),
+            id: self.shadow_id,
+            name: self.name,
+            user_agent: self.user_agent,
+            ip_address: self.ip_address,
+            first_seen_at: self.first_seen_at,
+            last_seen_at: self.last_seen_at,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Builder, sqlx::FromRow)]
+pub struct StaffInvitation {
+    pub id: i64,
+    pub institution_id: i64,
+    pub staff_id: i64,
+    pub mobile: String,
+    // sha256 of the latest invitation link sent
+    pub token_hash: String,
+    pub status: String,
+    pub invited_by: Option<i64>,
+    pub expires_at: DateTime<Utc>,
+    pub last_sent_at: DateTime<Utc>,
+    pub reminder_count: i32,
+    pub responded_at: Option<DateTime<Utc>>,
+    pub shadow_id: String,
+    pub modified_at: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl StaffInvitation {
+    // pending invitations past their expiry are reported as expired
+    pub fn invitation_status(&self) -> InvitationStatus {
+        match self.status.as_str() {
+            "Accepted" => InvitationStatus::Accepted,
+            "Declined" => InvitationStatus::Declined,
+            "Revoked" => InvitationStatus::Revoked,
+            _ if self.expires_at <= Utc::now() => InvitationStatus::Expired,
+            _ => InvitationStatus::Pending,
+        }
+    }
+
+    pub fn to_dto(self, staff: &Staff) -> StaffInvitationDto {
+        StaffInvitationDto {
+            status: self.invitation_status(),
+            id: self.shadow_id,
+            staff_id: staff.shadow_id.clone(),
+            first_name: staff.first_name.clone(),
+            last_name: staff.last_name.clone(),
+            mobile: self.mobile,
+            expires_at: self.expires_at,
+            last_sent_at: self.last_sent_at,
+            reminder_count: self.reminder_count,
+            responded_at: self.responded_at,
+            created_at: self.created_at,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Builder, sqlx::FromRow)]
+pub struct UserTotp {
+    pub user_id: i64,
+    // NonDeterministicEncrypted base32 secret
+    pub secret: Vec<u8>,
+    // sha256 hashes of the unused recovery codes
+    pub recovery_codes: Vec<String>,
+    pub last_used_step: Option<i64>,
+    pub enabled_at: Option<DateTime<Utc>>,
+    pub modified_at: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+}
+
 #[derive(Serialize, Deserialize, Debug, Builder, sqlx::FromRow, Clone)]
 pub struct Institution {
     pub id: i64,
@@ -64,7 +152,9 @@ pub struct Institution {
     pub shadow_id: String,
     pub modified_at: DateTime<Utc>,
     pub created_at: DateTime<Utc>,
+    // set on deactivation, the institution is purged at purge_at
     pub deleted_at: Option<DateTime<Utc>>,
+    pub purge_at: Option<DateTime<Utc>>,
 }
 
 impl From<Institution> for InstitutionDto {
@@ -85,6 +175,70 @@ impl From<Institution> for InstitutionDto {
     }
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone, Builder, sqlx::FromRow)]
+pub struct InstitutionExport {
+    pub id: i64,
+    pub institution_id: i64,
+    pub status: String,
+    // bucket key of the zip archive once it is ready
+    pub file_key: Option<String>,
+    pub requested_by: Option<i64>,
+    pub completed_at: Option<DateTime<Utc>>,
+    // the link is emailed again until this is set
+    pub link_sent_at: Option<DateTime<Utc>>,
+    pub shadow_id: String,
+    pub modified_at: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl InstitutionExport {
+    pub fn export_status(&self) -> InstitutionExportStatus {
+        match self.status.as_str() {
+            "Ready" => InstitutionExportStatus::Ready,
+            "Failed" => InstitutionExportStatus::Failed,
+            _ => InstitutionExportStatus::Pending,
+        }
+    }
+
+    pub fn to_dto(&self, download_url: Option<String>) -> InstitutionExportDto {
+        InstitutionExportDto {
+            id: self.shadow_id.clone(),
+            status: self.export_status(),
+            download_url,
+            completed_at: self.completed_at,
+            created_at: self.created_at,
+        }
+    }
+}
+
+// profile edit with the staff that made it
+#[derive(Serialize, Deserialize, Debug, Clone, sqlx::FromRow)]
+pub struct InstitutionChange {
+    pub shadow_id: String,
+    // [{field, from, to}]
+    pub changes: Value,
+    pub changed_by: Option<String>,
+    pub changed_by_first_name: Option<String>,
+    pub changed_by_last_name: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl From<InstitutionChange> for InstitutionChangeDto {
+    fn from(c: InstitutionChange) -> Self {
+        let changed_by_name = match (c.changed_by_first_name, c.changed_by_last_name) {
+            (Some(first_name), Some(last_name)) => Some(format!("{} {}", first_name, last_name)),
+            _ => None,
+        };
+        InstitutionChangeDto {
+            id: c.shadow_id,
+            changed_by: c.changed_by,
+            changed_by_name,
+            changes: serde_json::from_value(c.changes).unwrap_or_default(),
+            created_at: c.created_at,
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, Builder, sqlx::FromRow)]
 pub struct Department {
     pub id: i64,
@@ -93,7 +247,6 @@ pub struct Department {
     pub institution_id: i64,
     pub head_staff_id: Option<String>,
     pub shadow_id: String,
-    pub staffs_ids: serde_json::Value,
     pub phone_no: Option<String>,
     pub deleted_at: Option<DateTime<Utc>>,
     pub modified_at: DateTime<Utc>,
@@ -111,3 +264,54 @@ impl From<Department> for DepartmentDto {
         }
     }
 }
+
+#[derive(Serialize, Deserialize, Debug, Clone, Builder, sqlx::FromRow)]
+pub struct DepartmentMembership {
+    pub id: i64,
+    pub department_id: i64,
+    pub staff_id: i64,
+    pub role: String,
+    pub joined_at: DateTime<Utc>,
+    pub left_at: Option<DateTime<Utc>>,
+    pub modified_at: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Builder, sqlx::FromRow)]
+pub struct Role {
+    pub id: i64,
+    pub institution_id: i64,
+    pub name: String,
+    pub permissions: serde_json::Value,
+    pub shadow_id: String,
+    pub deleted_at: Option<DateTime<Utc>>,
+    pub modified_at: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl Role {
+    pub fn permitted_actions(&self) -> Vec<PermittedAction> {
+        serde_json::from_value(self.permissions.clone()).unwrap_or_default()
+    }
+}
+
+impl From<Role> for RoleDto {
+    fn from(r: Role) -> Self {
+        RoleDto {
+            permissions: r.permitted_actions(),
+            id: r.shadow_id,
+            name: r.name,
+            modified_at: r.modified_at,
+            created_at: r.created_at,
+        }
+    }
+}
+
+/// What purging an institution removed that lives outside the database
+#[derive(Debug, Default)]
+pub struct PurgedInstitution {
+    /// bucket keys referenced by the purged records
+    pub files: Vec<String>,
+    /// mobiles of the purged staffs, their users are purged with them
+    pub mobiles: Vec<String>,
+}
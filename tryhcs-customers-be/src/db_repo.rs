@@ -1,22 +1,75 @@
 use std::time;
 
-use crate::db_models::{Department, Institution, Staff, User};
+use crate::db_models::{
+    Department, DepartmentMembership, Institution, InstitutionChange, InstitutionExport,
+    PurgedInstitution, Role, Staff, StaffInvitation, User, UserDevice, UserTotp,
+};
 use async_trait::async_trait;
 use bon::Builder;
 use chrono::{DateTime, Utc};
 use eyre::{Context, Ok, Result};
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use serde_json::{json, Value};
-use sqlx::{query, query_as, Executor, PgPool};
+use sqlx::{query, query_as, Executor, PgPool, Postgres, Transaction};
 use tryhcs_commons_be::{
+    client_context::ClientContext,
     data_encryption::{
         DeterministicEncrypted, Encryptable, EncryptableData, Encryptor, NonDeterministicEncrypted,
     },
     ADMIN_DOMAIN, ADMIN_ROLE,
 };
-use tryhcs_shared::institution_params::{CreateInstitution, DepartmentMember, NewStaff};
+use tryhcs_shared::{
+    api_params::PaginatedQuery,
+    institution_params::{
+        CreateInstitution, DepartmentMember, InstitutionFieldChange, NewStaff, PermittedAction,
+    },
+};
 use uuid::Uuid;
 
+/// columns staff listings can be sorted by, the first is the default
+pub const STAFF_SORT_FIELDS: [&str; 4] = ["first_name", "last_name", "title", "created_at"];
+
+/// columns department listings can be sorted by, the first is the default
+pub const DEPARTMENT_SORT_FIELDS: [&str; 2] = ["name", "created_at"];
+
+/// (file name, query) of the records in an institution's export archive, the
+/// invitation token hashes are left out
+const INSTITUTION_EXPORT_QUERIES: [(&str, &str); 7] = [
+    ("institution", "select * from institutions where id = $1"),
+    ("staffs", "select * from staffs where institution_id = $1"),
+    ("departments", "select * from departments where institution_id = $1"),
+    (
+        "department_members",
+        "select m.* from department_members m join departments d on d.id = m.department_id where d.institution_id = $1",
+    ),
+    ("roles", "select * from institution_roles where institution_id = $1"),
+    (
+        "staff_invitations",
+        "select id, staff_id, mobile, status, invited_by, expires_at, last_sent_at, reminder_count, responded_at, shadow_id, modified_at, created_at from staff_invitations where institution_id = $1",
+    ),
+    (
+        "institution_changes",
+        "select * from institution_changes where institution_id = $1",
+    ),
+];
+
+/// Invitation about to be sent, the shadow id is the signed subject of the
+/// invitation link so it is generated before the row is saved.
+pub struct NewStaffInvitation {
+    pub shadow_id: Uuid,
+    pub token_hash: String,
+    pub invited_by: Option<i64>,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// Validated staff sheet row with the invitation to send the staff.
+pub struct StaffImportEntry {
+    pub staff: NewStaff,
+    pub invitation: NewStaffInvitation,
+    pub department_id: Option<i64>,
+    pub role: Option<String>,
+}
+
 #[async_trait]
 pub trait EhrDataRepo: Send + Sync {
     async fn find_institution_by_email(&self, email: &str) -> Result<Option<Institution>>;
@@ -33,10 +86,12 @@ pub trait EhrDataRepo: Send + Sync {
         domain: &str,
     ) -> Result<Department>;
 
+    /// staff accounts still waiting on their invitation to be accepted are left out
     async fn find_staff_institutions_by_mobile(&self, mobile: &str) -> Result<Vec<Institution>>;
 
     async fn find_staff_accounts_by_mobile(&self, mobile: &str) -> Result<Vec<Staff>>;
 
+    /// removed staff included, so they can be added back
     async fn find_staff_by_mobile_and_insitution_id_opts(
         &self,
         institution_id: i64,
@@ -51,9 +106,90 @@ pub trait EhrDataRepo: Send + Sync {
 
     async fn get_user(&self, mobile: &str) -> Result<Option<User>>;
 
-    async fn record_failed_attempts_user(&self, mobile: &str) -> Result<User>;
+    // locks the account for lockout_in_sec once max_attempts is reached, the
+    // count restarts when a previous lockout has elapsed
+    async fn record_failed_attempts_user(
+        &self,
+        mobile: &str,
+        max_attempts: i32,
+        lockout_in_sec: i64,
+    ) -> Result<User>;
+
+    async fn record_user_login(&self, mobile: &str) -> Result<User>;
+
+    async fn find_user_devices(&self, user_id: i64) -> Result<Vec<UserDevice>>;
+
+    // registers the device as trusted, or refreshes it when it already is
+    async fn trust_user_device(
+        &self,
+        user_id: i64,
+        device_id: &str,
+        client: &ClientContext,
+    ) -> Result<UserDevice>;
+
+    async fn rename_user_device(
+        &self,
+        user_id: i64,
+        shadow_id: &str,
+        name: &str,
+    ) -> Result<Option<UserDevice>>;
+
+    async fn delete_user_device(&self, user_id: i64, shadow_id: &str)
+        -> Result<Option<UserDevice>>;
+
+    async fn get_user_totp(&self, user_id: i64) -> Result<Option<UserTotp>>;
+
+    // replaces any pending enrollment, the totp stays disabled until verified
+    async fn save_user_totp_secret(&self, user_id: i64, secret: Vec<u8>) -> Result<UserTotp>;
+
+    async fn enable_user_totp(&self, user_id: i64, recovery_codes: &[String]) -> Result<UserTotp>;
+
+    async fn update_user_totp_recovery_codes(
+        &self,
+        user_id: i64,
+        recovery_codes: &[String],
+    ) -> Result<UserTotp>;
+
+    // records the step as used, None when it has already been used
+    async fn record_user_totp_step(&self, user_id: i64, step: i64) -> Result<Option<UserTotp>>;
+
+    // removes the recovery code, None when it isn't one of the user's codes
+    async fn consume_user_totp_recovery_code(
+        &self,
+        user_id: i64,
+        code_hash: &str,
+    ) -> Result<Option<UserTotp>>;
+
+    async fn delete_user_totp(&self, user_id: i64) -> Result<()>;
+
+    async fn find_institution_roles(&self, institution_id: i64) -> Result<Vec<Role>>;
+
+    async fn get_institution_role(
+        &self,
+        institution_id: i64,
+        role_id: &str,
+    ) -> Result<Option<Role>>;
+
+    async fn create_role(
+        &self,
+        institution_id: i64,
+        name: &str,
+        permissions: &[PermittedAction],
+    ) -> Result<Role>;
+
+    async fn update_role(
+        &self,
+        institution_id: i64,
+        role_id: &str,
+        name: &str,
+        permissions: &[PermittedAction],
+    ) -> Result<Role>;
+
+    async fn delete_role(&self, institution_id: i64, role_id: &str) -> Result<()>;
+
+    async fn reset_user_lockout(&self, mobile: &str) -> Result<User>;
 
-    async fn record_user_login(&self, mobile: &str, device_id: &str) -> Result<User>;
+    async fn update_user_password(&self, mobile: &str, password: &str) -> Result<User>;
 
     async fn edit_department(
         &self,
@@ -65,10 +201,164 @@ pub trait EhrDataRepo: Send + Sync {
         domain: &str,
     ) -> Result<Department>;
 
+    async fn find_staff_memberships(
+        &self,
+        institution_id: i64,
+        staff_id: i64,
+    ) -> Result<Vec<DepartmentMembership>>;
+
+    async fn find_department_memberships(
+        &self,
+        department_id: i64,
+    ) -> Result<Vec<DepartmentMembership>>;
+
+    // joins the staff to the department, or updates their role when they
+    // are already a member
+    async fn add_department_member(
+        &self,
+        department_id: i64,
+        staff_id: i64,
+        role: &str,
+    ) -> Result<DepartmentMembership>;
+
+    async fn remove_department_member(
+        &self,
+        department_id: i64,
+        staff_id: i64,
+    ) -> Result<Option<DepartmentMembership>>;
+
+    async fn count_role_members(&self, institution_id: i64, role: &str) -> Result<i64>;
+
+    async fn find_role_memberships(
+        &self,
+        institution_id: i64,
+        role: &str,
+    ) -> Result<Vec<DepartmentMembership>>;
+
     async fn delete_department(&self, shadow_id: &str) -> Result<()>;
 
     async fn create_user(&self, mobile: &str, password: &str) -> Result<User>;
 
+    async fn find_user_mobiles(&self) -> Result<Vec<String>>;
+
+    /// staff records of any institution holding the mobiles, deleted ones included
+    async fn find_staffs_by_mobiles(&self, mobiles: &[String]) -> Result<Vec<Staff>>;
+
+    /// creates all the staffs and their invitations or none
+    async fn import_staffs(
+        &self,
+        institution_id: i64,
+        entries: &[StaffImportEntry],
+    ) -> Result<Vec<(Staff, StaffInvitation)>>;
+
+    async fn get_institution(&self, institution_id: i64) -> Result<Option<Institution>>;
+
+    /// saves the profile and records the changed fields in its history
+    async fn update_institution(
+        &self,
+        i: Institution,
+        changed_by: Option<i64>,
+        changes: &[InstitutionFieldChange],
+    ) -> Result<Institution>;
+
+    /// page of profile edits, newest first
+    async fn paginate_institution_changes(
+        &self,
+        institution_id: i64,
+        pagination: &PaginatedQuery,
+    ) -> Result<(Vec<InstitutionChange>, i64)>;
+
+    async fn get_staff(&self, staff_id: i64) -> Result<Option<Staff>>;
+
+    /// like `get_institution` with deactivated institutions included
+    async fn get_institution_record(&self, institution_id: i64) -> Result<Option<Institution>>;
+
+    /// sets deleted_at and purge_at and records the deactivation in the history
+    async fn deactivate_institution(
+        &self,
+        institution_id: i64,
+        purge_at: DateTime<Utc>,
+        changed_by: Option<i64>,
+    ) -> Result<Institution>;
+
+    async fn find_institutions_due_purge(&self, now: DateTime<Utc>) -> Result<Vec<Institution>>;
+
+    /// purged institutions no longer have a workspace and count as deactivated
+    async fn is_workspace_deactivated(&self, workspace_code: &str) -> Result<bool>;
+
+    /// deletes the institution with its staffs, departments and the users left
+    /// without a staff account
+    async fn purge_institution(&self, institution_id: i64) -> Result<PurgedInstitution>;
+
+    /// every record of the institution as (file name, json array) pairs
+    async fn export_institution_records(&self, institution_id: i64)
+        -> Result<Vec<(String, Value)>>;
+
+    async fn create_institution_export(
+        &self,
+        institution_id: i64,
+        requested_by: Option<i64>,
+    ) -> Result<InstitutionExport>;
+
+    async fn get_institution_export(&self, shadow_id: &str) -> Result<Option<InstitutionExport>>;
+
+    /// newest first
+    async fn find_institution_exports(&self, institution_id: i64)
+        -> Result<Vec<InstitutionExport>>;
+
+    /// oldest first
+    async fn find_pending_exports(&self) -> Result<Vec<InstitutionExport>>;
+
+    /// ready exports whose link hasn't been emailed yet, oldest first
+    async fn find_unsent_export_links(&self) -> Result<Vec<InstitutionExport>>;
+
+    async fn mark_export_link_sent(&self, export_id: i64) -> Result<()>;
+
+    async fn complete_institution_export(
+        &self,
+        export_id: i64,
+        status: &str,
+        file_key: Option<String>,
+    ) -> Result<InstitutionExport>;
+
+    async fn create_staff_invitation(
+        &self,
+        institution_id: i64,
+        staff: &Staff,
+        invitation: &NewStaffInvitation,
+    ) -> Result<StaffInvitation>;
+
+    async fn get_staff_invitation(&self, shadow_id: &str) -> Result<Option<StaffInvitation>>;
+
+    async fn find_institution_invitations(
+        &self,
+        institution_id: i64,
+        status: &str,
+    ) -> Result<Vec<StaffInvitation>>;
+
+    /// the staff's most recent invitation, whatever its status
+    async fn find_latest_staff_invitation(&self, staff_id: i64)
+        -> Result<Option<StaffInvitation>>;
+
+    async fn update_staff_invitation(&self, i: StaffInvitation) -> Result<StaffInvitation>;
+
+    /// Accepts the invitation while it is still pending and unexpired, creating the
+    /// invitee's account with the hashed password when given. `None` when the
+    /// invitation was answered or expired meanwhile.
+    async fn accept_staff_invitation(
+        &self,
+        invitation_id: i64,
+        mobile: &str,
+        password: Option<&str>,
+    ) -> Result<Option<StaffInvitation>>;
+
+    /// unexpired pending invitations last sent before the given time that can still be reminded
+    async fn find_invitations_due_reminder(
+        &self,
+        sent_before: DateTime<Utc>,
+        max_reminders: i32,
+    ) -> Result<Vec<StaffInvitation>>;
+
     async fn create_staff(&self, institution_id: i64, s: &NewStaff) -> Result<Staff>;
 
     async fn update_staff(&self, s: Staff) -> Result<Staff>;
@@ -81,6 +371,13 @@ pub trait EhrDataRepo: Send + Sync {
         search_query: Option<String>,
     ) -> Result<Vec<Staff>>;
 
+    /// page of staffs with the total count of staffs matching the query
+    async fn paginate_institution_staffs(
+        &self,
+        institution_id: i64,
+        pagination: &PaginatedQuery,
+    ) -> Result<(Vec<Staff>, i64)>;
+
     async fn find_staff_departments(
         &self,
         institution_id: i64,
@@ -93,16 +390,25 @@ pub trait EhrDataRepo: Send + Sync {
         query: Option<String>,
     ) -> Result<Vec<Department>>;
 
+    /// page of departments with the total count of departments matching the query
+    async fn paginate_institution_departments(
+        &self,
+        institution_id: i64,
+        pagination: &PaginatedQuery,
+    ) -> Result<(Vec<Department>, i64)>;
+
+    // departments are looked up by the shadow id clients get in DepartmentDto,
+    // like the department edit and delete queries
     async fn get_institution_department(
         &self,
         institution_id: i64,
-        department_id: &str,
+        department_shadow_id: &str,
     ) -> Result<Option<Department>>;
 
     async fn find_department_staffs(
         &self,
         institution_id: i64,
-        department_id: &str,
+        department_shadow_id: &str,
     ) -> Result<Vec<Staff>>;
 }
 
@@ -139,7 +445,7 @@ impl EhrDataRepo for CustomerDB {
         query_as!(
             Staff,
             "update staffs set
-                first_name =$2 , last_name=$3, mobile=$4, title=$5, profile_image=$6
+                first_name =$2 , last_name=$3, mobile=$4, title=$5, profile_image=$6, deleted_at=$7
             where id = $1
             returning id, first_name, last_name, mobile, title, institution_id,  profile_image, deleted_at, modified_at, created_at, shadow_id",
             s.id,
@@ -147,7 +453,8 @@ impl EhrDataRepo for CustomerDB {
             s.last_name,
             s.mobile,
             s.title,
-            s.profile_image
+            s.profile_image,
+            s.deleted_at
         )
         .fetch_one(&self.customer_db)
         .await
@@ -184,7 +491,7 @@ impl EhrDataRepo for CustomerDB {
               (mobile ilike concat('%', $2::varchar, '%'))
             )
             and deleted_at is null
-            ORDER BY first_name asc
+            ORDER BY first_name asc, id asc
             ",
             institution_id,
             search_query
@@ -194,18 +501,86 @@ impl EhrDataRepo for CustomerDB {
         .wrap_err("Failed to fetch institution staffs")
     }
 
+    async fn paginate_institution_staffs(
+        &self,
+        institution_id: i64,
+        pagination: &PaginatedQuery,
+    ) -> Result<(Vec<Staff>, i64)> {
+        let search_query = pagination.search();
+        let sort_by = pagination
+            .sort_by
+            .clone()
+            .unwrap_or(STAFF_SORT_FIELDS[0].to_owned());
+
+        // id is the tie breaker so rows don't shift between pages
+        let staffs = query_as!(
+            Staff,
+            "select id, first_name, last_name, mobile, title, institution_id,  profile_image, deleted_at, modified_at, created_at, shadow_id from staffs
+            where institution_id = $1 and
+            ( ($2::varchar is null) or
+              (first_name ilike concat('%', $2::varchar, '%')) or
+              (last_name ilike concat('%', $2::varchar, '%')) or
+              (title ilike concat('%', $2::varchar, '%')) or
+              (mobile ilike concat('%', $2::varchar, '%'))
+            )
+            and deleted_at is null
+            ORDER BY
+              case when $3 = 'first_name' and not $4 then first_name end asc,
+              case when $3 = 'first_name' and $4 then first_name end desc,
+              case when $3 = 'last_name' and not $4 then last_name end asc,
+              case when $3 = 'last_name' and $4 then last_name end desc,
+              case when $3 = 'title' and not $4 then title end asc,
+              case when $3 = 'title' and $4 then title end desc,
+              case when $3 = 'created_at' and not $4 then created_at end asc,
+              case when $3 = 'created_at' and $4 then created_at end desc,
+              id asc
+            limit $5 offset $6
+            ",
+            institution_id,
+            search_query,
+            sort_by,
+            pagination.is_descending(),
+            pagination.limit() as i64,
+            pagination.offset()
+        )
+        .fetch_all(&self.customer_db)
+        .await
+        .wrap_err("Failed to fetch institution staffs page")?;
+
+        let total = query!(
+            r#"select count(*) as "total!" from staffs
+            where institution_id = $1 and
+            ( ($2::varchar is null) or
+              (first_name ilike concat('%', $2::varchar, '%')) or
+              (last_name ilike concat('%', $2::varchar, '%')) or
+              (title ilike concat('%', $2::varchar, '%')) or
+              (mobile ilike concat('%', $2::varchar, '%'))
+            )
+            and deleted_at is null
+            "#,
+            institution_id,
+            search_query
+        )
+        .fetch_one(&self.customer_db)
+        .await
+        .wrap_err("Failed to count institution staffs")?
+        .total;
+
+        Ok((staffs, total))
+    }
+
     async fn create_institution(&self, c: CreateInstitution) -> Result<(Institution, Staff)> {
         let mut txn = self.customer_db.begin().await?;
 
         let workspace_code = format!("hcs_{}", Uuid::new_v4().to_string().replace("-", ""));
 
-        let institution = query_as!(
+        let mut institution = query_as!(
         Institution,
         "insert into institutions
-            (name, email, classification, setting, address, town, state, workspace_code)
+            (name, email, classification, setting, address, town, state, workspace_code, logo)
         values
-            ($1, $2, $3, $4, $5, $6, $7, $8)
-        returning id, name, email, classification, setting, address, town, state, created_by, workspace_code, logo, deleted_at, modified_at, created_at, shadow_id",
+            ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+        returning id, name, email, classification, setting, address, town, state, created_by, workspace_code, logo, deleted_at, purge_at, modified_at, created_at, shadow_id",
         c.institution_name,
         c.email,
         c.classification,
@@ -213,7 +588,8 @@ impl EhrDataRepo for CustomerDB {
         c.address,
         c.town,
         c.state,
-        workspace_code
+        workspace_code,
+        c.logo
     )
     .fetch_one(&mut *txn)
     .await
@@ -243,27 +619,41 @@ returning id, first_name, last_name, mobile, title, institution_id,  profile_ima
 .await
 .wrap_err("Error creating staff")?;
 
-        let mut inital_staff_ids = json!(vec![DepartmentMember {
-            staff_id: admin_staff.shadow_id.clone(),
-            role: ADMIN_ROLE.to_string()
-        }]);
+        query!(
+            "update institutions set created_by = $2 where id = $1",
+            institution.id,
+            admin_staff.id
+        )
+        .execute(&mut *txn)
+        .await
+        .wrap_err("Failed to set institution creator")?;
+        institution.created_by = admin_staff.id;
 
         let department = query_as!(
             Department,
             "insert into departments
-                (name, institution_id, staffs_ids, domain)
+                (name, institution_id, domain)
             values
-                ($1, $2, $3, $4)
-            returning id, name, institution_id, staffs_ids, head_staff_id, phone_no, deleted_at, modified_at, created_at,domain, shadow_id",
+                ($1, $2, $3)
+            returning id, name, institution_id, head_staff_id, phone_no, deleted_at, modified_at, created_at,domain, shadow_id",
             ADMIN_DOMAIN,
             &institution.id,
-            &inital_staff_ids,
             ADMIN_DOMAIN
         )
         .fetch_one(&mut *txn)
         .await
         .wrap_err(format!("Failed to create department"))?;
 
+        query!(
+            "insert into department_members (department_id, staff_id, role) values ($1, $2, $3)",
+            department.id,
+            admin_staff.id,
+            ADMIN_ROLE
+        )
+        .execute(&mut *txn)
+        .await
+        .wrap_err("Failed to add department admin")?;
+
         txn.commit().await?;
 
         return Ok((institution, admin_staff));
@@ -278,31 +668,35 @@ returning id, first_name, last_name, mobile, title, institution_id,  profile_ima
         staffs_ids: &[DepartmentMember],
         domain: &str,
     ) -> Result<Department> {
-        query_as!(
+        let mut txn = self.customer_db.begin().await?;
+        let department = query_as!(
         Department,
         "insert into departments
-            (name, institution_id, head_staff_id, phone_no, staffs_ids, domain)
+            (name, institution_id, head_staff_id, phone_no, domain)
         values
-            ($1, $2, $3, $4, $5, $6)
-        returning id, name, institution_id, staffs_ids, head_staff_id, phone_no, deleted_at, modified_at, created_at, domain, shadow_id",
+            ($1, $2, $3, $4, $5)
+        returning id, name, institution_id, head_staff_id, phone_no, deleted_at, modified_at, created_at, domain, shadow_id",
         dept_name,
         institution_id,
         head_staff_id,
         phone_no,
-        json!(staffs_ids),
         domain
     )
-    .fetch_one(&self.customer_db)
+    .fetch_one(&mut *txn)
     .await
     .wrap_err(format!(
         "Failed to create department: {dept_name} institution_id: {institution_id}"
-    ))
+    ))?;
+
+        sync_department_members(&mut txn, &department, staffs_ids).await?;
+        txn.commit().await?;
+        Ok(department)
     }
 
     async fn find_institution_by_email(&self, email: &str) -> Result<Option<Institution>> {
         query_as!(
         Institution,
-        "select id, name, email, classification, setting, address, town, state, created_by, workspace_code, logo, deleted_at, modified_at, created_at, shadow_id from institutions
+        "select id, name, email, classification, setting, address, town, state, created_by, workspace_code, logo, deleted_at, purge_at, modified_at, created_at, shadow_id from institutions
         where email = $1",
         email
     )
@@ -321,10 +715,10 @@ returning id, first_name, last_name, mobile, title, institution_id,  profile_ima
     ) -> Result<Vec<Department>> {
         query_as!(
         Department,
-        "select id, name, institution_id, staffs_ids, head_staff_id, phone_no, deleted_at, modified_at, created_at, domain, shadow_id from departments
+        "select id, name, institution_id, head_staff_id, phone_no, deleted_at, modified_at, created_at, domain, shadow_id from departments
         where deleted_at is null and institution_id = $1 and
-        ($2::text is null or $2 ILIKE CONCAT('%', $2, '%') )
-        order by name asc
+        ($2::text is null or name ILIKE CONCAT('%', $2, '%') )
+        order by name asc, id asc
 ",
         institution_id,
         query.clone()
@@ -337,34 +731,87 @@ returning id, first_name, last_name, mobile, title, institution_id,  profile_ima
     ))
     }
 
+    async fn paginate_institution_departments(
+        &self,
+        institution_id: i64,
+        pagination: &PaginatedQuery,
+    ) -> Result<(Vec<Department>, i64)> {
+        let search_query = pagination.search();
+        let sort_by = pagination
+            .sort_by
+            .clone()
+            .unwrap_or(DEPARTMENT_SORT_FIELDS[0].to_owned());
+
+        let departments = query_as!(
+        Department,
+        "select id, name, institution_id, head_staff_id, phone_no, deleted_at, modified_at, created_at, domain, shadow_id from departments
+        where deleted_at is null and institution_id = $1 and
+        ($2::text is null or name ILIKE CONCAT('%', $2, '%') )
+        order by
+          case when $3 = 'name' and not $4 then name end asc,
+          case when $3 = 'name' and $4 then name end desc,
+          case when $3 = 'created_at' and not $4 then created_at end asc,
+          case when $3 = 'created_at' and $4 then created_at end desc,
+          id asc
+        limit $5 offset $6
+",
+        institution_id,
+        search_query,
+        sort_by,
+        pagination.is_descending(),
+        pagination.limit() as i64,
+        pagination.offset()
+    )
+    .fetch_all(&self.customer_db)
+    .await
+    .wrap_err(format!(
+        "Failed to fetch departments page for institution: {}",
+        institution_id
+    ))?;
+
+        let total = query!(
+            r#"select count(*) as "total!" from departments
+        where deleted_at is null and institution_id = $1 and
+        ($2::text is null or name ILIKE CONCAT('%', $2, '%') )
+            "#,
+            institution_id,
+            search_query
+        )
+        .fetch_one(&self.customer_db)
+        .await
+        .wrap_err("Failed to count institution departments")?
+        .total;
+
+        Ok((departments, total))
+    }
+
     async fn get_institution_department(
         &self,
         institution_id: i64,
-        department_id: &str,
+        department_shadow_id: &str,
     ) -> Result<Option<Department>> {
         query_as!(
         Department,
-        "select id, name, institution_id, staffs_ids, head_staff_id, phone_no, deleted_at, modified_at, created_at, domain, shadow_id from departments
+        "select id, name, institution_id, head_staff_id, phone_no, deleted_at, modified_at, created_at, domain, shadow_id from departments
         where deleted_at is null and
          institution_id = $1 and
-         id::varchar = $2
-        
+         shadow_id::varchar = $2
 ",
         institution_id,
-        department_id
+        department_shadow_id
     )
     .fetch_optional(&self.customer_db)
     .await
     .wrap_err(format!(
         "Failed to execute find institution: {} department_id: {} ",
-        institution_id, department_id
+        institution_id, department_shadow_id
     ))
     }
 
     async fn find_department_staffs(
         &self,
         institution_id: i64,
-        department_id: &str,
+        department_shadow_id: &str,
     ) -> Result<Vec<Staff>> {
         query_as!(
             Staff,
@@ -374,29 +821,29 @@ FROM staffs
 WHERE deleted_at IS NULL
   AND institution_id = $1
   AND id IN (
-      SELECT (elem->>'staff_id')::bigint
-      FROM departments d,
-           jsonb_array_elements(d.staffs_ids) AS elem
-      WHERE d.id::varchar = $2
-      LIMIT 1
+      SELECT m.staff_id
+      FROM department_members m
+      JOIN departments d ON d.id = m.department_id
+      WHERE d.shadow_id::varchar = $2 AND m.left_at IS NULL
   )
 ORDER BY first_name ASC
         ",
             institution_id,
-            department_id
+            department_shadow_id
         )
         .fetch_all(&self.customer_db)
         .await
         .wrap_err(format!(
-            "Failed to fetch institution: {institution_id} department: {department_id} staffs"
+            "Failed to fetch institution: {institution_id} department: {department_shadow_id} staffs"
         ))
     }
 
     async fn find_staff_institutions_by_mobile(&self, mobile: &str) -> Result<Vec<Institution>> {
         query_as!(
         Institution,
-        "select i.id, i.name, i.email, i.classification, i.setting, i.address, i.town, i.state, i.created_by, i.workspace_code, i.logo, i.deleted_at, i.modified_at, i.created_at, i.shadow_id from staffs s join institutions i on i.id = s.institution_id
-        where s.mobile = $1 and s.deleted_at is null
+        "select i.id, i.name, i.email, i.classification, i.setting, i.address, i.town, i.state, i.created_by, i.workspace_code, i.logo, i.deleted_at, i.purge_at, i.modified_at, i.created_at, i.shadow_id from staffs s join institutions i on i.id = s.institution_id
+        where s.mobile = $1 and s.deleted_at is null and i.deleted_at is null
+        and not exists (select 1 from staff_invitations si where si.staff_id = s.id and si.status = 'Pending')
         ",
         mobile
     )
@@ -410,6 +857,7 @@ ORDER BY first_name ASC
         Staff,
         "select id, first_name, last_name, mobile, title, institution_id,  profile_image, deleted_at, modified_at, created_at, shadow_id from staffs s
         where mobile = $1 and deleted_at is null
+        and not exists (select 1 from staff_invitations si where si.staff_id = s.id and si.status = 'Pending')
         ",
         mobile
     )
@@ -426,7 +874,7 @@ ORDER BY first_name ASC
         query_as!(
         Staff,
         "select s.id, s.first_name, s.last_name, s.mobile, s.title, s.institution_id,  s.profile_image, s.deleted_at, s.modified_at, s.created_at, shadow_id from staffs s
-        where s.mobile = $1 and s.institution_id = $2 and s.deleted_at is null
+        where s.mobile = $1 and s.institution_id = $2
         ",
         mobile,
         institution_id
@@ -457,7 +905,7 @@ ORDER BY first_name ASC
     async fn get_user(&self, mobile: &str) -> Result<Option<User>> {
         query_as!(
         User,
-        "select id, mobile, password, failed_attempts, device_ids, last_login_time, deleted_at, modified_at, created_at, shadow_id  from users where mobile = $1 and deleted_at is null",
+        "select id, mobile, password, failed_attempts, last_login_time, locked_until, deleted_at, modified_at, created_at, shadow_id  from users where mobile = $1 and deleted_at is null",
         mobile
     )
     .fetch_optional(&self.customer_db)
@@ -465,107 +913,1172 @@ ORDER BY first_name ASC
     .wrap_err("Error fetching user")
     }
 
-    async fn record_failed_attempts_user(&self, mobile: &str) -> Result<User> {
+    async fn record_failed_attempts_user(
+        &self,
+        mobile: &str,
+        max_attempts: i32,
+        lockout_in_sec: i64,
+    ) -> Result<User> {
         query_as!(
         User,
-        "update users  set failed_attempts = failed_attempts + 1 where mobile = $1 returning id, mobile, password, failed_attempts, device_ids, last_login_time, deleted_at, modified_at, created_at, shadow_id",
-        mobile
+        "update users set
+            failed_attempts = case when locked_until <= now() then 1 else failed_attempts + 1 end,
+            locked_until = case
+                when locked_until <= now() then null
+                when failed_attempts + 1 >= $2 then now() + make_interval(secs => $3::float8)
+                else locked_until
+            end
+        where mobile = $1 returning id, mobile, password, failed_attempts, last_login_time, locked_until, deleted_at, modified_at, created_at, shadow_id",
+        mobile,
+        max_attempts,
+        lockout_in_sec as f64
     )
     .fetch_one(&self.customer_db)
     .await
-    .wrap_err("Error fetching user")
+    .wrap_err("Error recording user failed attempts")
     }
 
-    async fn record_user_login(&self, mobile: &str, device_id: &str) -> Result<User> {
+    async fn record_user_login(&self, mobile: &str) -> Result<User> {
         query_as!(
         User,
-        "update users  set failed_attempts = failed_attempts + 1 where mobile = $1 returning id, mobile, password, failed_attempts, device_ids, last_login_time, deleted_at, modified_at, created_at, shadow_id",
+        "update users set failed_attempts = 0, locked_until = null, last_login_time = now() where mobile = $1 returning id, mobile, password, failed_attempts, last_login_time, locked_until, deleted_at, modified_at, created_at, shadow_id",
         mobile
     )
     .fetch_one(&self.customer_db)
     .await
-    .wrap_err("Error fetching user")
+    .wrap_err("Error recording user login")
     }
 
-    async fn find_staff_departments(
-        &self,
-        institution_id: i64,
-        staff_id: i64,
-    ) -> Result<Vec<Department>> {
+    async fn find_user_devices(&self, user_id: i64) -> Result<Vec<UserDevice>> {
         query_as!(
-            Department,
-            "SELECT id, name, institution_id, staffs_ids, head_staff_id, phone_no,
-       deleted_at, modified_at, created_at, domain, shadow_id
-FROM departments
-WHERE deleted_at IS NULL
-  AND institution_id = $1
-  AND EXISTS (
-    SELECT 1
-    FROM jsonb_array_elements(departments.staffs_ids) AS elem
-    WHERE (elem->>'staff_id')::bigint = $2
-  )
-",
-            institution_id,
-            staff_id
-        )
-        .fetch_all(&self.customer_db)
-        .await
-        .wrap_err(format!(
-            "Failed to execute find staff departments institution: {} staff_id: {} ",
-            institution_id, staff_id
-        ))
+        UserDevice,
+        "select id, user_id, device_id, name, user_agent, ip_address, shadow_id, first_seen_at, last_seen_at from user_devices where user_id = $1 order by last_seen_at desc",
+        user_id
+    )
+    .fetch_all(&self.customer_db)
+    .await
+    .wrap_err("Error fetching user devices")
     }
 
-    async fn edit_department(
+    async fn trust_user_device(
         &self,
-        department_id: &str,
-        dept_name: String,
-        head_staff_id: Option<String>,
-        phone_no: Option<String>,
-        staffs_ids: &[DepartmentMember],
-        domain: &str,
-    ) -> Result<Department> {
+        user_id: i64,
+        device_id: &str,
+        client: &ClientContext,
+    ) -> Result<UserDevice> {
         query_as!(
-        Department,
-        "update departments set
-            name = $2, head_staff_id = $3, phone_no=$4, staffs_ids = $5
-        where shadow_id::varchar = $1
-        returning id, name, institution_id, staffs_ids, head_staff_id, phone_no, deleted_at, modified_at, created_at, domain, shadow_id ",
-        department_id,
-        dept_name,
-        head_staff_id,
-        phone_no,
-        &json!(staffs_ids)
+        UserDevice,
+        "insert into user_devices (user_id, device_id, name, user_agent, ip_address) values ($1, $2, $3, $4, $5)
+        on conflict (user_id, device_id) do update set user_agent = $4, ip_address = $5, last_seen_at = now()
+        returning id, user_id, device_id, name, user_agent, ip_address, shadow_id, first_seen_at, last_seen_at",
+        user_id,
+        device_id,
+        client.device_name(),
+        client.user_agent,
+        client.ip_address
     )
     .fetch_one(&self.customer_db)
     .await
-    .wrap_err(format!(
-        "Failed to edit department_id: {department_id}"
-    ))
+    .wrap_err("Error trusting user device")
     }
 
-    async fn delete_department(&self, department_id: &str) -> Result<()> {
-        query!(
-            "update departments set
-            deleted_at = Now()
-        where shadow_id::varchar = $1",
-            department_id
-        )
-        .execute(&self.customer_db)
-        .await
-        .wrap_err("Failed to delete department")?;
-
-        Ok(())
+    async fn rename_user_device(
+        &self,
+        user_id: i64,
+        shadow_id: &str,
+        name: &str,
+    ) -> Result<Option<UserDevice>> {
+        query_as!(
+        UserDevice,
+        "update user_devices set name = $3 where user_id = $1 and shadow_id::varchar = $2 returning id, user_id, device_id, name, user_agent, ip_address, shadow_id, first_seen_at, last_seen_at",
+        user_id,
+        shadow_id,
+        name
+    )
+    .fetch_optional(&self.customer_db)
+    .await
+    .wrap_err("Error renaming user device")
     }
 
-    async fn create_user(&self, mobile: &str, password: &str) -> Result<User> {
-        let user = query_as!(User, "insert into users (mobile, password) values ($1::varchar, $2::varchar) ON CONFLICT (mobile) DO UPDATE SET deleted_at = null, password = $2::varchar returning id, mobile, password, failed_attempts, device_ids, last_login_time, deleted_at, modified_at, created_at, shadow_id",
-&mobile,
-&password
+    async fn delete_user_device(
+        &self,
+        user_id: i64,
+        shadow_id: &str,
+    ) -> Result<Option<UserDevice>> {
+        query_as!(
+        UserDevice,
+        "delete from user_devices where user_id = $1 and shadow_id::varchar = $2 returning id, user_id, device_id, name, user_agent, ip_address, shadow_id, first_seen_at, last_seen_at",
+        user_id,
+        shadow_id
+    )
+    .fetch_optional(&self.customer_db)
+    .await
+    .wrap_err("Error deleting user device")
+    }
+
+    async fn get_user_totp(&self, user_id: i64) -> Result<Option<UserTotp>> {
+        query_as!(
+            UserTotp,
+            "select user_id, secret, recovery_codes, last_used_step, enabled_at, modified_at, created_at from user_totp where user_id = $1",
+            user_id
+        )
+        .fetch_optional(&self.customer_db)
+        .await
+        .wrap_err("Error fetching user totp")
+    }
+
+    async fn save_user_totp_secret(&self, user_id: i64, secret: Vec<u8>) -> Result<UserTotp> {
+        query_as!(
+            UserTotp,
+            "insert into user_totp (user_id, secret) values ($1, $2)
+            on conflict (user_id) do update set secret = $2, recovery_codes = array[]::varchar[], last_used_step = null, enabled_at = null, modified_at = now()
+            returning user_id, secret, recovery_codes, last_used_step, enabled_at, modified_at, created_at",
+            user_id,
+            secret
+        )
+        .fetch_one(&self.customer_db)
+        .await
+        .wrap_err("Error saving user totp secret")
+    }
+
+    async fn enable_user_totp(&self, user_id: i64, recovery_codes: &[String]) -> Result<UserTotp> {
+        query_as!(
+            UserTotp,
+            "update user_totp set enabled_at = now(), recovery_codes = $2, modified_at = now() where user_id = $1
+            returning user_id, secret, recovery_codes, last_used_step, enabled_at, modified_at, created_at",
+            user_id,
+            recovery_codes
+        )
+        .fetch_one(&self.customer_db)
+        .await
+        .wrap_err("Error enabling user totp")
+    }
+
+    async fn update_user_totp_recovery_codes(
+        &self,
+        user_id: i64,
+        recovery_codes: &[String],
+    ) -> Result<UserTotp> {
+        query_as!(
+            UserTotp,
+            "update user_totp set recovery_codes = $2, modified_at = now() where user_id = $1
+            returning user_id, secret, recovery_codes, last_used_step, enabled_at, modified_at, created_at",
+            user_id,
+            recovery_codes
+        )
+        .fetch_one(&self.customer_db)
+        .await
+        .wrap_err("Error updating user totp recovery codes")
+    }
+
+    async fn record_user_totp_step(&self, user_id: i64, step: i64) -> Result<Option<UserTotp>> {
+        query_as!(
+            UserTotp,
+            "update user_totp set last_used_step = $2 where user_id = $1 and (last_used_step is null or last_used_step < $2)
+            returning user_id, secret, recovery_codes, last_used_step, enabled_at, modified_at, created_at",
+            user_id,
+            step
+        )
+        .fetch_optional(&self.customer_db)
+        .await
+        .wrap_err("Error recording user totp step")
+    }
+
+    async fn consume_user_totp_recovery_code(
+        &self,
+        user_id: i64,
+        code_hash: &str,
+    ) -> Result<Option<UserTotp>> {
+        query_as!(
+            UserTotp,
+            "update user_totp set recovery_codes = array_remove(recovery_codes, $2::varchar), modified_at = now() where user_id = $1 and $2::varchar = any(recovery_codes)
+            returning user_id, secret, recovery_codes, last_used_step, enabled_at, modified_at, created_at",
+            user_id,
+            code_hash
+        )
+        .fetch_optional(&self.customer_db)
+        .await
+        .wrap_err("Error consuming user totp recovery code")
+    }
+
+    async fn delete_user_totp(&self, user_id: i64) -> Result<()> {
+        query!("delete from user_totp where user_id = $1", user_id)
+            .execute(&self.customer_db)
+            .await
+            .wrap_err("Error deleting user totp")?;
+        Ok(())
+    }
+
+    async fn find_institution_roles(&self, institution_id: i64) -> Result<Vec<Role>> {
+        query_as!(
+            Role,
+            "select id, institution_id, name, permissions, shadow_id, deleted_at, modified_at, created_at from institution_roles
+            where institution_id = $1 and deleted_at is null
+            order by name asc",
+            institution_id
+        )
+        .fetch_all(&self.customer_db)
+        .await
+        .wrap_err(format!(
+            "Failed to fetch institution: {institution_id} roles"
+        ))
+    }
+
+    async fn get_institution_role(
+        &self,
+        institution_id: i64,
+        role_id: &str,
+    ) -> Result<Option<Role>> {
+        query_as!(
+            Role,
+            "select id, institution_id, name, permissions, shadow_id, deleted_at, modified_at, created_at from institution_roles
+            where institution_id = $1 and shadow_id::varchar = $2 and deleted_at is null",
+            institution_id,
+            role_id
+        )
+        .fetch_optional(&self.customer_db)
+        .await
+        .wrap_err(format!(
+            "Failed to fetch institution: {institution_id} role: {role_id}"
+        ))
+    }
+
+    async fn create_role(
+        &self,
+        institution_id: i64,
+        name: &str,
+        permissions: &[PermittedAction],
+    ) -> Result<Role> {
+        query_as!(
+            Role,
+            "insert into institution_roles (institution_id, name, permissions) values ($1, $2, $3)
+            returning id, institution_id, name, permissions, shadow_id, deleted_at, modified_at, created_at",
+            institution_id,
+            name,
+            json!(permissions)
+        )
+        .fetch_one(&self.customer_db)
+        .await
+        .wrap_err("Error creating role")
+    }
+
+    async fn update_role(
+        &self,
+        institution_id: i64,
+        role_id: &str,
+        name: &str,
+        permissions: &[PermittedAction],
+    ) -> Result<Role> {
+        query_as!(
+            Role,
+            "update institution_roles set name = $2, permissions = $3, modified_at = Now()
+            where shadow_id::varchar = $1 and institution_id = $4
+            returning id, institution_id, name, permissions, shadow_id, deleted_at, modified_at, created_at",
+            role_id,
+            name,
+            json!(permissions),
+            institution_id
+        )
+        .fetch_one(&self.customer_db)
+        .await
+        .wrap_err("Error updating role")
+    }
+
+    async fn delete_role(&self, institution_id: i64, role_id: &str) -> Result<()> {
+        query!(
+            "update institution_roles set deleted_at = Now() where shadow_id::varchar = $1 and institution_id = $2",
+            role_id,
+            institution_id
+        )
+        .execute(&self.customer_db)
+        .await
+        .wrap_err("Failed to delete role")?;
+        Ok(())
+    }
+
+    async fn reset_user_lockout(&self, mobile: &str) -> Result<User> {
+        query_as!(
+        User,
+        "update users set failed_attempts = 0, locked_until = null where mobile = $1 returning id, mobile, password, failed_attempts, last_login_time, locked_until, deleted_at, modified_at, created_at, shadow_id",
+        mobile
+    )
+    .fetch_one(&self.customer_db)
+    .await
+    .wrap_err("Error resetting user lockout")
+    }
+
+    async fn update_user_password(&self, mobile: &str, password: &str) -> Result<User> {
+        query_as!(
+        User,
+        "update users set password = $2, failed_attempts = 0, locked_until = null, modified_at = now() where mobile = $1 and deleted_at is null returning id, mobile, password, failed_attempts, last_login_time, locked_until, deleted_at, modified_at, created_at, shadow_id",
+        mobile,
+        password
+    )
+    .fetch_one(&self.customer_db)
+    .await
+    .wrap_err("Error updating user password")
+    }
+
+    async fn find_staff_departments(
+        &self,
+        institution_id: i64,
+        staff_id: i64,
+    ) -> Result<Vec<Department>> {
+        query_as!(
+            Department,
+            "SELECT id, name, institution_id, head_staff_id, phone_no,
+       deleted_at, modified_at, created_at, domain, shadow_id
+FROM departments
+WHERE deleted_at IS NULL
+  AND institution_id = $1
+  AND EXISTS (
+    SELECT 1
+    FROM department_members m
+    WHERE m.department_id = departments.id AND m.staff_id = $2 AND m.left_at IS NULL
+  )
+",
+            institution_id,
+            staff_id
+        )
+        .fetch_all(&self.customer_db)
+        .await
+        .wrap_err(format!(
+            "Failed to execute find staff departments institution: {} staff_id: {} ",
+            institution_id, staff_id
+        ))
+    }
+
+    async fn edit_department(
+        &self,
+        department_id: &str,
+        dept_name: String,
+        head_staff_id: Option<String>,
+        phone_no: Option<String>,
+        staffs_ids: &[DepartmentMember],
+        domain: &str,
+    ) -> Result<Department> {
+        let mut txn = self.customer_db.begin().await?;
+        let department = query_as!(
+        Department,
+        "update departments set
+            name = $2, head_staff_id = $3, phone_no=$4, modified_at = Now()
+        where shadow_id::varchar = $1
+        returning id, name, institution_id, head_staff_id, phone_no, deleted_at, modified_at, created_at, domain, shadow_id ",
+        department_id,
+        dept_name,
+        head_staff_id,
+        phone_no
+    )
+    .fetch_one(&mut *txn)
+    .await
+    .wrap_err(format!(
+        "Failed to edit department_id: {department_id}"
+    ))?;
+
+        sync_department_members(&mut txn, &department, staffs_ids).await?;
+        txn.commit().await?;
+        Ok(department)
+    }
+
+    async fn find_staff_memberships(
+        &self,
+        institution_id: i64,
+        staff_id: i64,
+    ) -> Result<Vec<DepartmentMembership>> {
+        query_as!(
+            DepartmentMembership,
+            "select m.id, m.department_id, m.staff_id, m.role, m.joined_at, m.left_at, m.modified_at, m.created_at
+            from department_members m
+            join departments d on d.id = m.department_id
+            where d.institution_id = $1 and d.deleted_at is null and m.staff_id = $2 and m.left_at is null",
+            institution_id,
+            staff_id
+        )
+        .fetch_all(&self.customer_db)
+        .await
+        .wrap_err(format!(
+            "Failed to fetch institution: {institution_id} staff: {staff_id} memberships"
+        ))
+    }
+
+    async fn find_department_memberships(
+        &self,
+        department_id: i64,
+    ) -> Result<Vec<DepartmentMembership>> {
+        query_as!(
+            DepartmentMembership,
+            "select id, department_id, staff_id, role, joined_at, left_at, modified_at, created_at
+            from department_members where department_id = $1 and left_at is null",
+            department_id
+        )
+        .fetch_all(&self.customer_db)
+        .await
+        .wrap_err(format!(
+            "Failed to fetch department: {department_id} memberships"
+        ))
+    }
+
+    async fn add_department_member(
+        &self,
+        department_id: i64,
+        staff_id: i64,
+        role: &str,
+    ) -> Result<DepartmentMembership> {
+        query_as!(
+            DepartmentMembership,
+            "insert into department_members (department_id, staff_id, role) values ($1, $2, $3)
+            on conflict (department_id, staff_id) where left_at is null
+            do update set role = excluded.role, modified_at = Now()
+            returning id, department_id, staff_id, role, joined_at, left_at, modified_at, created_at",
+            department_id,
+            staff_id,
+            role
+        )
+        .fetch_one(&self.customer_db)
+        .await
+        .wrap_err(format!(
+            "Failed to add staff: {staff_id} to department: {department_id}"
+        ))
+    }
+
+    async fn remove_department_member(
+        &self,
+        department_id: i64,
+        staff_id: i64,
+    ) -> Result<Option<DepartmentMembership>> {
+        query_as!(
+            DepartmentMembership,
+            "update department_members set left_at = Now(), modified_at = Now()
+            where department_id = $1 and staff_id = $2 and left_at is null
+            returning id, department_id, staff_id, role, joined_at, left_at, modified_at, created_at",
+            department_id,
+            staff_id
+        )
+        .fetch_optional(&self.customer_db)
+        .await
+        .wrap_err(format!(
+            "Failed to remove staff: {staff_id} from department: {department_id}"
+        ))
+    }
+
+    async fn count_role_members(&self, institution_id: i64, role: &str) -> Result<i64> {
+        let count = query!(
+            r#"select count(*) as "count!" from department_members m
+            join departments d on d.id = m.department_id
+            where d.institution_id = $1 and d.deleted_at is null and m.left_at is null and lower(m.role) = lower($2)"#,
+            institution_id,
+            role
+        )
+        .fetch_one(&self.customer_db)
+        .await
+        .wrap_err(format!(
+            "Failed to count institution: {institution_id} role: {role} members"
+        ))?
+        .count;
+        Ok(count)
+    }
+
+    async fn find_role_memberships(
+        &self,
+        institution_id: i64,
+        role: &str,
+    ) -> Result<Vec<DepartmentMembership>> {
+        query_as!(
+            DepartmentMembership,
+            "select m.id, m.department_id, m.staff_id, m.role, m.joined_at, m.left_at, m.modified_at, m.created_at
+            from department_members m
+            join departments d on d.id = m.department_id
+            where d.institution_id = $1 and d.deleted_at is null and m.left_at is null and lower(m.role) = lower($2)",
+            institution_id,
+            role
+        )
+        .fetch_all(&self.customer_db)
+        .await
+        .wrap_err(format!(
+            "Failed to fetch institution: {institution_id} role: {role} memberships"
+        ))
+    }
+
+    async fn delete_department(&self, department_id: &str) -> Result<()> {
+        query!(
+            "update departments set
+            deleted_at = Now()
+        where shadow_id::varchar = $1",
+            department_id
+        )
+        .execute(&self.customer_db)
+        .await
+        .wrap_err("Failed to delete department")?;
+
+        Ok(())
+    }
+
+    async fn create_user(&self, mobile: &str, password: &str) -> Result<User> {
+        let user = query_as!(User, "insert into users (mobile, password) values ($1::varchar, $2::varchar) ON CONFLICT (mobile) DO UPDATE SET deleted_at = null, password = $2::varchar returning id, mobile, password, failed_attempts, last_login_time, locked_until, deleted_at, modified_at, created_at, shadow_id",
+&mobile,
+&password
 ).fetch_one(&self.customer_db)
 .await
 .wrap_err(format!("Failed to create user account"))?;
 
         return Ok(user);
     }
+
+    async fn find_user_mobiles(&self) -> Result<Vec<String>> {
+        let users = query!("select mobile from users where deleted_at is null order by id")
+            .fetch_all(&self.customer_db)
+            .await
+            .wrap_err("Failed to fetch user mobiles")?;
+        Ok(users.into_iter().map(|u| u.mobile).collect())
+    }
+
+    async fn find_staffs_by_mobiles(&self, mobiles: &[String]) -> Result<Vec<Staff>> {
+        query_as!(
+            Staff,
+            "select id, first_name, last_name, mobile, title, institution_id,  profile_image, deleted_at, modified_at, created_at, shadow_id from staffs
+            where mobile = any($1)",
+            mobiles
+        )
+        .fetch_all(&self.customer_db)
+        .await
+        .wrap_err("Error fetching staffs with mobiles")
+    }
+
+    async fn import_staffs(
+        &self,
+        institution_id: i64,
+        entries: &[StaffImportEntry],
+    ) -> Result<Vec<(Staff, StaffInvitation)>> {
+        let mut txn = self.customer_db.begin().await?;
+        let mut imported = vec![];
+
+        for entry in entries {
+            let s = &entry.staff;
+            let staff = query_as!(
+                Staff,
+                "insert into staffs
+                (first_name, last_name, mobile, title, institution_id,  profile_image)
+            values
+                ($1, $2, $3, $4, $5, $6)
+            returning id, first_name, last_name, mobile, title, institution_id,  profile_image, deleted_at, modified_at, created_at, shadow_id ",
+                s.first_name,
+                s.last_name,
+                s.mobile,
+                s.title,
+                Some(institution_id),
+                s.profile_image
+            )
+            .fetch_one(&mut *txn)
+            .await
+            .wrap_err(format!("Failed to import staff: {}", s.mobile))?;
+
+            if let (Some(department_id), Some(role)) = (entry.department_id, &entry.role) {
+                query!(
+                    "insert into department_members (department_id, staff_id, role) values ($1, $2, $3)
+                    on conflict (department_id, staff_id) where left_at is null
+                    do update set role = excluded.role, modified_at = Now()",
+                    department_id,
+                    staff.id,
+                    role
+                )
+                .execute(&mut *txn)
+                .await
+                .wrap_err(format!(
+                    "Failed to add staff: {} to department: {department_id}",
+                    staff.id
+                ))?;
+            }
+
+            let invitation =
+                insert_staff_invitation(&mut *txn, institution_id, &staff, &entry.invitation)
+                    .await?;
+            imported.push((staff, invitation));
+        }
+
+        txn.commit().await?;
+        Ok(imported)
+    }
+
+    async fn get_institution(&self, institution_id: i64) -> Result<Option<Institution>> {
+        query_as!(
+            Institution,
+            "select id, name, email, classification, setting, address, town, state, created_by, workspace_code, logo, deleted_at, purge_at, modified_at, created_at, shadow_id from institutions
+            where id = $1 and deleted_at is null",
+            institution_id
+        )
+        .fetch_optional(&self.customer_db)
+        .await
+        .wrap_err(format!("Failed to fetch institution: {institution_id}"))
+    }
+
+    async fn update_institution(
+        &self,
+        i: Institution,
+        changed_by: Option<i64>,
+        changes: &[InstitutionFieldChange],
+    ) -> Result<Institution> {
+        let mut txn = self.customer_db.begin().await?;
+        let institution = query_as!(
+            Institution,
+            "update institutions set
+                name = $2, classification = $3, setting = $4, address = $5, town = $6, state = $7, logo = $8, modified_at = Now()
+            where id = $1
+            returning id, name, email, classification, setting, address, town, state, created_by, workspace_code, logo, deleted_at, purge_at, modified_at, created_at, shadow_id",
+            i.id,
+            i.name,
+            i.classification,
+            i.setting,
+            i.address,
+            i.town,
+            i.state,
+            i.logo
+        )
+        .fetch_one(&mut *txn)
+        .await
+        .wrap_err(format!("Failed to update institution: {}", i.id))?;
+
+        insert_institution_changes(&mut *txn, institution.id, changed_by, changes).await?;
+
+        txn.commit().await?;
+        Ok(institution)
+    }
+
+    async fn get_institution_record(&self, institution_id: i64) -> Result<Option<Institution>> {
+        query_as!(
+            Institution,
+            "select id, name, email, classification, setting, address, town, state, created_by, workspace_code, logo, deleted_at, purge_at, modified_at, created_at, shadow_id from institutions
+            where id = $1",
+            institution_id
+        )
+        .fetch_optional(&self.customer_db)
+        .await
+        .wrap_err(format!("Failed to fetch institution: {institution_id}"))
+    }
+
+    async fn deactivate_institution(
+        &self,
+        institution_id: i64,
+        purge_at: DateTime<Utc>,
+        changed_by: Option<i64>,
+    ) -> Result<Institution> {
+        let mut txn = self.customer_db.begin().await?;
+        let institution = query_as!(
+            Institution,
+            "update institutions set deleted_at = Now(), purge_at = $2, modified_at = Now()
+            where id = $1 and deleted_at is null
+            returning id, name, email, classification, setting, address, town, state, created_by, workspace_code, logo, deleted_at, purge_at, modified_at, created_at, shadow_id",
+            institution_id,
+            purge_at
+        )
+        .fetch_one(&mut *txn)
+        .await
+        .wrap_err(format!("Failed to deactivate institution: {institution_id}"))?;
+
+        let change = InstitutionFieldChange {
+            field: "status".into(),
+            from: Some("Active".into()),
+            to: Some("Deactivated".into()),
+        };
+        insert_institution_changes(&mut *txn, institution.id, changed_by, &[change]).await?;
+
+        txn.commit().await?;
+        Ok(institution)
+    }
+
+    async fn is_workspace_deactivated(&self, workspace_code: &str) -> Result<bool> {
+        let deactivated = query!(
+            r#"select deleted_at is not null as "deactivated!" from institutions where workspace_code = $1"#,
+            workspace_code
+        )
+        .fetch_optional(&self.customer_db)
+        .await
+        .wrap_err("Failed to fetch workspace status")?;
+        Ok(deactivated.map(|w| w.deactivated).unwrap_or(true))
+    }
+
+    async fn find_institutions_due_purge(&self, now: DateTime<Utc>) -> Result<Vec<Institution>> {
+        query_as!(
+            Institution,
+            "select id, name, email, classification, setting, address, town, state, created_by, workspace_code, logo, deleted_at, purge_at, modified_at, created_at, shadow_id from institutions
+            where deleted_at is not null and purge_at <= $1",
+            now
+        )
+        .fetch_all(&self.customer_db)
+        .await
+        .wrap_err("Failed to fetch institutions due a purge")
+    }
+
+    async fn purge_institution(&self, institution_id: i64) -> Result<PurgedInstitution> {
+        let mut txn = self.customer_db.begin().await?;
+
+        let files: Vec<String> = sqlx::query_scalar(
+            "select logo from institutions where id = $1 and logo is not null
+            union all
+            select profile_image from staffs where institution_id = $1 and profile_image is not null
+            union all
+            select file_key from institution_exports where institution_id = $1 and file_key is not null",
+        )
+        .bind(institution_id)
+        .fetch_all(&mut *txn)
+        .await
+        .wrap_err("Failed to fetch institution files")?;
+
+        let mobiles = query!(
+            "select mobile from staffs where institution_id = $1",
+            institution_id
+        )
+        .fetch_all(&mut *txn)
+        .await
+        .wrap_err("Failed to fetch institution staffs")?
+        .into_iter()
+        .map(|s| s.mobile)
+        .collect::<Vec<_>>();
+
+        query!(
+            "delete from staff_invitations where institution_id = $1",
+            institution_id
+        )
+        .execute(&mut *txn)
+        .await
+        .wrap_err("Failed to purge staff invitations")?;
+
+        // members go with their departments, roles, history and exports with
+        // the institution
+        query!(
+            "delete from departments where institution_id = $1",
+            institution_id
+        )
+        .execute(&mut *txn)
+        .await
+        .wrap_err("Failed to purge departments")?;
+
+        query!("delete from institutions where id = $1", institution_id)
+            .execute(&mut *txn)
+            .await
+            .wrap_err("Failed to purge institution")?;
+
+        query!(
+            "delete from staffs where institution_id = $1",
+            institution_id
+        )
+        .execute(&mut *txn)
+        .await
+        .wrap_err("Failed to purge staffs")?;
+
+        query!(
+            "delete from users u where u.mobile = any($1)
+            and not exists (select 1 from staffs s where s.mobile = u.mobile)",
+            &mobiles
+        )
+        .execute(&mut *txn)
+        .await
+        .wrap_err("Failed to purge users")?;
+
+        txn.commit().await?;
+        Ok(PurgedInstitution { files, mobiles })
+    }
+
+    async fn export_institution_records(
+        &self,
+        institution_id: i64,
+    ) -> Result<Vec<(String, Value)>> {
+        let mut records = vec![];
+        for (name, records_query) in INSTITUTION_EXPORT_QUERIES {
+            let export_query = format!(
+                "select coalesce(json_agg(r), '[]'::json) from ({}) r",
+                records_query
+            );
+            let rows: Value = sqlx::query_scalar(&export_query)
+                .bind(institution_id)
+                .fetch_one(&self.customer_db)
+                .await
+                .wrap_err(format!("Failed to export institution {name}"))?;
+            records.push((name.to_owned(), rows));
+        }
+        Ok(records)
+    }
+
+    async fn create_institution_export(
+        &self,
+        institution_id: i64,
+        requested_by: Option<i64>,
+    ) -> Result<InstitutionExport> {
+        query_as!(
+            InstitutionExport,
+            "insert into institution_exports (institution_id, requested_by) values ($1, $2)
+            returning id, institution_id, status, file_key, requested_by, completed_at, link_sent_at, shadow_id, modified_at, created_at",
+            institution_id,
+            requested_by
+        )
+        .fetch_one(&self.customer_db)
+        .await
+        .wrap_err(format!(
+            "Failed to create institution: {institution_id} export"
+        ))
+    }
+
+    async fn get_institution_export(&self, shadow_id: &str) -> Result<Option<InstitutionExport>> {
+        query_as!(
+            InstitutionExport,
+            "select id, institution_id, status, file_key, requested_by, completed_at, link_sent_at, shadow_id, modified_at, created_at from institution_exports
+            where shadow_id::varchar = $1",
+            shadow_id
+        )
+        .fetch_optional(&self.customer_db)
+        .await
+        .wrap_err(format!("Failed to fetch export: {shadow_id}"))
+    }
+
+    async fn find_institution_exports(
+        &self,
+        institution_id: i64,
+    ) -> Result<Vec<InstitutionExport>> {
+        query_as!(
+            InstitutionExport,
+            "select id, institution_id, status, file_key, requested_by, completed_at, link_sent_at, shadow_id, modified_at, created_at from institution_exports
+            where institution_id = $1
+            order by created_at desc, id desc",
+            institution_id
+        )
+        .fetch_all(&self.customer_db)
+        .await
+        .wrap_err(format!(
+            "Failed to fetch institution: {institution_id} exports"
+        ))
+    }
+
+    async fn find_pending_exports(&self) -> Result<Vec<InstitutionExport>> {
+        query_as!(
+            InstitutionExport,
+            "select id, institution_id, status, file_key, requested_by, completed_at, link_sent_at, shadow_id, modified_at, created_at from institution_exports
+            where status = 'Pending'
+            order by created_at, id"
+        )
+        .fetch_all(&self.customer_db)
+        .await
+        .wrap_err("Failed to fetch pending exports")
+    }
+
+    async fn find_unsent_export_links(&self) -> Result<Vec<InstitutionExport>> {
+        query_as!(
+            InstitutionExport,
+            "select id, institution_id, status, file_key, requested_by, completed_at, link_sent_at, shadow_id, modified_at, created_at from institution_exports
+            where status = 'Ready' and link_sent_at is null
+            order by completed_at, id"
+        )
+        .fetch_all(&self.customer_db)
+        .await
+        .wrap_err("Failed to fetch exports with unsent links")
+    }
+
+    async fn mark_export_link_sent(&self, export_id: i64) -> Result<()> {
+        query!(
+            "update institution_exports set link_sent_at = Now(), modified_at = Now() where id = $1",
+            export_id
+        )
+        .execute(&self.customer_db)
+        .await
+        .wrap_err(format!("Failed to mark export: {export_id} link sent"))?;
+        Ok(())
+    }
+
+    async fn complete_institution_export(
+        &self,
+        export_id: i64,
+        status: &str,
+        file_key: Option<String>,
+    ) -> Result<InstitutionExport> {
+        query_as!(
+            InstitutionExport,
+            "update institution_exports set status = $2, file_key = $3, completed_at = Now(), modified_at = Now()
+            where id = $1
+            returning id, institution_id, status, file_key, requested_by, completed_at, link_sent_at, shadow_id, modified_at, created_at",
+            export_id,
+            status,
+            file_key
+        )
+        .fetch_one(&self.customer_db)
+        .await
+        .wrap_err(format!("Failed to complete export: {export_id}"))
+    }
+
+    async fn paginate_institution_changes(
+        &self,
+        institution_id: i64,
+        pagination: &PaginatedQuery,
+    ) -> Result<(Vec<InstitutionChange>, i64)> {
+        let changes = query_as!(
+            InstitutionChange,
+            r#"select c.shadow_id, c.changes, c.created_at,
+                s.shadow_id::varchar as "changed_by?", s.first_name as "changed_by_first_name?", s.last_name as "changed_by_last_name?"
+            from institution_changes c
+            left join staffs s on s.id = c.changed_by
+            where c.institution_id = $1
+            order by c.created_at desc, c.id desc
+            limit $2 offset $3"#,
+            institution_id,
+            pagination.limit() as i64,
+            pagination.offset()
+        )
+        .fetch_all(&self.customer_db)
+        .await
+        .wrap_err(format!(
+            "Failed to fetch institution: {institution_id} changes"
+        ))?;
+
+        let total = query!(
+            r#"select count(*) as "total!" from institution_changes where institution_id = $1"#,
+            institution_id
+        )
+        .fetch_one(&self.customer_db)
+        .await
+        .wrap_err("Failed to count institution changes")?
+        .total;
+
+        Ok((changes, total))
+    }
+
+    async fn get_staff(&self, staff_id: i64) -> Result<Option<Staff>> {
+        query_as!(
+            Staff,
+            "select id, first_name, last_name, mobile, title, institution_id,  profile_image, deleted_at, modified_at, created_at, shadow_id from staffs
+            where id = $1",
+            staff_id
+        )
+        .fetch_optional(&self.customer_db)
+        .await
+        .wrap_err(format!("Failed to fetch staff: {staff_id}"))
+    }
+
+    async fn create_staff_invitation(
+        &self,
+        institution_id: i64,
+        staff: &Staff,
+        invitation: &NewStaffInvitation,
+    ) -> Result<StaffInvitation> {
+        insert_staff_invitation(&self.customer_db, institution_id, staff, invitation).await
+    }
+
+    async fn get_staff_invitation(&self, shadow_id: &str) -> Result<Option<StaffInvitation>> {
+        query_as!(
+            StaffInvitation,
+            "select id, institution_id, staff_id, mobile, token_hash, status, invited_by, expires_at, last_sent_at, reminder_count, responded_at, shadow_id, modified_at, created_at from staff_invitations
+            where shadow_id::varchar = $1",
+            shadow_id
+        )
+        .fetch_optional(&self.customer_db)
+        .await
+        .wrap_err(format!("Failed to fetch invitation: {shadow_id}"))
+    }
+
+    async fn find_institution_invitations(
+        &self,
+        institution_id: i64,
+        status: &str,
+    ) -> Result<Vec<StaffInvitation>> {
+        query_as!(
+            StaffInvitation,
+            "select id, institution_id, staff_id, mobile, token_hash, status, invited_by, expires_at, last_sent_at, reminder_count, responded_at, shadow_id, modified_at, created_at from staff_invitations
+            where institution_id = $1 and status = $2
+            order by created_at desc, id desc",
+            institution_id,
+            status
+        )
+        .fetch_all(&self.customer_db)
+        .await
+        .wrap_err(format!(
+            "Failed to fetch institution: {institution_id} invitations"
+        ))
+    }
+
+    async fn find_latest_staff_invitation(
+        &self,
+        staff_id: i64,
+    ) -> Result<Option<StaffInvitation>> {
+        query_as!(
+            StaffInvitation,
+            "select id, institution_id, staff_id, mobile, token_hash, status, invited_by, expires_at, last_sent_at, reminder_count, responded_at, shadow_id, modified_at, created_at from staff_invitations
+            where staff_id = $1
+            order by created_at desc, id desc
+            limit 1",
+            staff_id
+        )
+        .fetch_optional(&self.customer_db)
+        .await
+        .wrap_err(format!("Failed to fetch staff: {staff_id} invitation"))
+    }
+
+    async fn update_staff_invitation(&self, i: StaffInvitation) -> Result<StaffInvitation> {
+        query_as!(
+            StaffInvitation,
+            "update staff_invitations set
+                token_hash = $2, status = $3, expires_at = $4, last_sent_at = $5,
+                reminder_count = $6, responded_at = $7, modified_at = Now()
+            where id = $1
+            returning id, institution_id, staff_id, mobile, token_hash, status, invited_by, expires_at, last_sent_at, reminder_count, responded_at, shadow_id, modified_at, created_at",
+            i.id,
+            i.token_hash,
+            i.status,
+            i.expires_at,
+            i.last_sent_at,
+            i.reminder_count,
+            i.responded_at
+        )
+        .fetch_one(&self.customer_db)
+        .await
+        .wrap_err(format!("Failed to update invitation: {}", i.id))
+    }
+
+    async fn accept_staff_invitation(
+        &self,
+        invitation_id: i64,
+        mobile: &str,
+        password: Option<&str>,
+    ) -> Result<Option<StaffInvitation>> {
+        let mut txn = self.customer_db.begin().await?;
+        let invitation = query_as!(
+            StaffInvitation,
+            "update staff_invitations set status = 'Accepted', responded_at = Now(), modified_at = Now()
+            where id = $1 and status = 'Pending' and expires_at > Now()
+            returning id, institution_id, staff_id, mobile, token_hash, status, invited_by, expires_at, last_sent_at, reminder_count, responded_at, shadow_id, modified_at, created_at",
+            invitation_id
+        )
+        .fetch_optional(&mut *txn)
+        .await
+        .wrap_err(format!("Failed to accept invitation: {invitation_id}"))?;
+        let Some(invitation) = invitation else {
+            return Ok(None);
+        };
+
+        if let Some(password) = password {
+            query!(
+                "insert into users (mobile, password) values ($1::varchar, $2::varchar)
+                ON CONFLICT (mobile) DO UPDATE SET deleted_at = null, password = $2::varchar",
+                mobile,
+                password
+            )
+            .execute(&mut *txn)
+            .await
+            .wrap_err("Failed to create invited user account")?;
+        }
+
+        txn.commit().await?;
+        Ok(Some(invitation))
+    }
+
+    async fn find_invitations_due_reminder(
+        &self,
+        sent_before: DateTime<Utc>,
+        max_reminders: i32,
+    ) -> Result<Vec<StaffInvitation>> {
+        query_as!(
+            StaffInvitation,
+            "select id, institution_id, staff_id, mobile, token_hash, status, invited_by, expires_at, last_sent_at, reminder_count, responded_at, shadow_id, modified_at, created_at from staff_invitations
+            where status = 'Pending' and expires_at > Now() and last_sent_at < $1 and reminder_count < $2
+            order by last_sent_at asc",
+            sent_before,
+            max_reminders
+        )
+        .fetch_all(&self.customer_db)
+        .await
+        .wrap_err("Failed to fetch invitations due a reminder")
+    }
+}
+
+async fn insert_institution_changes<'e, E: Executor<'e, Database = Postgres>>(
+    executor: E,
+    institution_id: i64,
+    changed_by: Option<i64>,
+    changes: &[InstitutionFieldChange],
+) -> Result<()> {
+    query!(
+        "insert into institution_changes (institution_id, changed_by, changes) values ($1, $2, $3)",
+        institution_id,
+        changed_by,
+        serde_json::to_value(changes)?
+    )
+    .execute(executor)
+    .await
+    .wrap_err("Failed to record institution changes")?;
+    Ok(())
+}
+
+async fn insert_staff_invitation<'e, E: Executor<'e, Database = Postgres>>(
+    executor: E,
+    institution_id: i64,
+    staff: &Staff,
+    invitation: &NewStaffInvitation,
+) -> Result<StaffInvitation> {
+    query_as!(
+        StaffInvitation,
+        "insert into staff_invitations
+            (institution_id, staff_id, mobile, token_hash, invited_by, expires_at, shadow_id)
+        values
+            ($1, $2, $3, $4, $5, $6, $7)
+        returning id, institution_id, staff_id, mobile, token_hash, status, invited_by, expires_at, last_sent_at, reminder_count, responded_at, shadow_id, modified_at, created_at",
+        institution_id,
+        staff.id,
+        staff.mobile,
+        invitation.token_hash,
+        invitation.invited_by,
+        invitation.expires_at,
+        invitation.shadow_id
+    )
+    .fetch_one(executor)
+    .await
+    .wrap_err(format!("Failed to create invitation for staff: {}", staff.id))
+}
+
+// Applies the department's member list, staff missing from it leave the
+// department and only new members or role changes are written.
+async fn sync_department_members(
+    txn: &mut Transaction<'_, Postgres>,
+    department: &Department,
+    members: &[DepartmentMember],
+) -> Result<()> {
+    let mut staff_ids: Vec<String> = vec![];
+    let mut roles: Vec<String> = vec![];
+    for member in members {
+        if !staff_ids.contains(&member.staff_id) {
+            staff_ids.push(member.staff_id.clone());
+            roles.push(member.role.clone());
+        }
+    }
+
+    query!(
+        "update department_members set left_at = Now(), modified_at = Now()
+        where department_id = $1 and left_at is null and staff_id not in (
+            select s.id from staffs s where s.shadow_id::varchar = any($2)
+        )",
+        department.id,
+        &staff_ids
+    )
+    .execute(&mut **txn)
+    .await
+    .wrap_err(format!(
+        "Failed to remove department: {} members",
+        department.id
+    ))?;
+
+    query!(
+        "insert into department_members (department_id, staff_id, role)
+        select $1, s.id, m.role
+        from unnest($2::varchar[], $3::varchar[]) as m(staff_id, role)
+        join staffs s on s.shadow_id::varchar = m.staff_id and s.institution_id = $4
+        on conflict (department_id, staff_id) where left_at is null
+        do update set role = excluded.role, modified_at = Now()
+        where department_members.role <> excluded.role",
+        department.id,
+        &staff_ids,
+        &roles,
+        department.institution_id
+    )
+    .execute(&mut **txn)
+    .await
+    .wrap_err(format!(
+        "Failed to add department: {} members",
+        department.id
+    ))?;
+
+    Ok(())
 }
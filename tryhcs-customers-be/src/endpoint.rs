@@ -2,23 +2,28 @@ use std::sync::Arc;
 
 use axum::{
     extract::{Path, Query, State},
-    http::{HeaderMap, StatusCode},
+    http::StatusCode,
     routing::{delete, get, post, put},
     Json, Router,
 };
-use chrono::Duration;
 use either::Either;
-use serde_json::{json, Value};
-use tracing::error;
+use serde_json::Value;
 use tryhcs_commons_be::{
-    api_response::{convert_result_to_json_response, ErrorMessage},
-    AUTH_ID_HEADER_FIELD,
+    api_response::{convert_paginated_result_to_json_response, convert_result_to_json_response},
+    auth::{
+        DepartmentCreate, DepartmentDelete, DepartmentEdit, InstitutionSettingView,
+        PersonnelCreate, PersonnelDelete, PersonnelEdit,
+    },
+    client_context::ClientContext,
 };
 use tryhcs_shared::{
     api_params::PaginatedQuery,
     institution_params::{
-        AuthenticatedUser, AuthorizedInstitutionUser, CreateDepartment, CreateInstitution,
-        LoginReq, NewStaff, StaffDto, StaffId, VerifyOTP,
+        AcceptInvitationReq, ChangePasswordReq, CreateDepartment, CreateInstitution, CreateRole,
+        DeactivateInstitutionReq, DepartmentMember, DisableTotpReq, EditInstitution,
+        ExportDownloadReq, ForgotPasswordReq, InstitutionLogoUpload, InvitationTokenReq, LoginReq,
+        NewStaff, RefreshTokenReq, RenameDeviceReq, ResetPasswordReq, StaffImportUpload,
+        TotpCodeReq, TotpEnrollReq, UnlockAccountReq, VerifyOTP,
     },
     APIFileUpload,
 };
@@ -26,7 +31,7 @@ use tryhcs_shared::{
 use crate::{
     api::{self, upload_base64_file_api},
     app::CustomersApp,
-    params::{WorkspaceAdmin, WorkspaceUser},
+    params::{AuthenticatedSession, Requires, WorkspaceAdmin, WorkspaceUser},
 };
 
 pub fn customers_router(app: Arc<CustomersApp>) -> Router {
@@ -39,11 +44,53 @@ pub fn customers_router(app: Arc<CustomersApp>) -> Router {
         .route("/login", post(login_init_endpoint))
         .route("/login/complete", post(login_complete_endpoint))
         .route("/user/profile", get(get_user_profile_endpoint))
+        .route("/password/forgot", post(forgot_password_endpoint))
+        .route("/password/reset", post(reset_password_endpoint))
+        .route("/password/change", post(change_password_endpoint))
+        .route("/account/unlock", post(unlock_account_init_endpoint))
+        .route(
+            "/account/unlock/complete",
+            post(unlock_account_complete_endpoint),
+        )
+        .route("/token/refresh", post(refresh_token_endpoint))
+        .route("/logout", post(logout_endpoint))
+        .route("/sessions", get(find_sessions_endpoint))
+        .route("/sessions/{session_id}", delete(revoke_session_endpoint))
+        .route("/workspaces", get(find_workspaces_endpoint))
+        .route(
+            "/workspaces/{workspace_code}/switch",
+            post(switch_workspace_endpoint),
+        )
+        .route("/totp/enroll", post(enroll_totp_endpoint))
+        .route("/totp/verify", post(verify_totp_enrollment_endpoint))
+        .route(
+            "/totp/recovery-codes",
+            post(regenerate_totp_recovery_codes_endpoint),
+        )
+        .route("/totp/disable", post(disable_totp_endpoint))
+        .route("/devices", get(find_devices_endpoint))
+        .route("/devices/{device_id}", put(rename_device_endpoint))
+        .route("/devices/{device_id}", delete(revoke_device_endpoint))
         .route("/staffs", get(find_staffs_endpoint))
         .route("/staffs", post(add_staff))
+        .route("/staffs/import/dry-run", post(dry_run_staff_import))
+        .route("/staffs/import", post(import_staffs))
+        .route("/invitations", get(find_invitations_endpoint))
+        .route(
+            "/invitations/{invitation_id}/resend",
+            post(resend_invitation_endpoint),
+        )
+        .route(
+            "/invitations/{invitation_id}",
+            delete(revoke_invitation_endpoint),
+        )
+        .route("/invitations/details", post(get_invitation_endpoint))
+        .route("/invitations/accept", post(accept_invitation_endpoint))
+        .route("/invitations/decline", post(decline_invitation_endpoint))
         .route("/staffs/{staff_id}", get(get_staff_profile_endpoint))
         .route("/staffs/{staff_id}", put(edit_staff))
         .route("/staffs/{staff_id}", delete(delete_staff))
+        .route("/staffs/{staff_id}/unlock", post(unlock_staff))
         .route("/departments", get(find_departments_endpoint))
         .route(
             "/departments/{department_id}",
@@ -52,6 +99,41 @@ pub fn customers_router(app: Arc<CustomersApp>) -> Router {
         .route("/departments/{department_id}", put(edit_department))
         .route("/departments/{department_id}", delete(delete_department))
         .route("/departments", post(create_department))
+        .route(
+            "/departments/{department_id}/members",
+            post(add_department_member),
+        )
+        .route(
+            "/departments/{department_id}/members/{staff_id}",
+            delete(remove_department_member),
+        )
+        .route("/roles", get(find_roles))
+        .route("/roles", post(create_role))
+        .route("/roles/{role_id}", put(edit_role))
+        .route("/roles/{role_id}", delete(delete_role))
+        .route("/institution", get(get_institution_endpoint))
+        .route("/institution", put(edit_institution_endpoint))
+        .route("/institution/logo", put(upload_institution_logo_endpoint))
+        .route(
+            "/institution/history",
+            get(find_institution_changes_endpoint),
+        )
+        .route(
+            "/institution/deactivate",
+            post(deactivate_institution_endpoint),
+        )
+        .route(
+            "/institution/exports",
+            get(find_institution_exports_endpoint),
+        )
+        .route(
+            "/institution/exports",
+            post(request_institution_export_endpoint),
+        )
+        .route(
+            "/institution/exports/download",
+            post(get_export_download_endpoint),
+        )
         // File uploads module
         .route("/file-uploads/v1/upload", post(generic_upload_endpoint))
         // Finance module
@@ -97,51 +179,14 @@ pub async fn create_institution_complete_endpoint(
     convert_result_to_json_response(result)
 }
 
-async fn add_user_session(app: Arc<CustomersApp>, authenticated: &AuthenticatedUser) {
-    dbg!((&authenticated.token, &authenticated));
-
-    let mut duplicate = authenticated.clone();
-    duplicate.token = None;
-
-    match (serde_json::to_string(&duplicate), &authenticated.token) {
-        (Err(err), _) => {
-            tracing::error!(message="Failed to serialized authenticated user for session", err=?err);
-        }
-        (Ok(str_value), Some(token)) => {
-            if let Err(err) = app
-                .redis
-                .set_key(
-                    token,
-                    &str_value,
-                    Some(
-                        Duration::minutes(app.env.session_expires_in_min as i64).num_seconds()
-                            as u64,
-                    ),
-                )
-                .await
-            {
-                tracing::error!(message="Error occurred during session insertion", err=?err);
-            };
-        }
-        _ => {
-            tracing::error!(message = "Failed to setup authenticated user for session");
-        }
-    }
-}
-
 #[axum::debug_handler]
 pub async fn login_init_endpoint(
     State(app): State<Arc<CustomersApp>>,
+    client: ClientContext,
 
     Json(req): Json<LoginReq>,
 ) -> (StatusCode, Json<Value>) {
-    let result = api::login_init(app.as_ref(), &req).await;
-    if let Ok((_, Either::Left(Some(authenticated)))) = &result {
-        if let Some(authenticated) = &authenticated.auth {
-            add_user_session(app, authenticated).await;
-        }
-    }
-    convert_result_to_json_response(result)
+    convert_result_to_json_response(api::login_init(app.as_ref(), &req, &client).await)
 }
 
 #[axum::debug_handler]
@@ -149,61 +194,110 @@ pub async fn login_complete_endpoint(
     State(app): State<Arc<CustomersApp>>,
     Json(req): Json<VerifyOTP>,
 ) -> (StatusCode, Json<Value>) {
-    let result = api::login_complete(app.as_ref(), &req).await;
-    if let Ok((_, Either::Left(Some(authenticated)))) = &result {
-        add_user_session(app, authenticated).await;
-    }
-    convert_result_to_json_response(result)
+    convert_result_to_json_response(api::login_complete(app.as_ref(), &req).await)
+}
+
+#[axum::debug_handler]
+pub async fn forgot_password_endpoint(
+    State(app): State<Arc<CustomersApp>>,
+    Json(req): Json<ForgotPasswordReq>,
+) -> (StatusCode, Json<Value>) {
+    convert_result_to_json_response(api::forgot_password(app.as_ref(), &req).await)
+}
+
+#[axum::debug_handler]
+pub async fn reset_password_endpoint(
+    State(app): State<Arc<CustomersApp>>,
+    Json(req): Json<ResetPasswordReq>,
+) -> (StatusCode, Json<Value>) {
+    convert_result_to_json_response(api::reset_password(app.as_ref(), &req).await)
+}
+
+#[axum::debug_handler]
+pub async fn change_password_endpoint(
+    State(app): State<Arc<CustomersApp>>,
+    AuthenticatedSession(session): AuthenticatedSession,
+    Json(req): Json<ChangePasswordReq>,
+) -> (StatusCode, Json<Value>) {
+    convert_result_to_json_response(api::change_password(app.as_ref(), &session, &req).await)
+}
+
+#[axum::debug_handler]
+pub async fn unlock_account_init_endpoint(
+    State(app): State<Arc<CustomersApp>>,
+    Json(req): Json<UnlockAccountReq>,
+) -> (StatusCode, Json<Value>) {
+    convert_result_to_json_response(api::unlock_account_init(app.as_ref(), &req).await)
+}
+
+#[axum::debug_handler]
+pub async fn unlock_account_complete_endpoint(
+    State(app): State<Arc<CustomersApp>>,
+    Json(req): Json<VerifyOTP>,
+) -> (StatusCode, Json<Value>) {
+    convert_result_to_json_response(api::unlock_account_complete(app.as_ref(), &req).await)
+}
+
+#[axum::debug_handler]
+pub async fn refresh_token_endpoint(
+    State(app): State<Arc<CustomersApp>>,
+    Json(req): Json<RefreshTokenReq>,
+) -> (StatusCode, Json<Value>) {
+    convert_result_to_json_response(api::refresh_token(app.as_ref(), &req).await)
 }
 
 #[axum::debug_handler]
 pub async fn get_user_profile_endpoint(
+    State(_app): State<Arc<CustomersApp>>,
+    AuthenticatedSession(session): AuthenticatedSession,
+) -> (StatusCode, Json<Value>) {
+    convert_result_to_json_response(Ok((StatusCode::OK, Either::Left(Some(session.user)))))
+}
+
+#[axum::debug_handler]
+pub async fn logout_endpoint(
     State(app): State<Arc<CustomersApp>>,
-    headers: HeaderMap,
-    WorkspaceUser(user_): WorkspaceUser,
-) -> (StatusCode, Json<Value>) {
-    let response = {
-        match headers.get(AUTH_ID_HEADER_FIELD) {
-            None => (
-                StatusCode::UNAUTHORIZED,
-                Either::Right(ErrorMessage("Unauthorized".into())),
-            ),
-            Some(session_key) => {
-                if let Ok(session_key) = session_key.to_str() {
-                    match app.redis.get_key(session_key).await {
-                        Err(err) => {
-                            error!(message="Failed to get session from cache", err=?err);
-                            (
-                                StatusCode::UNAUTHORIZED,
-                                Either::Right(ErrorMessage("Unable to get profile".into())),
-                            )
-                        }
-                        Ok(session) => {
-                            let session = session
-                                .and_then(|s| serde_json::from_str::<AuthenticatedUser>(&s).ok());
-                            match session {
-                                None => {
-                                    error!(message = "Invalid session");
-                                    (
-                                        StatusCode::UNAUTHORIZED,
-                                        Either::Right(ErrorMessage("Unable to get profile".into())),
-                                    )
-                                }
-                                Some(data) => (StatusCode::OK, Either::Left(Some(data))),
-                            }
-                        }
-                    }
-                } else {
-                    (
-                        StatusCode::UNAUTHORIZED,
-                        Either::Right(ErrorMessage("Unable to get profile".into())),
-                    )
-                }
-            }
-        }
-    };
-
-    convert_result_to_json_response(Ok(response))
+    AuthenticatedSession(session): AuthenticatedSession,
+) -> (StatusCode, Json<Value>) {
+    convert_result_to_json_response(api::logout(app.as_ref(), &session).await)
+}
+
+#[axum::debug_handler]
+pub async fn find_sessions_endpoint(
+    State(app): State<Arc<CustomersApp>>,
+    AuthenticatedSession(session): AuthenticatedSession,
+) -> (StatusCode, Json<Value>) {
+    convert_result_to_json_response(api::find_user_sessions_api(app.as_ref(), &session).await)
+}
+
+#[axum::debug_handler]
+pub async fn find_workspaces_endpoint(
+    State(app): State<Arc<CustomersApp>>,
+    AuthenticatedSession(session): AuthenticatedSession,
+) -> (StatusCode, Json<Value>) {
+    convert_result_to_json_response(api::find_workspaces(app.as_ref(), &session).await)
+}
+
+#[axum::debug_handler]
+pub async fn switch_workspace_endpoint(
+    State(app): State<Arc<CustomersApp>>,
+    AuthenticatedSession(session): AuthenticatedSession,
+    Path(workspace_code): Path<String>,
+) -> (StatusCode, Json<Value>) {
+    convert_result_to_json_response(
+        api::switch_workspace(app.as_ref(), &session, &workspace_code).await,
+    )
+}
+
+#[axum::debug_handler]
+pub async fn revoke_session_endpoint(
+    State(app): State<Arc<CustomersApp>>,
+    AuthenticatedSession(session): AuthenticatedSession,
+    Path(session_id): Path<String>,
+) -> (StatusCode, Json<Value>) {
+    convert_result_to_json_response(
+        api::revoke_user_session(app.as_ref(), &session, &session_id).await,
+    )
 }
 
 #[axum::debug_handler]
@@ -215,13 +309,84 @@ pub async fn get_staff_profile_endpoint(
     convert_result_to_json_response(api::get_staff_profile(app.as_ref(), &user, &staff_id).await)
 }
 
+#[axum::debug_handler]
+pub async fn enroll_totp_endpoint(
+    State(app): State<Arc<CustomersApp>>,
+    AuthenticatedSession(session): AuthenticatedSession,
+    Json(req): Json<TotpEnrollReq>,
+) -> (StatusCode, Json<Value>) {
+    convert_result_to_json_response(api::enroll_totp(app.as_ref(), &session, &req).await)
+}
+
+#[axum::debug_handler]
+pub async fn verify_totp_enrollment_endpoint(
+    State(app): State<Arc<CustomersApp>>,
+    AuthenticatedSession(session): AuthenticatedSession,
+    Json(req): Json<TotpCodeReq>,
+) -> (StatusCode, Json<Value>) {
+    convert_result_to_json_response(api::verify_totp_enrollment(app.as_ref(), &session, &req).await)
+}
+
+#[axum::debug_handler]
+pub async fn regenerate_totp_recovery_codes_endpoint(
+    State(app): State<Arc<CustomersApp>>,
+    AuthenticatedSession(session): AuthenticatedSession,
+    Json(req): Json<TotpCodeReq>,
+) -> (StatusCode, Json<Value>) {
+    convert_result_to_json_response(
+        api::regenerate_totp_recovery_codes(app.as_ref(), &session, &req).await,
+    )
+}
+
+#[axum::debug_handler]
+pub async fn disable_totp_endpoint(
+    State(app): State<Arc<CustomersApp>>,
+    AuthenticatedSession(session): AuthenticatedSession,
+    Json(req): Json<DisableTotpReq>,
+) -> (StatusCode, Json<Value>) {
+    convert_result_to_json_response(api::disable_totp(app.as_ref(), &session, &req).await)
+}
+
+#[axum::debug_handler]
+pub async fn find_devices_endpoint(
+    State(app): State<Arc<CustomersApp>>,
+    AuthenticatedSession(session): AuthenticatedSession,
+) -> (StatusCode, Json<Value>) {
+    convert_result_to_json_response(api::find_user_devices(app.as_ref(), &session).await)
+}
+
+#[axum::debug_handler]
+pub async fn rename_device_endpoint(
+    State(app): State<Arc<CustomersApp>>,
+    AuthenticatedSession(session): AuthenticatedSession,
+    Path(device_id): Path<String>,
+    Json(req): Json<RenameDeviceReq>,
+) -> (StatusCode, Json<Value>) {
+    convert_result_to_json_response(
+        api::rename_user_device(app.as_ref(), &session, &device_id, &req).await,
+    )
+}
+
+#[axum::debug_handler]
+pub async fn revoke_device_endpoint(
+    State(app): State<Arc<CustomersApp>>,
+    AuthenticatedSession(session): AuthenticatedSession,
+    Path(device_id): Path<String>,
+) -> (StatusCode, Json<Value>) {
+    convert_result_to_json_response(
+        api::revoke_user_device(app.as_ref(), &session, &device_id).await,
+    )
+}
+
 #[axum::debug_handler]
 pub async fn find_staffs_endpoint(
     State(app): State<Arc<CustomersApp>>,
     WorkspaceUser(user): WorkspaceUser,
     Query(req_query): Query<PaginatedQuery>,
 ) -> (StatusCode, Json<Value>) {
-    convert_result_to_json_response(api::find_staffs(app.as_ref(), &user, &req_query).await)
+    convert_paginated_result_to_json_response(
+        api::find_staffs(app.as_ref(), &user, &req_query).await,
+    )
 }
 
 #[axum::debug_handler]
@@ -230,7 +395,9 @@ pub async fn find_departments_endpoint(
     WorkspaceUser(user): WorkspaceUser,
     Query(req_query): Query<PaginatedQuery>,
 ) -> (StatusCode, Json<Value>) {
-    convert_result_to_json_response(api::find_departments(app.as_ref(), &user, &req_query).await)
+    convert_paginated_result_to_json_response(
+        api::find_departments(app.as_ref(), &user, &req_query).await,
+    )
 }
 
 #[axum::debug_handler]
@@ -247,7 +414,7 @@ pub async fn get_department_and_staff_endpoint(
 #[axum::debug_handler]
 pub async fn create_department(
     State(app): State<Arc<CustomersApp>>,
-    WorkspaceAdmin(user): WorkspaceAdmin,
+    Requires(user, _): Requires<DepartmentCreate>,
     Json(req): Json<CreateDepartment>,
 ) -> (StatusCode, Json<Value>) {
     convert_result_to_json_response(api::create_department(app.as_ref(), &user, req).await)
@@ -256,7 +423,7 @@ pub async fn create_department(
 #[axum::debug_handler]
 pub async fn edit_department(
     State(app): State<Arc<CustomersApp>>,
-    WorkspaceAdmin(user): WorkspaceAdmin,
+    Requires(user, _): Requires<DepartmentEdit>,
     Path(department_id): Path<String>,
     Json(req): Json<CreateDepartment>,
 ) -> (StatusCode, Json<Value>) {
@@ -268,7 +435,7 @@ pub async fn edit_department(
 #[axum::debug_handler]
 pub async fn delete_department(
     State(app): State<Arc<CustomersApp>>,
-    WorkspaceAdmin(user): WorkspaceAdmin,
+    Requires(user, _): Requires<DepartmentDelete>,
     Path(department_id): Path<String>,
 ) -> (StatusCode, Json<Value>) {
     convert_result_to_json_response(
@@ -276,19 +443,114 @@ pub async fn delete_department(
     )
 }
 
+#[axum::debug_handler]
+pub async fn add_department_member(
+    State(app): State<Arc<CustomersApp>>,
+    Requires(user, _): Requires<DepartmentEdit>,
+    Path(department_id): Path<String>,
+    Json(req): Json<DepartmentMember>,
+) -> (StatusCode, Json<Value>) {
+    convert_result_to_json_response(
+        api::add_department_member(app.as_ref(), &user, &department_id, req).await,
+    )
+}
+
+#[axum::debug_handler]
+pub async fn remove_department_member(
+    State(app): State<Arc<CustomersApp>>,
+    Requires(user, _): Requires<DepartmentEdit>,
+    Path((department_id, staff_id)): Path<(String, String)>,
+) -> (StatusCode, Json<Value>) {
+    convert_result_to_json_response(
+        api::remove_department_member(app.as_ref(), &user, &department_id, &staff_id).await,
+    )
+}
+
 #[axum::debug_handler]
 pub async fn add_staff(
     State(app): State<Arc<CustomersApp>>,
-    WorkspaceAdmin(user): WorkspaceAdmin,
+    Requires(user, _): Requires<PersonnelCreate>,
     Json(req): Json<NewStaff>,
 ) -> (StatusCode, Json<Value>) {
     convert_result_to_json_response(api::add_staff(app.as_ref(), &user, req).await)
 }
 
+#[axum::debug_handler]
+pub async fn dry_run_staff_import(
+    State(app): State<Arc<CustomersApp>>,
+    Requires(user, _): Requires<PersonnelCreate>,
+    Json(req): Json<StaffImportUpload>,
+) -> (StatusCode, Json<Value>) {
+    convert_result_to_json_response(api::dry_run_staff_import(app.as_ref(), &user, &req).await)
+}
+
+#[axum::debug_handler]
+pub async fn import_staffs(
+    State(app): State<Arc<CustomersApp>>,
+    Requires(user, _): Requires<PersonnelCreate>,
+    Json(req): Json<StaffImportUpload>,
+) -> (StatusCode, Json<Value>) {
+    convert_result_to_json_response(api::import_staffs(app.as_ref(), &user, &req).await)
+}
+
+#[axum::debug_handler]
+pub async fn find_invitations_endpoint(
+    State(app): State<Arc<CustomersApp>>,
+    Requires(user, _): Requires<PersonnelCreate>,
+) -> (StatusCode, Json<Value>) {
+    convert_result_to_json_response(api::find_invitations(app.as_ref(), &user).await)
+}
+
+#[axum::debug_handler]
+pub async fn resend_invitation_endpoint(
+    State(app): State<Arc<CustomersApp>>,
+    Requires(user, _): Requires<PersonnelCreate>,
+    Path(invitation_id): Path<String>,
+) -> (StatusCode, Json<Value>) {
+    convert_result_to_json_response(
+        api::resend_invitation(app.as_ref(), &user, &invitation_id).await,
+    )
+}
+
+#[axum::debug_handler]
+pub async fn revoke_invitation_endpoint(
+    State(app): State<Arc<CustomersApp>>,
+    Requires(user, _): Requires<PersonnelCreate>,
+    Path(invitation_id): Path<String>,
+) -> (StatusCode, Json<Value>) {
+    convert_result_to_json_response(
+        api::revoke_invitation(app.as_ref(), &user, &invitation_id).await,
+    )
+}
+
+#[axum::debug_handler]
+pub async fn get_invitation_endpoint(
+    State(app): State<Arc<CustomersApp>>,
+    Json(req): Json<InvitationTokenReq>,
+) -> (StatusCode, Json<Value>) {
+    convert_result_to_json_response(api::get_invitation(app.as_ref(), &req).await)
+}
+
+#[axum::debug_handler]
+pub async fn accept_invitation_endpoint(
+    State(app): State<Arc<CustomersApp>>,
+    Json(req): Json<AcceptInvitationReq>,
+) -> (StatusCode, Json<Value>) {
+    convert_result_to_json_response(api::accept_invitation(app.as_ref(), &req).await)
+}
+
+#[axum::debug_handler]
+pub async fn decline_invitation_endpoint(
+    State(app): State<Arc<CustomersApp>>,
+    Json(req): Json<InvitationTokenReq>,
+) -> (StatusCode, Json<Value>) {
+    convert_result_to_json_response(api::decline_invitation(app.as_ref(), &req).await)
+}
+
 #[axum::debug_handler]
 pub async fn edit_staff(
     State(app): State<Arc<CustomersApp>>,
-    WorkspaceAdmin(user): WorkspaceAdmin,
+    Requires(user, _): Requires<PersonnelEdit>,
     Path(staff_id): Path<String>,
     Json(req): Json<NewStaff>,
 ) -> (StatusCode, Json<Value>) {
@@ -298,8 +560,123 @@ pub async fn edit_staff(
 #[axum::debug_handler]
 pub async fn delete_staff(
     State(app): State<Arc<CustomersApp>>,
-    WorkspaceAdmin(user): WorkspaceAdmin,
+    Requires(user, _): Requires<PersonnelDelete>,
     Path(staff_id): Path<String>,
 ) -> (StatusCode, Json<Value>) {
     convert_result_to_json_response(api::delete_staff(app.as_ref(), &user, &staff_id).await)
 }
+
+#[axum::debug_handler]
+pub async fn unlock_staff(
+    State(app): State<Arc<CustomersApp>>,
+    Requires(user, _): Requires<PersonnelEdit>,
+    Path(staff_id): Path<String>,
+) -> (StatusCode, Json<Value>) {
+    convert_result_to_json_response(api::unlock_staff(app.as_ref(), &user, &staff_id).await)
+}
+
+#[axum::debug_handler]
+pub async fn find_roles(
+    State(app): State<Arc<CustomersApp>>,
+    WorkspaceAdmin(user): WorkspaceAdmin,
+) -> (StatusCode, Json<Value>) {
+    convert_result_to_json_response(api::find_roles(app.as_ref(), &user).await)
+}
+
+#[axum::debug_handler]
+pub async fn create_role(
+    State(app): State<Arc<CustomersApp>>,
+    WorkspaceAdmin(user): WorkspaceAdmin,
+    Json(req): Json<CreateRole>,
+) -> (StatusCode, Json<Value>) {
+    convert_result_to_json_response(api::create_role(app.as_ref(), &user, req).await)
+}
+
+#[axum::debug_handler]
+pub async fn edit_role(
+    State(app): State<Arc<CustomersApp>>,
+    WorkspaceAdmin(user): WorkspaceAdmin,
+    Path(role_id): Path<String>,
+    Json(req): Json<CreateRole>,
+) -> (StatusCode, Json<Value>) {
+    convert_result_to_json_response(api::edit_role(app.as_ref(), &user, &role_id, req).await)
+}
+
+#[axum::debug_handler]
+pub async fn delete_role(
+    State(app): State<Arc<CustomersApp>>,
+    WorkspaceAdmin(user): WorkspaceAdmin,
+    Path(role_id): Path<String>,
+) -> (StatusCode, Json<Value>) {
+    convert_result_to_json_response(api::delete_role(app.as_ref(), &user, &role_id).await)
+}
+
+#[axum::debug_handler]
+pub async fn get_institution_endpoint(
+    State(app): State<Arc<CustomersApp>>,
+    Requires(user, _): Requires<InstitutionSettingView>,
+) -> (StatusCode, Json<Value>) {
+    convert_result_to_json_response(api::get_institution_profile(app.as_ref(), &user).await)
+}
+
+#[axum::debug_handler]
+pub async fn edit_institution_endpoint(
+    State(app): State<Arc<CustomersApp>>,
+    WorkspaceAdmin(user): WorkspaceAdmin,
+    Json(req): Json<EditInstitution>,
+) -> (StatusCode, Json<Value>) {
+    convert_result_to_json_response(api::edit_institution(app.as_ref(), &user, req).await)
+}
+
+#[axum::debug_handler]
+pub async fn upload_institution_logo_endpoint(
+    State(app): State<Arc<CustomersApp>>,
+    WorkspaceAdmin(user): WorkspaceAdmin,
+    Json(req): Json<InstitutionLogoUpload>,
+) -> (StatusCode, Json<Value>) {
+    convert_result_to_json_response(api::upload_institution_logo(app.as_ref(), &user, &req).await)
+}
+
+#[axum::debug_handler]
+pub async fn find_institution_changes_endpoint(
+    State(app): State<Arc<CustomersApp>>,
+    Requires(user, _): Requires<InstitutionSettingView>,
+    Query(req_query): Query<PaginatedQuery>,
+) -> (StatusCode, Json<Value>) {
+    convert_paginated_result_to_json_response(
+        api::find_institution_changes(app.as_ref(), &user, &req_query).await,
+    )
+}
+
+#[axum::debug_handler]
+pub async fn deactivate_institution_endpoint(
+    State(app): State<Arc<CustomersApp>>,
+    WorkspaceAdmin(user): WorkspaceAdmin,
+    Json(req): Json<DeactivateInstitutionReq>,
+) -> (StatusCode, Json<Value>) {
+    convert_result_to_json_response(api::deactivate_institution(app.as_ref(), &user, &req).await)
+}
+
+#[axum::debug_handler]
+pub async fn find_institution_exports_endpoint(
+    State(app): State<Arc<CustomersApp>>,
+    WorkspaceAdmin(user): WorkspaceAdmin,
+) -> (StatusCode, Json<Value>) {
+    convert_result_to_json_response(api::find_institution_exports(app.as_ref(), &user).await)
+}
+
+#[axum::debug_handler]
+pub async fn request_institution_export_endpoint(
+    State(app): State<Arc<CustomersApp>>,
+    WorkspaceAdmin(user): WorkspaceAdmin,
+) -> (StatusCode, Json<Value>) {
+    convert_result_to_json_response(api::request_institution_export(app.as_ref(), &user).await)
+}
+
+#[axum::debug_handler]
+pub async fn get_export_download_endpoint(
+    State(app): State<Arc<CustomersApp>>,
+    Json(req): Json<ExportDownloadReq>,
+) -> (StatusCode, Json<Value>) {
+    convert_result_to_json_response(api::get_export_download(app.as_ref(), &req).await)
+}
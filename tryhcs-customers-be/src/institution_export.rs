@@ -0,0 +1,120 @@
+use std::io::{Cursor, Write};
+
+use chrono::{DateTime, Utc};
+use serde_json::{json, Value};
+use zip::{write::SimpleFileOptions, CompressionMethod, ZipWriter};
+
+/// Contents of an institution's export archive
+#[derive(Debug, Default)]
+pub(crate) struct InstitutionArchive {
+    /// (name, json array) written as `{name}.json`
+    pub records: Vec<(String, Value)>,
+    /// (bucket key, content) written under `documents/`
+    pub documents: Vec<(String, Vec<u8>)>,
+    /// bucket keys referenced by the records that could not be fetched
+    pub missing_documents: Vec<String>,
+}
+
+// Bucket keys are written as relative paths, empty and dot segments are
+// dropped so no entry lands outside `documents/`.
+fn document_path(key: &str) -> String {
+    let segments: Vec<&str> = key
+        .split(['/', '\\'])
+        .filter(|s| !s.is_empty() && *s != "." && *s != "..")
+        .collect();
+    format!("documents/{}", segments.join("/"))
+}
+
+/// Zips the archive with a `manifest.json` listing its files
+pub(crate) fn write_institution_archive(
+    institution_name: &str,
+    generated_at: DateTime<Utc>,
+    archive: &InstitutionArchive,
+) -> eyre::Result<Vec<u8>> {
+    let options = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+    let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+    let mut files = vec![];
+
+    for (name, records) in &archive.records {
+        let path = format!("{}.json", name);
+        writer.start_file(path.as_str(), options)?;
+        writer.write_all(&serde_json::to_vec_pretty(records)?)?;
+        files.push(path);
+    }
+
+    for (key, content) in &archive.documents {
+        let path = document_path(key);
+        writer.start_file(path.as_str(), options)?;
+        writer.write_all(content)?;
+        files.push(path);
+    }
+
+    let manifest = json!({
+        "institution": institution_name,
+        "generated_at": generated_at,
+        "files": files,
+        "missing_documents": archive.missing_documents,
+    });
+    writer.start_file("manifest.json", options)?;
+    writer.write_all(&serde_json::to_vec_pretty(&manifest)?)?;
+
+    Ok(writer.finish()?.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Read};
+
+    use chrono::{TimeZone, Utc};
+    use serde_json::{json, Value};
+    use zip::ZipArchive;
+
+    use super::{document_path, write_institution_archive, InstitutionArchive};
+
+    #[test]
+    fn keeps_document_paths_inside_the_documents_folder() {
+        assert_eq!(
+            document_path("compliance/I1S2-T3--cac.pdf"),
+            "documents/compliance/I1S2-T3--cac.pdf"
+        );
+        assert_eq!(document_path("../../etc/passwd"), "documents/etc/passwd");
+        assert_eq!(document_path("/logos//./a.png"), "documents/logos/a.png");
+        assert_eq!(document_path("..\\..\\a.png"), "documents/a.png");
+    }
+
+    #[test]
+    fn writes_records_documents_and_manifest() {
+        let archive = InstitutionArchive {
+            records: vec![("staffs".into(), json!([{ "first_name": "Ada" }]))],
+            documents: vec![("logos/I1S2-T3".into(), b"logo".to_vec())],
+            missing_documents: vec!["compliance/I1S2-T4--cac.pdf".into()],
+        };
+        let generated_at = Utc.with_ymd_and_hms(2026, 1, 2, 3, 4, 5).unwrap();
+        let content = write_institution_archive("St. Mary Clinic", generated_at, &archive).unwrap();
+
+        let mut zip = ZipArchive::new(Cursor::new(content)).unwrap();
+        let mut read = |name: &str| {
+            let mut content = String::new();
+            zip.by_name(name)
+                .unwrap()
+                .read_to_string(&mut content)
+                .unwrap();
+            content
+        };
+        let staffs: Value = serde_json::from_str(&read("staffs.json")).unwrap();
+        assert_eq!(staffs, json!([{ "first_name": "Ada" }]));
+        assert_eq!(read("documents/logos/I1S2-T3"), "logo");
+
+        let manifest: Value = serde_json::from_str(&read("manifest.json")).unwrap();
+        assert_eq!(manifest["institution"], "St. Mary Clinic");
+        assert_eq!(manifest["generated_at"], "2026-01-02T03:04:05Z");
+        assert_eq!(
+            manifest["files"],
+            json!(["staffs.json", "documents/logos/I1S2-T3"])
+        );
+        assert_eq!(
+            manifest["missing_documents"],
+            json!(["compliance/I1S2-T4--cac.pdf"])
+        );
+    }
+}
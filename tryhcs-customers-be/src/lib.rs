@@ -3,4 +3,15 @@ pub mod app;
 pub(crate) mod db_models;
 pub mod db_repo;
 pub mod endpoint;
+pub(crate) mod institution_export;
 pub(crate) mod params;
+pub(crate) mod staff_import;
+
+// Both crates' migrations are applied to the same database and share its
+// migration history, so their versions must not overlap: customers-be
+// migrations start from 1 and compliance-be migrations from 1001.
+pub fn migrator() -> sqlx::migrate::Migrator {
+    let mut migrator = sqlx::migrate!("./migrations");
+    migrator.set_ignore_missing(true);
+    migrator
+}
@@ -5,24 +5,41 @@ use eyre::{Context, Result};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use tryhcs_commons_be::{
-    auth::{InstitutionAdminUser, TypeAuthenticated},
+    api_response::convert_error_to_json_response,
+    auth::{is_workspace_deactivated, InstitutionAdminUser, RequiredAction, TypeAuthenticated},
+    session::{find_session, touch_session, UserSession},
     AUTH_ID_HEADER_FIELD, WORKSPACE_CODE_HEADER_FIELD,
 };
 
 use either::Either;
-use std::sync::Arc;
+use std::{marker::PhantomData, sync::Arc};
 
 use axum::{extract::FromRequestParts, http::request::Parts, Json};
 use reqwest::StatusCode;
-use serde_json::json;
 
-use tryhcs_shared::{
-    api_params::ErrorMessage,
-    institution_params::{AuthenticatedUser, AuthorizedInstitutionUser},
-};
+use tryhcs_shared::{api_params::ErrorCode, institution_params::AuthorizedInstitutionUser};
 
 use crate::app::CustomersApp;
 
+fn reject(code: ErrorCode) -> (StatusCode, Json<Value>) {
+    convert_error_to_json_response(code.into())
+}
+
+async fn check_workspace_active(
+    state: &CustomersApp,
+    workspace_code: &str,
+) -> Result<(), (StatusCode, Json<Value>)> {
+    let deactivated_in_db = state.db_pool.is_workspace_deactivated(workspace_code);
+    match is_workspace_deactivated(state.redis.as_ref(), workspace_code, deactivated_in_db).await {
+        Ok(false) => Ok(()),
+        Ok(true) => Err(reject(ErrorCode::InstitutionDeactivated)),
+        Err(err) => {
+            tracing::error!(message="Workspace status error", err=?err);
+            Err(reject(ErrorCode::InternalError))
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub(crate) struct WorkspaceAdmin(pub InstitutionAdminUser);
 
@@ -33,11 +50,7 @@ impl FromRequestParts<Arc<CustomersApp>> for WorkspaceAdmin {
         req: &mut Parts,
         state: &Arc<CustomersApp>,
     ) -> Result<Self, Self::Rejection> {
-        let status = StatusCode::FORBIDDEN;
-        let status_code: u16 = status.as_u16();
-
         // Manually extract session_id and workspace_code from headers
-        let error_message = "Invalid session";
         let session_id = {
             let header_value = req
                 .headers
@@ -47,22 +60,12 @@ impl FromRequestParts<Arc<CustomersApp>> for WorkspaceAdmin {
 
             match header_value {
                 None => {
-                    return Err((
-                        StatusCode::UNAUTHORIZED,
-                        Json(
-                            json!({"message": error_message, "status_code": StatusCode::UNAUTHORIZED.as_u16()}),
-                        ),
-                    ));
+                    return Err(reject(ErrorCode::AuthInvalidSession));
                 }
                 Some(header_value) => {
                     let split = header_value.split(" ").into_iter().collect::<Vec<_>>();
                     if split.len() != 2 {
-                        return Err((
-                            StatusCode::UNAUTHORIZED,
-                            Json(
-                                json!({"message": error_message, "status_code": StatusCode::UNAUTHORIZED.as_u16()}),
-                            ),
-                        ));
+                        return Err(reject(ErrorCode::AuthInvalidSession));
                     }
 
                     if split
@@ -70,12 +73,7 @@ impl FromRequestParts<Arc<CustomersApp>> for WorkspaceAdmin {
                         .map(|v| !v.eq_ignore_ascii_case("Bearer"))
                         .unwrap_or(true)
                     {
-                        return Err((
-                            StatusCode::UNAUTHORIZED,
-                            Json(
-                                json!({"message": error_message, "status_code": StatusCode::UNAUTHORIZED.as_u16()}),
-                            ),
-                        ));
+                        return Err(reject(ErrorCode::AuthInvalidSession));
                     }
 
                     split.get(1).unwrap_or(&"").to_owned()
@@ -83,75 +81,56 @@ impl FromRequestParts<Arc<CustomersApp>> for WorkspaceAdmin {
             }
         };
 
-        let workspace_code = {
-            let header_value = req
-                .headers
-                .get(WORKSPACE_CODE_HEADER_FIELD)
-                .map(|v| v.to_str().ok())
-                .flatten();
+        // falls back to the workspace the session was switched to
+        let workspace_code = req
+            .headers
+            .get(WORKSPACE_CODE_HEADER_FIELD)
+            .and_then(|v| v.to_str().ok())
+            .map(|v| v.to_owned());
 
-            match header_value {
-                None => {
-                    return Err((
-                        StatusCode::FORBIDDEN,
-                        Json(
-                            json!({"message": "Workpace code is required!", "status_code": StatusCode::FORBIDDEN.as_u16()}),
-                        ),
-                    ));
-                }
-                Some(header_value) => header_value.to_owned(),
-            }
-        };
-
-        let cached_session = state.redis.get_key(&session_id).await;
-        let cached_session = cached_session.map(|c| {
-            c.map(|v| serde_json::from_str::<AuthenticatedUser>(&v).ok())
-                .flatten()
-        });
+        let cached_session = find_session(state.redis.as_ref(), session_id).await;
 
         let user = match cached_session {
             Err(err) => {
                 tracing::error!(message="Get session error", err=?err);
-                return Err((
-                    StatusCode::UNAUTHORIZED,
-                    Json(
-                        json!({"message": error_message, "status_code": StatusCode::UNAUTHORIZED.as_u16()}),
-                    ),
-                ));
+                return Err(reject(ErrorCode::AuthInvalidSession));
             }
             Ok(cached_session) => match cached_session {
                 None => {
                     tracing::error!(message = "Cache session not found");
-                    return Err((
-                        StatusCode::UNAUTHORIZED,
-                        Json(
-                            json!({"message": error_message, "status_code": StatusCode::UNAUTHORIZED.as_u16()}),
-                        ),
-                    ));
+                    return Err(reject(ErrorCode::AuthInvalidSession));
                 }
-                Some(auth_user) => {
+                Some(session) => {
+                    let workspace_code =
+                        match workspace_code.or(session.info.workspace_code.clone()) {
+                            None => {
+                                return Err(reject(ErrorCode::AuthWorkspaceRequired));
+                            }
+                            Some(workspace_code) => workspace_code,
+                        };
+                    check_workspace_active(state, &workspace_code).await?;
                     match AuthorizedInstitutionUser::from_authorized_user(
-                        auth_user.principal,
+                        session.user.principal.clone(),
                         &workspace_code,
                     ) {
                         Err(err) => {
                             tracing::error!(message="Error serializing session", err=?err);
-                            return Err((
-                                StatusCode::UNAUTHORIZED,
-                                Json(
-                                    json!({"message": error_message, "status_code": StatusCode::UNAUTHORIZED.as_u16()}),
-                                ),
-                            ));
+                            return Err(reject(ErrorCode::AuthInvalidSession));
                         }
                         Ok(institution_user) => match institution_user {
-                            Either::Right((status, message)) => {
-                                let status_code = status.as_u16();
-                                return Err((
-                                    status,
-                                    Json(json!({"message": message, "status_code": status_code})),
-                                ));
+                            Either::Right(error) => {
+                                return Err(convert_error_to_json_response(error));
+                            }
+                            Either::Left(data) => {
+                                touch_session(
+                                    state.redis.as_ref(),
+                                    &session,
+                                    Some(&workspace_code),
+                                    &state.env,
+                                )
+                                .await;
+                                data
                             }
-                            Either::Left(data) => data,
                         },
                     }
                 }
@@ -159,12 +138,9 @@ impl FromRequestParts<Arc<CustomersApp>> for WorkspaceAdmin {
         };
 
         match InstitutionAdminUser::new(user).await {
-            Err(ErrorMessage(message)) => {
-                tracing::error!("Error extracting user into admin; {}", &message);
-                return Err((
-                    status,
-                    Json(json!({"message": message, "status_code": status_code})),
-                ));
+            Err(error) => {
+                tracing::error!("Error extracting user into admin; {}", &error.message);
+                return Err(convert_error_to_json_response(error));
             }
             Ok(user) => {
                 return Ok(WorkspaceAdmin(user));
@@ -174,7 +150,7 @@ impl FromRequestParts<Arc<CustomersApp>> for WorkspaceAdmin {
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
-pub(crate)  struct WorkspaceUser(pub AuthorizedInstitutionUser);
+pub(crate) struct WorkspaceUser(pub AuthorizedInstitutionUser);
 
 impl FromRequestParts<Arc<CustomersApp>> for WorkspaceUser {
     type Rejection = (StatusCode, Json<Value>);
@@ -183,16 +159,6 @@ impl FromRequestParts<Arc<CustomersApp>> for WorkspaceUser {
         req: &mut Parts,
         state: &Arc<CustomersApp>,
     ) -> Result<Self, Self::Rejection> {
-        let wrap_error = |status: StatusCode, message: &str| {
-            let status_code = status.as_u16();
-            (
-                status,
-                Json(json!({"message": message,
-                 "status_code": status_code})),
-            )
-        };
-
-        let error_message = "Invalid session";
         let session_id = {
             let header_value = req
                 .headers
@@ -202,12 +168,12 @@ impl FromRequestParts<Arc<CustomersApp>> for WorkspaceUser {
 
             match header_value {
                 None => {
-                    return Err(wrap_error(StatusCode::UNAUTHORIZED, error_message));
+                    return Err(reject(ErrorCode::AuthInvalidSession));
                 }
                 Some(header_value) => {
                     let split = header_value.split(" ").into_iter().collect::<Vec<_>>();
                     if split.len() != 2 {
-                        return Err(wrap_error(StatusCode::UNAUTHORIZED, error_message));
+                        return Err(reject(ErrorCode::AuthInvalidSession));
                     }
 
                     if split
@@ -215,7 +181,7 @@ impl FromRequestParts<Arc<CustomersApp>> for WorkspaceUser {
                         .map(|v| !v.eq_ignore_ascii_case("Bearer"))
                         .unwrap_or(true)
                     {
-                        return Err(wrap_error(StatusCode::UNAUTHORIZED, error_message));
+                        return Err(reject(ErrorCode::AuthInvalidSession));
                     }
 
                     split.get(1).unwrap_or(&"").to_owned()
@@ -223,65 +189,55 @@ impl FromRequestParts<Arc<CustomersApp>> for WorkspaceUser {
             }
         };
 
-        let workspace_code = {
-            let header_value = req
-                .headers
-                .get(WORKSPACE_CODE_HEADER_FIELD)
-                .map(|v| v.to_str().ok())
-                .flatten();
+        // falls back to the workspace the session was switched to
+        let workspace_code = req
+            .headers
+            .get(WORKSPACE_CODE_HEADER_FIELD)
+            .and_then(|v| v.to_str().ok())
+            .map(|v| v.to_owned());
 
-            match header_value {
-                None => {
-                    return Err(wrap_error(
-                        StatusCode::FORBIDDEN,
-                        "Workpace code is required!",
-                    ));
-                }
-                Some(header_value) => header_value.to_owned(),
-            }
-        };
-
-        let cached_session = state.redis.get_key(&session_id).await;
-        dbg!(session_id, &workspace_code, &cached_session);
-
-        let cached_session = cached_session.map(|c| {
-            c.map(|v| {
-                dbg!(&serde_json::from_str::<AuthenticatedUser>(&v));
-                serde_json::from_str::<AuthenticatedUser>(&v).ok()
-            })
-            .flatten()
-        });
+        let cached_session = find_session(state.redis.as_ref(), session_id).await;
 
         match cached_session {
             Err(err) => {
                 tracing::error!(message="Get session error", err=?err);
-                return Err(wrap_error(StatusCode::UNAUTHORIZED, error_message));
+                return Err(reject(ErrorCode::AuthInvalidSession));
             }
 
             Ok(cached_session) => match cached_session {
                 None => {
                     tracing::error!(message = "Cache session not found");
-                    return Err(wrap_error(StatusCode::UNAUTHORIZED, error_message));
+                    return Err(reject(ErrorCode::AuthInvalidSession));
                 }
-                Some(auth_user) => {
+                Some(session) => {
+                    let workspace_code =
+                        match workspace_code.or(session.info.workspace_code.clone()) {
+                            None => {
+                                return Err(reject(ErrorCode::AuthWorkspaceRequired));
+                            }
+                            Some(workspace_code) => workspace_code,
+                        };
+                    check_workspace_active(state, &workspace_code).await?;
                     match AuthorizedInstitutionUser::from_authorized_user(
-                        auth_user.principal,
+                        session.user.principal.clone(),
                         &workspace_code,
                     ) {
                         Err(err) => {
                             tracing::error!(message="Error serializing session", err=?err);
-                            return Err(wrap_error(StatusCode::UNAUTHORIZED, error_message));
+                            return Err(reject(ErrorCode::AuthInvalidSession));
                         }
                         Ok(institution_user) => match institution_user {
-                            Either::Right((status, message)) => {
-                                let status_code = status.as_u16();
-                                return Err((
-                                    status,
-                                    Json(json!({"message": message,
-                                             "status_code": status_code})),
-                                ));
+                            Either::Right(error) => {
+                                return Err(convert_error_to_json_response(error));
                             }
                             Either::Left(data) => {
+                                touch_session(
+                                    state.redis.as_ref(),
+                                    &session,
+                                    Some(&workspace_code),
+                                    &state.env,
+                                )
+                                .await;
                                 return Ok(WorkspaceUser(data));
                             }
                         },
@@ -291,3 +247,77 @@ impl FromRequestParts<Arc<CustomersApp>> for WorkspaceUser {
         }
     }
 }
+
+// Session only authentication, used by account level operations that
+// don't belong to a workspace e.g logout and session management
+#[derive(Debug, Clone)]
+pub(crate) struct AuthenticatedSession(pub UserSession);
+
+impl FromRequestParts<Arc<CustomersApp>> for AuthenticatedSession {
+    type Rejection = (StatusCode, Json<Value>);
+
+    async fn from_request_parts(
+        req: &mut Parts,
+        state: &Arc<CustomersApp>,
+    ) -> Result<Self, Self::Rejection> {
+        let session_id = {
+            let header_value = req
+                .headers
+                .get(AUTH_ID_HEADER_FIELD)
+                .and_then(|v| v.to_str().ok());
+
+            match header_value {
+                None => {
+                    return Err(reject(ErrorCode::AuthInvalidSession));
+                }
+                Some(header_value) => match header_value.split_once(" ") {
+                    Some((scheme, token)) if scheme.eq_ignore_ascii_case("Bearer") => {
+                        token.to_owned()
+                    }
+                    _ => {
+                        return Err(reject(ErrorCode::AuthInvalidSession));
+                    }
+                },
+            }
+        };
+
+        match find_session(state.redis.as_ref(), &session_id).await {
+            Err(err) => {
+                tracing::error!(message="Get session error", err=?err);
+                Err(reject(ErrorCode::AuthInvalidSession))
+            }
+            Ok(None) => {
+                tracing::error!(message = "Cache session not found");
+                Err(reject(ErrorCode::AuthInvalidSession))
+            }
+            Ok(Some(session)) => {
+                touch_session(state.redis.as_ref(), &session, None, &state.env).await;
+                Ok(AuthenticatedSession(session))
+            }
+        }
+    }
+}
+
+// Workspace user that has been granted the action of `A` by one of their
+// roles, rejects with a 403 otherwise.
+pub(crate) struct Requires<A: RequiredAction>(pub AuthorizedInstitutionUser, pub PhantomData<A>);
+
+impl<A: RequiredAction> FromRequestParts<Arc<CustomersApp>> for Requires<A> {
+    type Rejection = (StatusCode, Json<Value>);
+
+    async fn from_request_parts(
+        req: &mut Parts,
+        state: &Arc<CustomersApp>,
+    ) -> Result<Self, Self::Rejection> {
+        let WorkspaceUser(user) = WorkspaceUser::from_request_parts(req, state).await?;
+        if !user.is_permitted(A::ACTION) {
+            tracing::error!(
+                "Staff: {} isn't permitted to {:?}",
+                &user.staff_id,
+                A::ACTION
+            );
+            return Err(reject(ErrorCode::AuthForbidden));
+        }
+        Ok(Requires(user, PhantomData))
+    }
+}
@@ -0,0 +1,273 @@
+use std::io::Cursor;
+
+use calamine::{Reader, Xlsx};
+
+/// uploads above this are rejected, onboarding larger institutions is done in batches
+pub(crate) const MAX_STAFF_IMPORT_ROWS: usize = 2000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum StaffImportFormat {
+    Csv,
+    Xlsx,
+}
+
+impl StaffImportFormat {
+    pub(crate) fn detect(file_name: Option<&str>, content_type: Option<&str>) -> Option<Self> {
+        if let Some(content_type) = content_type {
+            match content_type {
+                "text/csv" | "application/csv" => return Some(Self::Csv),
+                "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet" => {
+                    return Some(Self::Xlsx)
+                }
+                _ => {}
+            }
+        }
+
+        let extension = file_name?.rsplit_once('.')?.1.to_lowercase();
+        match extension.as_str() {
+            "csv" => Some(Self::Csv),
+            "xlsx" => Some(Self::Xlsx),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub(crate) struct StaffSheetRow {
+    pub row_number: u32,
+    pub first_name: String,
+    pub last_name: String,
+    pub mobile: String,
+    pub title: String,
+    pub department: Option<String>,
+    pub role: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum StaffColumn {
+    FirstName,
+    LastName,
+    Mobile,
+    Title,
+    Department,
+    Role,
+}
+
+impl StaffColumn {
+    fn from_header(header: &str) -> Option<Self> {
+        let header = header.trim().to_lowercase().replace([' ', '-'], "_");
+        match header.as_str() {
+            "first_name" | "firstname" => Some(Self::FirstName),
+            "last_name" | "lastname" | "surname" => Some(Self::LastName),
+            "mobile" | "mobile_number" | "phone" | "phone_number" => Some(Self::Mobile),
+            "title" => Some(Self::Title),
+            "department" => Some(Self::Department),
+            "role" => Some(Self::Role),
+            _ => None,
+        }
+    }
+}
+
+const REQUIRED_COLUMNS: [(StaffColumn, &str); 4] = [
+    (StaffColumn::FirstName, "first_name"),
+    (StaffColumn::LastName, "last_name"),
+    (StaffColumn::Mobile, "mobile"),
+    (StaffColumn::Title, "title"),
+];
+
+/// Reads the staff rows of the sheet, the `Err` message is meant for the uploader.
+pub(crate) fn parse_staff_sheet(
+    format: StaffImportFormat,
+    content: &[u8],
+) -> Result<Vec<StaffSheetRow>, String> {
+    let table = match format {
+        StaffImportFormat::Csv => read_csv(content)?,
+        StaffImportFormat::Xlsx => read_xlsx(content)?,
+    };
+
+    let mut lines = table.into_iter();
+    let Some(headers) = lines.next() else {
+        return Err("The uploaded file is empty".into());
+    };
+    let columns: Vec<Option<StaffColumn>> = headers
+        .iter()
+        .map(|h| StaffColumn::from_header(h))
+        .collect();
+
+    let missing: Vec<&str> = REQUIRED_COLUMNS
+        .iter()
+        .filter(|(column, _)| !columns.contains(&Some(*column)))
+        .map(|(_, name)| *name)
+        .collect();
+    if !missing.is_empty() {
+        return Err(format!("Missing required columns: {}", missing.join(", ")));
+    }
+
+    let mut rows = vec![];
+    for (index, line) in lines.enumerate() {
+        if line.iter().all(|cell| cell.trim().is_empty()) {
+            continue;
+        }
+
+        let mut row = StaffSheetRow {
+            row_number: index as u32 + 2,
+            ..Default::default()
+        };
+        for (column, cell) in columns.iter().zip(line) {
+            let cell = cell.trim().to_owned();
+            match column {
+                Some(StaffColumn::FirstName) => row.first_name = cell,
+                Some(StaffColumn::LastName) => row.last_name = cell,
+                Some(StaffColumn::Mobile) => row.mobile = cell,
+                Some(StaffColumn::Title) => row.title = cell,
+                Some(StaffColumn::Department) => {
+                    row.department = Some(cell).filter(|c| !c.is_empty())
+                }
+                Some(StaffColumn::Role) => row.role = Some(cell).filter(|c| !c.is_empty()),
+                None => {}
+            }
+        }
+        rows.push(row);
+    }
+
+    if rows.is_empty() {
+        return Err("The uploaded file has no staff rows".into());
+    }
+    if rows.len() > MAX_STAFF_IMPORT_ROWS {
+        return Err(format!(
+            "The uploaded file has {} rows, at most {} staffs can be imported at once",
+            rows.len(),
+            MAX_STAFF_IMPORT_ROWS
+        ));
+    }
+
+    Ok(rows)
+}
+
+fn read_csv(content: &[u8]) -> Result<Vec<Vec<String>>, String> {
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .trim(csv::Trim::All)
+        .from_reader(content);
+
+    // the reader drops empty lines, they are kept as empty rows so row numbers
+    // still point at the lines of the file
+    let mut table = vec![];
+    for record in reader.records() {
+        let record = record.map_err(|err| format!("Invalid CSV file: {}", err))?;
+        let line = record.position().map(|p| p.line() as usize).unwrap_or(0);
+        while table.len() + 1 < line {
+            table.push(vec![]);
+        }
+        table.push(record.iter().map(|cell| cell.to_owned()).collect());
+    }
+    Ok(table)
+}
+
+fn read_xlsx(content: &[u8]) -> Result<Vec<Vec<String>>, String> {
+    let mut workbook =
+        Xlsx::new(Cursor::new(content)).map_err(|err| format!("Invalid XLSX file: {}", err))?;
+    let sheet = workbook
+        .worksheet_range_at(0)
+        .ok_or("The uploaded workbook has no sheets".to_string())?
+        .map_err(|err| format!("Invalid XLSX file: {}", err))?;
+
+    Ok(sheet
+        .rows()
+        .map(|row| row.iter().map(|cell| cell.to_string()).collect())
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_staff_sheet, StaffImportFormat, MAX_STAFF_IMPORT_ROWS};
+
+    #[test]
+    fn detects_format_from_content_type_then_extension() {
+        assert_eq!(
+            StaffImportFormat::detect(Some("staffs.xlsx"), Some("text/csv")),
+            Some(StaffImportFormat::Csv)
+        );
+        assert_eq!(
+            StaffImportFormat::detect(
+                None,
+                Some("application/vnd.openxmlformats-officedocument.spreadsheetml.sheet")
+            ),
+            Some(StaffImportFormat::Xlsx)
+        );
+        assert_eq!(
+            StaffImportFormat::detect(Some("Staffs.CSV"), Some("application/octet-stream")),
+            Some(StaffImportFormat::Csv)
+        );
+        assert_eq!(
+            StaffImportFormat::detect(Some("staffs.xlsx"), None),
+            Some(StaffImportFormat::Xlsx)
+        );
+        assert_eq!(StaffImportFormat::detect(Some("staffs.xls"), None), None);
+        assert_eq!(StaffImportFormat::detect(Some("staffs"), None), None);
+        assert_eq!(StaffImportFormat::detect(None, None), None);
+    }
+
+    #[test]
+    fn reads_csv_rows_with_header_aliases() {
+        let sheet = "First Name,Surname,Phone Number,Title,Department,Role,Notes\n\
+                     Adaeze,Obi,08031234567,Dr,Surgery,Surgeon,on call\n\
+                     \n\
+                     ,,,,,,\n\
+                     Tunde,Bello,08037654321,Nurse,,,\n";
+        let rows = parse_staff_sheet(StaffImportFormat::Csv, sheet.as_bytes()).unwrap();
+
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0].row_number, 2);
+        assert_eq!(rows[0].first_name, "Adaeze");
+        assert_eq!(rows[0].last_name, "Obi");
+        assert_eq!(rows[0].mobile, "08031234567");
+        assert_eq!(rows[0].title, "Dr");
+        assert_eq!(rows[0].department.as_deref(), Some("Surgery"));
+        assert_eq!(rows[0].role.as_deref(), Some("Surgeon"));
+        // the blank lines are skipped but still counted
+        assert_eq!(rows[1].row_number, 5);
+        assert_eq!(rows[1].first_name, "Tunde");
+        assert_eq!(rows[1].department, None);
+        assert_eq!(rows[1].role, None);
+    }
+
+    #[test]
+    fn rejects_sheets_missing_required_columns() {
+        let sheet = "first_name,mobile\nAdaeze,08031234567\n";
+        let err = parse_staff_sheet(StaffImportFormat::Csv, sheet.as_bytes()).unwrap_err();
+
+        assert_eq!(err, "Missing required columns: last_name, title");
+    }
+
+    #[test]
+    fn rejects_sheets_without_staff_rows() {
+        let err = parse_staff_sheet(StaffImportFormat::Csv, b"").unwrap_err();
+        assert_eq!(err, "The uploaded file is empty");
+
+        let sheet = "first_name,last_name,mobile,title\n,,,\n";
+        let err = parse_staff_sheet(StaffImportFormat::Csv, sheet.as_bytes()).unwrap_err();
+        assert_eq!(err, "The uploaded file has no staff rows");
+    }
+
+    #[test]
+    fn caps_the_rows_of_a_sheet() {
+        let mut sheet = String::from("first_name,last_name,mobile,title\n");
+        for i in 0..=MAX_STAFF_IMPORT_ROWS {
+            sheet.push_str(&format!("Staff,{},080{:08},Dr\n", i, i));
+        }
+        let err = parse_staff_sheet(StaffImportFormat::Csv, sheet.as_bytes()).unwrap_err();
+
+        assert!(err.starts_with(&format!(
+            "The uploaded file has {} rows",
+            MAX_STAFF_IMPORT_ROWS + 1
+        )));
+    }
+
+    #[test]
+    fn rejects_invalid_xlsx() {
+        let err = parse_staff_sheet(StaffImportFormat::Xlsx, b"first_name,last_name").unwrap_err();
+        assert!(err.starts_with("Invalid XLSX file"));
+    }
+}
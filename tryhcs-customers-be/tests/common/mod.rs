@@ -0,0 +1,200 @@
+#![allow(dead_code)]
+
+use std::sync::Arc;
+
+use either::Either;
+use scrypt::{
+    password_hash::{rand_core::OsRng, PasswordHasher, SaltString},
+    Params, Scrypt,
+};
+use sqlx::PgPool;
+use tryhcs_commons_be::{
+    api_response::ApiResponse,
+    auth::TypeAuthenticated,
+    client_context::ClientContext,
+    env::EnvConfig,
+    redis::MemoryCache,
+    session::{find_session, UserSession},
+    signed_token::sign_token,
+};
+use tryhcs_customers_be::{api::login_init, app::CustomersApp, db_repo::CustomerDB};
+use tryhcs_shared::{
+    api_params::ErrorCode,
+    institution_params::{AuthorizedInstitutionUser, CreateInstitution, LoginReq, NewStaff},
+};
+
+pub const PASSWORD: &str = "Tryhcs-Passw0rd";
+pub const ADMIN: &str = "+2348149464289";
+pub const STAFF: &str = "+2348031234567";
+
+pub struct Workspace {
+    pub id: i64,
+    pub code: String,
+}
+
+pub fn app(pool: PgPool) -> CustomersApp {
+    let s3_config = aws_sdk_s3::Config::builder()
+        .behavior_version(aws_sdk_s3::config::BehaviorVersion::latest())
+        .region(aws_sdk_s3::config::Region::new("auto"))
+        .build();
+    CustomersApp {
+        db_pool: Arc::new(CustomerDB { customer_db: pool }),
+        s3_client: aws_sdk_s3::Client::from_conf(s3_config),
+        env: EnvConfig {
+            session_expires_in_min: 30,
+            refresh_token_expires_in_days: 30,
+            default_phone_region: "NG".into(),
+            max_failed_attempts: 5,
+            invitation_expires_in_hr: 72,
+            ..Default::default()
+        },
+        redis: Arc::new(MemoryCache::default()),
+        encryptor: None,
+        institution_data: vec![],
+        platform_staff: vec![],
+    }
+}
+
+// the lowest scrypt cost so the tests don't wait on the production one, the
+// cost is read back from the hash when the password is verified
+fn hash_password(password: &str) -> String {
+    let salt = SaltString::generate(&mut OsRng);
+    let params = Params::new(4, 8, 1, Params::RECOMMENDED_LEN).unwrap();
+    Scrypt
+        .hash_password_customized(password.as_bytes(), None, None, params, &salt)
+        .unwrap()
+        .to_string()
+}
+
+/// Creates an institution with the mobile as its admin
+pub async fn create_institution(app: &CustomersApp, name: &str, admin_mobile: &str) -> Workspace {
+    let (institution, _) = app
+        .db_pool
+        .create_institution(CreateInstitution {
+            institution_name: name.into(),
+            email: format!("{}@example.com", name.to_lowercase().replace(' ', "")),
+            classification: "Hospital".into(),
+            setting: "Urban".into(),
+            address: None,
+            town: None,
+            state: None,
+            first_name: "Adaeze".into(),
+            last_name: "Obi".into(),
+            mobile: admin_mobile.into(),
+            title: "Dr".into(),
+            password: hash_password(PASSWORD),
+            logo: None,
+        })
+        .await
+        .unwrap();
+    Workspace {
+        id: institution.id,
+        code: institution.workspace_code,
+    }
+}
+
+/// Adds a staff who can log in and returns their staff id
+pub async fn add_staff(app: &CustomersApp, workspace: &Workspace, mobile: &str) -> String {
+    let staff = app
+        .db_pool
+        .create_staff(
+            workspace.id,
+            &NewStaff {
+                first_name: "Musa".into(),
+                last_name: "Ibrahim".into(),
+                mobile: mobile.into(),
+                title: "Nurse".into(),
+                profile_image: None,
+            },
+        )
+        .await
+        .unwrap();
+    create_user(app, mobile).await;
+    staff.shadow_id
+}
+
+/// Creates the user account the mobile logs in with
+pub async fn create_user(app: &CustomersApp, mobile: &str) {
+    app.db_pool
+        .create_user(mobile, &hash_password(PASSWORD))
+        .await
+        .unwrap();
+}
+
+/// Logs in on a device the user has already trusted, so no OTP is sent
+pub async fn login(app: &CustomersApp, mobile: &str, device_id: &str) -> UserSession {
+    let user = app.db_pool.get_user(mobile).await.unwrap().unwrap();
+    app.db_pool
+        .trust_user_device(user.id, device_id, &ClientContext::default())
+        .await
+        .unwrap();
+
+    let login_req = LoginReq {
+        phone_number: mobile.into(),
+        password: PASSWORD.into(),
+        device_id: device_id.into(),
+    };
+    let response = ok(login_init(app, &login_req, &ClientContext::default())
+        .await
+        .unwrap());
+    let token = response.auth.unwrap().token.unwrap();
+    session(app, &token).await.unwrap()
+}
+
+/// The link token of the staff's latest invitation, signed again since only its hash is saved
+pub async fn invitation_token(app: &CustomersApp, mobile: &str) -> String {
+    let staff = app
+        .db_pool
+        .find_staffs_by_mobiles(&[mobile.into()])
+        .await
+        .unwrap()
+        .remove(0);
+    let invitation = app
+        .db_pool
+        .find_latest_staff_invitation(staff.id)
+        .await
+        .unwrap()
+        .unwrap();
+    sign_token(
+        app.env.invitation_signing_key.as_bytes(),
+        &invitation.shadow_id,
+        invitation.expires_at.timestamp(),
+    )
+}
+
+pub async fn session(app: &CustomersApp, token: &str) -> Option<UserSession> {
+    find_session(app.redis.as_ref(), token).await.unwrap()
+}
+
+/// The session's account in the workspace
+pub fn account(session: &UserSession, workspace_code: &str) -> AuthorizedInstitutionUser {
+    match AuthorizedInstitutionUser::from_authorized_user(
+        session.user.principal.clone(),
+        workspace_code,
+    )
+    .unwrap()
+    {
+        Either::Left(account) => account,
+        Either::Right(error) => panic!("no account in the workspace: {:?}", error.code),
+    }
+}
+
+pub fn ok<T>((_, response): ApiResponse<T>) -> T {
+    match response {
+        Either::Left(data) => data.expect("the response has no data"),
+        Either::Right(error) => panic!("request failed: {:?} {}", error.code, error.message),
+    }
+}
+
+pub fn succeeded<T>((_, response): ApiResponse<T>) {
+    if let Either::Right(error) = response {
+        panic!("request failed: {:?} {}", error.code, error.message);
+    }
+}
+
+pub fn error_code<T>((_, response): ApiResponse<T>) -> ErrorCode {
+    match response {
+        Either::Left(_) => panic!("request succeeded"),
+        Either::Right(error) => error.code,
+    }
+}
@@ -0,0 +1,46 @@
+mod common;
+
+use common::{
+    account, app, create_institution, invitation_token, login, ok, succeeded, ADMIN, PASSWORD,
+    STAFF,
+};
+use sqlx::PgPool;
+use tryhcs_customers_be::api::{accept_invitation, add_staff, decline_invitation};
+use tryhcs_shared::institution_params::{AcceptInvitationReq, InvitationTokenReq, NewStaff};
+
+fn new_staff() -> NewStaff {
+    NewStaff {
+        first_name: "Musa".into(),
+        last_name: "Ibrahim".into(),
+        mobile: STAFF.into(),
+        title: "Nurse".into(),
+        profile_image: None,
+    }
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn invites_a_declined_staff_again_when_re_added(pool: PgPool) {
+    let app = app(pool);
+    let workspace = create_institution(&app, "St Mary Clinic", ADMIN).await;
+    let admin = account(&login(&app, ADMIN, "laptop").await, &workspace.code);
+
+    ok(add_staff(&app, &admin, new_staff()).await.unwrap());
+    let declined = invitation_token(&app, STAFF).await;
+    succeeded(
+        decline_invitation(&app, &InvitationTokenReq { token: declined })
+            .await
+            .unwrap(),
+    );
+
+    ok(add_staff(&app, &admin, new_staff()).await.unwrap());
+    // the staff has no account until the new invitation is accepted
+    assert!(app.db_pool.get_user(STAFF).await.unwrap().is_none());
+    let req = AcceptInvitationReq {
+        token: invitation_token(&app, STAFF).await,
+        password: Some(PASSWORD.into()),
+    };
+    succeeded(accept_invitation(&app, &req).await.unwrap());
+
+    let staff = login(&app, STAFF, "staff-phone").await;
+    account(&staff, &workspace.code);
+}
@@ -0,0 +1,229 @@
+mod common;
+
+use common::{
+    account, add_staff, app, create_institution, error_code, login, ok, session, succeeded,
+    Workspace, ADMIN, STAFF,
+};
+use sqlx::PgPool;
+use tryhcs_commons_be::{auth::InstitutionAdminUser, ADMIN_ROLE};
+use tryhcs_customers_be::{
+    api::{
+        add_department_member, create_role, delete_role, delete_staff, edit_role, find_workspaces,
+        remove_department_member, switch_workspace,
+    },
+    app::CustomersApp,
+};
+use tryhcs_shared::{
+    api_params::ErrorCode,
+    institution_params::{BasePermission, CreateRole, DepartmentMember, PermittedAction},
+};
+
+fn nurse_role() -> CreateRole {
+    CreateRole {
+        name: "Nurse".into(),
+        permissions: vec![PermittedAction::MedicalHistory(BasePermission::View)],
+    }
+}
+
+// a department without members, returns its id
+async fn create_ward(app: &CustomersApp, workspace: &Workspace) -> String {
+    app.db_pool
+        .create_department(workspace.id, "Ward".into(), None, None, &[], "Ward")
+        .await
+        .unwrap()
+        .shadow_id
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn role_permissions_reach_the_member_sessions(pool: PgPool) {
+    let app = app(pool);
+    let workspace = create_institution(&app, "St Mary Clinic", ADMIN).await;
+    let staff_id = add_staff(&app, &workspace, STAFF).await;
+    let ward = create_ward(&app, &workspace).await;
+    let admin = account(&login(&app, ADMIN, "laptop").await, &workspace.code);
+    let staff_session = login(&app, STAFF, "staff-phone").await;
+    assert!(account(&staff_session, &workspace.code)
+        .permissions
+        .is_empty());
+
+    ok(
+        create_role(&app, &InstitutionAdminUser(admin.clone()), nurse_role())
+            .await
+            .unwrap(),
+    );
+    let member = DepartmentMember {
+        staff_id: staff_id.clone(),
+        role: "Nurse".into(),
+    };
+    ok(add_department_member(&app, &admin, &ward, member)
+        .await
+        .unwrap());
+
+    let staff_session = session(&app, &staff_session.info.token).await.unwrap();
+    assert_eq!(
+        account(&staff_session, &workspace.code).permissions,
+        vec![PermittedAction::MedicalHistory(BasePermission::View)]
+    );
+
+    succeeded(
+        remove_department_member(&app, &admin, &ward, &staff_id)
+            .await
+            .unwrap(),
+    );
+    let staff_session = session(&app, &staff_session.info.token).await.unwrap();
+    assert!(account(&staff_session, &workspace.code)
+        .permissions
+        .is_empty());
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn keeps_assigned_roles(pool: PgPool) {
+    let app = app(pool);
+    let workspace = create_institution(&app, "St Mary Clinic", ADMIN).await;
+    let staff_id = add_staff(&app, &workspace, STAFF).await;
+    let ward = create_ward(&app, &workspace).await;
+    let admin = account(&login(&app, ADMIN, "laptop").await, &workspace.code);
+    let institution_admin = InstitutionAdminUser(admin.clone());
+
+    let superuser = CreateRole {
+        name: ADMIN_ROLE.into(),
+        ..nurse_role()
+    };
+    let response = create_role(&app, &institution_admin, superuser)
+        .await
+        .unwrap();
+    assert_eq!(error_code(response), ErrorCode::RoleInvalidName);
+
+    let role = ok(create_role(&app, &institution_admin, nurse_role())
+        .await
+        .unwrap());
+    let response = create_role(&app, &institution_admin, nurse_role())
+        .await
+        .unwrap();
+    assert_eq!(error_code(response), ErrorCode::RoleAlreadyExists);
+
+    let member = DepartmentMember {
+        staff_id,
+        role: "Nurse".into(),
+    };
+    ok(add_department_member(&app, &admin, &ward, member)
+        .await
+        .unwrap());
+
+    let renamed = CreateRole {
+        name: "Ward Nurse".into(),
+        ..nurse_role()
+    };
+    let response = edit_role(&app, &institution_admin, &role.id, renamed)
+        .await
+        .unwrap();
+    assert_eq!(error_code(response), ErrorCode::RoleInUse);
+    let response = delete_role(&app, &institution_admin, &role.id)
+        .await
+        .unwrap();
+    assert_eq!(error_code(response), ErrorCode::RoleInUse);
+
+    // the permissions of an assigned role can still change
+    let edited = CreateRole {
+        name: "Nurse".into(),
+        permissions: vec![PermittedAction::Labouratory(BasePermission::View)],
+    };
+    let role = ok(edit_role(&app, &institution_admin, &role.id, edited)
+        .await
+        .unwrap());
+    assert_eq!(
+        role.permissions,
+        vec![PermittedAction::Labouratory(BasePermission::View)]
+    );
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn only_a_superuser_hands_out_the_superuser_role(pool: PgPool) {
+    let app = app(pool);
+    let workspace = create_institution(&app, "St Mary Clinic", ADMIN).await;
+    let staff_id = add_staff(&app, &workspace, STAFF).await;
+    let ward = create_ward(&app, &workspace).await;
+    let admin = account(&login(&app, ADMIN, "laptop").await, &workspace.code);
+    let staff = account(&login(&app, STAFF, "staff-phone").await, &workspace.code);
+
+    let member = DepartmentMember {
+        staff_id: staff_id.clone(),
+        role: ADMIN_ROLE.into(),
+    };
+    let response = add_department_member(&app, &staff, &ward, member.clone())
+        .await
+        .unwrap();
+    assert_eq!(error_code(response), ErrorCode::AuthForbidden);
+
+    // the admin is the only Superuser of the institution
+    let admin_department = admin.departments[0].id.clone();
+    let response = remove_department_member(&app, &admin, &admin_department, &admin.staff_id)
+        .await
+        .unwrap();
+    assert_eq!(error_code(response), ErrorCode::RoleLastSuperuser);
+
+    ok(add_department_member(&app, &admin, &ward, member)
+        .await
+        .unwrap());
+    succeeded(
+        remove_department_member(&app, &admin, &admin_department, &admin.staff_id)
+            .await
+            .unwrap(),
+    );
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn deleting_a_staff_ends_their_sessions(pool: PgPool) {
+    let app = app(pool);
+    let workspace = create_institution(&app, "St Mary Clinic", ADMIN).await;
+    let staff_id = add_staff(&app, &workspace, STAFF).await;
+    let admin = account(&login(&app, ADMIN, "laptop").await, &workspace.code);
+    let phone = login(&app, STAFF, "staff-phone").await;
+    let laptop = login(&app, STAFF, "staff-laptop").await;
+
+    succeeded(delete_staff(&app, &admin, &staff_id).await.unwrap());
+    assert!(session(&app, &phone.info.token).await.is_none());
+    assert!(session(&app, &laptop.info.token).await.is_none());
+
+    // the last Superuser can't be deleted
+    let response = delete_staff(&app, &admin, &admin.staff_id).await.unwrap();
+    assert_eq!(error_code(response), ErrorCode::RoleLastSuperuser);
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn scopes_the_session_to_a_workspace(pool: PgPool) {
+    let app = app(pool);
+    let workspace = create_institution(&app, "St Mary Clinic", ADMIN).await;
+    let other_workspace = create_institution(&app, "Lagoon Hospital", STAFF).await;
+
+    let user_session = login(&app, ADMIN, "laptop").await;
+    let workspaces = ok(find_workspaces(&app, &user_session).await.unwrap());
+    assert_eq!(workspaces.len(), 1);
+    assert!(!workspaces[0].current);
+
+    let switched = ok(switch_workspace(&app, &user_session, &workspace.code)
+        .await
+        .unwrap());
+    assert_eq!(
+        switched.staff_id,
+        account(&user_session, &workspace.code).staff_id
+    );
+    let user_session = session(&app, &user_session.info.token).await.unwrap();
+    assert_eq!(
+        user_session.info.workspace_code.as_deref(),
+        Some(workspace.code.as_str())
+    );
+    let workspaces = ok(find_workspaces(&app, &user_session).await.unwrap());
+    assert!(workspaces[0].current);
+
+    // a workspace the user doesn't belong to isn't switched to
+    let response = switch_workspace(&app, &user_session, &other_workspace.code)
+        .await
+        .unwrap();
+    assert!(response.1.is_right());
+    let user_session = session(&app, &user_session.info.token).await.unwrap();
+    assert_eq!(
+        user_session.info.workspace_code.as_deref(),
+        Some(workspace.code.as_str())
+    );
+}
@@ -0,0 +1,108 @@
+mod common;
+
+use common::{
+    account, add_staff, app, create_institution, error_code, login, ok, session, ADMIN, PASSWORD,
+    STAFF,
+};
+use sqlx::PgPool;
+use tryhcs_commons_be::auth::{is_workspace_deactivated, InstitutionAdminUser};
+use tryhcs_customers_be::api::{
+    deactivate_institution, process_institution_exports, purge_deactivated_institutions,
+};
+use tryhcs_shared::{
+    api_params::ErrorCode,
+    institution_params::{DeactivateInstitutionReq, InstitutionExportStatus},
+};
+
+#[sqlx::test(migrations = "./migrations")]
+async fn deactivates_and_purges_the_institution(pool: PgPool) {
+    let mut app = app(pool);
+    // purged as soon as the job runs
+    app.env.institution_purge_grace_in_days = 0;
+    let workspace = create_institution(&app, "St Mary Clinic", ADMIN).await;
+    add_staff(&app, &workspace, STAFF).await;
+    let admin = InstitutionAdminUser(account(
+        &login(&app, ADMIN, "laptop").await,
+        &workspace.code,
+    ));
+    let staff = login(&app, STAFF, "staff-phone").await;
+
+    let wrong_password = DeactivateInstitutionReq {
+        password: "not-the-password".into(),
+    };
+    let response = deactivate_institution(&app, &admin, &wrong_password)
+        .await
+        .unwrap();
+    assert_eq!(error_code(response), ErrorCode::AuthInvalidCurrentPassword);
+    assert_eq!(purge_deactivated_institutions(&app).await.unwrap(), 0);
+
+    let req = DeactivateInstitutionReq {
+        password: PASSWORD.into(),
+    };
+    let deactivation = ok(deactivate_institution(&app, &admin, &req).await.unwrap());
+    assert!(deactivation.purge_at <= chrono::Utc::now());
+    // the workspace is flagged without asking the database
+    let deactivated =
+        is_workspace_deactivated(app.redis.as_ref(), &workspace.code, async { Ok(false) });
+    assert!(deactivated.await.unwrap());
+
+    assert_eq!(purge_deactivated_institutions(&app).await.unwrap(), 1);
+    assert!(session(&app, &staff.info.token).await.is_none());
+    assert!(app
+        .db_pool
+        .get_institution(workspace.id)
+        .await
+        .unwrap()
+        .is_none());
+    assert!(app.db_pool.get_user(STAFF).await.unwrap().is_none());
+    assert!(app
+        .db_pool
+        .is_workspace_deactivated(&workspace.code)
+        .await
+        .unwrap());
+    // the flag is cleared, the purged workspace is deactivated by the database from here on
+    let deactivated =
+        is_workspace_deactivated(app.redis.as_ref(), &workspace.code, async { Ok(false) });
+    assert!(!deactivated.await.unwrap());
+
+    assert_eq!(purge_deactivated_institutions(&app).await.unwrap(), 0);
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn emails_the_export_links_not_yet_sent(pool: PgPool) {
+    let app = app(pool);
+    let workspace = create_institution(&app, "St Mary Clinic", ADMIN).await;
+    let export = app
+        .db_pool
+        .create_institution_export(workspace.id, None)
+        .await
+        .unwrap();
+    // the archive was uploaded but the email failed on an earlier run
+    app.db_pool
+        .complete_institution_export(
+            export.id,
+            InstitutionExportStatus::Ready.as_str(),
+            Some("exports/archive.zip".into()),
+        )
+        .await
+        .unwrap();
+    assert_eq!(
+        app.db_pool.find_unsent_export_links().await.unwrap().len(),
+        1
+    );
+
+    assert_eq!(process_institution_exports(&app).await.unwrap(), 0);
+    assert!(app
+        .db_pool
+        .find_unsent_export_links()
+        .await
+        .unwrap()
+        .is_empty());
+    let export = app
+        .db_pool
+        .get_institution_export(&export.shadow_id)
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(export.link_sent_at.is_some());
+}
@@ -0,0 +1,97 @@
+mod common;
+
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use common::{
+    add_staff, app, create_institution, create_user, error_code, login, ok, session, succeeded,
+    ADMIN, PASSWORD, STAFF,
+};
+use sqlx::PgPool;
+use tryhcs_commons_be::{client_context::ClientContext, platform_staff::PlatformStaffSource};
+use tryhcs_customers_be::api::{find_user_sessions_api, login_init, logout, revoke_user_session};
+use tryhcs_shared::{api_params::ErrorCode, institution_params::LoginReq};
+
+const REVIEWER: &str = "+2348091234567";
+
+struct PlatformStaff(&'static str);
+
+#[async_trait]
+impl PlatformStaffSource for PlatformStaff {
+    async fn is_platform_staff(&self, mobile: &str) -> eyre::Result<bool> {
+        Ok(mobile == self.0)
+    }
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn lists_the_sessions_of_the_user(pool: PgPool) {
+    let app = app(pool);
+    let workspace = create_institution(&app, "St Mary Clinic", ADMIN).await;
+    add_staff(&app, &workspace, STAFF).await;
+
+    let laptop = login(&app, ADMIN, "laptop").await;
+    let phone = login(&app, ADMIN, "phone").await;
+    login(&app, STAFF, "staff-phone").await;
+
+    let sessions = ok(find_user_sessions_api(&app, &phone).await.unwrap());
+    assert_eq!(sessions.len(), 2);
+    let current: Vec<_> = sessions.iter().filter(|s| s.current).collect();
+    assert_eq!(current.len(), 1);
+    assert_eq!(current[0].id, phone.info.id);
+    assert!(sessions
+        .iter()
+        .any(|s| s.id == laptop.info.id && s.device_id == "laptop"));
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn revokes_only_the_user_sessions(pool: PgPool) {
+    let app = app(pool);
+    let workspace = create_institution(&app, "St Mary Clinic", ADMIN).await;
+    add_staff(&app, &workspace, STAFF).await;
+
+    let laptop = login(&app, ADMIN, "laptop").await;
+    let phone = login(&app, ADMIN, "phone").await;
+    let staff = login(&app, STAFF, "staff-phone").await;
+
+    // another user's session isn't found
+    let response = revoke_user_session(&app, &phone, &staff.info.id)
+        .await
+        .unwrap();
+    assert_eq!(error_code(response), ErrorCode::SessionNotFound);
+    assert!(session(&app, &staff.info.token).await.is_some());
+
+    succeeded(
+        revoke_user_session(&app, &phone, &laptop.info.id)
+            .await
+            .unwrap(),
+    );
+    assert!(session(&app, &laptop.info.token).await.is_none());
+    assert!(session(&app, &phone.info.token).await.is_some());
+    let sessions = ok(find_user_sessions_api(&app, &phone).await.unwrap());
+    assert_eq!(sessions.len(), 1);
+
+    succeeded(logout(&app, &phone).await.unwrap());
+    assert!(session(&app, &phone.info.token).await.is_none());
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn logs_in_platform_staff_without_a_workspace(pool: PgPool) {
+    let mut app = app(pool);
+    create_user(&app, REVIEWER).await;
+
+    // a user account alone doesn't log in
+    let login_req = LoginReq {
+        phone_number: REVIEWER.into(),
+        password: PASSWORD.into(),
+        device_id: "laptop".into(),
+    };
+    let response = login_init(&app, &login_req, &ClientContext::default())
+        .await
+        .unwrap();
+    assert_eq!(error_code(response), ErrorCode::AuthInvalidCredentials);
+
+    app.platform_staff = vec![Arc::new(PlatformStaff(REVIEWER))];
+    let reviewer = login(&app, REVIEWER, "laptop").await;
+    assert_eq!(reviewer.info.mobile, REVIEWER);
+    assert!(reviewer.user.principal.accounts.is_empty());
+}
@@ -14,12 +14,7 @@ pub enum NotificationChannel {
 }
 
 pub async fn send_sms(env: &EnvConfig, mobile: &str, message: &str) -> eyre::Result<()> {
-    let api = SendchampApi {
-        base_url: &env.sendchamp_base_url,
-        api_key: &env.sendchamp_api_key,
-        sendchamp_sender_id: &env.sendchamp_sender_id,
-    };
-    if let Err(err) = api.send_message(mobile, message).await {
+    if let Err(err) = try_send_sms(env, mobile, message).await {
         tracing::error!(
             "Error sending SMS to mobile={}, message={}, err={:?}",
             mobile,
@@ -30,3 +25,14 @@ pub async fn send_sms(env: &EnvConfig, mobile: &str, message: &str) -> eyre::Res
 
     Ok(())
 }
+
+/// Like [send_sms] but hands the delivery error back to callers that need
+/// to know whether the message went out.
+pub async fn try_send_sms(env: &EnvConfig, mobile: &str, message: &str) -> eyre::Result<()> {
+    let api = SendchampApi {
+        base_url: &env.sendchamp_base_url,
+        api_key: &env.sendchamp_api_key,
+        sendchamp_sender_id: &env.sendchamp_sender_id,
+    };
+    api.send_message(mobile, message).await
+}
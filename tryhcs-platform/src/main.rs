@@ -1,29 +1,78 @@
 use axum::{
+    middleware,
     response::{Html, IntoResponse},
     routing::get,
     Router,
 };
 use std::sync::Arc;
+use tokio_cron_scheduler::{Job, JobScheduler};
 use tower::ServiceBuilder;
 use tower_http::cors::{Any, CorsLayer};
-use tracing::info;
-use tryhcs_commons_be::{env::EnvConfig, file_upload::get_upload_client, redis::RedisCache};
+use tracing::{error, info, warn};
+use tryhcs_commons_be::{
+    api_response::correlation_id_middleware,
+    data_encryption::Encryptor,
+    env::EnvConfig,
+    file_upload::get_upload_client,
+    redis::{Cache, RedisCache},
+    utils::is_phone_region,
+};
 use tryhcs_compliance_be::{
-    app::ComplianceApp, endpoints::compliance_router, integrations::youverify::YouverifyApi,
+    api::send_license_expiry_reminders, app::ComplianceApp, endpoints::compliance_router,
+    integrations::verification_provider, lookups::IdentityLookups, name_match::NameMatcher,
     repo::ComplianceDB,
 };
-use tryhcs_customers_be::{app::CustomersApp, db_repo::CustomerDB, endpoint::customers_router};
+use tryhcs_customers_be::{
+    api::{process_institution_exports, purge_deactivated_institutions, send_invitation_reminders},
+    app::CustomersApp,
+    db_repo::CustomerDB,
+    endpoint::customers_router,
+};
 
 use eyre::Context;
+use rand::{distributions::Alphanumeric, Rng};
 use sqlx::postgres::PgPoolOptions;
 
+mod migrations;
+
+// applies the pending migrations and exits without serving
+const MIGRATE_ONLY_FLAG: &str = "--migrate-only";
+
+const INVITATION_REMINDERS_SCHEDULE: &str = "0 0 * * * *";
+// held for most of the hour so only one instance sends the reminders
+const INVITATION_REMINDERS_LOCK: &str = "JOB-LOCK-INVITATION-REMINDERS";
+const INVITATION_REMINDERS_LOCK_IN_SEC: u64 = 50 * 60;
+
+const INSTITUTION_EXPORTS_SCHEDULE: &str = "0 */5 * * * *";
+const INSTITUTION_EXPORTS_LOCK: &str = "JOB-LOCK-INSTITUTION-EXPORTS";
+const INSTITUTION_EXPORTS_LOCK_IN_SEC: u64 = 4 * 60;
+
+const INSTITUTION_PURGE_SCHEDULE: &str = "0 30 2 * * *";
+const INSTITUTION_PURGE_LOCK: &str = "JOB-LOCK-INSTITUTION-PURGE";
+const INSTITUTION_PURGE_LOCK_IN_SEC: u64 = 12 * 60 * 60;
+
+const LICENSE_REMINDERS_SCHEDULE: &str = "0 0 8 * * *";
+const LICENSE_REMINDERS_LOCK: &str = "JOB-LOCK-LICENSE-REMINDERS";
+const LICENSE_REMINDERS_LOCK_IN_SEC: u64 = 12 * 60 * 60;
+
 #[tokio::main]
 async fn main() -> eyre::Result<()> {
     dotenvy::dotenv().ok();
 
     tracing_subscriber::fmt().init();
 
-    let env: EnvConfig = envy::from_env::<EnvConfig>().wrap_err("loaded config files")?;
+    let mut env: EnvConfig = envy::from_env::<EnvConfig>().wrap_err("loaded config files")?;
+    if env.invitation_signing_key.is_empty() {
+        warn!("No invitation signing key, links sent only work on this instance until it restarts");
+        env.invitation_signing_key = rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(64)
+            .map(char::from)
+            .collect();
+    }
+    if !is_phone_region(&env.default_phone_region) {
+        eyre::bail!("Invalid default_phone_region: {}", env.default_phone_region);
+    }
 
     info!("Connecting to databases");
     let customer_db_pool = PgPoolOptions::new()
@@ -32,6 +81,17 @@ async fn main() -> eyre::Result<()> {
         .await
         .wrap_err("Failed to connect to db")?;
 
+    migrations::run_migrations(&customer_db_pool)
+        .await
+        .wrap_err("Failed to migrate database")?;
+    migrations::run_data_migrations(&customer_db_pool, &env)
+        .await
+        .wrap_err("Failed to migrate data")?;
+    if std::env::args().any(|arg| arg.eq(MIGRATE_ONLY_FLAG)) {
+        info!("Migrations applied, exiting");
+        return Ok(());
+    }
+
     let customer_db = Arc::new(CustomerDB {
         customer_db: customer_db_pool.clone(),
     });
@@ -45,10 +105,17 @@ async fn main() -> eyre::Result<()> {
     let redis_client = Arc::new(RedisCache(redis::Client::open(env.redis_url.as_str())?));
     info!("connected to redis");
 
-    let youverify_api: Arc<YouverifyApi> = Arc::new(YouverifyApi {
-        env: Arc::new(env.clone()),
-    });
+    let verification =
+        verification_provider(&env).wrap_err("Invalid verification provider config")?;
+    info!("Verifying identities with {}", env.verification_provider);
+    NameMatcher::from_env(&env).wrap_err("Invalid name match thresholds")?;
     let s3_client = get_upload_client(&env).await?;
+    let encryptor = Encryptor::from_env(&env)
+        .wrap_err("Invalid data encryption config")?
+        .map(Arc::new);
+    if encryptor.is_none() {
+        warn!("No data encryption key, authenticator apps and identity lookup caching are disabled");
+    }
 
     // Create Arc<CustomerApp> and Arc<ComplianceApp>
     let customer_app: Arc<CustomersApp> = Arc::new(CustomersApp {
@@ -56,14 +123,38 @@ async fn main() -> eyre::Result<()> {
         s3_client: s3_client.clone(),
         env: env.clone(),
         redis: redis_client.clone(),
+        encryptor: encryptor.clone(),
+        institution_data: vec![compliance_db.clone()],
+        platform_staff: vec![compliance_db.clone()],
     });
+    let session_app = customer_app.clone();
+    let session_pool = customer_db_pool.clone();
+    tokio::spawn(async move {
+        if let Err(err) = migrations::run_session_migrations(&session_pool, &session_app).await {
+            error!(err=?err, "Failed to refresh sessions");
+        }
+    });
+
+    let lookups = IdentityLookups::new(
+        verification.clone(),
+        redis_client.clone(),
+        encryptor.clone(),
+        compliance_db.clone(),
+        &env,
+    );
     let compliance_app = Arc::new(ComplianceApp {
-        compliance: youverify_api,
+        compliance: verification,
+        lookups,
         env: env.clone(),
         redis: redis_client.clone(),
         compliance_repo: compliance_db,
+        s3_client: s3_client.clone(),
     });
 
+    let scheduler = schedule_jobs(customer_app.clone(), compliance_app.clone()).await?;
+    scheduler.start().await.wrap_err("Failed to start jobs")?;
+    info!("Scheduled background jobs");
+
     let app_router = app_router(customer_app.clone(), compliance_app.clone()).await?;
     info!("Mounted app routes");
 
@@ -86,17 +177,133 @@ async fn app_router(
     let cors_layer = CorsLayer::new()
         .allow_methods(Any)
         .allow_origin(Any)
-        .allow_headers(Any);
+        .allow_headers(Any)
+        .expose_headers(Any);
 
     let router = Router::new()
         .route("/", get(health_info))
         .nest("/workspace/v1", customers_router(customer_app.clone()))
         .nest("/compliance", compliance_router(compliance_app.clone()))
-        .layer(ServiceBuilder::new().layer(cors_layer));
+        .layer(
+            ServiceBuilder::new()
+                .layer(cors_layer)
+                .layer(middleware::from_fn(correlation_id_middleware)),
+        );
 
     Ok(router)
 }
 
+async fn schedule_jobs(
+    customer_app: Arc<CustomersApp>,
+    compliance_app: Arc<ComplianceApp>,
+) -> eyre::Result<JobScheduler> {
+    let scheduler = JobScheduler::new().await?;
+
+    let app = customer_app.clone();
+    let reminders_job = Job::new_async(INVITATION_REMINDERS_SCHEDULE, move |_, _| {
+        let app = app.clone();
+        Box::pin(async move {
+            if !acquire_job_lock(
+                app.redis.as_ref(),
+                INVITATION_REMINDERS_LOCK,
+                INVITATION_REMINDERS_LOCK_IN_SEC,
+            )
+            .await
+            {
+                return;
+            }
+
+            match send_invitation_reminders(app.as_ref()).await {
+                Ok(reminded) => info!("Sent {} invitation reminders", reminded),
+                Err(err) => tracing::error!(err=?err, "Failed to send invitation reminders"),
+            }
+        })
+    })?;
+    scheduler.add(reminders_job).await?;
+
+    let app = customer_app.clone();
+    let exports_job = Job::new_async(INSTITUTION_EXPORTS_SCHEDULE, move |_, _| {
+        let app = app.clone();
+        Box::pin(async move {
+            if !acquire_job_lock(
+                app.redis.as_ref(),
+                INSTITUTION_EXPORTS_LOCK,
+                INSTITUTION_EXPORTS_LOCK_IN_SEC,
+            )
+            .await
+            {
+                return;
+            }
+
+            match process_institution_exports(app.as_ref()).await {
+                Ok(0) => {}
+                Ok(exported) => info!("Processed {} institution exports", exported),
+                Err(err) => tracing::error!(err=?err, "Failed to process institution exports"),
+            }
+        })
+    })?;
+    scheduler.add(exports_job).await?;
+
+    let app = customer_app.clone();
+    let purge_job = Job::new_async(INSTITUTION_PURGE_SCHEDULE, move |_, _| {
+        let app = app.clone();
+        Box::pin(async move {
+            if !acquire_job_lock(
+                app.redis.as_ref(),
+                INSTITUTION_PURGE_LOCK,
+                INSTITUTION_PURGE_LOCK_IN_SEC,
+            )
+            .await
+            {
+                return;
+            }
+
+            match purge_deactivated_institutions(app.as_ref()).await {
+                Ok(purged) => info!("Purged {} deactivated institutions", purged),
+                Err(err) => tracing::error!(err=?err, "Failed to purge deactivated institutions"),
+            }
+        })
+    })?;
+    scheduler.add(purge_job).await?;
+
+    let app = compliance_app.clone();
+    let license_reminders_job = Job::new_async(LICENSE_REMINDERS_SCHEDULE, move |_, _| {
+        let app = app.clone();
+        Box::pin(async move {
+            if !acquire_job_lock(
+                app.redis.as_ref(),
+                LICENSE_REMINDERS_LOCK,
+                LICENSE_REMINDERS_LOCK_IN_SEC,
+            )
+            .await
+            {
+                return;
+            }
+
+            match send_license_expiry_reminders(app.as_ref()).await {
+                Ok(reminded) => info!("Sent {} license expiry reminders", reminded),
+                Err(err) => tracing::error!(err=?err, "Failed to send license expiry reminders"),
+            }
+        })
+    })?;
+    scheduler.add(license_reminders_job).await?;
+
+    Ok(scheduler)
+}
+
+// The first instance to increment the lock runs the job, the lock expires
+// before the next run.
+async fn acquire_job_lock(redis: &dyn Cache, lock: &str, lock_in_sec: u64) -> bool {
+    match redis.increment_key(lock, lock_in_sec).await {
+        Ok(1) => true,
+        Ok(_) => false,
+        Err(err) => {
+            tracing::error!(err=?err, "Failed to acquire job lock: {}", lock);
+            false
+        }
+    }
+}
+
 async fn health_info() -> impl IntoResponse {
     "Alive"
 }
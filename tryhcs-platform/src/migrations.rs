@@ -0,0 +1,320 @@
+use std::collections::BTreeSet;
+
+use eyre::eyre;
+use sqlx::{
+    migrate::{Migrate, Migrator},
+    PgPool, Postgres, Transaction,
+};
+use tracing::{info, warn};
+use tryhcs_commons_be::{env::EnvConfig, utils::normalize_phone};
+use tryhcs_customers_be::{api::refresh_all_user_sessions, app::CustomersApp};
+
+const NORMALIZE_PHONE_NUMBERS: &str = "normalize_phone_numbers";
+const REFRESH_SESSION_PERMISSIONS: &str = "refresh_session_permissions";
+
+// (table, column) pairs holding phone numbers, the unique ones can't take a
+// number that another row already normalized to
+const PHONE_COLUMNS: [(&str, &str, bool); 4] = [
+    ("users", "mobile", true),
+    ("staffs", "mobile", true),
+    ("staff_invitations", "mobile", false),
+    (
+        "healthcare_compliance",
+        "licensed_medical_doctor_phone_no",
+        false,
+    ),
+];
+
+// Applies the pending migrations of every crate, the platform refuses to
+// start on a schema that a failed migration left dirty or that has
+// migrations this binary doesn't know about.
+pub async fn run_migrations(pool: &PgPool) -> eyre::Result<()> {
+    // the table each crate's init migration creates first
+    let migrators = [
+        ("customers", tryhcs_customers_be::migrator(), "institutions"),
+        (
+            "compliance",
+            tryhcs_compliance_be::migrator(),
+            "corporate_compliance",
+        ),
+    ];
+
+    let known_versions = migrators
+        .iter()
+        .flat_map(|(_, m, _)| m.iter().map(|v| v.version))
+        .collect::<BTreeSet<_>>();
+    check_schema(pool, &known_versions).await?;
+
+    for (name, migrator, init_table) in migrators.iter() {
+        adopt_init_schema(pool, name, migrator, init_table).await?;
+    }
+
+    for (name, migrator, _) in migrators.iter() {
+        migrator
+            .run(pool)
+            .await
+            .map_err(|err| eyre!("Failed to run {} migrations: {}", name, err))?;
+        info!("Applied {} migrations", name);
+    }
+
+    Ok(())
+}
+
+// Databases set up by hand from the init scripts, before the migrations were
+// numbered, have the init schema without a migration history. Their init
+// migration is recorded as applied so only the later migrations run.
+async fn adopt_init_schema(
+    pool: &PgPool,
+    name: &str,
+    migrator: &Migrator,
+    init_table: &str,
+) -> eyre::Result<()> {
+    let Some(init) = migrator.iter().min_by_key(|m| m.version) else {
+        return Ok(());
+    };
+    let has_init_schema: bool = sqlx::query_scalar("select to_regclass($1) is not null")
+        .bind(init_table)
+        .fetch_one(pool)
+        .await?;
+    if !has_init_schema {
+        return Ok(());
+    }
+
+    let mut conn = pool.acquire().await?;
+    conn.ensure_migrations_table().await?;
+    let recorded = sqlx::query(
+        "insert into _sqlx_migrations (version, description, success, checksum, execution_time)
+        values ($1, $2, true, $3, 0) on conflict (version) do nothing",
+    )
+    .bind(init.version)
+    .bind(init.description.as_ref())
+    .bind(init.checksum.as_ref())
+    .execute(&mut *conn)
+    .await?
+    .rows_affected();
+    if recorded > 0 {
+        info!(
+            "Recorded the {} init migration {} of the existing schema as applied",
+            name, init.version
+        );
+    }
+    Ok(())
+}
+
+async fn check_schema(pool: &PgPool, known_versions: &BTreeSet<i64>) -> eyre::Result<()> {
+    let has_history: bool =
+        sqlx::query_scalar("select to_regclass('_sqlx_migrations') is not null")
+            .fetch_one(pool)
+            .await?;
+    if !has_history {
+        return Ok(());
+    }
+
+    let applied: Vec<(i64, bool)> =
+        sqlx::query_as("select version, success from _sqlx_migrations order by version")
+            .fetch_all(pool)
+            .await?;
+
+    if let Some((version, _)) = applied.iter().find(|(_, success)| !success) {
+        return Err(eyre!(
+            "Database schema is dirty, migration {} failed and needs to be resolved manually",
+            version
+        ));
+    }
+
+    let unknown_versions = applied
+        .iter()
+        .map(|(version, _)| *version)
+        .filter(|version| !known_versions.contains(version))
+        .collect::<Vec<_>>();
+    if !unknown_versions.is_empty() {
+        return Err(eyre!(
+            "Database schema is ahead of this build, unknown migrations: {:?}",
+            unknown_versions
+        ));
+    }
+
+    Ok(())
+}
+
+// Runs the data migrations that haven't been applied, each in its own transaction
+pub async fn run_data_migrations(pool: &PgPool, env: &EnvConfig) -> eyre::Result<()> {
+    let mut txn = pool.begin().await?;
+    let applied: bool =
+        sqlx::query_scalar("select exists(select 1 from data_migrations where name = $1)")
+            .bind(NORMALIZE_PHONE_NUMBERS)
+            .fetch_one(&mut *txn)
+            .await?;
+    if !applied {
+        normalize_phone_numbers(&mut txn, &env.default_phone_region).await?;
+        sqlx::query("insert into data_migrations (name) values ($1)")
+            .bind(NORMALIZE_PHONE_NUMBERS)
+            .execute(&mut *txn)
+            .await?;
+        info!("Applied data migration: {}", NORMALIZE_PHONE_NUMBERS);
+    }
+    retry_phone_number_conflicts(&mut txn, &env.default_phone_region).await?;
+    txn.commit().await?;
+    Ok(())
+}
+
+// Reloads the principal of the sessions opened before permissions were cached
+// on them. Sessions live in the cache so this runs once the app is up, it's
+// recorded like the data migrations once every user has been refreshed.
+pub async fn run_session_migrations(pool: &PgPool, app: &CustomersApp) -> eyre::Result<()> {
+    let applied: bool =
+        sqlx::query_scalar("select exists(select 1 from data_migrations where name = $1)")
+            .bind(REFRESH_SESSION_PERMISSIONS)
+            .fetch_one(pool)
+            .await?;
+    if applied {
+        return Ok(());
+    }
+
+    refresh_all_user_sessions(app).await?;
+    sqlx::query("insert into data_migrations (name) values ($1) on conflict do nothing")
+        .bind(REFRESH_SESSION_PERMISSIONS)
+        .execute(pool)
+        .await?;
+    info!("Applied data migration: {}", REFRESH_SESSION_PERMISSIONS);
+    Ok(())
+}
+
+// Rewrites stored phone numbers to E.164. Invalid numbers and numbers whose
+// normalized form belongs to another account are left as they are and recorded
+// in phone_number_conflicts to be merged by hand.
+async fn normalize_phone_numbers(
+    txn: &mut Transaction<'_, Postgres>,
+    default_region: &str,
+) -> eyre::Result<()> {
+    for (table, column, unique) in PHONE_COLUMNS {
+        let rows: Vec<(i64, String)> =
+            sqlx::query_as(&format!("select id, {column} from {table} order by id"))
+                .fetch_all(&mut **txn)
+                .await?;
+
+        let mut normalized = 0;
+        for (id, phone) in rows {
+            match rewrite_phone(txn, (table, column, unique), id, &phone, default_region).await? {
+                PhoneRewrite::Unchanged => {}
+                PhoneRewrite::Normalized => normalized += 1,
+                PhoneRewrite::Conflict(e164) => {
+                    warn!("{table}.{column} of row {id} was left as {phone}, normalized: {e164:?}");
+                    sqlx::query(
+                        "insert into phone_number_conflicts (table_name, column_name, row_id, phone, normalized)
+                        values ($1, $2, $3, $4, $5)
+                        on conflict (table_name, column_name, row_id) do update
+                        set phone = $4, normalized = $5, resolved_at = null",
+                    )
+                    .bind(table)
+                    .bind(column)
+                    .bind(id)
+                    .bind(&phone)
+                    .bind(e164)
+                    .execute(&mut **txn)
+                    .await?;
+                }
+            }
+        }
+        info!(
+            "Normalized {} phone numbers in {}.{}",
+            normalized, table, column
+        );
+    }
+
+    Ok(())
+}
+
+// Numbers left by the normalization are rewritten once they no longer conflict,
+// conflicts whose row was since deleted or changed are resolved with it.
+async fn retry_phone_number_conflicts(
+    txn: &mut Transaction<'_, Postgres>,
+    default_region: &str,
+) -> eyre::Result<()> {
+    let conflicts: Vec<(i64, String, String, i64, String)> = sqlx::query_as(
+        "select id, table_name, column_name, row_id, phone from phone_number_conflicts
+        where resolved_at is null order by id",
+    )
+    .fetch_all(&mut **txn)
+    .await?;
+
+    let mut unresolved = 0;
+    for (conflict_id, table, column, row_id, phone) in conflicts {
+        let Some(phone_column) = PHONE_COLUMNS
+            .into_iter()
+            .find(|(t, c, _)| *t == table && *c == column)
+        else {
+            continue;
+        };
+        let current: Option<String> =
+            sqlx::query_scalar(&format!("select {column} from {table} where id = $1"))
+                .bind(row_id)
+                .fetch_optional(&mut **txn)
+                .await?;
+
+        let resolved = match current {
+            Some(current) if current == phone => !matches!(
+                rewrite_phone(txn, phone_column, row_id, &phone, default_region).await?,
+                PhoneRewrite::Conflict(_)
+            ),
+            _ => true,
+        };
+        if !resolved {
+            unresolved += 1;
+            continue;
+        }
+        sqlx::query("update phone_number_conflicts set resolved_at = Now() where id = $1")
+            .bind(conflict_id)
+            .execute(&mut **txn)
+            .await?;
+    }
+
+    if unresolved > 0 {
+        warn!(
+            "{} phone numbers couldn't be normalized, see phone_number_conflicts",
+            unresolved
+        );
+    }
+    Ok(())
+}
+
+enum PhoneRewrite {
+    Unchanged,
+    Normalized,
+    // invalid numbers have no normalized form
+    Conflict(Option<String>),
+}
+
+async fn rewrite_phone(
+    txn: &mut Transaction<'_, Postgres>,
+    (table, column, unique): (&str, &str, bool),
+    id: i64,
+    phone: &str,
+    default_region: &str,
+) -> eyre::Result<PhoneRewrite> {
+    let Some(e164) = normalize_phone(phone, default_region) else {
+        return Ok(PhoneRewrite::Conflict(None));
+    };
+    if e164 == phone {
+        return Ok(PhoneRewrite::Unchanged);
+    }
+
+    if unique {
+        let taken: bool = sqlx::query_scalar(&format!(
+            "select exists(select 1 from {table} where {column} = $1)"
+        ))
+        .bind(&e164)
+        .fetch_one(&mut **txn)
+        .await?;
+        if taken {
+            return Ok(PhoneRewrite::Conflict(Some(e164)));
+        }
+    }
+
+    sqlx::query(&format!("update {table} set {column} = $1 where id = $2"))
+        .bind(&e164)
+        .bind(id)
+        .execute(&mut **txn)
+        .await?;
+    Ok(PhoneRewrite::Normalized)
+}
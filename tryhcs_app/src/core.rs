@@ -18,7 +18,9 @@ use tryhcs_shared::{
 use wasm_bindgen::prelude::*;
 
 use crate::{
-    hcs_api::{HcsApi, AUTH_TOKEN_STORAGE_KEY, CURRENT_WORKSPACE_STORAGE_KEY},
+    hcs_api::{
+        HcsApi, AUTH_TOKEN_STORAGE_KEY, CURRENT_WORKSPACE_STORAGE_KEY, REFRESH_TOKEN_STORAGE_KEY,
+    },
     hcs_endpoints::HcsEndpoints,
     state_engine::global_state::GlobalState,
     storage::{AppStorage, Storage},
@@ -221,7 +223,7 @@ impl GuestApplication {
         &self,
         login_req: &LoginReq,
     ) -> eyre::Result<Either<AuthenticatedApplication, InitiatedOtp>, ErrorMessage> {
-        let internal_auth_error: ErrorMessage = ErrorMessage("#Authentication failure".into());
+        let internal_auth_error: ErrorMessage = ErrorMessage::from("#Authentication failure");
 
         let login_res = self.core.hcs_api.login(login_req).await?;
         if let Some(otp) = login_res.otp {
@@ -243,7 +245,7 @@ impl GuestApplication {
         &self,
         authenticated: &AuthenticatedUser,
     ) -> Result<(), ErrorMessage> {
-        let internal_auth_error: ErrorMessage = ErrorMessage("#Authentication failure".into());
+        let internal_auth_error: ErrorMessage = ErrorMessage::from("#Authentication failure");
 
         match &authenticated.token {
             None => {
@@ -260,6 +262,18 @@ impl GuestApplication {
             }
         };
 
+        if let Some(refresh_token) = &authenticated.refresh_token {
+            if let Err(error_message) = self
+                .core
+                .storage
+                .set(REFRESH_TOKEN_STORAGE_KEY, refresh_token)
+                .await
+            {
+                error!(message="failed to store refresh token in storage", error_message=?error_message);
+                return Err(internal_auth_error);
+            }
+        }
+
         let mut is_valid_workspace_code = false;
         if let Ok(Some(workspace_code)) = self.core.storage.get(CURRENT_WORKSPACE_STORAGE_KEY).await
         {
@@ -274,7 +288,7 @@ impl GuestApplication {
             let workspace = authenticated.principal.accounts.first();
             match workspace {
                 None => {
-                    return Err(ErrorMessage("User does not have any workspace".into()));
+                    return Err(ErrorMessage::from("User does not have any workspace"));
                 }
                 Some(user) => {
                     if let Err(error_message) = self
@@ -416,7 +430,7 @@ impl AuthenticatedApplication {
             .await?
             .into_iter()
             .find(|staff| staff.id.eq(&staff_id.0));
-        staff.ok_or(ErrorMessage("Staff not found".into()))
+        staff.ok_or(ErrorMessage::from("Staff not found"))
     }
 
     pub async fn search_staffs_directory(
@@ -491,6 +505,6 @@ impl AuthenticatedApplication {
             .await?
             .into_iter()
             .find(|department| department.id.eq(&department_id.0));
-        department.ok_or(ErrorMessage("Department not found".into()))
+        department.ok_or(ErrorMessage::from("Department not found"))
     }
 }
@@ -12,15 +12,16 @@ use tryhcs_shared::institution_params::{
 use crate::core::AppHook;
 use crate::storage::Storage;
 use crate::{core::HcsAppConfig, hcs_endpoints::HcsEndpoints, utils::encrypt_payload};
-use tryhcs_shared::api_params::{ApiResponseError, PaginatedResult};
+use tryhcs_shared::api_params::{ApiResponseError, PaginatedResult, MAX_PAGE_SIZE};
 use tryhcs_shared::{
     api_params::{ApiResponseData, ErrorMessage},
     encryption::Encryption,
-    institution_params::{AuthenticatedUser, LoginResponse},
+    institution_params::{AuthenticatedUser, LoginResponse, RefreshTokenReq},
 };
 
 pub const REQUEST_FAILED_ERROR: &str = "REQUEST FAILED";
 pub const AUTH_TOKEN_STORAGE_KEY: &str = "SYSTEM|AUTH_TOKEN";
+pub const REFRESH_TOKEN_STORAGE_KEY: &str = "SYSTEM|REFRESH_TOKEN";
 pub const CURRENT_WORKSPACE_STORAGE_KEY: &str = "SYSTEM|WORKSPACE_ID";
 
 #[derive(Clone)]
@@ -47,19 +48,125 @@ impl HcsApi {
     }
 
     pub async fn get(&self, url: &str) -> eyre::Result<Either<String, ErrorMessage>, ErrorMessage> {
-        let client = reqwest::Client::new();
         debug!(method="GET", url=?url);
+        self.send(reqwest::Method::GET, url, None).await
+    }
 
-        let request = client.get(url);
-        let request = self.add_request_headers(request).await;
-        let request = request.send().await;
+    // Sends the request, an unauthorized response is retried once after the
+    // session has been refreshed. The app is logged out when the refresh fails.
+    async fn send(
+        &self,
+        method: reqwest::Method,
+        url: &str,
+        body: Option<String>,
+    ) -> eyre::Result<Either<String, ErrorMessage>, ErrorMessage> {
+        let mut response = self.send_once(method.clone(), url, body.clone()).await;
+
+        if let Ok(res) = &response {
+            // only authenticated requests are refreshed, a failed login is
+            // also unauthorized
+            let is_authenticated =
+                matches!(self.storage.get(AUTH_TOKEN_STORAGE_KEY).await, Ok(Some(_)));
+            if is_authenticated && res.status() == reqwest::StatusCode::UNAUTHORIZED {
+                if self.refresh_session().await {
+                    response = self.send_once(method, url, body).await;
+                } else {
+                    self.log_out().await;
+                }
+            }
+        }
 
-        match self.extract_response(url, request).await {
+        match self.extract_response(url, response).await {
             Ok(value) => value,
             Err(value) => return value,
         }
     }
 
+    async fn send_once(
+        &self,
+        method: reqwest::Method,
+        url: &str,
+        body: Option<String>,
+    ) -> Result<reqwest::Response, reqwest::Error> {
+        let client = reqwest::Client::new();
+        let request = client.request(method, url);
+        let mut request = self.add_request_headers(request).await;
+        if let Some(body) = body {
+            request = request.body(body);
+        }
+        request.send().await
+    }
+
+    async fn refresh_session(&self) -> bool {
+        let refresh_token = match self.storage.get(REFRESH_TOKEN_STORAGE_KEY).await {
+            Ok(Some(refresh_token)) => refresh_token,
+            Ok(None) => return false,
+            Err(error_message) => {
+                error!(message="Storage error while getting the refresh token", err=?error_message);
+                return false;
+            }
+        };
+
+        let url = format!("{}/workspace/v1/token/refresh", self.config.base_api_url);
+        let req = RefreshTokenReq { refresh_token };
+        let body = match encrypt_payload(self.encryption.as_ref(), &req) {
+            Ok(body) => body,
+            Err(error_message) => {
+                error!(message="Failed to encrypt refresh token request", err=?error_message);
+                return false;
+            }
+        };
+
+        let response = self
+            .send_once(reqwest::Method::POST, &url, Some(body))
+            .await;
+        let response = match self.extract_response(&url, response).await {
+            Ok(Ok(response)) => response,
+            Ok(Err(_)) | Err(_) => return false,
+        };
+        let authenticated = match self
+            .decrypt_response::<ApiResponseData<AuthenticatedUser>, ApiResponseError>(&response)
+            .await
+        {
+            Ok(response) => response.data,
+            Err(error_message) => {
+                info!(message="Session refresh rejected", err=?error_message);
+                return false;
+            }
+        };
+
+        match (&authenticated.token, &authenticated.refresh_token) {
+            (Some(token), Some(refresh_token)) => {
+                if let Err(error_message) = self.storage.set(AUTH_TOKEN_STORAGE_KEY, token).await {
+                    error!(message="failed to store token in storage", error_message=?error_message);
+                    return false;
+                }
+                if let Err(error_message) = self
+                    .storage
+                    .set(REFRESH_TOKEN_STORAGE_KEY, refresh_token)
+                    .await
+                {
+                    error!(message="failed to store refresh token in storage", error_message=?error_message);
+                    return false;
+                }
+                true
+            }
+            _ => false,
+        }
+    }
+
+    async fn log_out(&self) {
+        for key in [AUTH_TOKEN_STORAGE_KEY, REFRESH_TOKEN_STORAGE_KEY] {
+            if let Err(error_message) = self.storage.delete(key).await {
+                error!(message="failed to clear credentials from storage", error_message=?error_message);
+            }
+        }
+
+        if let Some(app_hooks) = &*self.app_hooks {
+            app_hooks.on_log_out();
+        }
+    }
+
     async fn add_request_headers(
         &self,
         mut request: reqwest::RequestBuilder,
@@ -113,15 +220,8 @@ impl HcsApi {
         url: &str,
         body: String,
     ) -> eyre::Result<Either<String, ErrorMessage>, ErrorMessage> {
-        let client = reqwest::Client::new();
         debug!(url=?url, method="POST", body=?body);
-        let request = client.post(url);
-        let request = self.add_request_headers(request).await;
-        let request = request.body(body).send().await;
-        match self.extract_response(url, request).await {
-            Ok(value) => value,
-            Err(value) => return value,
-        }
+        self.send(reqwest::Method::POST, url, Some(body)).await
     }
 
     pub async fn put(
@@ -129,15 +229,8 @@ impl HcsApi {
         url: &str,
         body: String,
     ) -> eyre::Result<Either<String, ErrorMessage>, ErrorMessage> {
-        let client = reqwest::Client::new();
         debug!(method="POST", url=?url,  body=?body);
-        let request = client.put(url);
-        let request = self.add_request_headers(request).await;
-        let request = request.body(body).send().await;
-        match self.extract_response(url, request).await {
-            Ok(value) => value,
-            Err(value) => return value,
-        }
+        self.send(reqwest::Method::PUT, url, Some(body)).await
     }
 
     pub async fn delete(
@@ -145,15 +238,8 @@ impl HcsApi {
         url: &str,
         body: String,
     ) -> eyre::Result<Either<String, ErrorMessage>, ErrorMessage> {
-        let client = reqwest::Client::new();
         debug!(method="DELETE", url=?url,  body=?body);
-        let request = client.delete(url);
-        let request = self.add_request_headers(request).await;
-        let request = request.body(body).send().await;
-        match self.extract_response(url, request).await {
-            Ok(value) => value,
-            Err(value) => return value,
-        }
+        self.send(reqwest::Method::DELETE, url, Some(body)).await
     }
 
     async fn extract_response(
@@ -186,7 +272,7 @@ impl HcsApi {
                 debug!(url=?url, status=?status, response=?response_str);
 
                 if !status.is_success() {
-                    return Err(Ok(Either::Right(ErrorMessage(response_str))));
+                    return Err(Ok(Either::Right(response_str.into())));
                 }
                 return Ok(Ok(Either::Left(response_str)));
             }
@@ -210,8 +296,8 @@ impl HcsApi {
                     }
                 };
             }
-            Either::Right(ErrorMessage(error)) => {
-                let result = self.encryption.decrypt(&error)?;
+            Either::Right(error) => {
+                let result = self.encryption.decrypt(&error.message)?;
                 match serde_json::from_str::<E>(&result) {
                     Err(e) => {
                         error!(message="Failure deserialization failed. ", err=?e);
@@ -224,6 +310,34 @@ impl HcsApi {
             }
         }
     }
+
+    // Listings are paginated, the app keeps the whole directory to search it
+    // offline so every page is fetched.
+    async fn get_all_pages<T: DeserializeOwned>(
+        &self,
+        url: &str,
+    ) -> eyre::Result<Vec<T>, ErrorMessage> {
+        let mut items = vec![];
+        let mut page_number = 1;
+        loop {
+            let page_url = format!(
+                "{}?page_size={}&page_number={}",
+                url, MAX_PAGE_SIZE, page_number
+            );
+            let request = self.get(&page_url).await?;
+            let page = self
+                .decrypt_response::<PaginatedResult<T>, ApiResponseError>(&request)
+                .await?;
+
+            let fetched_all =
+                page.data.is_empty() || items.len() + page.data.len() >= page.total as usize;
+            items.extend(page.data);
+            if fetched_all {
+                return Ok(items);
+            }
+            page_number += 1;
+        }
+    }
 }
 
 #[async_trait::async_trait(?Send)]
@@ -292,21 +406,13 @@ impl HcsEndpoints for HcsApi {
         &self,
     ) -> eyre::Result<Vec<tryhcs_shared::institution_params::StaffDto>, ErrorMessage> {
         let url = format!("{}/workspace/v1/staffs", self.config.base_api_url);
-        let request = self.get(&url).await?;
-        let response = self
-            .decrypt_response::<PaginatedResult<StaffDto>, ApiResponseError>(&request)
-            .await?;
-        Ok(response.data)
+        self.get_all_pages::<StaffDto>(&url).await
     }
 
     async fn search_departments(
         &self,
     ) -> eyre::Result<Vec<tryhcs_shared::institution_params::DepartmentDto>, ErrorMessage> {
         let url = format!("{}/workspace/v1/departments", self.config.base_api_url);
-        let request = self.get(&url).await?;
-        let response = self
-            .decrypt_response::<PaginatedResult<DepartmentDto>, ApiResponseError>(&request)
-            .await?;
-        Ok(response.data)
+        self.get_all_pages::<DepartmentDto>(&url).await
     }
 }
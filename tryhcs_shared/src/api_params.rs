@@ -3,17 +3,236 @@ use reqwest::StatusCode;
 use serde::{Deserialize, Serialize};
 use ts_rs::TS;
 
+use crate::validation::FieldError;
+
 #[derive(Serialize, Deserialize, Debug, Clone, TS)]
 #[ts(export)]
-pub struct ErrorMessage(pub String);
+pub struct ErrorMessage {
+    pub message: String,
+    /// code of the API error the message came from, `None` for client errors
+    pub error_code: Option<ErrorCode>,
+}
 impl<T: AsRef<str>> From<T> for ErrorMessage {
     fn from(value: T) -> Self {
-        ErrorMessage(format!("{}", value.as_ref()))
+        ErrorMessage {
+            message: value.as_ref().to_string(),
+            error_code: None,
+        }
     }
 }
 
 pub type ApiResponse<T> = (StatusCode, Either<Option<T>, ErrorMessage>);
 
+/// Stable codes clients can branch on instead of the error message, each one
+/// always comes back with the same HTTP status.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, TS)]
+#[ts(export)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ErrorCode {
+    InternalError,
+    ValidationFailed,
+
+    AuthInvalidCredentials,
+    AuthAccountLocked,
+    AuthInvalidSession,
+    AuthForbidden,
+    AuthWorkspaceRequired,
+    AuthUnknownWorkspace,
+    AuthInvalidCurrentPassword,
+    AuthPasswordPolicy,
+    AuthPasswordRequired,
+    AccountNotFound,
+
+    OtpExpired,
+    OtpInvalid,
+    OtpTooManyAttempts,
+    OtpRateLimited,
+    TotpInvalidCode,
+    TotpTooManyAttempts,
+    TotpAlreadyEnabled,
+    TotpNotEnabled,
+    TotpNoPendingEnrollment,
+    TotpUnavailable,
+
+    DeviceNotFound,
+    SessionNotFound,
+
+    InstitutionNotFound,
+    InstitutionEmailTaken,
+    InstitutionDeactivated,
+    ExportNotReady,
+    ExportLinkInvalid,
+    StaffNotFound,
+    StaffAlreadyExists,
+    StaffNotDepartmentMember,
+    DepartmentNotFound,
+    DepartmentAlreadyExists,
+    DepartmentInvalidName,
+    RoleNotFound,
+    RoleAlreadyExists,
+    RoleInvalidName,
+    RoleInUse,
+    RoleLastSuperuser,
+    RoleUnknown,
+    SortFieldUnknown,
+
+    ImportUnsupportedFormat,
+    ImportInvalidFile,
+    ImportInvalidRows,
+
+    InvitationInvalid,
+    InvitationNotFound,
+    InvitationNotPending,
+
+    ComplianceLocked,
+    ComplianceIncomplete,
+    ComplianceBvnMismatch,
+    ComplianceNameMismatch,
+    ComplianceLookupFailed,
+    ComplianceNotSubmitted,
+    ComplianceReviewConflict,
+}
+
+impl ErrorCode {
+    pub fn status(&self) -> StatusCode {
+        use ErrorCode::*;
+        match self {
+            InternalError => StatusCode::INTERNAL_SERVER_ERROR,
+            TotpUnavailable => StatusCode::SERVICE_UNAVAILABLE,
+            AuthInvalidCredentials
+            | AuthAccountLocked
+            | AuthInvalidSession
+            | AuthInvalidCurrentPassword => StatusCode::UNAUTHORIZED,
+            AuthForbidden
+            | AuthWorkspaceRequired
+            | AuthUnknownWorkspace
+            | InstitutionDeactivated
+            | ComplianceLocked
+            | ComplianceReviewConflict => StatusCode::FORBIDDEN,
+            AccountNotFound
+            | TotpNotEnabled
+            | TotpNoPendingEnrollment
+            | DeviceNotFound
+            | SessionNotFound
+            | InstitutionNotFound
+            | StaffNotFound
+            | StaffNotDepartmentMember
+            | DepartmentNotFound
+            | RoleNotFound
+            | InvitationNotFound => StatusCode::NOT_FOUND,
+            TotpAlreadyEnabled
+            | InstitutionEmailTaken
+            | StaffAlreadyExists
+            | DepartmentAlreadyExists
+            | RoleAlreadyExists
+            | RoleInUse
+            | RoleLastSuperuser
+            | InvitationNotPending
+            | ExportNotReady
+            | ComplianceNotSubmitted => StatusCode::CONFLICT,
+            OtpTooManyAttempts | OtpRateLimited | TotpTooManyAttempts => {
+                StatusCode::TOO_MANY_REQUESTS
+            }
+            ValidationFailed
+            | AuthPasswordPolicy
+            | AuthPasswordRequired
+            | OtpExpired
+            | OtpInvalid
+            | TotpInvalidCode
+            | RoleInvalidName
+            | DepartmentInvalidName
+            | RoleUnknown
+            | SortFieldUnknown
+            | ImportUnsupportedFormat
+            | ImportInvalidFile
+            | ImportInvalidRows
+            | InvitationInvalid
+            | ExportLinkInvalid
+            | ComplianceIncomplete
+            | ComplianceBvnMismatch
+            | ComplianceNameMismatch
+            | ComplianceLookupFailed => StatusCode::BAD_REQUEST,
+        }
+    }
+
+    /// used when the error doesn't carry a more specific message
+    pub fn message(&self) -> &'static str {
+        use ErrorCode::*;
+        match self {
+            InternalError => "Request processing failed",
+            ValidationFailed => "One or more fields are invalid",
+            AuthInvalidCredentials => "Invalid credentials",
+            AuthAccountLocked => "Account locked, max attempts reached",
+            AuthInvalidSession => "Invalid session, please login again",
+            AuthForbidden => "You are not permitted to perform this action",
+            AuthWorkspaceRequired => "Workspace code is required",
+            AuthUnknownWorkspace => "Unknown Workspace/Institution",
+            AuthInvalidCurrentPassword => "Invalid current password",
+            AuthPasswordPolicy => "Password doesn't meet the password policy",
+            AuthPasswordRequired => "Password is required",
+            AccountNotFound => "Account not found",
+            OtpExpired => "OTP Expired",
+            OtpInvalid => "Invalid or Expired OTP",
+            OtpTooManyAttempts => "Too many invalid attempts, please request a new OTP",
+            OtpRateLimited => "Too many OTP requests, please try again later",
+            TotpInvalidCode => "Invalid authenticator code",
+            TotpTooManyAttempts => "Too many invalid attempts, please login again",
+            TotpAlreadyEnabled => "Authenticator app is already enabled",
+            TotpNotEnabled => "Authenticator app is not enabled",
+            TotpNoPendingEnrollment => "No pending authenticator enrollment",
+            TotpUnavailable => "Authenticator apps aren't available yet",
+            DeviceNotFound => "Device not found",
+            SessionNotFound => "Session not found",
+            InstitutionNotFound => "Institution not found",
+            InstitutionEmailTaken => "Email is already registered to another institution",
+            InstitutionDeactivated => "Institution has been deactivated",
+            ExportNotReady => "Data export is still being generated",
+            ExportLinkInvalid => "Export link is invalid or expired",
+            StaffNotFound => "Staff not found",
+            StaffAlreadyExists => "Staff already exists",
+            StaffNotDepartmentMember => "Staff isn't a member of the department",
+            DepartmentNotFound => "Department not found",
+            DepartmentAlreadyExists => "Department already exists",
+            DepartmentInvalidName => "Invalid department name",
+            RoleNotFound => "Role not found",
+            RoleAlreadyExists => "Role already exists",
+            RoleInvalidName => "Invalid role name",
+            RoleInUse => "Role is assigned to department members",
+            RoleLastSuperuser => "The institution's last Superuser can't be removed",
+            RoleUnknown => "Unknown role",
+            SortFieldUnknown => "Unknown sort field",
+            ImportUnsupportedFormat => "Only CSV and XLSX files can be imported",
+            ImportInvalidFile => "The uploaded file can't be read",
+            ImportInvalidRows => "Some rows failed validation, review them with a dry run",
+            InvitationInvalid => "Invitation link is invalid or expired",
+            InvitationNotFound => "Invitation not found",
+            InvitationNotPending => "Invitation is no longer pending",
+            ComplianceLocked => "Verified compliance documents can't be edited",
+            ComplianceIncomplete => "Compliance details not provided",
+            ComplianceBvnMismatch => "Invalid BVN credentials",
+            ComplianceNameMismatch => "Registered names don't match the submitted details",
+            ComplianceLookupFailed => "Compliance details couldn't be verified",
+            ComplianceNotSubmitted => "Compliance section isn't awaiting review",
+            ComplianceReviewConflict => "Reviewers can't review an institution they are staff of",
+        }
+    }
+}
+
+/// Body of every response, `data` is set on success, `error_code` and for
+/// validation failures `errors` on failure. `correlation_id` is also returned
+/// in the `X-Correlation-Id` header and tagged on the server logs.
+#[derive(Serialize, Deserialize, Debug, Clone, TS)]
+#[ts(export)]
+pub struct ApiEnvelope<T> {
+    pub status_code: u16,
+    pub message: String,
+    pub data: Option<T>,
+    pub error_code: Option<ErrorCode>,
+    #[serde(default)]
+    pub errors: Vec<FieldError>,
+    pub correlation_id: Option<String>,
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone, TS)]
 #[ts(export)]
 pub struct ApiResponseData<T> {
@@ -24,24 +243,24 @@ pub struct ApiResponseData<T> {
 #[derive(Serialize, Deserialize, Debug, Clone, TS)]
 #[ts(export)]
 pub struct ApiResponseError {
-    pub error_message: Option<String>,
-    pub message: Option<String>,
+    pub message: String,
+    pub status_code: u16,
+    pub error_code: Option<ErrorCode>,
+    #[serde(default)]
+    pub errors: Vec<FieldError>,
+    pub correlation_id: Option<String>,
 }
 
-impl Into<ErrorMessage> for ApiResponseError {
-    fn into(self) -> ErrorMessage {
-        if let Some(message) = self.error_message {
-            return ErrorMessage(message);
-        }
-
-        if let Some(message) = self.message {
-            return ErrorMessage(message);
+impl From<ApiResponseError> for ErrorMessage {
+    fn from(error: ApiResponseError) -> Self {
+        ErrorMessage {
+            message: error.message,
+            error_code: error.error_code,
         }
-
-        return ErrorMessage("#Error".into());
     }
 }
 
+/// A page of `data` in the same envelope as every other response
 #[derive(Serialize, Deserialize, Debug, Clone, TS)]
 #[ts(export)]
 pub struct PaginatedResult<T> {
@@ -49,8 +268,12 @@ pub struct PaginatedResult<T> {
     pub page_number: i32,
     pub total: i64,
     pub data: Vec<T>,
-    pub status: u16,
-    pub error_message: Option<String>,
+    pub status_code: u16,
+    pub message: String,
+    pub error_code: Option<ErrorCode>,
+    #[serde(default)]
+    pub errors: Vec<FieldError>,
+    pub correlation_id: Option<String>,
 }
 
 impl<T> Default for PaginatedResult<T> {
@@ -60,8 +283,27 @@ impl<T> Default for PaginatedResult<T> {
             page_number: 0,
             total: 0,
             data: vec![],
-            status: 500,
-            error_message: None,
+            status_code: 500,
+            message: ErrorCode::InternalError.message().into(),
+            error_code: Some(ErrorCode::InternalError),
+            errors: vec![],
+            correlation_id: None,
+        }
+    }
+}
+
+impl<T> PaginatedResult<T> {
+    pub fn new(data: Vec<T>, total: i64, query: &PaginatedQuery) -> Self {
+        Self {
+            page_size: query.limit(),
+            page_number: query.page(),
+            total,
+            data,
+            status_code: StatusCode::OK.as_u16(),
+            message: "Successful!".into(),
+            error_code: None,
+            errors: vec![],
+            correlation_id: None,
         }
     }
 }
@@ -72,10 +314,53 @@ impl<T: Serialize> Into<serde_json::Value> for PaginatedResult<T> {
     }
 }
 
+pub const DEFAULT_PAGE_SIZE: i32 = 20;
+pub const MAX_PAGE_SIZE: i32 = 100;
+
+#[derive(Serialize, Deserialize, Debug, TS, Default, Clone, Copy, PartialEq, Eq)]
+#[ts(export)]
+#[serde(rename_all = "lowercase")]
+pub enum SortDirection {
+    #[default]
+    Asc,
+    Desc,
+}
+
 #[derive(Serialize, Deserialize, Debug, TS, Default, Clone)]
 #[ts(export)]
 pub struct PaginatedQuery {
     pub query: Option<String>,
     pub page_size: Option<i32>,
     pub page_number: Option<i32>,
+    pub sort_by: Option<String>,
+    pub sort_direction: Option<SortDirection>,
+}
+
+impl PaginatedQuery {
+    /// page size clamped to `1..=MAX_PAGE_SIZE`, defaults to `DEFAULT_PAGE_SIZE`
+    pub fn limit(&self) -> i32 {
+        self.page_size
+            .unwrap_or(DEFAULT_PAGE_SIZE)
+            .clamp(1, MAX_PAGE_SIZE)
+    }
+
+    /// page numbers are 1-based
+    pub fn page(&self) -> i32 {
+        self.page_number.unwrap_or(1).max(1)
+    }
+
+    pub fn offset(&self) -> i64 {
+        (self.page() as i64 - 1) * self.limit() as i64
+    }
+
+    pub fn is_descending(&self) -> bool {
+        self.sort_direction == Some(SortDirection::Desc)
+    }
+
+    pub fn search(&self) -> Option<String> {
+        self.query
+            .as_ref()
+            .map(|q| q.trim().to_owned())
+            .filter(|q| !q.is_empty())
+    }
 }
@@ -1,9 +1,11 @@
 use bon::Builder;
+use chrono::{DateTime, NaiveDate, Utc};
 use derive_more::{Display, FromStr};
 use serde::{Deserialize, Serialize};
 use ts_rs::TS;
 
 use crate::institution_params::{StaffId, StaffShadowId};
+use crate::validation::{is_bank_code, is_license_no, is_rc_number, is_tin, Validate, ValidationCode, ValidationErrors};
 
 #[derive(Debug, Clone, Serialize, Deserialize, TS)]
 #[ts(export)]
@@ -11,6 +13,9 @@ pub struct ComplianceEvaluation {
     pub compliance_status: ComplianceStatus,
     pub compliance_message: String,
     pub rejected: Vec<String>,
+    // latest review of each rejected section
+    #[serde(default)]
+    pub rejections: Vec<ComplianceReviewDto>,
 }
 
 #[derive(Debug, Clone, Display, FromStr, PartialEq, Eq, Serialize, Deserialize, TS)]
@@ -40,6 +45,7 @@ pub struct CorporateComplianceDto {
     pub corporate_bank_code: String,
     pub private_healthcare_certificate_url: Option<String>,
     pub stage: String,
+    pub name_matches: Vec<NameMatchDto>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Builder, Clone, TS)]
@@ -63,6 +69,29 @@ pub struct FinancialComplianceDto {
     pub director_legal_gov_id_type: String,
     pub director_legal_gov_id_url: String,
     pub stage: String,
+    pub name_matches: Vec<NameMatchDto>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[ts(export)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum NameMatchOutcome {
+    Matched,
+    /// left for the reviewer to confirm
+    Review,
+    Blocked,
+}
+
+/// How closely the name a lookup returned for `field` matches the `against` name, scored 0 to 100
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct NameMatchDto {
+    pub field: String,
+    pub name: String,
+    pub against: String,
+    pub against_name: String,
+    pub score: u8,
+    pub outcome: NameMatchOutcome,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, TS)]
@@ -75,6 +104,22 @@ pub struct CorporateComplianceEdit {
     pub private_healthcare_certificate_url: Option<String>,
 }
 
+impl Validate for CorporateComplianceEdit {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::new();
+        errors.field("rc_no", &self.rc_no).required().max_length(50)
+            .format(is_rc_number, "a CAC registration number like RC123456");
+        errors.field("tin", &self.tin).required().max_length(50)
+            .format(is_tin, "a TIN like 12345678-0001 or a 10 digit TIN");
+        errors.field("corporate_account_number", &self.corporate_account_number).required().digits(10);
+        errors.field("corporate_bank_code", &self.corporate_bank_code).required()
+            .format(is_bank_code, "a 3 to 6 digit bank code");
+        errors.field("private_healthcare_certificate_url", self.private_healthcare_certificate_url.as_deref().unwrap_or_default())
+            .max_length(255);
+        errors.into_result()
+    }
+}
+
 #[derive(Debug, Clone, TS)]
 #[ts(export)]
 pub struct NewComplainceEdit(pub StaffShadowId, pub CorporateComplianceEdit);
@@ -90,6 +135,19 @@ pub struct HealthcareComplianceEdit {
     pub licensed_medical_doctor_phone_no: String,
 }
 
+impl Validate for HealthcareComplianceEdit {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::new();
+        errors.field("licensed_medical_doctor_name", &self.licensed_medical_doctor_name).required().max_length(100);
+        errors.field("licensed_medical_doctor_mdcn_no", &self.licensed_medical_doctor_mdcn_no).required().max_length(100);
+        errors.field("licensed_medical_doctor_mdcn_speciality", &self.licensed_medical_doctor_mdcn_speciality).required().max_length(255);
+        errors.field("licensed_medical_doctor_mdcn_image_url", &self.licensed_medical_doctor_mdcn_image_url).required().max_length(255);
+        errors.field("licensed_medical_doctor_email", &self.licensed_medical_doctor_email).required().max_length(255).email();
+        errors.field("licensed_medical_doctor_phone_no", &self.licensed_medical_doctor_phone_no).required().max_length(255);
+        errors.into_result()
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct NewHealthcareComplainceEdit(pub StaffShadowId, pub HealthcareComplianceEdit);
 
@@ -104,5 +162,319 @@ pub struct FinancialComplianceEdit {
     
 }
 
+impl Validate for FinancialComplianceEdit {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::new();
+        errors.field("director_legal_name", &self.director_legal_name).required().max_length(100);
+        errors.field("director_legal_bvn", &self.director_legal_bvn).required().digits(11);
+        errors.field("director_legal_dob", &self.director_legal_dob).required().date();
+        errors.field("director_legal_gov_id_type", &self.director_legal_gov_id_type).required().max_length(255);
+        errors.field("director_legal_gov_id_url", &self.director_legal_gov_id_url).required().max_length(255);
+        errors.into_result()
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct NewinancialComplainceEdit(pub StaffShadowId, pub FinancialComplianceEdit);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[ts(export)]
+#[serde(rename_all = "lowercase")]
+pub enum ComplianceSection {
+    Corporate,
+    Financial,
+    Healthcare,
+    /// the licenses of the staffs, only found in the history
+    License,
+}
+
+impl ComplianceSection {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ComplianceSection::Corporate => "corporate",
+            ComplianceSection::Financial => "financial",
+            ComplianceSection::Healthcare => "healthcare",
+            ComplianceSection::License => "license",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        [ComplianceSection::Corporate, ComplianceSection::Financial, ComplianceSection::Healthcare, ComplianceSection::License]
+            .into_iter()
+            .find(|s| s.as_str().eq_ignore_ascii_case(value))
+    }
+
+    /// fields a reviewer can give a rejection reason for
+    pub fn fields(&self) -> &'static [&'static str] {
+        match self {
+            ComplianceSection::Corporate => &["rc_no", "tin", "corporate_account_number", "corporate_bank_code", "private_healthcare_certificate_url"],
+            ComplianceSection::Financial => &["director_legal_name", "director_legal_bvn", "director_legal_dob", "director_legal_gov_id_type", "director_legal_gov_id_url"],
+            ComplianceSection::Healthcare => &["licensed_medical_doctor_name", "licensed_medical_doctor_mdcn_no", "licensed_medical_doctor_mdcn_speciality",
+                "licensed_medical_doctor_mdcn_image_url", "licensed_medical_doctor_email", "licensed_medical_doctor_phone_no"],
+            ComplianceSection::License => &["license_type", "license_no", "license_certificate_url", "expires_at"],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub enum ComplianceReviewDecision {
+    Approve,
+    Reject,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct ComplianceFieldReason {
+    pub field: String,
+    pub reason: String,
+}
+
+/// A rejection needs a reason for at least one field of the section, reasons
+/// given with an approval are kept as notes.
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct ComplianceReviewReq {
+    pub decision: ComplianceReviewDecision,
+    #[serde(default)]
+    pub reasons: Vec<ComplianceFieldReason>,
+}
+
+impl Validate for ComplianceReviewReq {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::new();
+        if self.decision == ComplianceReviewDecision::Reject && self.reasons.is_empty() {
+            errors.add("reasons", ValidationCode::Required, "reasons are required to reject a section".into());
+        }
+        for (i, reason) in self.reasons.iter().enumerate() {
+            errors.field(&format!("reasons[{}].field", i), &reason.field).required();
+            errors.field(&format!("reasons[{}].reason", i), &reason.reason).required().max_length(500);
+        }
+        errors.into_result()
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct ComplianceReviewDto {
+    pub id: String,
+    pub section: ComplianceSection,
+    pub decision: ComplianceStatus,
+    pub reasons: Vec<ComplianceFieldReason>,
+    pub reviewed_by: String,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Institution with a section awaiting review, the stages are None for
+/// sections not provided yet
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct ComplianceQueueItemDto {
+    pub institution_id: String,
+    pub institution_name: String,
+    pub institution_email: String,
+    pub compliance_status: String,
+    pub corporate_stage: Option<String>,
+    pub financial_stage: Option<String>,
+    pub healthcare_stage: Option<String>,
+    pub submitted_at: Option<DateTime<Utc>>,
+    /// a looked up name only partly matches and needs the reviewer's attention
+    pub name_match_review: bool,
+}
+
+/// short lived link to a document uploaded with a section
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct ComplianceDocumentDto {
+    pub section: ComplianceSection,
+    pub field: String,
+    pub url: String,
+}
+
+/// What a reviewer sees of an institution, reviews are newest first
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct ComplianceReviewDetailsDto {
+    pub institution_id: String,
+    pub institution_name: String,
+    pub institution_email: String,
+    pub compliance: ComplianceResponse,
+    pub documents: Vec<ComplianceDocumentDto>,
+    pub reviews: Vec<ComplianceReviewDto>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct ComplianceFieldChange {
+    pub field: String,
+    pub from: Option<String>,
+    pub to: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[ts(export)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ComplianceHistoryAction {
+    Created,
+    Edited,
+    StatusChanged,
+}
+
+impl ComplianceHistoryAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ComplianceHistoryAction::Created => "CREATED",
+            ComplianceHistoryAction::Edited => "EDITED",
+            ComplianceHistoryAction::StatusChanged => "STATUS_CHANGED",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        [ComplianceHistoryAction::Created, ComplianceHistoryAction::Edited, ComplianceHistoryAction::StatusChanged]
+            .into_iter()
+            .find(|a| a.as_str().eq_ignore_ascii_case(value))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[ts(export)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ComplianceActorType {
+    Staff,
+    PlatformStaff,
+}
+
+impl ComplianceActorType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ComplianceActorType::Staff => "STAFF",
+            ComplianceActorType::PlatformStaff => "PLATFORM_STAFF",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        [ComplianceActorType::Staff, ComplianceActorType::PlatformStaff]
+            .into_iter()
+            .find(|a| a.as_str().eq_ignore_ascii_case(value))
+    }
+}
+
+/// A change of a compliance section, `actor` is the shadow id of the staff or
+/// platform staff and `lookup` the identity provider result backing the change
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct ComplianceHistoryDto {
+    pub id: String,
+    pub section: ComplianceSection,
+    pub action: ComplianceHistoryAction,
+    pub actor: String,
+    pub actor_type: ComplianceActorType,
+    pub changes: Vec<ComplianceFieldChange>,
+    pub lookup: Option<serde_json::Value>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Councils licensing the professions of healthcare staff
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[ts(export)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum LicenseType {
+    /// Medical and Dental Council of Nigeria
+    Mdcn,
+    /// Pharmacists Council of Nigeria
+    Pcn,
+    /// Nursing and Midwifery Council of Nigeria
+    Nmcn,
+    /// Radiographers Registration Board of Nigeria
+    Rrbn,
+    /// Medical Laboratory Science Council of Nigeria
+    Mlscn,
+}
+
+pub const LICENSE_TYPES: [&str; 5] = ["MDCN", "PCN", "NMCN", "RRBN", "MLSCN"];
+
+impl LicenseType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LicenseType::Mdcn => "MDCN",
+            LicenseType::Pcn => "PCN",
+            LicenseType::Nmcn => "NMCN",
+            LicenseType::Rrbn => "RRBN",
+            LicenseType::Mlscn => "MLSCN",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        [LicenseType::Mdcn, LicenseType::Pcn, LicenseType::Nmcn, LicenseType::Rrbn, LicenseType::Mlscn]
+            .into_iter()
+            .find(|t| t.as_str().eq_ignore_ascii_case(value))
+    }
+}
+
+/// `expires_at` is `YYYY-MM-DD`, a verified license takes the expiry date of the registry
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct StaffLicenseEdit {
+    pub license_type: String,
+    pub license_no: String,
+    pub license_certificate_url: String,
+    pub expires_at: String,
+}
+
+impl Validate for StaffLicenseEdit {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::new();
+        errors.field("license_type", &self.license_type).required().one_of(&LICENSE_TYPES);
+        errors.field("license_no", &self.license_no).required().max_length(100)
+            .format(is_license_no, "made of letters, digits, / and -");
+        errors.field("license_certificate_url", &self.license_certificate_url).required().max_length(255);
+        errors.field("expires_at", &self.expires_at).required().date();
+        errors.into_result()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[ts(export)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum LicenseExpiry {
+    Valid,
+    ExpiringSoon,
+    Expired,
+    Unknown,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct StaffLicenseDto {
+    pub id: String,
+    pub license_type: LicenseType,
+    pub license_no: Option<String>,
+    pub license_certificate_url: Option<String>,
+    pub expires_at: Option<NaiveDate>,
+    pub expiry: LicenseExpiry,
+    pub stage: String,
+    pub modified_at: DateTime<Utc>,
+}
+
+/// A staff on the license board, `compliant` when one of the licenses is
+/// verified and hasn't expired
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct StaffLicenseBoardItemDto {
+    pub staff_id: String,
+    pub first_name: String,
+    pub last_name: String,
+    pub title: String,
+    pub compliant: bool,
+    pub licenses: Vec<StaffLicenseDto>,
+}
+
+/// Identity lookups of an institution in a month, `billable` ones reached the provider
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct VerificationUsageDto {
+    pub month: String,
+    pub lookup_type: String,
+    pub lookups: i64,
+    pub billable: i64,
+}
@@ -3,6 +3,11 @@ use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use ts_rs::TS;
 
+use crate::validation::{Validate, ValidationCode, ValidationErrors};
+
+pub const INSTITUTION_CLASSIFICATIONS: [&str; 3] = ["PRIMARY", "SECONDARY", "TERTIARY"];
+pub const INSTITUTION_SETTINGS: [&str; 2] = ["PRIVATE", "PUBLIC"];
+
 #[derive(Serialize, Deserialize, Debug, Clone, TS)]
 #[ts(export)]
 pub struct InstitutionDto {
@@ -24,6 +29,7 @@ pub struct InstitutionDto {
 pub struct AuthenticatedUser {
     pub principal: AuthorizedUser,
     pub token: Option<String>,
+    pub refresh_token: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, Builder, TS)]
@@ -44,6 +50,10 @@ pub struct AuthorizedInstitutionUser {
     pub profile_image_url: Option<String>,
     pub departments: Vec<DepartmentDto>,
     pub institution: InstitutionDto,
+    // union of the actions granted by the staff's roles across departments
+    #[serde(default)]
+    #[builder(default)]
+    pub permissions: Vec<PermittedAction>,
 }
 
 impl AuthorizedInstitutionUser {
@@ -51,6 +61,10 @@ impl AuthorizedInstitutionUser {
         false
         // self.departments.iter().any(|d| ADMIN_DEPT.eq_ignore_ascii_case(&d.name))
     }
+
+    pub fn is_permitted(&self, action: PermittedAction) -> bool {
+        self.permissions.contains(&action)
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, Copy, TS)]
@@ -94,6 +108,74 @@ pub struct VerifyOTP {
     pub session_id: String,
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone, TS)]
+#[ts(export)]
+pub struct ForgotPasswordReq {
+    pub phone_number: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, TS)]
+#[ts(export)]
+pub struct UnlockAccountReq {
+    pub phone_number: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, TS)]
+#[ts(export)]
+pub struct ResetPasswordReq {
+    pub otp_code: String,
+    pub session_id: String,
+    pub new_password: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, TS)]
+#[ts(export)]
+pub struct ChangePasswordReq {
+    pub current_password: String,
+    pub new_password: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, TS)]
+#[ts(export)]
+pub struct TotpCodeReq {
+    // a code from the authenticator app or an unused recovery code
+    pub code: String,
+}
+
+// the password is asked again before the authenticator app is changed
+#[derive(Serialize, Deserialize, Debug, Clone, TS)]
+#[ts(export)]
+pub struct TotpEnrollReq {
+    pub password: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, TS)]
+#[ts(export)]
+pub struct DisableTotpReq {
+    pub password: String,
+    // a code from the authenticator app or an unused recovery code
+    pub code: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, TS)]
+#[ts(export)]
+pub struct TotpEnrollmentDto {
+    pub secret: String,
+    pub otpauth_url: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, TS)]
+#[ts(export)]
+pub struct TotpRecoveryCodesDto {
+    pub recovery_codes: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, TS)]
+#[ts(export)]
+pub struct RefreshTokenReq {
+    pub refresh_token: String,
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone, Builder, TS)]
 #[ts(export)]
 pub struct InitiatedOtp {
@@ -122,6 +204,9 @@ pub struct LoginReq {
 #[ts(export)]
 pub struct LoginResponse {
     pub otp: Option<InitiatedOtp>,
+    // the login is completed with a code from the authenticator app instead of an SMS OTP
+    #[serde(default)]
+    pub totp: Option<InitiatedOtp>,
     pub auth: Option<AuthenticatedUser>,
 }
 
@@ -133,7 +218,7 @@ pub struct DepartmentId(pub i64);
 #[ts(export)]
 pub struct DepartmentShadowId(pub String);
 
-#[derive(Serialize, Deserialize, Debug, Clone, Copy, TS)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, TS)]
 #[ts(export)]
 pub enum BasePermission {
     Create,
@@ -142,14 +227,14 @@ pub enum BasePermission {
     Delete,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone, Copy, TS)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, TS)]
 #[ts(export)]
 pub enum FinancialPermission {
     ViewReports,
     Withdraw,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone, Copy, TS)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, TS)]
 #[ts(export)]
 pub enum PermittedAction {
     MedicalHistory(BasePermission),
@@ -161,6 +246,59 @@ pub enum PermittedAction {
     FinancialManagement(FinancialPermission),
 }
 
+impl PermittedAction {
+    // every action, granted to the Superuser role and Admin department members
+    pub fn all() -> Vec<PermittedAction> {
+        let base_permissions = [
+            BasePermission::Create,
+            BasePermission::View,
+            BasePermission::Edit,
+            BasePermission::Delete,
+        ];
+        let mut actions = vec![];
+        for p in base_permissions {
+            actions.push(PermittedAction::MedicalHistory(p));
+            actions.push(PermittedAction::Labouratory(p));
+            actions.push(PermittedAction::Billing(p));
+            actions.push(PermittedAction::InstitutionSetting(p));
+            actions.push(PermittedAction::PersonnelManagement(p));
+            actions.push(PermittedAction::DepartmentManagement(p));
+        }
+        actions.push(PermittedAction::FinancialManagement(
+            FinancialPermission::ViewReports,
+        ));
+        actions.push(PermittedAction::FinancialManagement(
+            FinancialPermission::Withdraw,
+        ));
+        actions
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Builder, TS)]
+#[ts(export)]
+pub struct RoleDto {
+    pub id: String,
+    pub name: String,
+    pub permissions: Vec<PermittedAction>,
+    pub modified_at: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Builder, TS)]
+#[ts(export)]
+pub struct CreateRole {
+    pub name: String,
+    pub permissions: Vec<PermittedAction>,
+}
+
+impl Validate for CreateRole {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::new();
+        errors.field("name", &self.name).required().max_length(100);
+        errors.into_result()
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, Builder, Clone, TS)]
 #[ts(export)]
 pub struct DepartmentMember {
@@ -168,6 +306,34 @@ pub struct DepartmentMember {
     pub role: String,
 }
 
+impl DepartmentMember {
+    fn check(&self, errors: &mut ValidationErrors, prefix: &str) {
+        let staff_id = format!("{}staff_id", prefix);
+        errors
+            .field(&staff_id, &self.staff_id)
+            .required()
+            .max_length(40);
+        let role = format!("{}role", prefix);
+        errors.field(&role, &self.role).required().max_length(100);
+    }
+}
+
+impl Validate for DepartmentMember {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::new();
+        self.check(&mut errors, "");
+        errors.into_result()
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Builder, Clone, TS)]
+#[ts(export)]
+pub struct DepartmentMemberDto {
+    pub staff_id: String,
+    pub role: String,
+    pub joined_at: DateTime<Utc>,
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone, Builder, TS)]
 #[ts(export)]
 pub struct CreateDepartment {
@@ -178,7 +344,31 @@ pub struct CreateDepartment {
     pub staff_ids: Vec<DepartmentMember>,
 }
 
-#[derive(Serialize, Deserialize, Debug, TS)]
+impl Validate for CreateDepartment {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::new();
+        errors.field("name", &self.name).required().max_length(100);
+        errors
+            .field("domain", &self.domain)
+            .required()
+            .max_length(100);
+        errors
+            .field(
+                "head_staff_id",
+                self.head_staff_id.as_deref().unwrap_or_default(),
+            )
+            .max_length(40);
+        errors
+            .field("phone_no", self.phone_no.as_deref().unwrap_or_default())
+            .max_length(30);
+        for (index, member) in self.staff_ids.iter().enumerate() {
+            member.check(&mut errors, &format!("staff_ids[{}].", index));
+        }
+        errors.into_result()
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, TS)]
 #[ts(export)]
 pub struct NewStaff {
     pub first_name: String,
@@ -188,7 +378,146 @@ pub struct NewStaff {
     pub profile_image: Option<String>,
 }
 
-#[derive(Serialize, Deserialize, Debug, Builder, TS)]
+// lengths follow the staffs columns
+impl Validate for NewStaff {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::new();
+        errors
+            .field("first_name", &self.first_name)
+            .required()
+            .max_length(70);
+        errors
+            .field("last_name", &self.last_name)
+            .required()
+            .max_length(70);
+        errors
+            .field("mobile", &self.mobile)
+            .required()
+            .max_length(30);
+        errors.field("title", &self.title).required().max_length(70);
+        errors
+            .field(
+                "profile_image",
+                self.profile_image.as_deref().unwrap_or_default(),
+            )
+            .max_length(255);
+        errors.into_result()
+    }
+}
+
+/// CSV or XLSX sheet of staffs, the format is picked from the file
+/// extension or content type. Columns: first_name, last_name, mobile,
+/// title and optionally department and role.
+#[derive(Serialize, Deserialize, Debug, Clone, TS)]
+#[ts(export)]
+pub struct StaffImportUpload {
+    pub file_name: Option<String>,
+    pub content_type: Option<String>,
+    pub base64_data: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, TS)]
+#[ts(export)]
+pub enum StaffImportRowStatus {
+    Valid,
+    Invalid,
+    Imported,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, TS)]
+#[ts(export)]
+pub struct StaffImportRow {
+    /// line of the row in the uploaded sheet, the header is line 1
+    pub row_number: u32,
+    pub first_name: String,
+    pub last_name: String,
+    pub mobile: String,
+    pub title: String,
+    pub department: Option<String>,
+    pub role: Option<String>,
+    pub status: StaffImportRowStatus,
+    pub errors: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, TS)]
+#[ts(export)]
+pub struct StaffImportReport {
+    pub dry_run: bool,
+    pub total_rows: u32,
+    pub valid_rows: u32,
+    pub invalid_rows: u32,
+    /// imported rows whose invitation SMS could not be sent, the invitation
+    /// can be re-sent from the pending invitations
+    pub failed_invites: u32,
+    pub rows: Vec<StaffImportRow>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, TS)]
+#[ts(export)]
+pub enum InvitationStatus {
+    Pending,
+    Accepted,
+    Declined,
+    Revoked,
+    Expired,
+}
+
+impl InvitationStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            InvitationStatus::Pending => "Pending",
+            InvitationStatus::Accepted => "Accepted",
+            InvitationStatus::Declined => "Declined",
+            InvitationStatus::Revoked => "Revoked",
+            InvitationStatus::Expired => "Expired",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Builder, TS)]
+#[ts(export)]
+pub struct StaffInvitationDto {
+    pub id: String,
+    pub staff_id: String,
+    pub first_name: String,
+    pub last_name: String,
+    pub mobile: String,
+    pub status: InvitationStatus,
+    pub expires_at: DateTime<Utc>,
+    pub last_sent_at: DateTime<Utc>,
+    pub reminder_count: i32,
+    pub responded_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// What the invitee sees before accepting, `requires_password` is false
+/// when the mobile already has an account from another institution.
+#[derive(Serialize, Deserialize, Debug, Clone, Builder, TS)]
+#[ts(export)]
+pub struct InvitationDetailsDto {
+    pub institution_name: String,
+    pub first_name: String,
+    pub last_name: String,
+    pub title: String,
+    pub mobile: String,
+    pub requires_password: bool,
+    pub expires_at: DateTime<Utc>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, TS)]
+#[ts(export)]
+pub struct InvitationTokenReq {
+    pub token: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, TS)]
+#[ts(export)]
+pub struct AcceptInvitationReq {
+    pub token: String,
+    pub password: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Builder, TS)]
 #[ts(export)]
 pub struct CreateInstitution {
     pub institution_name: String,
@@ -207,3 +536,279 @@ pub struct CreateInstitution {
 
     pub logo: Option<String>,
 }
+
+impl Validate for CreateInstitution {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::new();
+        errors
+            .field("institution_name", &self.institution_name)
+            .required()
+            .max_length(255);
+        errors
+            .field("email", &self.email)
+            .required()
+            .max_length(100)
+            .email();
+        errors
+            .field("classification", &self.classification)
+            .required()
+            .one_of(&INSTITUTION_CLASSIFICATIONS);
+        errors
+            .field("setting", &self.setting)
+            .required()
+            .one_of(&INSTITUTION_SETTINGS);
+        errors
+            .field("address", self.address.as_deref().unwrap_or_default())
+            .max_length(100);
+        errors
+            .field("town", self.town.as_deref().unwrap_or_default())
+            .max_length(100);
+        errors
+            .field("state", self.state.as_deref().unwrap_or_default())
+            .max_length(50);
+
+        errors
+            .field("first_name", &self.first_name)
+            .required()
+            .max_length(70);
+        errors
+            .field("last_name", &self.last_name)
+            .required()
+            .max_length(70);
+        errors
+            .field("mobile", &self.mobile)
+            .required()
+            .max_length(30);
+        errors.field("title", &self.title).required().max_length(70);
+        errors.field("password", &self.password).required();
+
+        errors
+            .field("logo", self.logo.as_deref().unwrap_or_default())
+            .max_length(255);
+        errors.into_result()
+    }
+}
+
+/// Profile fields admins can change after registration, the email and
+/// workspace code stay fixed and the logo has its own upload endpoint.
+#[derive(Serialize, Deserialize, Debug, Clone, Builder, TS)]
+#[ts(export)]
+pub struct EditInstitution {
+    pub institution_name: String,
+    pub classification: String,
+    pub setting: String,
+    pub address: Option<String>,
+    pub town: Option<String>,
+    pub state: Option<String>,
+}
+
+impl Validate for EditInstitution {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::new();
+        errors
+            .field("institution_name", &self.institution_name)
+            .required()
+            .max_length(255);
+        errors
+            .field("classification", &self.classification)
+            .required()
+            .one_of(&INSTITUTION_CLASSIFICATIONS);
+        errors
+            .field("setting", &self.setting)
+            .required()
+            .one_of(&INSTITUTION_SETTINGS);
+        errors
+            .field("address", self.address.as_deref().unwrap_or_default())
+            .max_length(100);
+        errors
+            .field("town", self.town.as_deref().unwrap_or_default())
+            .max_length(100);
+        errors
+            .field("state", self.state.as_deref().unwrap_or_default())
+            .max_length(50);
+        errors.into_result()
+    }
+}
+
+pub const INSTITUTION_LOGO_CONTENT_TYPES: [&str; 3] = ["image/png", "image/jpeg", "image/webp"];
+
+/// The logo type read from the file's leading bytes, `None` for anything but
+/// the accepted image types whatever the upload claims to be.
+pub fn sniff_logo_content_type(content: &[u8]) -> Option<&'static str> {
+    match content {
+        [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A, ..] => Some("image/png"),
+        [0xFF, 0xD8, 0xFF, ..] => Some("image/jpeg"),
+        [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'E', b'B', b'P', ..] => Some("image/webp"),
+        _ => None,
+    }
+}
+
+/// base64 length of a 1MB file
+pub const INSTITUTION_LOGO_MAX_BASE64_LENGTH: usize = 1_398_104;
+
+#[derive(Serialize, Deserialize, Debug, Clone, TS)]
+#[ts(export)]
+pub struct InstitutionLogoUpload {
+    pub content_type: String,
+    pub base64_data: String,
+}
+
+impl Validate for InstitutionLogoUpload {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::new();
+        errors
+            .field("content_type", &self.content_type)
+            .required()
+            .one_of(&INSTITUTION_LOGO_CONTENT_TYPES);
+        errors.field("base64_data", &self.base64_data).required();
+        if self.base64_data.trim().len() > INSTITUTION_LOGO_MAX_BASE64_LENGTH {
+            errors.add(
+                "base64_data",
+                ValidationCode::TooLong,
+                "logo must be at most 1MB".into(),
+            );
+        }
+        errors.into_result()
+    }
+}
+
+/// `logo_url` is a short lived link to the logo, `created_by` the id of the
+/// staff that registered the institution.
+#[derive(Serialize, Deserialize, Debug, Clone, Builder, TS)]
+#[ts(export)]
+pub struct InstitutionProfileDto {
+    pub institution: InstitutionDto,
+    pub logo_url: Option<String>,
+    pub created_by: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub modified_at: DateTime<Utc>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, TS)]
+#[ts(export)]
+pub struct InstitutionFieldChange {
+    pub field: String,
+    pub from: Option<String>,
+    pub to: Option<String>,
+}
+
+/// One profile save, `changed_by` is empty for changes made by the platform
+#[derive(Serialize, Deserialize, Debug, Clone, Builder, TS)]
+#[ts(export)]
+pub struct InstitutionChangeDto {
+    pub id: String,
+    pub changed_by: Option<String>,
+    pub changed_by_name: Option<String>,
+    pub changes: Vec<InstitutionFieldChange>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// The admin's password confirms the deactivation
+#[derive(Serialize, Deserialize, Debug, Clone, TS)]
+#[ts(export)]
+pub struct DeactivateInstitutionReq {
+    pub password: String,
+}
+
+impl Validate for DeactivateInstitutionReq {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::new();
+        errors.field("password", &self.password).required();
+        errors.into_result()
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, TS)]
+#[ts(export)]
+pub enum InstitutionExportStatus {
+    Pending,
+    Ready,
+    Failed,
+}
+
+impl InstitutionExportStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            InstitutionExportStatus::Pending => "Pending",
+            InstitutionExportStatus::Ready => "Ready",
+            InstitutionExportStatus::Failed => "Failed",
+        }
+    }
+}
+
+/// Archive of all the institution's data, `download_url` is a short lived
+/// link set once the archive is ready.
+#[derive(Serialize, Deserialize, Debug, Clone, Builder, TS)]
+#[ts(export)]
+pub struct InstitutionExportDto {
+    pub id: String,
+    pub status: InstitutionExportStatus,
+    pub download_url: Option<String>,
+    pub completed_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// The institution's data is purged at `purge_at`, the export is emailed to
+/// the institution once it is ready.
+#[derive(Serialize, Deserialize, Debug, Clone, Builder, TS)]
+#[ts(export)]
+pub struct InstitutionDeactivationDto {
+    pub purge_at: DateTime<Utc>,
+    pub export: InstitutionExportDto,
+}
+
+/// Token of the export link emailed to the institution, it still works once
+/// the institution is deactivated.
+#[derive(Serialize, Deserialize, Debug, Clone, TS)]
+#[ts(export)]
+pub struct ExportDownloadReq {
+    pub token: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Builder, TS)]
+#[ts(export)]
+pub struct UserDeviceDto {
+    pub id: String,
+    pub name: String,
+    pub user_agent: Option<String>,
+    pub ip_address: Option<String>,
+    pub current: bool,
+    pub first_seen_at: DateTime<Utc>,
+    pub last_seen_at: DateTime<Utc>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, TS)]
+#[ts(export)]
+pub struct RenameDeviceReq {
+    pub name: String,
+}
+
+impl Validate for RenameDeviceReq {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::new();
+        errors.field("name", &self.name).required().max_length(100);
+        errors.into_result()
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Builder, TS)]
+#[ts(export)]
+pub struct SessionDto {
+    pub id: String,
+    pub device_id: String,
+    pub workspace_code: Option<String>,
+    pub current: bool,
+    pub created_at: DateTime<Utc>,
+    pub last_seen_at: DateTime<Utc>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Builder, TS)]
+#[ts(export)]
+pub struct WorkspaceDto {
+    pub workspace_code: String,
+    pub institution_id: String,
+    pub institution_name: String,
+    pub logo: Option<String>,
+    pub staff_id: String,
+    pub current: bool,
+}
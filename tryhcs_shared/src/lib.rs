@@ -4,6 +4,7 @@ pub mod encryption;
 pub mod finance_params;
 pub mod institution_params;
 pub mod records_param;
+pub mod validation;
 
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
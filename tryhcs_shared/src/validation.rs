@@ -0,0 +1,220 @@
+use std::fmt;
+
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, TS)]
+#[ts(export)]
+#[serde(rename_all = "snake_case")]
+pub enum ValidationCode {
+    Required,
+    TooLong,
+    InvalidEmail,
+    InvalidPhone,
+    InvalidFormat,
+    InvalidChoice,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, TS)]
+#[ts(export)]
+pub struct FieldError {
+    pub field: String,
+    pub code: ValidationCode,
+    pub message: String,
+}
+
+/// Every invalid field of a request body. Handlers return it as the error of
+/// their `eyre::Result` and `api_response` turns it into a 400 listing the fields.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq, TS)]
+#[ts(export)]
+pub struct ValidationErrors {
+    pub errors: Vec<FieldError>,
+}
+
+impl ValidationErrors {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn single(field: &str, code: ValidationCode, message: String) -> Self {
+        let mut errors = Self::new();
+        errors.add(field, code, message);
+        errors
+    }
+
+    pub fn add(&mut self, field: &str, code: ValidationCode, message: String) {
+        self.errors.push(FieldError {
+            field: field.to_owned(),
+            code,
+            message,
+        });
+    }
+
+    /// Checks on a single field, only the first failing check of the field is reported.
+    /// Empty values only fail `required`, so optional fields pass `unwrap_or_default()`.
+    pub fn field<'a>(&'a mut self, field: &'a str, value: &'a str) -> FieldCheck<'a> {
+        FieldCheck {
+            errors: self,
+            field,
+            value: value.trim(),
+            failed: false,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.errors.is_empty()
+    }
+
+    pub fn into_result(self) -> Result<(), ValidationErrors> {
+        if self.is_empty() {
+            Ok(())
+        } else {
+            Err(self)
+        }
+    }
+}
+
+impl fmt::Display for ValidationErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let messages: Vec<&str> = self.errors.iter().map(|e| e.message.as_str()).collect();
+        write!(f, "{}", messages.join("; "))
+    }
+}
+
+impl std::error::Error for ValidationErrors {}
+
+pub struct FieldCheck<'a> {
+    errors: &'a mut ValidationErrors,
+    field: &'a str,
+    value: &'a str,
+    failed: bool,
+}
+
+impl FieldCheck<'_> {
+    fn check(
+        mut self,
+        valid: impl FnOnce(&str) -> bool,
+        code: ValidationCode,
+        message: impl FnOnce(&str) -> String,
+    ) -> Self {
+        if !self.failed && !self.value.is_empty() && !valid(self.value) {
+            self.errors.add(self.field, code, message(self.field));
+            self.failed = true;
+        }
+        self
+    }
+
+    pub fn required(mut self) -> Self {
+        if !self.failed && self.value.is_empty() {
+            self.errors.add(
+                self.field,
+                ValidationCode::Required,
+                format!("{} is required", self.field),
+            );
+            self.failed = true;
+        }
+        self
+    }
+
+    pub fn max_length(self, max: usize) -> Self {
+        self.check(
+            |v| v.chars().count() <= max,
+            ValidationCode::TooLong,
+            |field| format!("{} must be at most {} characters", field, max),
+        )
+    }
+
+    pub fn email(self) -> Self {
+        self.check(is_valid_email, ValidationCode::InvalidEmail, |field| {
+            format!("{} is not a valid email address", field)
+        })
+    }
+
+    pub fn digits(self, len: usize) -> Self {
+        self.check(
+            |v| v.len() == len && v.chars().all(|c| c.is_ascii_digit()),
+            ValidationCode::InvalidFormat,
+            |field| format!("{} must be {} digits", field, len),
+        )
+    }
+
+    /// `YYYY-MM-DD`
+    pub fn date(self) -> Self {
+        self.check(
+            |v| NaiveDate::parse_from_str(v, "%Y-%m-%d").is_ok(),
+            ValidationCode::InvalidFormat,
+            |field| format!("{} must be a date formatted as YYYY-MM-DD", field),
+        )
+    }
+
+    pub fn format(self, valid: fn(&str) -> bool, expected: &str) -> Self {
+        self.check(valid, ValidationCode::InvalidFormat, |field| {
+            format!("{} must be {}", field, expected)
+        })
+    }
+
+    /// case-insensitive
+    pub fn one_of(self, choices: &[&str]) -> Self {
+        self.check(
+            |v| choices.iter().any(|c| c.eq_ignore_ascii_case(v)),
+            ValidationCode::InvalidChoice,
+            |field| format!("{} must be one of: {}", field, choices.join(", ")),
+        )
+    }
+}
+
+pub trait Validate {
+    fn validate(&self) -> Result<(), ValidationErrors>;
+}
+
+pub fn is_valid_email(value: &str) -> bool {
+    let Some((local, domain)) = value.split_once('@') else {
+        return false;
+    };
+    !local.is_empty()
+        && !domain.contains('@')
+        && !value.chars().any(char::is_whitespace)
+        && domain
+            .split('.')
+            .all(|label| !label.is_empty() && !label.starts_with('-') && !label.ends_with('-'))
+        && domain.contains('.')
+}
+
+/// CAC registration numbers, with an optional RC, BN or IT prefix
+pub fn is_rc_number(value: &str) -> bool {
+    let upper = value.to_ascii_uppercase();
+    let number = ["RC", "BN", "IT"]
+        .iter()
+        .find_map(|prefix| upper.strip_prefix(prefix))
+        .map(|rest| rest.trim_start_matches([' ', '-']))
+        .unwrap_or(&upper);
+    (1..=8).contains(&number.len()) && number.chars().all(|c| c.is_ascii_digit())
+}
+
+/// FIRS TINs (`12345678-0001`) and the 10 digit JTB TINs
+pub fn is_tin(value: &str) -> bool {
+    match value.split_once('-') {
+        Some((first, second)) => {
+            first.len() == 8
+                && second.len() == 4
+                && first
+                    .chars()
+                    .chain(second.chars())
+                    .all(|c| c.is_ascii_digit())
+        }
+        None => value.len() == 10 && value.chars().all(|c| c.is_ascii_digit()),
+    }
+}
+
+/// bank codes are 3 digits for commercial banks and up to 6 for microfinance banks
+pub fn is_bank_code(value: &str) -> bool {
+    (3..=6).contains(&value.len()) && value.chars().all(|c| c.is_ascii_digit())
+}
+
+/// council license numbers, e.g. `MDCN/12345` or `RN-2019-0042`
+pub fn is_license_no(value: &str) -> bool {
+    value
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '/' || c == '-')
+}
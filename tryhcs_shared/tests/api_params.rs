@@ -0,0 +1,21 @@
+use tryhcs_shared::api_params::{ApiResponseError, ErrorCode, ErrorMessage};
+
+#[test]
+fn api_errors_keep_their_code() {
+    let response: ApiResponseError = serde_json::from_str(
+        r#"{"message":"OTP has expired","status_code":400,"error_code":"OTP_EXPIRED","correlation_id":"abc"}"#,
+    )
+    .unwrap();
+    let error = ErrorMessage::from(response);
+
+    assert_eq!(error.message, "OTP has expired");
+    assert_eq!(error.error_code, Some(ErrorCode::OtpExpired));
+}
+
+#[test]
+fn client_errors_have_no_code() {
+    let error = ErrorMessage::from("Staff not found");
+
+    assert_eq!(error.message, "Staff not found");
+    assert_eq!(error.error_code, None);
+}
@@ -0,0 +1,173 @@
+use tryhcs_shared::{
+    compliance_params::{
+        ComplianceFieldReason, ComplianceReviewDecision, ComplianceReviewReq,
+        CorporateComplianceEdit, FinancialComplianceEdit,
+    },
+    institution_params::{
+        sniff_logo_content_type, EditInstitution, InstitutionLogoUpload, NewStaff,
+    },
+    validation::{is_license_no, is_rc_number, is_tin, is_valid_email, Validate, ValidationCode},
+};
+
+fn new_staff() -> NewStaff {
+    NewStaff {
+        first_name: "Ada".into(),
+        last_name: "Obi".into(),
+        mobile: "+2348149464289".into(),
+        title: "Nurse".into(),
+        profile_image: None,
+    }
+}
+
+#[test]
+fn valid_staff_passes() {
+    assert!(new_staff().validate().is_ok());
+}
+
+#[test]
+fn reports_each_invalid_field_once() {
+    let staff = NewStaff {
+        first_name: " ".into(),
+        last_name: "O".repeat(71),
+        ..new_staff()
+    };
+
+    let invalid = staff.validate().unwrap_err();
+    let fields: Vec<(&str, ValidationCode)> = invalid
+        .errors
+        .iter()
+        .map(|e| (e.field.as_str(), e.code))
+        .collect();
+    assert_eq!(
+        fields,
+        vec![
+            ("first_name", ValidationCode::Required),
+            ("last_name", ValidationCode::TooLong)
+        ]
+    );
+}
+
+#[test]
+fn validates_compliance_formats() {
+    let corporate = CorporateComplianceEdit {
+        rc_no: "RC-1234567".into(),
+        tin: "12345678-0001".into(),
+        corporate_account_number: "0123456789".into(),
+        corporate_bank_code: "058".into(),
+        private_healthcare_certificate_url: None,
+    };
+    assert!(corporate.validate().is_ok());
+
+    let financial = FinancialComplianceEdit {
+        director_legal_name: "Ada Obi".into(),
+        director_legal_bvn: "2234567890".into(),
+        director_legal_dob: "12/04/1980".into(),
+        director_legal_gov_id_type: "NIN".into(),
+        director_legal_gov_id_url: "uploads/nin.png".into(),
+    };
+    let invalid = financial.validate().unwrap_err();
+    let fields: Vec<&str> = invalid.errors.iter().map(|e| e.field.as_str()).collect();
+    assert_eq!(fields, vec!["director_legal_bvn", "director_legal_dob"]);
+}
+
+#[test]
+fn rejects_unknown_institution_settings() {
+    let edit = EditInstitution {
+        institution_name: "St. Mary Clinic".into(),
+        classification: "primary".into(),
+        setting: "Mixed".into(),
+        address: None,
+        town: Some("Ikeja".into()),
+        state: Some("Lagos".into()),
+    };
+    let invalid = edit.validate().unwrap_err();
+
+    assert_eq!(invalid.errors.len(), 1);
+    assert_eq!(invalid.errors[0].field, "setting");
+    assert_eq!(invalid.errors[0].code, ValidationCode::InvalidChoice);
+}
+
+#[test]
+fn limits_institution_logo_types_and_size() {
+    let logo = |content_type: &str, length: usize| InstitutionLogoUpload {
+        content_type: content_type.into(),
+        base64_data: "A".repeat(length),
+    };
+
+    assert!(logo("image/png", 1_000).validate().is_ok());
+    let invalid = logo("image/svg+xml", 1_000).validate().unwrap_err();
+    assert_eq!(invalid.errors[0].field, "content_type");
+    let invalid = logo("image/webp", 1_400_000).validate().unwrap_err();
+    assert_eq!(invalid.errors[0].field, "base64_data");
+    assert_eq!(invalid.errors[0].code, ValidationCode::TooLong);
+}
+
+#[test]
+fn sniffs_logo_type_from_content() {
+    let png = [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A, 0, 0];
+    assert_eq!(sniff_logo_content_type(&png), Some("image/png"));
+    assert_eq!(
+        sniff_logo_content_type(&[0xFF, 0xD8, 0xFF, 0xE0]),
+        Some("image/jpeg")
+    );
+    assert_eq!(
+        sniff_logo_content_type(b"RIFF\x10\0\0\0WEBPVP8 "),
+        Some("image/webp")
+    );
+    // an SVG or script uploaded as a PNG
+    assert_eq!(
+        sniff_logo_content_type(b"<svg xmlns=\"http://www.w3.org/2000/svg\">"),
+        None
+    );
+    assert_eq!(sniff_logo_content_type(b"RIFF\x10\0\0\0WAVEfmt "), None);
+    assert_eq!(sniff_logo_content_type(&[]), None);
+}
+
+#[test]
+fn checks_identifier_formats() {
+    assert!(is_valid_email("admin@clinic.com.ng"));
+    assert!(!is_valid_email("admin@clinic"));
+    assert!(!is_valid_email("admin clinic@mail.com"));
+
+    assert!(is_rc_number("1234567"));
+    assert!(is_rc_number("bn 1234"));
+    assert!(!is_rc_number("RC"));
+
+    assert!(is_tin("1234567890"));
+    assert!(!is_tin("1234-5678"));
+
+    assert!(is_license_no("MDCN/12345"));
+    assert!(is_license_no("RN-2019-0042"));
+    assert!(!is_license_no("../../admin"));
+    assert!(!is_license_no("12345?expand=all"));
+}
+
+#[test]
+fn requires_reasons_to_reject_compliance() {
+    let approval = ComplianceReviewReq {
+        decision: ComplianceReviewDecision::Approve,
+        reasons: vec![],
+    };
+    assert!(approval.validate().is_ok());
+
+    let rejection = ComplianceReviewReq {
+        decision: ComplianceReviewDecision::Reject,
+        reasons: vec![],
+    };
+    let invalid = rejection.validate().unwrap_err();
+    assert_eq!(invalid.errors.len(), 1);
+    assert_eq!(invalid.errors[0].field, "reasons");
+    assert_eq!(invalid.errors[0].code, ValidationCode::Required);
+
+    let rejection = ComplianceReviewReq {
+        decision: ComplianceReviewDecision::Reject,
+        reasons: vec![ComplianceFieldReason {
+            field: "tin".into(),
+            reason: " ".into(),
+        }],
+    };
+    let invalid = rejection.validate().unwrap_err();
+    assert_eq!(invalid.errors.len(), 1);
+    assert_eq!(invalid.errors[0].field, "reasons[0].reason");
+    assert_eq!(invalid.errors[0].code, ValidationCode::Required);
+}